@@ -0,0 +1,131 @@
+//! Times `status` on a generated repository, run with `cargo bench --bench status`.
+//!
+//! `BENCH_FILES` sets how many files the repository gets (20000 by default) and `BENCH_RUNS`
+//! how many times each case is timed. The last case adds as many untracked files again. The
+//! repository is made with our own `init` and `add` in a directory under the system temp dir,
+//! and removed afterwards.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_git-clone");
+
+/// files per directory, so the tree is a few levels deep like a real checkout
+const FAN_OUT: usize = 50;
+
+const STATUS: &[&str] = &["status", "--porcelain"];
+/// listing every untracked file rather than stopping at untracked directories
+const STATUS_ALL: &[&str] = &["status", "--porcelain", "--untracked-files=all"];
+
+fn main() {
+    let files = env_number("BENCH_FILES", 20_000);
+    let runs = env_number("BENCH_RUNS", 5);
+    let repo = env::temp_dir().join(format!("git-clone-bench-{}", std::process::id()));
+
+    generate(&repo, files);
+    println!("status on {} files, best of {} runs", files, runs);
+    report("clean", time(&repo, runs, STATUS, |_| {}));
+    // new mtimes with the same contents, so every entry has to be hashed
+    report(
+        "touched",
+        time(&repo, runs, STATUS, |repo| touch_all(repo, files)),
+    );
+    report(
+        "modified",
+        time(&repo, runs, STATUS, |repo| {
+            fs::write(repo.join(file_path(0)), "changed\n").unwrap()
+        }),
+    );
+    // as many files again that were never added, in directories of their own and among the
+    // tracked ones, so the scan for untracked files has to read every directory
+    add_untracked(&repo, files);
+    report("untracked", time(&repo, runs, STATUS_ALL, |_| {}));
+
+    fs::remove_dir_all(&repo).unwrap();
+}
+
+fn env_number(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// `d<n>/d<n>/f<n>`, spreading files over nested directories.
+fn file_path(n: usize) -> PathBuf {
+    let mut path = PathBuf::new();
+    let mut dir = n / FAN_OUT;
+    while dir > 0 {
+        path.push(format!("d{}", dir % FAN_OUT));
+        dir /= FAN_OUT;
+    }
+    path.push(format!("f{}", n));
+    path
+}
+
+fn generate(repo: &Path, files: usize) {
+    fs::create_dir_all(repo).unwrap();
+    run(repo, &["init"]);
+    for n in 0..files {
+        let path = repo.join(file_path(n));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("file {}\n", n)).unwrap();
+    }
+    run(repo, &["add", "."]);
+}
+
+fn add_untracked(repo: &Path, files: usize) {
+    for n in 0..files {
+        let path = file_path(n);
+        let path = match n % 2 {
+            0 => path.with_file_name(format!("u{}", n)),
+            _ => Path::new("untracked").join(path),
+        };
+        let path = repo.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("untracked {}\n", n)).unwrap();
+    }
+}
+
+fn touch_all(repo: &Path, files: usize) {
+    for n in 0..files {
+        let path = repo.join(file_path(n));
+        let contents = fs::read(&path).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
+
+/// The quickest of `runs` runs of `args`, with `prepare` run before each one untimed.
+fn time(repo: &Path, runs: usize, args: &[&str], prepare: impl Fn(&Path)) -> Duration {
+    (0..runs)
+        .map(|_| {
+            prepare(repo);
+            let start = Instant::now();
+            run(repo, args);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn run(repo: &Path, args: &[&str]) {
+    let output = Command::new(BIN)
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn report(case: &str, elapsed: Duration) {
+    println!("{:<10}{:>10.1?}", case, elapsed);
+}
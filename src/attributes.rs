@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
+
+use crate::wildmatch::wildmatch;
+
+/// The state of one attribute for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum State {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: String,
+    /// the directory of the `.gitattributes` the rule came from, relative to the top, empty
+    /// for the top level and info/attributes
+    base: String,
+    /// `None` for `!attr`, which takes the attribute back to unspecified
+    attrs: Vec<(String, Option<State>)>,
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        let Some(relative) = relative_to(path, &self.base) else {
+            return false;
+        };
+        if self.pattern.contains('/') {
+            wildmatch(self.pattern.trim_start_matches('/'), relative)
+        } else {
+            // without a slash the pattern is matched against the file name alone
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.pattern, name)
+        }
+    }
+}
+
+fn relative_to<'a>(path: &'a str, base: &str) -> Option<&'a str> {
+    if base.is_empty() {
+        Some(path)
+    } else {
+        path.strip_prefix(base)?.strip_prefix('/')
+    }
+}
+
+/// The attributes that apply to `path` (relative to the top of the worktree), from the
+/// `.gitattributes` files in its directory and every directory above it, then
+/// `.git/info/attributes`. Later rules win, so deeper files override shallower ones.
+pub fn for_path(path: &str) -> Result<HashMap<String, State>> {
+    let mut rules = Vec::new();
+    load(Path::new(".gitattributes"), "", &mut rules)?;
+    let mut dir = String::new();
+    let components: Vec<&str> = path.split('/').collect();
+    for component in &components[..components.len().saturating_sub(1)] {
+        if !dir.is_empty() {
+            dir.push('/');
+        }
+        dir.push_str(component);
+        load(&Path::new(&dir).join(".gitattributes"), &dir, &mut rules)?;
+    }
+    load(Path::new(".git/info/attributes"), "", &mut rules)?;
+
+    let mut attrs = HashMap::new();
+    for rule in rules.iter().filter(|r| r.matches(path)) {
+        for (name, value) in &rule.attrs {
+            match value {
+                Some(value) => attrs.insert(name.clone(), value.clone()),
+                None => attrs.remove(name),
+            };
+        }
+    }
+    Ok(attrs)
+}
+
+fn load(file: &Path, base: &str, rules: &mut Vec<Rule>) -> Result<()> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+    };
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let Some(pattern) = words.next() else {
+            continue;
+        };
+        let mut attrs = Vec::new();
+        for word in words {
+            if word == "binary" {
+                // the one built in macro
+                for name in ["diff", "merge", "text"] {
+                    attrs.push((name.to_string(), Some(State::Unset)));
+                }
+                continue;
+            }
+            let attr = if let Some(name) = word.strip_prefix('-') {
+                (name.to_string(), Some(State::Unset))
+            } else if let Some(name) = word.strip_prefix('!') {
+                (name.to_string(), None)
+            } else if let Some((name, value)) = word.split_once('=') {
+                (name.to_string(), Some(State::Value(value.to_string())))
+            } else {
+                (word.to_string(), Some(State::Set))
+            };
+            attrs.push(attr);
+        }
+        rules.push(Rule {
+            pattern: pattern.to_string(),
+            base: base.to_string(),
+            attrs,
+        });
+    }
+    Ok(())
+}
@@ -1,14 +1,147 @@
-use anyhow::Result;
-use std::fs;
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeSet, HashSet},
+    io,
+    path::PathBuf,
+};
 
-use crate::objects::index::IndexEntry;
+use crate::{
+    ignore::Ignore,
+    objects::{
+        index::{IndexEntry, IndexFile},
+        object::{Kind, Object},
+    },
+    pathspec::{self, Pathspec},
+    worktree,
+};
 
-pub fn invoke(file: &std::path::PathBuf) -> Result<()> {
-    if fs::exists(".git/index").is_err() {
-        fs::File::create_new(".git/index");
+pub(crate) struct Options {
+    /// add ignored files too
+    pub force: bool,
+}
+
+pub fn invoke(options: &Options, paths: &[String]) -> Result<()> {
+    if paths.is_empty() {
+        eprintln!("Nothing specified, nothing added.");
+        eprintln!("hint: Maybe you wanted to say 'git add .'?");
+        return Ok(());
+    }
+    let pathspec = Pathspec::new(paths);
+    let mut index = IndexFile::read_or_empty()?;
+    let ignore = Ignore::standard()?;
+    let files = worktree::walk(&ignore)?;
+
+    let tracked: HashSet<String> = index
+        .entries
+        .iter()
+        .filter_map(|e| e.entry_path.to_str().map(|p| p.to_string()))
+        .collect();
+    let mut matched = vec![false; pathspec.items.len()];
+    let mut ignored = BTreeSet::new();
+    let mut to_add = Vec::new();
+
+    for file in &files {
+        let mut wanted = false;
+        for (i, item) in pathspec.items.iter().enumerate() {
+            if pathspec::item_matches(item, &file.path) {
+                matched[i] = true;
+                wanted = true;
+            }
+        }
+        if !wanted {
+            continue;
+        }
+        if file.ignored && !options.force && !tracked.contains(&file.path) {
+            // only complain when the ignored file or directory was asked for by name, not when
+            // it just happens to be inside a directory being added
+            let root = ignored_root(&ignore, &file.path);
+            let named = pathspec.items.iter().any(|item| {
+                !pathspec::has_glob(item)
+                    && (item == root
+                        || item
+                            .strip_prefix(root)
+                            .is_some_and(|rest| rest.starts_with('/')))
+            });
+            if named {
+                ignored.insert(root.to_string());
+            }
+            continue;
+        }
+        to_add.push(file.path.as_str());
+    }
+
+    // tracked files under the pathspec that are gone from the worktree are removed
+    let present: HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+    let mut removed = HashSet::new();
+    for path in &tracked {
+        if present.contains(path.as_str()) {
+            continue;
+        }
+        for (i, item) in pathspec.items.iter().enumerate() {
+            if pathspec::item_matches(item, path) {
+                matched[i] = true;
+                removed.insert(path.clone());
+            }
+        }
+    }
+
+    if let Some(i) = matched.iter().position(|m| !m) {
+        anyhow::bail!(
+            "pathspec '{}' did not match any files",
+            paths[i].trim_end_matches('/')
+        );
+    }
+
+    index
+        .entries
+        .retain(|e| e.entry_path.to_str().is_none_or(|p| !removed.contains(p)));
+    for path in &to_add {
+        add_file(&mut index, path)?;
+    }
+    // what is untracked changes in the directories that gained or lost an entry
+    if let Some(cache) = &mut index.untracked_cache {
+        let added = to_add.iter().copied().filter(|p| !tracked.contains(*p));
+        for path in added.chain(removed.iter().map(|p| p.as_str())) {
+            cache.invalidate_path(path);
+        }
+    }
+    index.write().context("writing the index")?;
+
+    if !ignored.is_empty() {
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in &ignored {
+            eprintln!("{}", path);
+        }
+        eprintln!("hint: Use -f if you really want to add them.");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The outermost directory above `path`, or `path` itself, that the ignore rules exclude.
+fn ignored_root<'a>(ignore: &Ignore, path: &'a str) -> &'a str {
+    for (i, c) in path.char_indices() {
+        if c == '/' && ignore.is_ignored(&path[..i], true) {
+            return &path[..i];
+        }
+    }
+    path
+}
+
+/// Writes the blob for `path` and puts it in the index at stage 0, replacing whatever entries
+/// the path had before, conflicts included.
+fn add_file(index: &mut IndexFile, path: &str) -> Result<()> {
+    let contents = worktree::contents_for_index(path)?;
+    let hash = Object {
+        kind: Kind::Blob,
+        len: contents.len() as u64,
+        reader: Box::new(io::Cursor::new(contents)),
     }
+    .write()
+    .with_context(|| format!("writing the blob for {}", path))?;
 
-    let index_entry = IndexEntry::from_path("Cargo.toml".into(), "sdlkajjlks", 0)?;
-    println!("{:#?}", index_entry);
+    let entry = IndexEntry::from_path(PathBuf::from(path), &hash, 0)?;
+    index.entries.retain(|e| e.entry_path != entry.entry_path);
+    index.entries.push(entry);
     Ok(())
 }
@@ -0,0 +1,661 @@
+//! `apply`: reads patches, git's or plain unified diffs, and applies them to the worktree, the
+//! index or both.
+//!
+//! Every patch in an input is checked before anything is written, so an input either applies
+//! as a whole or not at all. What earlier patches in the input made of a path is what later
+//! ones for the same path see.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs,
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config,
+    convert, diff,
+    merge::{self, Labels, Style},
+    objects::{
+        index::{IndexEntry, IndexFile},
+        object::{self, Kind, Object},
+    },
+    patch::{
+        self,
+        whitespace::{self, Action, Report},
+        FilePatch,
+    },
+    worktree::{self, EntryState},
+};
+
+pub(crate) struct Options {
+    /// only see whether the patches apply
+    pub check: bool,
+    /// apply to the index as well, which must match the worktree for the files patched
+    pub index: bool,
+    /// apply to the index only
+    pub cached: bool,
+    /// merge with the blobs a patch names when it doesn't apply; implies `index`
+    pub three_way: bool,
+    pub reverse: bool,
+    /// leading components to take off the names in the patches, else git's `a/` and `b/`
+    pub strip: Option<usize>,
+    /// how many context lines must match, `-C`
+    pub context: Option<usize>,
+    /// trust hunks without context to be where their headers say
+    pub unidiff_zero: bool,
+    /// what to do about whitespace errors, `apply.whitespace` when not given
+    pub whitespace: Option<String>,
+    pub ignore_space_change: bool,
+}
+
+/// The blobs of the base, ours and theirs of a conflict.
+type Stages = [Option<String>; 3];
+
+/// A file's patch checked and ready to be written.
+struct Outcome {
+    contents: Vec<u8>,
+    mode: u32,
+    /// the blobs of the base, ours and theirs when a three-way merge left conflicts, without
+    /// a base for a new file
+    conflict: Option<Stages>,
+}
+
+struct Apply<'a> {
+    options: &'a Options,
+    apply_options: patch::apply::Options,
+    /// how a three-way merge shows conflicts
+    merge_options: merge::Options,
+    report: Report,
+    /// the index, when the patches are checked against it or applied to it
+    index: Option<IndexFile>,
+    /// what the input's patches so far made of each path, `None` where they took the file
+    /// away
+    table: HashMap<String, Option<(Vec<u8>, u32)>>,
+    /// paths the current input deletes or renames away, which other files may take
+    to_be_deleted: HashSet<String>,
+}
+
+pub fn invoke(options: &Options, files: &[String]) -> Result<()> {
+    let config = Config::load()?;
+    let (action, squelch) = match options
+        .whitespace
+        .as_deref()
+        .or(config.get("apply.whitespace"))
+    {
+        Some(value) => Action::parse(value)?,
+        // only applying has anything to warn about by default
+        None if options.check => (Action::Nowarn, 5),
+        None => (Action::Warn, 5),
+    };
+    let ignore_space_change =
+        options.ignore_space_change || config.get("apply.ignorewhitespace") == Some("change");
+    let index = match options.index || options.cached || options.three_way {
+        true => Some(IndexFile::read_or_empty()?),
+        false => None,
+    };
+    let mut state = Apply {
+        options,
+        apply_options: patch::apply::Options {
+            unidiff_zero: options.unidiff_zero,
+            context: options.context,
+            ignore_space_change,
+        },
+        merge_options: merge::Options {
+            style: Style::configured(&config)?.unwrap_or(Style::Merge),
+            ..Default::default()
+        },
+        report: Report::new(action, squelch),
+        index,
+        table: HashMap::new(),
+        to_be_deleted: HashSet::new(),
+    };
+
+    let stdin = ["-".to_string()];
+    let mut conflicts = false;
+    let mut applied = false;
+    for file in if files.is_empty() { &stdin[..] } else { files } {
+        let input = match file.as_str() {
+            "-" => {
+                let mut input = Vec::new();
+                io::stdin().read_to_end(&mut input)?;
+                state.report.input = "<stdin>".to_string();
+                input
+            }
+            _ => {
+                state.report.input = file.clone();
+                match fs::read(file) {
+                    Ok(input) => input,
+                    Err(e) => {
+                        eprintln!("error: can't open patch '{}': {}", file, e);
+                        std::process::exit(128);
+                    }
+                }
+            }
+        };
+        let patches = match patch::parse(&input, options.strip) {
+            Ok(patches) => patches,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(128);
+            }
+        };
+        if patches.is_empty() {
+            eprintln!("error: No valid patches in input (allow with \"--allow-empty\")");
+            std::process::exit(128);
+        }
+        match state.apply_input(patches)? {
+            Some(conflicted) => {
+                applied = !options.check;
+                conflicts |= conflicted;
+            }
+            None => std::process::exit(1),
+        }
+    }
+
+    if state.report.finish(applied) {
+        std::process::exit(128);
+    }
+    if let (true, Some(index)) = (applied, &mut state.index) {
+        index.write().context("writing the index")?;
+    }
+    if conflicts {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+impl Apply<'_> {
+    /// Checks and, unless only checking, writes the patches of one input. `None` if they
+    /// don't apply, else whether a three-way merge left conflicts.
+    fn apply_input(&mut self, mut patches: Vec<FilePatch>) -> Result<Option<bool>> {
+        if self.options.reverse {
+            // undoing goes from the last patch back, which matters when two touch one path
+            patches.reverse();
+            patches.iter_mut().for_each(FilePatch::reverse);
+        }
+        self.check_whitespace(&mut patches);
+        self.table.clear();
+        self.to_be_deleted = patches
+            .iter()
+            .filter(|p| p.is_delete || p.is_rename)
+            .filter_map(|p| p.old_name.clone())
+            .collect();
+
+        // whitespace errors refused with --whitespace=error leave nothing to apply, or to check
+        // unless asked to
+        let refused = |report: &Report| report.action == Action::Error && report.errors > 0;
+        if refused(&self.report) && !self.options.check {
+            return Ok(Some(false));
+        }
+        let mut outcomes = Vec::new();
+        let mut failed = false;
+        for patch in &mut patches {
+            // git gives up on the whole input at a path that could write outside the worktree
+            if let Err(e) = check_unsafe_paths(patch) {
+                eprintln!("error: {}", e);
+                std::process::exit(128);
+            }
+            match self.check(patch) {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            return Ok(None);
+        }
+        if self.options.check || refused(&self.report) {
+            return Ok(Some(false));
+        }
+        let conflicted = self.write(&patches, outcomes)?;
+        for path in &conflicted {
+            eprintln!("U {}", path);
+        }
+        Ok(Some(!conflicted.is_empty()))
+    }
+
+    /// Looks for whitespace errors in the lines the patches add, and with `--whitespace=fix`
+    /// in their context too, since fixed context can match lines fixed before. Once an old
+    /// line is seen to end in CRLF, CRs at the end of lines stop counting as whitespace.
+    fn check_whitespace(&mut self, patches: &mut [FilePatch]) {
+        let warn = self.report.action != Action::Nowarn;
+        let context = self.report.action == Action::Fix && !self.options.reverse;
+        for patch in patches {
+            for line in patch.hunks.iter().flat_map(|h| &h.lines) {
+                if line.sign != b'+' && line.text.ends_with(b"\r\n") {
+                    patch.crlf_in_old = true;
+                }
+                if warn && (line.sign == b'+' || (line.sign == b' ' && context)) {
+                    let text = line.text.strip_suffix(b"\n").unwrap_or(&line.text);
+                    let errors = whitespace::check(text, patch.crlf_in_old);
+                    self.report.record(errors, text, line.line_number);
+                }
+            }
+        }
+    }
+
+    /// Applies a file's patch to what it should apply to, checking that the file is there,
+    /// or isn't for a new one, and what the index says about it.
+    fn check(&mut self, patch: &mut FilePatch) -> Result<Outcome> {
+        let name = patch.name().to_string();
+        let mut image = Vec::new();
+        if let (Some(old_name), false) = (&patch.old_name, patch.is_new) {
+            let (contents, mode) = self.preimage(old_name)?;
+            let expected = *patch.old_mode.get_or_insert(mode);
+            if expected >> 12 != mode >> 12 {
+                bail!("{}: wrong type", old_name);
+            }
+            if expected != mode {
+                eprintln!(
+                    "warning: {} has type {:o}, expected {:o}",
+                    old_name, mode, expected
+                );
+            }
+            if !patch.is_delete && patch.new_mode.is_none() {
+                patch.new_mode = Some(mode);
+            }
+            image = contents;
+        }
+
+        if let Some(new_name) = patch.new_name.as_deref().filter(|_| !patch.is_delete) {
+            if self.is_beyond_symlink(new_name) {
+                bail!("affected file '{}' is beyond a symbolic link", new_name);
+            }
+        }
+
+        let mut direct_to_three_way = false;
+        if let Some(new_name) = &patch.new_name {
+            if patch.is_new || patch.is_rename || patch.is_copy {
+                let ok_if_exists = matches!(self.table.get(new_name), Some(None))
+                    || self.to_be_deleted.contains(new_name);
+                if let Err(e) = self.check_to_create(new_name, ok_if_exists) {
+                    // a new file that is already there may still merge with what is
+                    if !(self.options.three_way && patch.is_new) {
+                        return Err(e);
+                    }
+                    direct_to_three_way = true;
+                    image = self.preimage(new_name)?.0;
+                }
+                if patch.new_mode.is_none() {
+                    patch.new_mode = Some(0o100644);
+                }
+            }
+        }
+
+        let (contents, conflict) = match self.apply_data(patch, image, direct_to_three_way) {
+            Ok(Some(applied)) => applied,
+            Ok(None) => bail!("{}: patch does not apply", name),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                bail!("{}: patch does not apply", name);
+            }
+        };
+
+        let mode = patch.new_mode.or(patch.old_mode).unwrap_or(0o100644);
+        if let Some(new_name) = &patch.new_name {
+            self.table
+                .insert(new_name.clone(), Some((contents.clone(), mode)));
+        }
+        if let (Some(old_name), true) = (&patch.old_name, patch.is_delete || patch.is_rename) {
+            self.table.insert(old_name.clone(), None);
+        }
+        Ok(Outcome {
+            contents,
+            mode,
+            conflict,
+        })
+    }
+
+    /// The contents and mode a patch to `name` applies to: what an earlier patch left there,
+    /// else the index's blob with `--cached`, else the file in the worktree.
+    fn preimage(&self, name: &str) -> Result<(Vec<u8>, u32)> {
+        if let Some(previous) = self.table.get(name) {
+            return previous
+                .clone()
+                .ok_or_else(|| anyhow!("path {} has been renamed/deleted", name));
+        }
+        let entry = match &self.index {
+            Some(index) => Some(
+                index
+                    .entries
+                    .iter()
+                    .find(|e| e.entry_path == Path::new(name) && e.stage() == 0)
+                    .ok_or_else(|| anyhow!("{}: does not exist in index", name))?,
+            ),
+            None => None,
+        };
+        if let (true, Some(entry)) = (self.options.cached, entry) {
+            return Ok((diff::read_blob(&entry.hash)?, entry.mode()));
+        }
+        if has_symlink_leading_path(name) {
+            eprintln!("error: reading from '{}' beyond a symbolic link", name);
+            bail!("{}: patch does not apply", name);
+        }
+        let metadata = match fs::symlink_metadata(name) {
+            Ok(metadata) => metadata,
+            // with the index to go by, a file missing from the worktree is as the index has it
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match entry {
+                Some(entry) => return Ok((diff::read_blob(&entry.hash)?, entry.mode())),
+                None => bail!("{}: No such file or directory", name),
+            },
+            Err(e) => return Err(e).with_context(|| format!("reading metadata of {}", name)),
+        };
+        if let (Some(index), Some(entry)) = (&self.index, entry) {
+            match worktree::check_entry(index, entry)? {
+                EntryState::Unchanged | EntryState::Stale(_) => {}
+                _ => bail!("{}: does not match index", name),
+            }
+        }
+        let mode = if metadata.is_symlink() {
+            0o120000
+        } else if metadata.permissions().mode() & 0o100 != 0 {
+            0o100755
+        } else {
+            0o100644
+        };
+        Ok((worktree::contents_for_index(name)?, mode))
+    }
+
+    /// Whether one of the directories above `name` is a symlink, as earlier patches in the
+    /// input leave it, else in the index or the worktree. Writing there would go wherever the
+    /// link points.
+    fn is_beyond_symlink(&self, name: &str) -> bool {
+        let is_link = |mode: u32| mode & 0o170000 == 0o120000;
+        let mut dir = name;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            dir = parent;
+            if let Some(previous) = self.table.get(dir) {
+                if previous.as_ref().is_some_and(|(_, mode)| is_link(*mode)) {
+                    return true;
+                }
+                continue;
+            }
+            if self.to_be_deleted.contains(dir) {
+                continue;
+            }
+            let in_index = self.index.as_ref().is_some_and(|index| {
+                index
+                    .entries
+                    .iter()
+                    .any(|e| e.entry_path == Path::new(dir) && e.stage() == 0 && is_link(e.mode()))
+            });
+            let in_worktree = !self.options.cached
+                && fs::symlink_metadata(dir).is_ok_and(|m| m.file_type().is_symlink());
+            if in_index || in_worktree {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Checks that nothing is in the way of a file the patch creates.
+    fn check_to_create(&self, name: &str, ok_if_exists: bool) -> Result<()> {
+        if let Some(index) = &self.index {
+            let in_index = index
+                .entries
+                .iter()
+                .any(|e| e.entry_path == Path::new(name) && e.stage() == 0);
+            if in_index && (!ok_if_exists || !self.options.cached) {
+                bail!("{}: already exists in index", name);
+            }
+        }
+        if self.options.cached {
+            return Ok(());
+        }
+        match fs::symlink_metadata(name) {
+            Ok(metadata) if !metadata.is_dir() && !ok_if_exists => {
+                bail!("{}: already exists in working directory", name)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What the patch makes of `image`, with the blobs of a three-way merge that left
+    /// conflicts. `None` once the reasons it doesn't apply have been shown.
+    fn apply_data(
+        &mut self,
+        patch: &FilePatch,
+        image: Vec<u8>,
+        direct_to_three_way: bool,
+    ) -> Result<Option<(Vec<u8>, Option<Stages>)>> {
+        if self.options.three_way {
+            match self.three_way(patch, &image, direct_to_three_way) {
+                Ok(Some(merged)) => return Ok(Some(merged)),
+                Ok(None) => {}
+                Err(e) => eprintln!("error: {}", e),
+            }
+            if direct_to_three_way {
+                return Ok(None);
+            }
+            eprintln!("Falling back to direct application...");
+        }
+        let contents = patch::apply::apply(patch, image, &self.apply_options, &mut self.report)?;
+        if patch.is_delete && !contents.is_empty() {
+            bail!("removal patch leaves file contents");
+        }
+        Ok(Some((contents, None)))
+    }
+
+    /// Applies the patch to the blob it was made against and merges the result with `ours`.
+    /// `None` for patches a merge doesn't help with.
+    fn three_way(
+        &mut self,
+        patch: &FilePatch,
+        ours: &[u8],
+        direct: bool,
+    ) -> Result<Option<(Vec<u8>, Option<Stages>)>> {
+        if patch.is_delete || (patch.is_new && !direct) || !patch.changes_contents() {
+            return Ok(None);
+        }
+        let base = match patch.is_new {
+            true => Vec::new(),
+            false => patch
+                .old_id
+                .as_deref()
+                .and_then(|id| object::expand_id(id).ok())
+                .and_then(|id| diff::read_blob(&id).ok())
+                .ok_or_else(|| {
+                    anyhow!("repository lacks the necessary blob to perform 3-way merge.")
+                })?,
+        };
+        if direct {
+            eprintln!("Performing three-way merge...");
+        }
+        let theirs =
+            patch::apply::apply(patch, base.clone(), &self.apply_options, &mut self.report)?;
+        let base_id = (!patch.is_new).then(|| write_blob(&base)).transpose()?;
+        let ours_id = write_blob(ours)?;
+        let theirs_id = write_blob(&theirs)?;
+
+        let name = patch.new_name.as_deref().unwrap_or_default();
+        let merged = if [&base[..], ours, &theirs]
+            .iter()
+            .any(|c| convert::is_binary(c))
+        {
+            eprintln!(
+                "warning: Cannot merge binary files: {} (ours vs. theirs)",
+                name
+            );
+            merge::Merged {
+                contents: ours.to_vec(),
+                conflicts: 1,
+            }
+        } else {
+            let labels = Labels {
+                ours: "ours",
+                base: "base",
+                theirs: "theirs",
+            };
+            merge::merge(&base, ours, &theirs, &labels, &self.merge_options)
+        };
+        if merged.conflicts == 0 {
+            eprintln!("Applied patch to '{}' cleanly.", name);
+            return Ok(Some((merged.contents, None)));
+        }
+        eprintln!("Applied patch to '{}' with conflicts.", name);
+        let stages = [base_id, Some(ours_id), Some(theirs_id)];
+        Ok(Some((merged.contents, Some(stages))))
+    }
+
+    /// Writes the checked patches out: first every file that goes away, so that a file can
+    /// take the place of one deleted or renamed, then the new contents. Returns the paths
+    /// left with conflicts, sorted.
+    fn write(&mut self, patches: &[FilePatch], outcomes: Vec<Outcome>) -> Result<Vec<String>> {
+        for patch in patches {
+            if patch.is_new || patch.is_copy {
+                continue;
+            }
+            if let Some(old_name) = &patch.old_name {
+                self.remove(old_name, patch.is_delete || patch.is_rename)?;
+            }
+        }
+        let mut conflicted = Vec::new();
+        for (patch, outcome) in patches.iter().zip(outcomes) {
+            let Some(new_name) = patch.new_name.as_deref().filter(|_| !patch.is_delete) else {
+                continue;
+            };
+            if outcome.conflict.is_some() {
+                conflicted.push(new_name.to_string());
+            }
+            self.create(new_name, outcome)?;
+        }
+        conflicted.sort();
+        Ok(conflicted)
+    }
+
+    /// Takes `name` out of the index and the worktree, with the directories it leaves empty
+    /// if `remove_empty_dirs`.
+    fn remove(&mut self, name: &str, remove_empty_dirs: bool) -> Result<()> {
+        if let Some(index) = &mut self.index {
+            index.entries.retain(|e| e.entry_path != Path::new(name));
+            if let Some(cache) = &mut index.untracked_cache {
+                cache.invalidate_path(name);
+            }
+        }
+        if self.options.cached {
+            return Ok(());
+        }
+        match fs::remove_file(name) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("warning: unable to unlink '{}': {}", name, e);
+                return Ok(());
+            }
+            _ => {}
+        }
+        if remove_empty_dirs {
+            let mut dir = Path::new(name).parent();
+            while let Some(parent) = dir.filter(|d| !d.as_os_str().is_empty()) {
+                if fs::remove_dir(parent).is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a file's new contents to the worktree and the index, or its conflict stages to
+    /// the index.
+    fn create(&mut self, name: &str, outcome: Outcome) -> Result<()> {
+        if !self.options.cached {
+            write_file(name, &outcome.contents, outcome.mode)?;
+        }
+        let Some(index) = &mut self.index else {
+            return Ok(());
+        };
+        let path = PathBuf::from(name);
+        index.entries.retain(|e| e.entry_path != path);
+        match &outcome.conflict {
+            Some(stages) => {
+                for (stage, id) in (1..).zip(stages) {
+                    if let Some(id) = id {
+                        let entry = IndexEntry::without_stat(path.clone(), id, outcome.mode, stage);
+                        index.entries.push(entry);
+                    }
+                }
+            }
+            None => {
+                let hash = write_blob(&outcome.contents)?;
+                let entry = match self.options.cached {
+                    true => IndexEntry::without_stat(path, &hash, outcome.mode, 0),
+                    false => IndexEntry::from_path(path, &hash, 0)?,
+                };
+                index.entries.push(entry);
+            }
+        }
+        if let Some(cache) = &mut index.untracked_cache {
+            cache.invalidate_path(name);
+        }
+        Ok(())
+    }
+}
+
+/// Refuses names git wouldn't take into a tree: absolute ones and ones with an empty, `.`,
+/// `..` or `.git` component. Old names only count where the file is read or removed.
+fn check_unsafe_paths(patch: &FilePatch) -> Result<()> {
+    let reads_old = patch.is_delete || !(patch.is_new || patch.is_copy);
+    let old_name = patch.old_name.as_deref().filter(|_| reads_old);
+    let new_name = patch.new_name.as_deref().filter(|_| !patch.is_delete);
+    for name in [old_name, new_name].into_iter().flatten() {
+        let valid = name.split('/').all(|component| {
+            !matches!(component, "" | "." | "..") && !component.eq_ignore_ascii_case(".git")
+        });
+        if !valid {
+            bail!("invalid path '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+/// Whether one of the directories above `name` in the worktree is a symlink.
+fn has_symlink_leading_path(name: &str) -> bool {
+    let mut dir = name;
+    while let Some((parent, _)) = dir.rsplit_once('/') {
+        dir = parent;
+        if fs::symlink_metadata(dir).is_ok_and(|m| m.file_type().is_symlink()) {
+            return true;
+        }
+    }
+    false
+}
+
+fn write_blob(contents: &[u8]) -> Result<String> {
+    Object {
+        kind: Kind::Blob,
+        len: contents.len() as u64,
+        reader: Box::new(io::Cursor::new(contents.to_vec())),
+    }
+    .write()
+    .context("writing a blob")
+}
+
+/// Writes `contents` to `path` as a file of `mode`, making the directories above it.
+fn write_file(path: &str, contents: &[u8], mode: u32) -> Result<()> {
+    let file = Path::new(path);
+    if let Some(parent) = file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+    }
+    if fs::symlink_metadata(file).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(file).with_context(|| format!("removing {}", path))?;
+    }
+    if mode & 0o170000 == 0o120000 {
+        return symlink(OsStr::from_bytes(contents), file)
+            .with_context(|| format!("creating link {}", path));
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(if mode & 0o100 != 0 { 0o777 } else { 0o666 })
+        .open(file)
+        .and_then(|mut f| f.write_all(contents))
+        .with_context(|| format!("writing {}", path))
+}
@@ -1,37 +1,211 @@
-use crate::objects::{
-    commit::Commit,
-    object::{self, Object},
-    tree::Tree,
+use crate::{
+    objects::{
+        commit::Commit,
+        error::Error,
+        object::{self, Object},
+        tag::Tag,
+        tree::Tree,
+    },
+    revision,
 };
 use anyhow::{Context, Result};
-use std::io::{BufReader, Read};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+pub(crate) struct Options {
+    pub pretty_print: bool,
+    pub show_type: bool,
+    pub show_size: bool,
+    pub exists: bool,
+    /// `--batch[=<format>]`, an empty format meaning the default one
+    pub batch: Option<String>,
+    pub batch_check: Option<String>,
+    pub batch_all_objects: bool,
+    /// don't flush after every object in batch mode
+    pub buffer: bool,
+}
+
+pub fn invoke(options: &Options, args: &[String]) -> Result<()> {
+    if options.batch.is_some() || options.batch_check.is_some() {
+        anyhow::ensure!(args.is_empty(), "batch modes take object names on stdin");
+        return batch(options);
+    }
 
-pub fn invoke(pretty_print: bool, hash: &str) -> Result<()> {
-    anyhow::ensure!(pretty_print, "must have pretty print for now");
+    let (kind, name) = match args {
+        [name] => (None, name),
+        // `cat-file <type> <object>`
+        [kind, name] => (Some(object::Kind::try_from(kind.as_str())?), name),
+        _ => anyhow::bail!("usage: cat-file (-t | -s | -e | -p | <type>) <object>"),
+    };
+
+    let hash = revision::resolve(name)?;
+    if options.exists {
+        // -e only says through the exit code whether an object that was named is there
+        let found = Object::try_from(hash.as_str()).is_ok();
+        std::process::exit(if found { 0 } else { 1 });
+    }
+
+    let obj: Object = hash.as_str().try_into().context("parsing object")?;
+    let mut stdout = io::stdout().lock();
+
+    if options.show_type {
+        writeln!(stdout, "{}", obj.kind)?;
+    } else if options.show_size {
+        writeln!(stdout, "{}", obj.len)?;
+    } else if let Some(kind) = kind {
+        let hash = revision::peel(&hash, kind)?;
+        let mut obj = Object::try_from(hash.as_str())?;
+        io::copy(&mut obj.reader, &mut stdout)?;
+    } else if options.pretty_print {
+        pretty_print(obj, &mut stdout)?;
+    } else {
+        anyhow::bail!("usage: cat-file (-t | -s | -e | -p | <type>) <object>");
+    }
 
-    let mut obj: Object = hash.try_into().context("parsing object")?;
+    Ok(())
+}
+
+fn pretty_print(mut obj: Object, out: &mut impl Write) -> Result<()> {
     match obj.kind {
         object::Kind::Blob => {
-            let mut buffer = Vec::new();
-            obj.reader.read_to_end(&mut buffer)?;
-            let contents = String::from_utf8_lossy(&buffer);
-
-            print!("{}", &contents);
+            io::copy(&mut obj.reader, out)?;
         }
         object::Kind::Tree => {
             let mut bufread = BufReader::new(&mut obj.reader);
             let tree = Tree::read(&mut bufread)?;
 
             for entry in tree.entries {
-                println!("{}", entry);
+                writeln!(out, "{}", entry)?;
             }
         }
         object::Kind::Commit => {
             let mut bufread = BufReader::new(&mut obj.reader);
             let commit = Commit::read(&mut bufread)?;
-            print!("{}", &commit);
+            write!(out, "{}", &commit)?;
+        }
+        object::Kind::Tag => {
+            let mut bufread = BufReader::new(&mut obj.reader);
+            let tag = Tag::read(&mut bufread)?;
+            write!(out, "{}", &tag)?;
+        }
+    }
+    Ok(())
+}
+
+/// `--batch` and `--batch-check`: one object name per line of stdin (or every object with
+/// `--batch-all-objects`), answered with a line in the given format and, for `--batch`, the
+/// raw contents followed by a newline.
+fn batch(options: &Options) -> Result<()> {
+    let (format, with_contents) = match (&options.batch, &options.batch_check) {
+        (Some(format), _) => (format.as_str(), true),
+        (None, Some(format)) => (format.as_str(), false),
+        (None, None) => unreachable!("only called in a batch mode"),
+    };
+    let format = if format.is_empty() {
+        DEFAULT_FORMAT
+    } else {
+        format
+    };
+    // %(rest) is only split off the input when the format asks for it
+    let split_rest = format.contains("%(rest)");
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+
+    if options.batch_all_objects {
+        for hash in object::all_ids()? {
+            batch_one(&mut out, format, &hash, &hash, "", with_contents)?;
         }
+        out.flush()?;
+        return Ok(());
     }
 
+    for line in io::stdin().lock().lines() {
+        let line = line.context("reading stdin")?;
+        let (name, rest) = if split_rest {
+            match line.split_once(char::is_whitespace) {
+                Some((name, rest)) => (name, rest.trim_start()),
+                None => (line.as_str(), ""),
+            }
+        } else {
+            (line.as_str(), "")
+        };
+
+        match revision::resolve(name) {
+            Ok(hash) => batch_one(&mut out, format, name, &hash, rest, with_contents)?,
+            Err(e) if is_ambiguous(&e) => writeln!(out, "{} ambiguous", name)?,
+            Err(_) => writeln!(out, "{} missing", name)?,
+        }
+        if !options.buffer {
+            // whoever is on the other end of the pipe is waiting for this answer
+            out.flush()?;
+        }
+    }
+    out.flush()?;
     Ok(())
 }
+
+fn batch_one(
+    out: &mut impl Write,
+    format: &str,
+    name: &str,
+    hash: &str,
+    rest: &str,
+    with_contents: bool,
+) -> Result<()> {
+    let mut obj = match Object::try_from(hash) {
+        Ok(obj) => obj,
+        Err(Error::NotFound(_)) => {
+            writeln!(out, "{} missing", name)?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    writeln!(out, "{}", expand_format(format, hash, &obj, rest)?)?;
+    if with_contents {
+        let mut contents = Vec::with_capacity(obj.len as usize);
+        obj.reader.read_to_end(&mut contents)?;
+        out.write_all(&contents)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Fills in the `%(atom)` placeholders of a batch format.
+fn expand_format(format: &str, hash: &str, obj: &Object, rest: &str) -> Result<String> {
+    let mut line = String::new();
+    let mut remaining = format;
+    while let Some(start) = remaining.find("%(") {
+        line.push_str(&remaining[..start]);
+        let end = remaining[start..]
+            .find(')')
+            .with_context(|| format!("unterminated placeholder in format {}", format))?;
+        let atom = &remaining[start + 2..start + end];
+        match atom {
+            "objectname" => line.push_str(hash),
+            "objecttype" => line.push_str(&obj.kind.to_string()),
+            "objectsize" => line.push_str(&obj.len.to_string()),
+            "objectsize:disk" => {
+                let size = fs::metadata(object::loose_path(hash))?.len();
+                line.push_str(&size.to_string());
+            }
+            // loose objects are never deltas
+            "deltabase" => line.push_str(crate::refs::ZERO_HASH),
+            "rest" => line.push_str(rest),
+            _ => anyhow::bail!("unknown format element: {}", atom),
+        }
+        remaining = &remaining[start + end + 1..];
+    }
+    line.push_str(remaining);
+    Ok(line)
+}
+
+fn is_ambiguous(e: &anyhow::Error) -> bool {
+    e.chain()
+        .any(|e| matches!(e.downcast_ref::<Error>(), Some(Error::Ambiguous(_))))
+}
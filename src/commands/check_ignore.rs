@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use crate::{ignore::Ignore, objects::index::IndexFile};
+
+pub(crate) struct Options {
+    /// show the pattern that matched each path
+    pub verbose: bool,
+    /// with --verbose, also show paths that no pattern matched
+    pub non_matching: bool,
+    /// check tracked files as well
+    pub no_index: bool,
+    pub stdin: bool,
+    pub zero: bool,
+}
+
+pub fn invoke(options: &Options, paths: &[String]) -> Result<()> {
+    anyhow::ensure!(
+        !options.non_matching || options.verbose,
+        "--non-matching is only valid with --verbose"
+    );
+    anyhow::ensure!(
+        !options.zero || options.stdin,
+        "-z only makes sense with --stdin"
+    );
+    anyhow::ensure!(
+        !options.stdin || paths.is_empty(),
+        "cannot specify pathnames with --stdin"
+    );
+    anyhow::ensure!(options.stdin || !paths.is_empty(), "no path specified");
+
+    let tracked: HashSet<String> = if options.no_index {
+        HashSet::new()
+    } else {
+        IndexFile::read_or_empty()?
+            .entries
+            .iter()
+            .filter_map(|e| e.entry_path.to_str().map(|p| p.to_string()))
+            .collect()
+    };
+    let mut ignore = Ignore::standard()?;
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut any_ignored = false;
+
+    let mut check = |path: &str, out: &mut io::BufWriter<io::StdoutLock>| -> Result<()> {
+        let normalized = path.trim_start_matches("./");
+        let trailing_slash = normalized.ends_with('/');
+        let normalized = normalized.trim_end_matches('/');
+        // as git does, a path is taken for a directory if it is one on disk, without
+        // following symlinks
+        let is_dir = trailing_slash
+            || fs::symlink_metadata(normalized).is_ok_and(|m| m.file_type().is_dir());
+        // tracked files aren't subject to the ignore rules at all
+        let pattern = if tracked.contains(normalized) {
+            None
+        } else {
+            ignore
+                .check_path(normalized, is_dir)?
+                .filter(|p| options.verbose || !p.is_negated())
+        };
+        if pattern.is_some() {
+            any_ignored = true;
+        }
+        match (&pattern, options.verbose) {
+            (Some(p), true) => write_verbose(
+                out,
+                options.zero,
+                &p.source,
+                &p.line_number.to_string(),
+                &p.text,
+                path,
+            )?,
+            (None, true) if options.non_matching => {
+                write_verbose(out, options.zero, "", "", "", path)?
+            }
+            (Some(_), false) => write!(out, "{}{}", path, if options.zero { '\0' } else { '\n' })?,
+            _ => {}
+        }
+        // answers go out as they come so this can be driven line by line from another process
+        if options.stdin {
+            out.flush()?;
+        }
+        Ok(())
+    };
+
+    if options.stdin {
+        let stdin = io::stdin().lock();
+        let separator = if options.zero { b'\0' } else { b'\n' };
+        for path in stdin.split(separator) {
+            let path = String::from_utf8(path.context("reading stdin")?)
+                .context("paths must be valid utf-8")?;
+            check(&path, &mut out)?;
+        }
+    } else {
+        for path in paths {
+            check(path, &mut out)?;
+        }
+    }
+    out.flush()?;
+    if !any_ignored {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn write_verbose(
+    out: &mut impl Write,
+    zero: bool,
+    source: &str,
+    line: &str,
+    pattern: &str,
+    path: &str,
+) -> Result<()> {
+    if zero {
+        write!(out, "{}\0{}\0{}\0{}\0", source, line, pattern, path)?;
+    } else {
+        writeln!(out, "{}:{}:{}\t{}", source, line, pattern, path)?;
+    }
+    Ok(())
+}
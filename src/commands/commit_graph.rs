@@ -0,0 +1,91 @@
+use anyhow::Result;
+use std::io::{self, BufRead};
+
+use crate::{
+    objects::{
+        commit_graph::{self, CommitGraph, Split},
+        object::Kind,
+    },
+    refs, revision,
+};
+
+pub(crate) struct WriteOptions {
+    /// `--reachable`, every commit the refs reach
+    pub reachable: bool,
+    /// `--stdin-commits`, the commits listed on stdin and what they reach
+    pub stdin_commits: bool,
+    /// `--append`, keep the commits already in the graph
+    pub append: bool,
+    /// `--split[=<strategy>]`, the empty string when given without a strategy
+    pub split: Option<String>,
+}
+
+/// `commit-graph write`. Like git without `--reachable` or `--stdin-commits` it takes the
+/// commits in packfiles, and as every object here is loose there are none to write.
+pub fn write(options: &WriteOptions) -> Result<()> {
+    if options.reachable && options.stdin_commits {
+        anyhow::bail!("use at most one of --reachable, --stdin-commits, or --stdin-packs");
+    }
+    let split = match options.split.as_deref() {
+        None => None,
+        Some("") => Some(Split::Merge),
+        Some("no-merge") => Some(Split::NoMerge),
+        Some("replace") => Some(Split::Replace),
+        Some(other) => anyhow::bail!("unrecognized --split argument, {}", other),
+    };
+
+    let mut commits = Vec::new();
+    if options.reachable {
+        for (_, hash) in refs::list()? {
+            // refs to trees or blobs have no commits behind them
+            if let Ok(commit) = revision::peel(&hash, Kind::Commit) {
+                commits.push(commit);
+            }
+        }
+    } else if options.stdin_commits {
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            let id = line.trim();
+            if id.is_empty() {
+                continue;
+            }
+            // git reports these as errors rather than dying, so they exit 1
+            if !refs::is_hash(id) {
+                eprintln!("error: unexpected non-hex object ID: {}", id);
+                std::process::exit(1);
+            }
+            match revision::peel(&id.to_ascii_lowercase(), Kind::Commit) {
+                Ok(commit) => commits.push(commit),
+                Err(_) => {
+                    eprintln!("error: invalid commit object id: {}", id);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if options.append {
+        if let Some(graph) = CommitGraph::load()? {
+            for position in 0..graph.len() {
+                commits.push(graph.hash_at(position)?);
+            }
+        }
+    }
+
+    commit_graph::write(&commits, split)?;
+    Ok(())
+}
+
+/// `commit-graph verify`, which reports each problem and fails if there were any.
+pub fn verify(shallow: bool) -> Result<()> {
+    let Some(graph) = CommitGraph::load()? else {
+        return Ok(());
+    };
+    let problems = graph.verify(shallow)?;
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
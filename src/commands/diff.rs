@@ -0,0 +1,322 @@
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    config::Config,
+    diff::{
+        self,
+        color::{ColorMoved, When},
+        pairs::{self, FilePair, Format, Side},
+        stat::{self, Dirstat, DirstatMode, StatWidths},
+        words::WordDiff,
+    },
+    objects::{index::IndexFile, object::Kind},
+    pathspec::Pathspec,
+    regex::Regex,
+    rename,
+    revision::{self, Revision},
+};
+
+/// What was given on the command line, over the `diff.*` config. The plumbing commands take
+/// the same options.
+pub(crate) struct Options {
+    /// `-U<n>`
+    pub context: Option<usize>,
+    /// `--diff-algorithm`
+    pub algorithm: Option<diff::Algorithm>,
+    /// `--indent-heuristic` or `--no-indent-heuristic`
+    pub indent_heuristic: Option<bool>,
+    /// `--patch`, `--raw`, `--name-only` or `--name-status`, otherwise a patch for `diff` and
+    /// raw output for the plumbing
+    pub format: Option<Format>,
+    /// `-M` or `--no-renames`, otherwise `diff.renames` decides for `diff` and the plumbing
+    /// doesn't look
+    pub renames: Option<bool>,
+    /// `-C`, also pair new files with ones that are still there
+    pub copies: bool,
+    /// the similarity `-M<n>` or `-C<n>` asked for, out of `rename::MAX_SCORE`
+    pub rename_score: Option<u32>,
+    /// `--stat` and the widths it was given
+    pub stat: Option<StatWidths>,
+    pub numstat: bool,
+    pub shortstat: bool,
+    /// `--dirstat` parameters, applied over `diff.dirstat`
+    pub dirstat: Option<String>,
+    /// `--color` or `--no-color`, otherwise `color.diff` or `color.ui` decide for `diff` and
+    /// the plumbing doesn't colour
+    pub color: Option<When>,
+    /// `--word-diff`, `--color-words`, or a word regex on its own
+    pub word_diff: Option<WordDiff>,
+    /// `--word-diff-regex` or the regex given to `--color-words`, over `diff.wordRegex`
+    pub word_regex: Option<String>,
+    /// `--color-moved` or `--no-color-moved`, over `diff.colorMoved` for `diff`
+    pub color_moved: Option<ColorMoved>,
+}
+
+impl Options {
+    /// The options for the diffs themselves. Only `diff` looks at `diff.colorMoved`.
+    fn diff_options(&self, porcelain: bool) -> Result<diff::Options> {
+        let config = Config::load()?;
+        let mut options = diff::Options::from_config(&config)?;
+        if let (true, Some(moved)) = (porcelain, config.get("diff.colormoved")) {
+            options.color_moved = ColorMoved::parse(moved)?;
+        }
+        if let Some(context) = self.context {
+            options.context = context;
+        }
+        if let Some(algorithm) = self.algorithm {
+            options.algorithm = algorithm;
+        }
+        if let Some(indent_heuristic) = self.indent_heuristic {
+            options.indent_heuristic = indent_heuristic;
+        }
+        if self.word_diff.is_some() {
+            options.word_diff = self.word_diff;
+        }
+        if let Some(regex) = &self.word_regex {
+            options.word_regex = Some(Regex::new(regex)?);
+        }
+        if let Some(moved) = self.color_moved {
+            options.color_moved = moved;
+        }
+        Ok(options)
+    }
+
+    /// Turns colours on or off for what is written. Colouring words needs colours whatever
+    /// else says.
+    fn set_color(&self, porcelain: bool) -> Result<()> {
+        let when = match self.color {
+            _ if self.word_diff == Some(WordDiff::Color) => When::Always,
+            Some(when) => when,
+            None if porcelain => {
+                let config = Config::load()?;
+                config
+                    .get("color.diff")
+                    .or_else(|| config.get("color.ui"))
+                    .map_or(When::Auto, When::from_config)
+            }
+            None => When::Never,
+        };
+        colored::control::set_override(when.enabled());
+        Ok(())
+    }
+
+    fn rename_options(&self, porcelain: bool) -> Result<Option<rename::Options>> {
+        let config = Config::load()?;
+        let mut renames = match self.renames {
+            Some(false) => return Ok(None),
+            Some(true) => Some(rename::Options::from_config(&config, "diff").unwrap_or_default()),
+            None if porcelain => rename::Options::from_config(&config, "diff"),
+            None => None,
+        };
+        if let Some(renames) = &mut renames {
+            renames.copies |= self.copies;
+            if let Some(score) = self.rename_score {
+                renames.min_score = score;
+            }
+        }
+        Ok(renames)
+    }
+
+    /// The format asked for. Without one, a summary is all that is shown if one was asked
+    /// for, otherwise the command's default.
+    pub fn format(&self, porcelain: bool) -> Option<Format> {
+        if self.format.is_some() || self.summaries() {
+            return self.format;
+        }
+        Some(if porcelain {
+            Format::Patch
+        } else {
+            Format::Raw
+        })
+    }
+
+    /// Whether the output is about files rather than whole subtrees, as patches and
+    /// summaries are.
+    pub fn wants_files(&self) -> bool {
+        self.format == Some(Format::Patch) || self.summaries()
+    }
+
+    fn summaries(&self) -> bool {
+        self.stat.is_some() || self.numstat || self.shortstat || self.dirstat.is_some()
+    }
+}
+
+/// `diff [--cached] [<commit> [<commit>]] [--] [<path>...]`, or two blobs.
+pub fn invoke(options: &Options, cached: bool, args: &[String], paths: &[String]) -> Result<()> {
+    let (revs, pathspec) = split_args(args, paths)?;
+    let index = IndexFile::read_or_empty()?;
+    let pairs = match revs {
+        [] if cached => {
+            // on an unborn branch everything in the index is new
+            let head = match revision::resolve("HEAD") {
+                Ok(head) => tree_files(&head, true, &pathspec)?,
+                Err(_) => BTreeMap::new(),
+            };
+            pairs::tree_to_index(head, &index, &pathspec)?
+        }
+        [] => pairs::index_to_worktree(&index, &pathspec)?,
+        [rev] => match revision::parse(rev)? {
+            Revision::Single(hash) => {
+                let tree = tree_files(&hash, true, &pathspec)?;
+                if cached {
+                    pairs::tree_to_index(tree, &index, &pathspec)?
+                } else {
+                    pairs::tree_to_worktree(tree, &index, &pathspec)?
+                }
+            }
+            Revision::Range { from, to } => pairs::compare(
+                tree_files(&from, true, &pathspec)?,
+                tree_files(&to, true, &pathspec)?,
+            ),
+            // what the right side did since the sides went their own ways
+            Revision::Symmetric { left, right } => {
+                let bases = revision::merge_bases(&left, &right)?;
+                let Some(base) = bases.first() else {
+                    anyhow::bail!("{}: no merge base", rev);
+                };
+                pairs::compare(
+                    tree_files(base, true, &pathspec)?,
+                    tree_files(&right, true, &pathspec)?,
+                )
+            }
+        },
+        [old, new] => {
+            let old_hash = revision::peel_tags(&revision::resolve(old)?)?;
+            let new_hash = revision::peel_tags(&revision::resolve(new)?)?;
+            if revision::kind_of(&old_hash)? == Kind::Blob
+                && revision::kind_of(&new_hash)? == Kind::Blob
+            {
+                pairs::blobs(blob_side(old, old_hash)?, blob_side(new, new_hash)?)
+            } else {
+                pairs::compare(
+                    tree_files(&old_hash, true, &pathspec)?,
+                    tree_files(&new_hash, true, &pathspec)?,
+                )
+            }
+        }
+        _ => anyhow::bail!("usage: diff [<options>] [<commit> [<commit>]] [--] [<path>...]"),
+    };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    show(&mut out, pairs, options, true)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Splits the arguments into revisions and a pathspec. With paths after `--` everything
+/// before it is a revision, otherwise the revisions are the leading arguments that name
+/// objects and the rest have to be files that exist, as git asks for `--` to tell them
+/// apart.
+pub(crate) fn split_args<'a>(
+    args: &'a [String],
+    paths: &[String],
+) -> Result<(&'a [String], Pathspec)> {
+    split_args_by(args, paths, |arg| revision::parse(arg).is_ok())
+}
+
+/// Like `split_args`, with the command saying what it takes as a revision.
+pub(crate) fn split_args_by<'a>(
+    args: &'a [String],
+    paths: &[String],
+    is_revision: impl Fn(&str) -> bool,
+) -> Result<(&'a [String], Pathspec)> {
+    if !paths.is_empty() {
+        return Ok((args, Pathspec::new(paths)));
+    }
+    let revs = args.iter().take_while(|arg| is_revision(arg)).count();
+    let (revs, paths) = args.split_at(revs);
+    if let Some(missing) = paths.iter().find(|p| !Path::new(p).exists()) {
+        anyhow::bail!(
+            "ambiguous argument '{}': unknown revision or path not in the working tree.\n\
+             Use '--' to separate paths from revisions, like this:\n\
+             'git <command> [<revision>...] -- [<file>...]'",
+            missing
+        );
+    }
+    Ok((revs, Pathspec::new(paths)))
+}
+
+/// The files of the tree a commit or tree-ish names.
+pub(crate) fn tree_files(
+    name: &str,
+    recursive: bool,
+    pathspec: &Pathspec,
+) -> Result<BTreeMap<String, Side>> {
+    let tree = revision::peel(&revision::resolve(name)?, Kind::Tree)?;
+    pairs::tree_files(&tree, recursive, pathspec)
+}
+
+/// Looks for renames as asked, then lists the pairs and summarizes them as asked, the patch
+/// last and set off by a blank line. The porcelain abbreviates hashes in raw output.
+pub(crate) fn show(
+    out: &mut impl Write,
+    mut pairs: Vec<FilePair>,
+    options: &Options,
+    porcelain: bool,
+) -> Result<()> {
+    if pairs.is_empty() {
+        return Ok(());
+    }
+    options.set_color(porcelain)?;
+    let format = options.format(porcelain);
+    if let Some(renames) = options.rename_options(porcelain)? {
+        pairs::find_renames(&mut pairs, &renames)?;
+    }
+    let config = Config::load()?;
+    let diff_options = options.diff_options(porcelain)?;
+    let mut separate = false;
+    if let Some(listing) = format.filter(|f| *f != Format::Patch) {
+        pairs::write(out, &pairs, listing, porcelain, &diff_options)?;
+        separate = true;
+    }
+
+    let dirstat = match &options.dirstat {
+        Some(params) => {
+            let defaults = Dirstat::default().parse(config.get("diff.dirstat").unwrap_or(""))?;
+            Some(defaults.parse(params)?)
+        }
+        None => None,
+    };
+    let by_lines = dirstat.is_some_and(|d| d.mode == DirstatMode::Lines);
+    let mut stats = Vec::new();
+    if options.stat.is_some() || options.numstat || options.shortstat || by_lines {
+        stats = stat::collect(&pairs, &diff_options)?;
+        if options.numstat {
+            stat::write_numstat(out, &stats)?;
+        }
+        if let Some(mut widths) = options.stat {
+            if widths.graph_width.is_none() {
+                widths.graph_width = config
+                    .get("diff.statgraphwidth")
+                    .and_then(|w| w.parse().ok());
+            }
+            stat::write_stat(out, &stats, &widths)?;
+        }
+        if options.shortstat {
+            stat::write_shortstat(out, &stats)?;
+        }
+        separate = true;
+    }
+    if let Some(dirstat) = dirstat {
+        stat::write_dirstat(out, &pairs, &stats, &dirstat)?;
+    }
+
+    if format == Some(Format::Patch) {
+        if separate {
+            writeln!(out)?;
+        }
+        pairs::write(out, &pairs, Format::Patch, porcelain, &diff_options)?;
+    }
+    Ok(())
+}
+
+/// A blob given on the command line, under the path and mode its tree or the index has for
+/// it, or as git does for a bare id, the name given and a regular file's mode.
+fn blob_side(name: &str, hash: String) -> Result<Side> {
+    let (path, mode) = revision::path_of(name)?.unwrap_or_else(|| (name.to_string(), 0o100644));
+    Ok(Side::object(path, mode, hash))
+}
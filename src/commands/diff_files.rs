@@ -0,0 +1,14 @@
+use anyhow::Result;
+use std::io::{self, Write};
+
+use crate::{commands::diff, diff::pairs, objects::index::IndexFile, pathspec::Pathspec};
+
+/// `diff-files [<path>...]`: the worktree against the index.
+pub fn invoke(diff_options: &diff::Options, paths: &[String]) -> Result<()> {
+    let index = IndexFile::read_or_empty()?;
+    let pairs = pairs::index_to_worktree(&index, &Pathspec::new(paths))?;
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    diff::show(&mut out, pairs, diff_options, false)?;
+    out.flush()?;
+    Ok(())
+}
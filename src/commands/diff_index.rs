@@ -0,0 +1,33 @@
+use anyhow::Result;
+use std::io::{self, Write};
+
+use crate::{
+    commands::diff::{self, split_args, tree_files},
+    diff::pairs,
+    objects::index::IndexFile,
+};
+
+/// `diff-index [--cached] <tree-ish> [<path>...]`: the worktree, or with `cached` the index,
+/// against a tree.
+pub fn invoke(
+    diff_options: &diff::Options,
+    cached: bool,
+    args: &[String],
+    paths: &[String],
+) -> Result<()> {
+    let (revs, pathspec) = split_args(args, paths)?;
+    let [tree_ish] = revs else {
+        anyhow::bail!("usage: diff-index [<options>] <tree-ish> [<path>...]");
+    };
+    let tree = tree_files(tree_ish, true, &pathspec)?;
+    let index = IndexFile::read_or_empty()?;
+    let pairs = if cached {
+        pairs::tree_to_index(tree, &index, &pathspec)?
+    } else {
+        pairs::tree_to_worktree(tree, &index, &pathspec)?
+    };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    diff::show(&mut out, pairs, diff_options, false)?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use crate::{
+    commands::diff::{self, split_args, tree_files},
+    diff::pairs,
+    objects::{commit::Commit, object::Kind},
+    revision,
+};
+
+pub(crate) struct Options {
+    /// `-r`, go into subtrees instead of showing them as entries; a patch always does
+    pub recursive: bool,
+    /// `--root`, show a commit with no parents against the empty tree
+    pub root: bool,
+}
+
+/// `diff-tree <tree-ish> <tree-ish> [<path>...]`, or one commit against its parent with the
+/// commit's id first.
+pub fn invoke(
+    diff_options: &diff::Options,
+    options: &Options,
+    args: &[String],
+    paths: &[String],
+) -> Result<()> {
+    let (revs, pathspec) = split_args(args, paths)?;
+    let recursive = options.recursive || diff_options.wants_files();
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match revs {
+        [commit] => {
+            let hash = revision::peel(&revision::resolve(commit)?, Kind::Commit)?;
+            let commit = Commit::read_from_hash(&hash)?;
+            let old = match commit.parents.as_slice() {
+                [parent] => tree_files(parent, recursive, &pathspec)?,
+                [] if options.root => BTreeMap::new(),
+                // a merge needs a combined diff, which isn't shown here
+                _ => return Ok(()),
+            };
+            let new = pairs::tree_files(&commit.tree, recursive, &pathspec)?;
+            let pairs = pairs::compare(old, new);
+            if !pairs.is_empty() {
+                writeln!(out, "{}", hash)?;
+                diff::show(&mut out, pairs, diff_options, false)?;
+            }
+        }
+        [old, new] => {
+            let pairs = pairs::compare(
+                tree_files(old, recursive, &pathspec)?,
+                tree_files(new, recursive, &pathspec)?,
+            );
+            diff::show(&mut out, pairs, diff_options, false)?;
+        }
+        _ => anyhow::bail!("usage: diff-tree [<options>] <tree-ish> [<tree-ish>] [<path>...]"),
+    }
+    out.flush()?;
+    Ok(())
+}
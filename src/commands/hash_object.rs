@@ -1,29 +1,141 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    io::{self, BufRead, Read},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result};
 
-use crate::objects::object::{Kind, Object};
+use crate::{
+    convert,
+    objects::{
+        commit::Commit,
+        object::{self, Kind},
+        tag::Tag,
+        tree::Tree,
+    },
+};
 
-pub fn invoke(file: &Option<PathBuf>, write: &bool) -> Result<()> {
-    if let Some(file) = file {
-        let file = fs::File::open(file).context("opening the file to read the contents")?;
-        let len = file.metadata().context("retrieving metadata")?.len();
-        let mut obj = Object {
-            kind: Kind::Blob,
-            reader: Box::new(file),
-            len,
-        };
-        if *write {
-            let result = obj.write().context("writing file to objects")?;
+pub(crate) struct Options {
+    pub write: bool,
+    pub stdin: bool,
+    pub stdin_paths: bool,
+    pub kind: String,
+    /// skip checking that the content parses as the given type
+    pub literally: bool,
+    /// the path to pick attributes and filters by, instead of the file's own
+    pub path: Option<PathBuf>,
+    pub no_filters: bool,
+}
+
+pub fn invoke(options: &Options, files: &[PathBuf]) -> Result<()> {
+    // --literally takes any type at all, so only the others have to be ones git knows
+    if !options.literally {
+        Kind::try_from(options.kind.as_str())?;
+    }
+    let kind = options.kind.as_str();
+    anyhow::ensure!(
+        !(options.stdin && options.stdin_paths),
+        "can't use --stdin-paths with --stdin"
+    );
+    anyhow::ensure!(
+        !options.stdin_paths || files.is_empty(),
+        "can't give files with --stdin-paths"
+    );
+    anyhow::ensure!(
+        !(options.path.is_some() && options.no_filters),
+        "can't use --path with --no-filters"
+    );
+    anyhow::ensure!(
+        options.stdin || options.stdin_paths || !files.is_empty(),
+        "need to input file"
+    );
 
-            println!("{}", result);
-            Ok(())
+    if options.stdin {
+        let mut contents = Vec::new();
+        io::stdin()
+            .read_to_end(&mut contents)
+            .context("reading stdin")?;
+        // stdin has no path of its own, so only --path brings in filters
+        let path = if options.no_filters {
+            None
         } else {
-            let result = obj.hash().context("computing hash")?;
-            println!("{}", result);
-            Ok(())
+            options.path.as_deref()
+        };
+        hash_one(options, kind, contents, path)?;
+    }
+
+    if options.stdin_paths {
+        for line in io::stdin().lock().lines() {
+            let file = PathBuf::from(line.context("reading stdin")?);
+            hash_file(options, kind, &file)?;
         }
+    }
+
+    for file in files {
+        hash_file(options, kind, file)?;
+    }
+    Ok(())
+}
+
+fn hash_file(options: &Options, kind: &str, file: &Path) -> Result<()> {
+    let contents = fs::read(file)
+        .with_context(|| format!("opening {} to read the contents", file.display()))?;
+    let path = if options.no_filters {
+        None
     } else {
-        anyhow::bail!("need to input file");
+        Some(options.path.as_deref().unwrap_or(file))
+    };
+    hash_one(options, kind, contents, path)
+}
+
+fn hash_one(options: &Options, kind: &str, contents: Vec<u8>, path: Option<&Path>) -> Result<()> {
+    // filters only make sense for file contents
+    let contents = match path {
+        Some(path) if kind == "blob" => {
+            let path = path.to_str().context("path must be valid utf-8")?;
+            convert::convert_to_git(path.trim_start_matches("./"), contents)?
+        }
+        _ => contents,
+    };
+    if !options.literally {
+        validate(Kind::try_from(kind)?, &contents)?;
+    }
+
+    let result = if options.write {
+        object::write_typed(kind, &contents).context("writing file to objects")?
+    } else {
+        object::hash_typed(kind, &contents).context("computing hash")?
+    };
+    println!("{}", result);
+    Ok(())
+}
+
+/// Refuses content that wouldn't parse back as `kind`, so a typo can't put a broken tree or
+/// commit into the repository.
+fn validate(kind: Kind, contents: &[u8]) -> Result<()> {
+    let mut reader = contents;
+    match kind {
+        Kind::Blob => {}
+        Kind::Tree => {
+            Tree::read(&mut reader).context("content is not a valid tree")?;
+        }
+        Kind::Commit => {
+            let commit = Commit::read(&mut reader).context("content is not a valid commit")?;
+            anyhow::ensure!(
+                crate::refs::is_hash(&commit.tree),
+                "content is not a valid commit: bad tree {}",
+                commit.tree
+            );
+        }
+        Kind::Tag => {
+            let tag = Tag::read(&mut reader).context("content is not a valid tag")?;
+            anyhow::ensure!(
+                crate::refs::is_hash(&tag.object),
+                "content is not a valid tag: bad object {}",
+                tag.object
+            );
+        }
     }
+    Ok(())
 }
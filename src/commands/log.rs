@@ -0,0 +1,220 @@
+use anyhow::Result;
+use std::io::{self, Write};
+
+use crate::{
+    commands::diff::split_args_by,
+    date,
+    objects::object::{self, Kind},
+    pretty::{self, Decorations, Format, Pretty},
+    refs,
+    regex::Regex,
+    revision::{
+        self,
+        graph::Graph,
+        walk::{self, Walk},
+        Revision,
+    },
+};
+
+pub(crate) struct Options {
+    /// `-n`, show at most this many commits
+    pub max_count: Option<usize>,
+    /// `--oneline`, the abbreviated hash and the subject
+    pub oneline: bool,
+    /// `--abbrev-commit`, abbreviated hashes on the commit lines
+    pub abbrev_commit: bool,
+    /// `--format` or `--pretty`
+    pub format: Option<String>,
+    /// `--graph`, draw the history beside the commits
+    pub graph: bool,
+    /// `--author`, basic regexes matched against `Name <email>`
+    pub authors: Vec<String>,
+    /// `--grep`, basic regexes matched against the message
+    pub grep: Vec<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub topo_order: bool,
+    pub reverse: bool,
+    pub first_parent: bool,
+    /// `--all`, start from every ref and HEAD as well as the revisions given
+    pub all: bool,
+    /// `--follow`, the history of the one path given, back through its renames
+    pub follow: bool,
+}
+
+/// `log [<revision-range>...] [[--] <path>...]`, HEAD when no revisions are given.
+pub fn invoke(options: &Options, args: &[String], paths: &[String]) -> Result<()> {
+    if options.graph && options.reverse {
+        anyhow::bail!("options '--reverse' and '--graph' cannot be used together");
+    }
+    let pretty = match (&options.format, options.oneline) {
+        (Some(format), _) => Pretty::parse(format)?,
+        (None, true) => Pretty::parse("oneline")?,
+        (None, false) => Pretty::parse("medium")?,
+    };
+    let abbrev_commit = options.abbrev_commit || options.oneline;
+
+    let (revs, pathspec) = split_args_by(args, paths, |arg| {
+        revision::parse(arg.strip_prefix('^').unwrap_or(arg)).is_ok()
+    })?;
+    if options.follow && pathspec.items.len() != 1 {
+        anyhow::bail!("--follow requires exactly one pathspec");
+    }
+    let mut tips = tips(revs, options.all)?;
+    if options.all {
+        for (_, hash) in refs::list()? {
+            // refs to trees or blobs have no history to show
+            if let Ok(commit) = revision::peel(&hash, Kind::Commit) {
+                tips.push((commit, false));
+            }
+        }
+        if let Some(head) = refs::resolve("HEAD")? {
+            tips.push((revision::peel(&head, Kind::Commit)?, false));
+        }
+    }
+    let walk_options = walk::Options {
+        topo_order: options.topo_order || options.graph,
+        reverse: options.reverse,
+        first_parent: options.first_parent,
+        max_count: options.max_count,
+        since: options.since.as_deref().map(date::parse).transpose()?,
+        until: options.until.as_deref().map(date::parse).transpose()?,
+        authors: compile(&options.authors)?,
+        grep: compile(&options.grep)?,
+        pathspec,
+        follow: options.follow,
+        rewrite_parents: options.graph,
+    };
+    let mut walk = Walk::new(walk_options, &tips)?;
+    let decorations = if pretty.wants_decorations() {
+        Decorations::load()?
+    } else {
+        Decorations::default()
+    };
+
+    let mut graph = options.graph.then(Graph::new);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut shown_one = false;
+    let mut missing_newline = false;
+    while let Some(hash) = walk.next()? {
+        if let Some(graph) = &mut graph {
+            // the graph draws lines only to parents that will be shown
+            let mut parents = walk.parents(&hash).to_vec();
+            if options.first_parent {
+                parents.truncate(1);
+            }
+            let mut shown = Vec::new();
+            for parent in parents {
+                if walk.is_shown(&parent)? {
+                    shown.push(parent);
+                }
+            }
+            graph.update(&hash, shown);
+        }
+
+        // commits shown with a separator rather than ended by a newline get one before
+        // all but the first, beside the graph if the last didn't end its line itself
+        if shown_one && !pretty.terminated {
+            if let Some(graph) = &mut graph {
+                if !missing_newline {
+                    graph.show_padding(&mut out)?;
+                }
+            }
+            writeln!(out)?;
+        }
+        if let Some(graph) = &mut graph {
+            graph.show_commit(&mut out)?;
+        }
+
+        if !matches!(pretty.format, Format::User(_)) {
+            if pretty.format != Format::Oneline {
+                write!(out, "commit ")?;
+            }
+            let shown = if abbrev_commit {
+                object::abbreviate(&hash, 7)?
+            } else {
+                hash.clone()
+            };
+            write!(out, "{}", shown)?;
+            if pretty.format == Format::Oneline {
+                write!(out, " ")?;
+            } else {
+                writeln!(out)?;
+                if let Some(graph) = &mut graph {
+                    graph.show_oneline(&mut out)?;
+                }
+            }
+        }
+
+        let text = pretty::message(
+            &pretty,
+            &hash,
+            walk.commit(&hash),
+            walk.parents(&hash),
+            &decorations,
+        )?;
+        missing_newline = text.last() != Some(&b'\n');
+        match &mut graph {
+            Some(graph) => graph.show_commit_msg(&mut out, &text)?,
+            None => out.write_all(&text)?,
+        }
+        if pretty.terminated && !pretty.is_empty() {
+            if let Some(graph) = &mut graph {
+                if !missing_newline {
+                    graph.show_padding(&mut out)?;
+                }
+            }
+            writeln!(out)?;
+        }
+        shown_one = true;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// The commits the revisions start from, each marked whether it is excluded: `^A` and the
+/// left of `A..B` are, and `A...B` excludes the merge bases of its sides. Without any, HEAD
+/// unless `--all` gives the tips.
+fn tips(revs: &[String], all: bool) -> Result<Vec<(String, bool)>> {
+    let commit = |hash: &str| revision::peel(hash, Kind::Commit);
+    let mut tips = Vec::new();
+    for rev in revs {
+        if let Some(excluded) = rev.strip_prefix('^') {
+            tips.push((commit(&revision::resolve(excluded)?)?, true));
+            continue;
+        }
+        match revision::parse(rev)? {
+            Revision::Single(hash) => tips.push((commit(&hash)?, false)),
+            Revision::Range { from, to } => {
+                tips.push((commit(&from)?, true));
+                tips.push((commit(&to)?, false));
+            }
+            Revision::Symmetric { left, right } => {
+                let bases = revision::merge_bases(&left, &right)?;
+                tips.push((commit(&left)?, false));
+                tips.push((commit(&right)?, false));
+                for base in bases {
+                    tips.push((base, true));
+                }
+            }
+        }
+    }
+    if revs.is_empty() && !all {
+        let Some(head) = refs::resolve("HEAD")? else {
+            let (branch, _) = refs::resolve_name("HEAD")?;
+            anyhow::bail!(
+                "your current branch '{}' does not have any commits yet",
+                refs::shorten(&branch)
+            );
+        };
+        tips.push((commit(&head)?, false));
+    }
+    Ok(tips)
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| Regex::basic(pattern))
+        .collect()
+}
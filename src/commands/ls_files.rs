@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use crate::{
+    ignore::Ignore,
+    objects::index::{IndexEntry, IndexFile},
+    quote::quote_path,
+    worktree,
+};
+
+pub(crate) struct Options {
+    pub cached: bool,
+    pub deleted: bool,
+    pub modified: bool,
+    pub others: bool,
+    pub ignored: bool,
+    pub exclude_standard: bool,
+    pub exclude: Vec<String>,
+    pub stage: bool,
+    pub unmerged: bool,
+    pub zero: bool,
+    pub debug: bool,
+}
+
+pub fn invoke(options: &Options) -> Result<()> {
+    let index = IndexFile::read_or_empty()?;
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let terminator = if options.zero { '\0' } else { '\n' };
+    let name = |path: &str| match options.zero {
+        true => path.to_string(),
+        false => quote_path(path),
+    };
+
+    let mut ignore = if options.exclude_standard {
+        Ignore::standard()?
+    } else {
+        Ignore::default()
+    };
+    for pattern in &options.exclude {
+        ignore.add_pattern(pattern);
+    }
+    anyhow::ensure!(
+        !options.ignored || options.exclude_standard || !options.exclude.is_empty(),
+        "ls-files -i must be used with either -o or -c, and with an exclude option"
+    );
+
+    // --unmerged shows stages like --stage, and with no mode given --cached is the default
+    let stage = options.stage || options.unmerged;
+    let cached = options.cached
+        || !(options.deleted || options.modified || options.others || options.unmerged)
+        || (options.stage && !options.others);
+
+    if options.others {
+        let tracked: HashSet<&str> = index
+            .entries
+            .iter()
+            .filter_map(|e| e.entry_path.to_str())
+            .collect();
+        for file in worktree::walk(&ignore)? {
+            if !tracked.contains(file.path.as_str()) && file.ignored == options.ignored {
+                write!(out, "{}{}", name(&file.path), terminator)?;
+            }
+        }
+    }
+
+    for entry in &index.entries {
+        let path = entry
+            .entry_path
+            .to_str()
+            .context("path must be valid utf-8")?;
+        if options.ignored
+            && ignore
+                .check_path(path, false)?
+                .is_none_or(|p| p.is_negated())
+        {
+            continue;
+        }
+
+        if (cached || stage) && (!options.unmerged || entry.stage() != 0) {
+            if stage {
+                write!(
+                    out,
+                    "{:06o} {} {}\t{}{}",
+                    entry.mode(),
+                    entry.hash,
+                    entry.stage(),
+                    name(path),
+                    terminator
+                )?;
+            } else {
+                write!(out, "{}{}", name(path), terminator)?;
+            }
+            if options.debug {
+                write_debug(&mut out, entry)?;
+            }
+        }
+
+        // each of these is its own listing, so a deleted file shows up under both
+        let missing = std::fs::symlink_metadata(&entry.entry_path).is_err();
+        if options.deleted && missing {
+            write!(out, "{}{}", name(path), terminator)?;
+        }
+        if options.modified && (missing || is_modified(entry)?) {
+            write!(out, "{}{}", name(path), terminator)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn is_modified(entry: &IndexEntry) -> Result<bool> {
+    let current = IndexEntry::from_path(entry.entry_path.clone(), &entry.hash, entry.flags)
+        .context("constructing index entry from path")?;
+    Ok(current != *entry)
+}
+
+/// The stat data cached in the entry, in the same layout as git's `--debug`.
+fn write_debug(out: &mut impl Write, entry: &IndexEntry) -> Result<()> {
+    writeln!(
+        out,
+        "  ctime: {}:{}",
+        entry.ctime_seconds, entry.ctime_nanoseconds
+    )?;
+    writeln!(
+        out,
+        "  mtime: {}:{}",
+        entry.mtime_seconds, entry.mtime_nanoseconds
+    )?;
+    writeln!(out, "  dev: {}\tino: {}", entry.device, entry.inode)?;
+    writeln!(out, "  uid: {}\tgid: {}", entry.user_id, entry.group_id)?;
+    writeln!(
+        out,
+        "  size: {}\tflags: {:x}",
+        entry.file_size,
+        // the low 12 bits are just the length of the name
+        ((entry.extended_flags as u32) << 16) | (entry.flags & 0xf000) as u32
+    )?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use std::io::{self, Write};
+
+use crate::{
+    objects::{
+        object::{self, Kind, Object},
+        tree::{Tree, TreeEntry},
+    },
+    quote::quote_path,
+    revision,
+};
+
+pub(crate) struct Options {
+    pub recursive: bool,
+    pub show_trees: bool,
+    pub dirs_only: bool,
+    pub name_only: bool,
+    pub long: bool,
+    pub abbrev: Option<usize>,
+    /// NUL terminated entries and unquoted paths
+    pub zero: bool,
+}
+
+pub fn invoke(options: &Options, tree_ish: &str, paths: &[String]) -> Result<()> {
+    let tree = revision::peel(&revision::resolve(tree_ish)?, Kind::Tree)?;
+    let paths: Vec<&str> = paths.iter().map(|p| p.trim_start_matches("./")).collect();
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    walk(&mut out, options, &tree, "", &paths)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn walk(
+    out: &mut impl Write,
+    options: &Options,
+    tree: &str,
+    prefix: &str,
+    paths: &[&str],
+) -> Result<()> {
+    // only trees are left to show with `-d`, so recursing has to show them
+    let show_trees = options.show_trees || (options.dirs_only && options.recursive);
+    for entry in Tree::read_from_hash(tree)?.entries {
+        let path = format!("{}{}", prefix, entry.filename);
+
+        if entry.kind() == Kind::Tree {
+            // a tree is opened when recursing, or when a path asks for something inside it;
+            // `dir/` asks for the contents of dir
+            let descend =
+                (options.recursive && matches(&path, paths)) || wanted_inside(&path, paths);
+            if descend {
+                // git shows the trees it opens on the way to a path too
+                if show_trees {
+                    print_entry(out, options, &entry, &path)?;
+                }
+                walk(out, options, &entry.hash, &format!("{}/", path), paths)?;
+            } else if matches(&path, paths) {
+                print_entry(out, options, &entry, &path)?;
+            }
+        } else if !options.dirs_only && matches(&path, paths) {
+            print_entry(out, options, &entry, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` is one of `paths` or below one of them, no paths meaning everything.
+fn matches(path: &str, paths: &[&str]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|p| {
+            let p = p.trim_end_matches('/');
+            p.is_empty() || path == p || path.starts_with(&format!("{}/", p))
+        })
+}
+
+fn wanted_inside(path: &str, paths: &[&str]) -> bool {
+    paths.iter().any(|p| p.starts_with(&format!("{}/", path)))
+}
+
+fn print_entry(
+    out: &mut impl Write,
+    options: &Options,
+    entry: &TreeEntry,
+    path: &str,
+) -> Result<()> {
+    let name = match options.zero {
+        true => path.to_string(),
+        false => quote_path(path),
+    };
+    let end = if options.zero { '\0' } else { '\n' };
+    if options.name_only {
+        write!(out, "{}{}", name, end)?;
+        return Ok(());
+    }
+
+    let hash = match options.abbrev {
+        Some(len) => object::abbreviate(&entry.hash, len)?,
+        None => entry.hash.clone(),
+    };
+    if options.long {
+        let size = match entry.kind() {
+            Kind::Blob => Object::try_from(entry.hash.as_str())?.len.to_string(),
+            _ => "-".to_string(),
+        };
+        write!(
+            out,
+            "{:06} {} {} {:>7}\t{}{}",
+            entry.mode,
+            entry.kind(),
+            hash,
+            size,
+            name,
+            end
+        )?;
+    } else {
+        let shown = TreeEntry {
+            mode: entry.mode,
+            filename: name,
+            hash,
+        };
+        write!(out, "{}{}", shown, end)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    objects::{commit::Commit, object::Kind},
+    refs::{self, reflog},
+    revision::{self, reach::Reach},
+};
+
+pub(crate) struct Options {
+    /// `--all`, every best common ancestor rather than just one
+    pub all: bool,
+    /// `--octopus`, the bases of all the commits at once
+    pub octopus: bool,
+    /// `--independent`, the commits none of the others can reach
+    pub independent: bool,
+    /// `--is-ancestor`, answer in the exit code whether the first reaches the second
+    pub is_ancestor: bool,
+    /// `--fork-point`, where a commit forked from a branch, going by the branch's reflog
+    pub fork_point: bool,
+}
+
+const USAGE: &str = "usage: git merge-base [-a | --all] <commit> <commit>...";
+
+/// `merge-base`, which exits 1 without printing anything when there's no answer.
+pub fn invoke(options: &Options, args: &[String]) -> Result<()> {
+    let mut reach = Reach::new()?;
+    let found = if options.is_ancestor {
+        if options.all {
+            anyhow::bail!("options '--is-ancestor' and '--all' cannot be used together");
+        }
+        let [commit, reference] = args else {
+            anyhow::bail!("--is-ancestor takes exactly two commits");
+        };
+        reach.is_ancestor(&commit_of(commit)?, &[commit_of(reference)?])?
+    } else if options.independent {
+        if options.all {
+            anyhow::bail!("options '--independent' and '--all' cannot be used together");
+        }
+        let commits = commits_of(args)?;
+        show(&reach.independent(&commits)?, true)
+    } else if options.octopus {
+        let commits = commits_of(args)?;
+        let bases = reach.octopus_merge_bases(&commits)?;
+        show(&reach.independent(&bases)?, options.all)
+    } else if options.fork_point {
+        let (branch, commit) = match args {
+            [branch] => (branch, "HEAD"),
+            [branch, commit] => (branch, commit.as_str()),
+            _ => anyhow::bail!("usage: git merge-base --fork-point <ref> [<commit>]"),
+        };
+        let hash = revision::resolve(commit)
+            .ok()
+            .with_context(|| format!("Not a valid object name: '{}'", commit))?;
+        let commit = revision::peel(&hash, Kind::Commit)?;
+        let history = branch_history(branch)?;
+        match reach.fork_point(&commit, &history)? {
+            Some(base) => show(&[base], false),
+            None => false,
+        }
+    } else {
+        let [first, rest @ ..] = args else {
+            anyhow::bail!(USAGE);
+        };
+        if rest.is_empty() {
+            anyhow::bail!(USAGE);
+        }
+        let first = commit_of(first)?;
+        let rest = commits_of(rest)?;
+        show(&reach.merge_bases(&first, &rest)?, options.all)
+    };
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Prints the first of the commits or all of them, saying whether there were any.
+fn show(commits: &[String], all: bool) -> bool {
+    let shown = if all { commits.len() } else { 1 };
+    for commit in commits.iter().take(shown) {
+        println!("{}", commit);
+    }
+    !commits.is_empty()
+}
+
+fn commits_of(args: &[String]) -> Result<Vec<String>> {
+    args.iter().map(|arg| commit_of(arg)).collect()
+}
+
+fn commit_of(arg: &str) -> Result<String> {
+    let hash = revision::resolve(arg)
+        .ok()
+        .with_context(|| format!("Not a valid object name {}", arg))?;
+    revision::peel(&hash, Kind::Commit)
+        .ok()
+        .with_context(|| format!("Not a valid commit name {}", arg))
+}
+
+/// Every commit the branch's reflog says it pointed at, oldest first and each once, or just
+/// where it points now if it has no reflog.
+fn branch_history(branch: &str) -> Result<Vec<String>> {
+    let name = refs::dwim(branch)?.with_context(|| format!("No such ref: '{}'", branch))?;
+    let mut ids = Vec::new();
+    for (nth, entry) in reflog::read(&name)?.into_iter().enumerate() {
+        // the first entry also says where the branch was before the log began
+        if nth == 0 {
+            ids.push(entry.old);
+        }
+        ids.push(entry.new);
+    }
+    if ids.is_empty() {
+        ids.extend(refs::resolve(&name)?);
+    }
+
+    let mut history: Vec<String> = Vec::new();
+    for id in ids {
+        if refs::is_hash(&id)
+            && id.bytes().any(|b| b != b'0')
+            && !history.contains(&id)
+            && Commit::read_from_hash(&id).is_ok()
+        {
+            history.push(id);
+        }
+    }
+    Ok(history)
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use std::{
+    fs,
+    io::{self, Write},
+};
+
+use crate::{
+    config::Config,
+    convert,
+    merge::{self, Favor, Labels, Style, MARKER_SIZE},
+};
+
+pub(crate) struct Options {
+    /// `-p`, print the result rather than writing it over the current file
+    pub stdout: bool,
+    /// `--diff3` or `--zdiff3`, else `merge.conflictStyle`
+    pub style: Option<Style>,
+    /// `--ours`, `--theirs` or `--union`, resolving conflicts instead of marking them
+    pub favor: Option<Favor>,
+    /// `--marker-size`, the default when not positive
+    pub marker_size: isize,
+    /// `-L`, what to call the current file, the base and the other file in the markers
+    pub labels: Vec<String>,
+}
+
+/// `merge-file <current> <base> <other>`, which exits with the number of conflicts left,
+/// 127 at most. Problems with the files are reported as errors exiting 255, as they would be
+/// taken for conflicts otherwise.
+pub fn invoke(options: &Options, current: &str, base: &str, other: &str) -> Result<()> {
+    if options.labels.len() > 3 {
+        eprintln!("error: too many labels on the command line");
+        std::process::exit(129);
+    }
+    let files = [current, base, other];
+    let mut contents = Vec::new();
+    for file in files {
+        let data = match fs::read(file) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("error: Could not stat {}: {}", file, e);
+                std::process::exit(255);
+            }
+        };
+        if convert::is_binary(&data) {
+            eprintln!("error: Cannot merge binary files: {}", file);
+            std::process::exit(255);
+        }
+        contents.push(data);
+    }
+
+    let style = match options.style {
+        Some(style) => style,
+        None => Style::configured(&Config::load()?)?.unwrap_or(Style::Merge),
+    };
+    let label = |nth: usize| options.labels.get(nth).map_or(files[nth], |l| l.as_str());
+    let labels = Labels {
+        ours: label(0),
+        base: label(1),
+        theirs: label(2),
+    };
+    let merge_options = merge::Options {
+        style,
+        favor: options.favor,
+        marker_size: match options.marker_size {
+            size if size > 0 => size as usize,
+            _ => MARKER_SIZE,
+        },
+        join_without_alnum: true,
+    };
+    let merged = merge::merge(
+        &contents[1],
+        &contents[0],
+        &contents[2],
+        &labels,
+        &merge_options,
+    );
+
+    if options.stdout {
+        let mut stdout = io::stdout();
+        stdout.write_all(&merged.contents)?;
+        // exiting with the count doesn't flush
+        stdout.flush()?;
+    } else if let Err(e) = fs::write(current, &merged.contents) {
+        eprintln!("error: Could not open {} for writing: {}", current, e);
+        std::process::exit(255);
+    }
+    if merged.conflicts > 0 {
+        std::process::exit(merged.conflicts.min(127) as i32);
+    }
+    Ok(())
+}
@@ -1,5 +1,22 @@
 pub(crate) mod add;
+pub(crate) mod apply;
 pub(crate) mod cat_file;
+pub(crate) mod check_ignore;
+pub(crate) mod commit_graph;
+pub(crate) mod diff;
+pub(crate) mod diff_files;
+pub(crate) mod diff_index;
+pub(crate) mod diff_tree;
 pub(crate) mod hash_object;
+pub(crate) mod log;
+pub(crate) mod ls_files;
 pub(crate) mod ls_tree;
+pub(crate) mod merge_base;
+pub(crate) mod merge_file;
+pub(crate) mod reflog;
+pub(crate) mod rev_parse;
+pub(crate) mod show_ref;
 pub(crate) mod status;
+pub(crate) mod symbolic_ref;
+pub(crate) mod update_index;
+pub(crate) mod update_ref;
@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use std::io::{self, Write};
+
+use crate::refs::{self, reflog};
+
+pub fn invoke(action: &Option<String>, name: &Option<String>) -> Result<()> {
+    match (action.as_deref(), name) {
+        (None, _) | (Some("show"), _) => show(name.as_deref().unwrap_or("HEAD")),
+        (Some("exists"), Some(name)) => {
+            let full = refs::dwim(name)?.unwrap_or_else(|| name.clone());
+            if !reflog::exists(&full) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        (Some("exists"), None) => anyhow::bail!("usage: reflog exists <ref>"),
+        // `reflog main` is short for `reflog show main`
+        (Some(other), None) => show(other),
+        (Some(other), Some(_)) => anyhow::bail!("unknown reflog subcommand {}", other),
+    }
+}
+
+fn show(name: &str) -> Result<()> {
+    let (shown, full) = if name == "HEAD" {
+        ("HEAD", "HEAD".to_string())
+    } else {
+        let full = refs::dwim(name)?.with_context(|| format!("unknown ref {}", name))?;
+        (name, full)
+    };
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for (n, entry) in reflog::read(&full)?.iter().rev().enumerate() {
+        writeln!(
+            out,
+            "{} {}@{{{}}}: {}",
+            &entry.new[..7],
+            shown,
+            n,
+            entry.message
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    objects::object,
+    refs,
+    revision::{self, Revision},
+};
+
+pub(crate) struct Options {
+    pub verify: bool,
+    pub quiet: bool,
+    pub short: Option<usize>,
+    pub abbrev_ref: bool,
+    pub symbolic_full_name: bool,
+    pub git_dir: bool,
+    pub show_toplevel: bool,
+    pub is_inside_work_tree: bool,
+}
+
+pub fn invoke(options: &Options, revs: &[String]) -> Result<()> {
+    if options.git_dir {
+        println!(".git");
+    }
+    if options.show_toplevel {
+        println!("{}", std::env::current_dir()?.display());
+    }
+    if options.is_inside_work_tree {
+        println!("true");
+    }
+
+    if options.verify {
+        anyhow::ensure!(revs.len() == 1, "Needed a single revision");
+        return match revision::resolve(&revs[0]) {
+            Ok(hash) => print_hash(&hash, options),
+            Err(_) if options.quiet => std::process::exit(1),
+            Err(e) => Err(e),
+        };
+    }
+
+    for rev in revs {
+        if options.abbrev_ref || options.symbolic_full_name {
+            print_name(rev, options)?;
+            continue;
+        }
+        if let Some(excluded) = rev.strip_prefix('^') {
+            let hash = revision::resolve(excluded)?;
+            print!("^");
+            print_hash(&hash, options)?;
+            continue;
+        }
+        match revision::parse(rev)? {
+            Revision::Single(hash) => print_hash(&hash, options)?,
+            Revision::Range { from, to } => {
+                print_hash(&to, options)?;
+                print!("^");
+                print_hash(&from, options)?;
+            }
+            Revision::Symmetric { left, right } => {
+                print_hash(&right, options)?;
+                print_hash(&left, options)?;
+                for base in revision::merge_bases(&left, &right)? {
+                    print!("^");
+                    print_hash(&base, options)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_hash(hash: &str, options: &Options) -> Result<()> {
+    match options.short {
+        Some(len) => println!("{}", object::abbreviate(hash, len)?),
+        None => println!("{}", hash),
+    }
+    Ok(())
+}
+
+/// `--abbrev-ref` and `--symbolic-full-name` print the ref an argument names rather than the
+/// object, eg `main` or `refs/heads/main` for HEAD.
+fn print_name(rev: &str, options: &Options) -> Result<()> {
+    let rev = if rev == "@" { "HEAD" } else { rev };
+    let name = refs::dwim(rev)?.with_context(|| format!("{} is not a ref", rev))?;
+    let (full, _) = refs::resolve_name(&name)?;
+    if options.abbrev_ref {
+        // a detached HEAD resolves to itself
+        println!("{}", refs::shorten(&full));
+    } else if full != "HEAD" {
+        println!("{}", full);
+    }
+    Ok(())
+}
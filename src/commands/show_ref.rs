@@ -0,0 +1,49 @@
+use anyhow::Result;
+use std::io::{self, Write};
+
+use crate::refs::{self, packed::PackedRefs};
+
+pub fn invoke(head: bool, dereference: bool, hash_only: bool, patterns: &[String]) -> Result<()> {
+    let mut refs = refs::list()?;
+    if head {
+        if let Some(hash) = refs::resolve("HEAD")? {
+            refs.insert(0, ("HEAD".to_string(), hash));
+        }
+    }
+    let packed = PackedRefs::read()?;
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut found = false;
+    for (name, hash) in refs {
+        // patterns match whole trailing components, so "main" matches refs/heads/main
+        let matches = patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|p| name == *p || name.ends_with(&format!("/{}", p)));
+        if !matches {
+            continue;
+        }
+        found = true;
+        print_ref(&mut out, &name, &hash, hash_only)?;
+        if dereference {
+            if let Some(peeled) = packed.get(&name).and_then(|r| r.peeled.as_ref()) {
+                print_ref(&mut out, &format!("{}^{{}}", name), peeled, hash_only)?;
+            }
+        }
+    }
+
+    out.flush()?;
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_ref(out: &mut impl Write, name: &str, hash: &str, hash_only: bool) -> Result<()> {
+    if hash_only {
+        writeln!(out, "{}", hash)?;
+    } else {
+        writeln!(out, "{} {}", hash, name)?;
+    }
+    Ok(())
+}
@@ -1,132 +1,881 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
 use std::{
-    collections::HashSet,
-    fs,
-    io::{BufReader, Read},
+    collections::{BTreeMap, HashSet},
+    io::{self, Write},
     path::Path,
 };
 
-use crate::objects::{
-    commit::Commit,
-    index::{IndexEntry, IndexFile},
-    object::Object,
-    tree::Tree,
+use crate::{
+    config::Config,
+    fsmonitor,
+    objects::{commit::Commit, index::IndexFile, object, tree::Tree},
+    quote::{quote_path_with, Quoting},
+    refs::{self, Ref},
+    rename,
+    revision::{self, reach::Reach},
+    worktree::{self, EntryState},
 };
 
-pub fn invoke() -> Result<()> {
-    let files_in_commit = get_files_in_commit()?;
-    let mut modified_files = Vec::new();
-    let mut staged_files = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
 
-    let index_file = IndexFile::read_from_index()?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UntrackedMode {
+    No,
+    /// untracked directories are shown as `dir/` instead of listing what is in them
+    Normal,
+    All,
+}
 
-    for entry in index_file.entries.iter() {
-        let other = IndexEntry::from_path(entry.entry_path.clone(), &entry.hash, entry.flags)
-            .context("constructing index entry from path")?;
-        if other != *entry {
-            modified_files.push(entry.entry_path.clone());
-        }
+pub(crate) struct Options {
+    pub format: Format,
+    /// show the branch and its upstream in the short formats
+    pub branch: bool,
+    /// NUL terminated entries and unquoted paths
+    pub zero: bool,
+    pub untracked: UntrackedMode,
+    /// `--find-renames` or `--no-renames`, otherwise `status.renames` decides
+    pub renames: Option<bool>,
+    /// `--find-copies`, also pair new files with ones changed in the index
+    pub copies: bool,
+    /// the similarity `--find-renames=<n>` or `--find-copies=<n>` asked for, out of
+    /// `rename::MAX_SCORE`
+    pub rename_score: Option<u32>,
+}
 
-        if !files_in_commit.contains(entry.hash.as_str()) {
-            //ie if files_in_commit does not contain one of the index files, it is new, ie staged
-            //for commit
-            staged_files.push(entry.entry_path.clone());
-        }
+/// A path that is the same in the index and worktree as in HEAD isn't listed, so everything
+/// here differs somewhere.
+struct Change {
+    path: String,
+    /// X of the short format, the index against HEAD, `' '` if they are the same
+    staged: char,
+    /// the HEAD path an `R` or `C` came from, and how alike they are out of
+    /// `rename::MAX_SCORE`
+    from: Option<(String, u32)>,
+    /// Y of the short format, the worktree against the index
+    unstaged: char,
+    head: Option<Side>,
+    index: Side,
+    /// 0 when the file is gone from the worktree or the index
+    worktree_mode: u32,
+}
+
+/// A path with conflict stages in the index.
+struct Unmerged {
+    path: String,
+    /// the base, ours and theirs
+    stages: [Option<Side>; 3],
+    worktree_mode: u32,
+}
+
+#[derive(Clone)]
+struct Side {
+    mode: u32,
+    hash: String,
+}
+
+struct Branch {
+    /// the short branch name, `None` when HEAD is detached
+    name: Option<String>,
+    /// `None` on a branch with no commits yet
+    oid: Option<String>,
+    upstream: Option<Upstream>,
+}
+
+struct Upstream {
+    /// as git shows it, eg `origin/main`
+    name: String,
+    /// commits ahead and behind, `None` if the upstream ref doesn't exist any more
+    ahead_behind: Option<(usize, usize)>,
+}
+
+struct Status {
+    branch: Branch,
+    in_merge: bool,
+    changes: Vec<Change>,
+    unmerged: Vec<Unmerged>,
+    untracked: Vec<String>,
+}
+
+impl Status {
+    fn committable(&self) -> bool {
+        self.changes.iter().any(|c| c.staged != ' ')
     }
 
-    let index_hash_set: HashSet<String> = index_file
-        .entries
-        .into_iter()
-        .filter_map(|e| {
-            let path_str = e.entry_path.to_str()?;
-            path_str.parse::<String>().ok()
-        })
+    fn worktree_dirty(&self) -> bool {
+        !self.unmerged.is_empty() || self.changes.iter().any(|c| c.unstaged != ' ')
+    }
+}
+
+pub fn invoke(options: &Options) -> Result<()> {
+    let status = collect(options)?;
+    let quoting = Quoting::from_config(&Config::load()?);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match options.format {
+        Format::Long => write_long(&mut out, &status, options, quoting)?,
+        Format::Short | Format::PorcelainV1 => write_short(&mut out, &status, options, quoting)?,
+        Format::PorcelainV2 => write_v2(&mut out, &status, options, quoting)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn collect(options: &Options) -> Result<Status> {
+    let branch = branch_info()?;
+    // an unborn branch has nothing in HEAD, so everything in the index is new
+    let mut head_files = match &branch.oid {
+        Some(oid) => head_tree_files(oid)?,
+        None => BTreeMap::new(),
+    };
+    let mut index_file = IndexFile::read_or_empty()?;
+    let cache_before = untracked_cache_data(&index_file);
+    let token_before = index_file.fsmonitor_token.clone();
+    let trust_cache = fsmonitor::refresh(&mut index_file)?;
+
+    let mut changes = Vec::new();
+    let mut conflicts: BTreeMap<String, Unmerged> = BTreeMap::new();
+    let mut refreshed = Vec::new();
+    let states = worktree::check_entries(&index_file)?;
+    let clean: Vec<bool> = states
+        .iter()
+        .map(|s| matches!(s, EntryState::Unchanged | EntryState::Stale(_)))
         .collect();
+    for (i, (entry, state)) in index_file.entries.iter().zip(states).enumerate() {
+        let path = entry
+            .entry_path
+            .to_str()
+            .context("path must be valid utf-8")?
+            .to_string();
+        let side = Side {
+            mode: entry.mode(),
+            hash: entry.hash.clone(),
+        };
+        let (unstaged, worktree_mode) = match state {
+            EntryState::Unchanged => (' ', entry.mode()),
+            EntryState::Stale(current) => {
+                let mode = current.mode();
+                refreshed.push((i, current));
+                (' ', mode)
+            }
+            EntryState::Modified(current) => ('M', current.mode()),
+            EntryState::TypeChanged(current) => ('T', current.mode()),
+            EntryState::Deleted => ('D', 0),
+        };
+        let head = head_files.remove(&path);
 
-    let mut unstaged = Vec::new();
-    check_dir_for_unstaged(Path::new("."), &index_hash_set, &mut unstaged)?;
+        if entry.stage() != 0 {
+            let unmerged = conflicts.entry(path.clone()).or_insert(Unmerged {
+                path,
+                stages: [None, None, None],
+                worktree_mode,
+            });
+            unmerged.stages[entry.stage() as usize - 1] = Some(side);
+            continue;
+        }
 
-    println!("modified----------------------------------");
-    for file in modified_files {
-        println!("{:#?}", file);
+        let staged = match &head {
+            None => 'A',
+            Some(head) if head.mode >> 12 != side.mode >> 12 => 'T',
+            Some(head) if head.mode != side.mode || head.hash != side.hash => 'M',
+            Some(_) => ' ',
+        };
+        if staged == ' ' && unstaged == ' ' {
+            continue;
+        }
+        changes.push(Change {
+            path,
+            staged,
+            from: None,
+            unstaged,
+            head,
+            index: side,
+            worktree_mode,
+        });
     }
-    println!("staged-------------------------------------");
-    for file in staged_files {
-        println!("{:#?}", file);
+
+    // what is left of HEAD has been taken out of the index
+    for (path, head) in head_files {
+        changes.push(Change {
+            path,
+            staged: 'D',
+            from: None,
+            unstaged: ' ',
+            head: Some(head),
+            index: Side {
+                mode: 0,
+                hash: refs::ZERO_HASH.to_string(),
+            },
+            worktree_mode: 0,
+        });
     }
-    println!("unstaged-------------------------------------");
-    for file in unstaged {
-        println!("{}", file.red());
+    if let Some(renames) = rename_options(options)? {
+        find_renames(&mut changes, &renames)?;
     }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
 
-    Ok(())
+    let untracked = if options.untracked == UntrackedMode::No {
+        Vec::new()
+    } else {
+        let all = options.untracked == UntrackedMode::All;
+        worktree::untracked(&mut index_file, all, trust_cache)?
+    };
+
+    // save the work for next time, like git this is skipped quietly when the index is locked
+    for (i, current) in &refreshed {
+        index_file.entries[*i].refresh_from(current);
+    }
+    fsmonitor::mark_clean(&mut index_file, &clean);
+    if !refreshed.is_empty()
+        || index_file.fsmonitor_token.is_some()
+        || index_file.fsmonitor_token != token_before
+        || untracked_cache_data(&index_file) != cache_before
+    {
+        let _ = index_file.write();
+    }
+
+    Ok(Status {
+        branch,
+        in_merge: Path::new(".git/MERGE_HEAD").exists(),
+        changes,
+        unmerged: conflicts.into_values().collect(),
+        untracked,
+    })
 }
 
-fn check_dir_for_unstaged(
-    dir: &Path,
-    index_hash_set: &HashSet<String>,
-    mut unstaged: &mut Vec<String>,
-) -> Result<()> {
-    anyhow::ensure!(dir.is_dir(), "must pass dir for this to work");
-
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() && !(path.starts_with("./target") || path.starts_with("./.git")) {
-            // workaround
-            // for the fact that no .gitignore support yet
-            check_dir_for_unstaged(&path, &index_hash_set, &mut unstaged)?;
-        } else {
-            let path_trunc = path.to_str().context("unlikely error in path trunc")?;
-            if !index_hash_set.contains(&path_trunc[2..])
-                && !(path.starts_with("./target") || path.starts_with("./.git"))
-            {
-                unstaged.push(path_trunc[2..].to_string());
+fn rename_options(options: &Options) -> Result<Option<rename::Options>> {
+    let config = Config::load()?;
+    let mut renames = match options.renames {
+        Some(false) => return Ok(None),
+        Some(true) => Some(rename::Options::from_config(&config, "status").unwrap_or_default()),
+        None => rename::Options::from_config(&config, "status"),
+    };
+    if let Some(renames) = &mut renames {
+        renames.copies |= options.copies;
+        if let Some(score) = options.rename_score {
+            renames.min_score = score;
+        }
+    }
+    Ok(renames)
+}
+
+/// Pairs files new to the index with ones taken out of it, and for copies with ones that
+/// were changed, turning the pairs into `R` and `C` entries. A renamed file's deletion goes.
+fn find_renames(changes: &mut Vec<Change>, options: &rename::Options) -> Result<()> {
+    let (mut sources, mut source_at) = (Vec::new(), Vec::new());
+    let (mut targets, mut target_at) = (Vec::new(), Vec::new());
+    for (i, change) in changes.iter().enumerate() {
+        match (change.staged, &change.head) {
+            ('A', _) => {
+                targets.push(rename::File {
+                    path: change.path.clone(),
+                    mode: change.index.mode,
+                    hash: change.index.hash.clone(),
+                    in_worktree: false,
+                });
+                target_at.push(i);
             }
+            ('D' | 'M', Some(head)) => {
+                sources.push(rename::Source {
+                    file: rename::File {
+                        path: change.path.clone(),
+                        mode: head.mode,
+                        hash: head.hash.clone(),
+                        in_worktree: false,
+                    },
+                    deleted: change.staged == 'D',
+                });
+                source_at.push(i);
+            }
+            _ => {}
         }
     }
+    if sources.is_empty() || targets.is_empty() {
+        return Ok(());
+    }
+
+    let mut renamed = HashSet::new();
+    for found in rename::detect(&sources, &targets, options)? {
+        let source = &changes[source_at[found.source]];
+        let (path, head) = (source.path.clone(), source.head.clone());
+        if !found.copy {
+            renamed.insert(source_at[found.source]);
+        }
+        let target = &mut changes[target_at[found.target]];
+        target.staged = if found.copy { 'C' } else { 'R' };
+        target.head = head;
+        target.from = Some((path, found.score));
+    }
+    let mut i = 0;
+    changes.retain(|_| {
+        i += 1;
+        !renamed.contains(&(i - 1))
+    });
     Ok(())
 }
 
-fn get_head_hash() -> Result<String> {
-    let mut head_file = fs::File::open(".git/HEAD").context("opening HEAD file")?;
-    let mut head_ref = String::new();
+fn untracked_cache_data(index: &IndexFile) -> Option<Vec<u8>> {
+    index.untracked_cache.as_ref().map(|cache| {
+        let mut data = Vec::new();
+        cache.write_to(&mut data);
+        data
+    })
+}
 
-    head_file
-        .read_to_string(&mut head_ref)
-        .context("reading HEAD file")?;
+fn branch_info() -> Result<Branch> {
+    let name = match refs::read("HEAD")? {
+        Some(Ref::Symbolic(target)) => Some(
+            target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+        ),
+        _ => None,
+    };
+    let oid = refs::resolve("HEAD")?;
+    let upstream = match &name {
+        Some(name) => upstream(name, oid.as_deref())?,
+        None => None,
+    };
+    Ok(Branch {
+        name,
+        oid,
+        upstream,
+    })
+}
 
-    let head_hash = if let Some((_, head_ref)) = head_ref.split_once(" ") {
-        let head_ref = format!(".git/{}", &head_ref);
+/// The branch `branch.<name>.remote` and `branch.<name>.merge` point at, and how far apart it
+/// and HEAD are.
+fn upstream(name: &str, head: Option<&str>) -> Result<Option<Upstream>> {
+    let config = Config::load()?;
+    let (Some(remote), Some(merge)) = (
+        config.get(&format!("branch.{}.remote", name)),
+        config.get(&format!("branch.{}.merge", name)),
+    ) else {
+        return Ok(None);
+    };
+    let full_name = if remote == "." {
+        merge.to_string()
+    } else {
+        // where fetching from the remote stores its branch, which isn't tracked when no
+        // refspec takes it
+        let key = format!("remote.{}.fetch", remote);
+        // git only checks negative refspecs when a refspec names the branch exactly
+        let named = config.get_all(&key).any(|refspec| {
+            let src = refspec.trim_start_matches('+');
+            src.split_once(':').map_or(src, |(src, _)| src) == merge
+        });
+        let excluded = named
+            && config.get_all(&key).any(|refspec| {
+                refspec
+                    .strip_prefix('^')
+                    .is_some_and(|src| map_refspec(&format!("{}:{}", src, src), merge).is_some())
+            });
+        match config
+            .get_all(&key)
+            .find_map(|refspec| map_refspec(refspec, merge))
+        {
+            Some(full_name) if !excluded => full_name,
+            _ => return Ok(None),
+        }
+    };
+
+    let ahead_behind = match refs::resolve(&full_name)? {
+        Some(theirs) => Some(match head {
+            Some(head) => Reach::new()?.ahead_behind(head, &theirs)?,
+            None => (0, revision::ancestors(&[theirs])?.len()),
+        }),
+        None => None,
+    };
+    Ok(Some(Upstream {
+        name: refs::shorten(&full_name).to_string(),
+        ahead_behind,
+    }))
+}
+
+/// The destination a fetch `refspec` gives the remote ref `name`, if it takes it at all.
+/// Negative refspecs take nothing themselves.
+fn map_refspec(refspec: &str, name: &str) -> Option<String> {
+    if refspec.starts_with('^') {
+        return None;
+    }
+    let (src, dst) = refspec.trim_start_matches('+').split_once(':')?;
+    match (src.split_once('*'), dst.split_once('*')) {
+        (Some((prefix, suffix)), Some((dst_prefix, dst_suffix))) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(format!("{}{}{}", dst_prefix, matched, dst_suffix))
+        }
+        (None, None) if src == name => Some(dst.to_string()),
+        _ => None,
+    }
+}
 
-        let mut head_hash_file =
-            fs::File::open(head_ref.as_str().trim()).context("opening head_hash file")?;
+/// The files of the tree HEAD's commit points at, by path.
+fn head_tree_files(head_hash: &str) -> Result<BTreeMap<String, Side>> {
+    let commit = Commit::read_from_hash(head_hash)?;
+    let mut files = BTreeMap::new();
+    Tree::read_from_hash(&commit.tree)
+        .context("reading tree from commit")?
+        .flatten("", &mut files)
+        .context("listing files in commit")?;
+    Ok(files
+        .into_iter()
+        .map(|(path, entry)| {
+            let side = Side {
+                mode: entry.octal_mode(),
+                hash: entry.hash,
+            };
+            (path, side)
+        })
+        .collect())
+}
 
-        let mut head_hash = String::new();
-        head_hash_file
-            .read_to_string(&mut head_hash)
-            .context("reading head hash file")?;
+/// The XY of a conflict in the short formats, from which stages are present.
+fn unmerged_code(unmerged: &Unmerged) -> &'static str {
+    match unmerged.stages.each_ref().map(Option::is_some) {
+        [true, false, false] => "DD",
+        [false, true, false] => "AU",
+        [true, true, false] => "UD",
+        [false, false, true] => "UA",
+        [true, false, true] => "DU",
+        [false, true, true] => "AA",
+        _ => "UU",
+    }
+}
+
+fn unmerged_label(unmerged: &Unmerged) -> &'static str {
+    match unmerged_code(unmerged) {
+        "DD" => "both deleted:",
+        "AU" => "added by us:",
+        "UD" => "deleted by them:",
+        "UA" => "added by them:",
+        "DU" => "deleted by us:",
+        "AA" => "both added:",
+        _ => "both modified:",
+    }
+}
 
-        head_hash.trim().to_string()
+fn change_label(code: char) -> &'static str {
+    match code {
+        'A' => "new file:",
+        'D' => "deleted:",
+        'T' => "typechange:",
+        'R' => "renamed:",
+        'C' => "copied:",
+        _ => "modified:",
+    }
+}
+
+fn write_long(
+    out: &mut impl Write,
+    status: &Status,
+    options: &Options,
+    quoting: Quoting,
+) -> Result<()> {
+    let quote_path = |path: &str| quote_path_with(path, quoting);
+    let branch = &status.branch;
+    match (&branch.name, &branch.oid) {
+        (Some(name), _) => writeln!(out, "On branch {}", name)?,
+        (None, Some(oid)) => writeln!(
+            out,
+            "{}",
+            format!("HEAD detached at {}", object::abbreviate(oid, 7)?).red()
+        )?,
+        (None, None) => writeln!(out, "Not currently on any branch.")?,
+    }
+    if let Some(upstream) = &branch.upstream {
+        write_tracking(out, upstream)?;
+    }
+
+    if status.in_merge {
+        if status.unmerged.is_empty() {
+            writeln!(out, "All conflicts fixed but you are still merging.")?;
+            writeln!(out, "  (use \"git commit\" to conclude merge)")?;
+        } else {
+            writeln!(out, "You have unmerged paths.")?;
+            writeln!(out, "  (fix conflicts and run \"git commit\")")?;
+            writeln!(out, "  (use \"git merge --abort\" to abort the merge)")?;
+        }
+        writeln!(out)?;
+    }
+    let initial = branch.oid.is_none();
+    if initial {
+        writeln!(out)?;
+        writeln!(out, "No commits yet")?;
+        writeln!(out)?;
+    }
+    // what git suggests for taking something back out of the index
+    let unstage_hint = if status.in_merge {
+        None
+    } else if initial {
+        Some("  (use \"git rm --cached <file>...\" to unstage)")
     } else {
-        // we are in detached state where the HEAD file stores a hash
-        head_ref.trim().to_string()
+        Some("  (use \"git restore --staged <file>...\" to unstage)")
+    };
+
+    if !status.unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        if let Some(hint) = unstage_hint {
+            writeln!(out, "{}", hint)?;
+        }
+        // a file deleted on one side may need either, one deleted on both only rm
+        let codes: Vec<&str> = status.unmerged.iter().map(unmerged_code).collect();
+        let deleted_on_one_side = codes.iter().any(|c| matches!(*c, "UD" | "DU"));
+        let both_deleted = codes.contains(&"DD");
+        let hint = if !deleted_on_one_side && !both_deleted {
+            "  (use \"git add <file>...\" to mark resolution)"
+        } else if !deleted_on_one_side && codes.iter().all(|c| *c == "DD") {
+            "  (use \"git rm <file>...\" to mark resolution)"
+        } else {
+            "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+        };
+        writeln!(out, "{}", hint)?;
+        for unmerged in &status.unmerged {
+            let line = format!(
+                "{:<17}{}",
+                unmerged_label(unmerged),
+                quote_path(&unmerged.path)
+            );
+            writeln!(out, "\t{}", line.red())?;
+        }
+        writeln!(out)?;
+    }
+
+    if status.committable() {
+        writeln!(out, "Changes to be committed:")?;
+        if let Some(hint) = unstage_hint {
+            writeln!(out, "{}", hint)?;
+        }
+        for change in status.changes.iter().filter(|c| c.staged != ' ') {
+            let path = match &change.from {
+                Some((from, _)) => format!("{} -> {}", quote_path(from), quote_path(&change.path)),
+                None => quote_path(&change.path),
+            };
+            let line = format!("{:<12}{}", change_label(change.staged), path);
+            writeln!(out, "\t{}", line.green())?;
+        }
+        writeln!(out)?;
+    }
+
+    let unstaged: Vec<&Change> = status
+        .changes
+        .iter()
+        .filter(|c| c.unstaged != ' ')
+        .collect();
+    if !unstaged.is_empty() {
+        writeln!(out, "Changes not staged for commit:")?;
+        if unstaged.iter().any(|c| c.unstaged == 'D') {
+            writeln!(
+                out,
+                "  (use \"git add/rm <file>...\" to update what will be committed)"
+            )?;
+        } else {
+            writeln!(
+                out,
+                "  (use \"git add <file>...\" to update what will be committed)"
+            )?;
+        }
+        writeln!(
+            out,
+            "  (use \"git restore <file>...\" to discard changes in working directory)"
+        )?;
+        for change in unstaged {
+            let line = format!(
+                "{:<12}{}",
+                change_label(change.unstaged),
+                quote_path(&change.path)
+            );
+            writeln!(out, "\t{}", line.red())?;
+        }
+        writeln!(out)?;
+    }
+
+    if !status.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        writeln!(
+            out,
+            "  (use \"git add <file>...\" to include in what will be committed)"
+        )?;
+        for path in &status.untracked {
+            writeln!(out, "\t{}", quote_path(path).red())?;
+        }
+        writeln!(out)?;
+    } else if options.untracked == UntrackedMode::No {
+        writeln!(
+            out,
+            "Untracked files not listed (use -u option to show untracked files)"
+        )?;
+    }
+
+    if !status.committable() {
+        if status.worktree_dirty() {
+            writeln!(
+                out,
+                "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+            )?;
+        } else if !status.untracked.is_empty() {
+            writeln!(
+                out,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            )?;
+        } else if initial {
+            writeln!(
+                out,
+                "nothing to commit (create/copy files and use \"git add\" to track)"
+            )?;
+        } else if options.untracked == UntrackedMode::No {
+            writeln!(out, "nothing to commit (use -u to show untracked files)")?;
+        } else {
+            writeln!(out, "nothing to commit, working tree clean")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tracking(out: &mut impl Write, upstream: &Upstream) -> Result<()> {
+    let name = &upstream.name;
+    let commits = |n: usize| if n == 1 { "commit" } else { "commits" };
+    match upstream.ahead_behind {
+        None => {
+            writeln!(
+                out,
+                "Your branch is based on '{}', but the upstream is gone.",
+                name
+            )?;
+            writeln!(out, "  (use \"git branch --unset-upstream\" to fixup)")?;
+        }
+        Some((0, 0)) => writeln!(out, "Your branch is up to date with '{}'.", name)?,
+        Some((ahead, 0)) => {
+            writeln!(
+                out,
+                "Your branch is ahead of '{}' by {} {}.",
+                name,
+                ahead,
+                commits(ahead)
+            )?;
+            writeln!(out, "  (use \"git push\" to publish your local commits)")?;
+        }
+        Some((0, behind)) => {
+            writeln!(
+                out,
+                "Your branch is behind '{}' by {} {}, and can be fast-forwarded.",
+                name,
+                behind,
+                commits(behind)
+            )?;
+            writeln!(out, "  (use \"git pull\" to update your local branch)")?;
+        }
+        Some((ahead, behind)) => {
+            writeln!(out, "Your branch and '{}' have diverged,", name)?;
+            writeln!(
+                out,
+                "and have {} and {} different commits each, respectively.",
+                ahead, behind
+            )?;
+            writeln!(
+                out,
+                "  (use \"git pull\" to merge the remote branch into yours)"
+            )?;
+        }
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// `-s` and `--porcelain=v1`, which are the same apart from colour, and quote paths with
+/// spaces.
+fn write_short(
+    out: &mut impl Write,
+    status: &Status,
+    options: &Options,
+    quoting: Quoting,
+) -> Result<()> {
+    let color = options.format == Format::Short;
+    let paint = |s: &str, green: bool| -> String {
+        match (color, green) {
+            (false, _) => s.to_string(),
+            (true, true) => s.green().to_string(),
+            (true, false) => s.red().to_string(),
+        }
     };
-    Ok(head_hash)
+    let end = if options.zero { '\0' } else { '\n' };
+    let path = |p: &str| {
+        if options.zero {
+            p.to_string()
+        } else {
+            quote_path_with(
+                p,
+                Quoting {
+                    spaces: true,
+                    ..quoting
+                },
+            )
+        }
+    };
+
+    if options.branch {
+        let branch = &status.branch;
+        let mut line = String::from("## ");
+        match (&branch.name, &branch.oid) {
+            (Some(name), None) => {
+                line.push_str(&format!("No commits yet on {}", paint(name, true)))
+            }
+            (Some(name), Some(_)) => line.push_str(&paint(name, true)),
+            (None, _) => line.push_str(&paint("HEAD (no branch)", false)),
+        }
+        if let (Some(upstream), Some(_)) = (&branch.upstream, &branch.oid) {
+            line.push_str(&format!("...{}", paint(&upstream.name, false)));
+            match upstream.ahead_behind {
+                None => line.push_str(&format!(" [{}]", paint("gone", false))),
+                Some((0, 0)) => {}
+                Some((ahead, 0)) => {
+                    line.push_str(&format!(" [ahead {}]", paint(&ahead.to_string(), true)))
+                }
+                Some((0, behind)) => {
+                    line.push_str(&format!(" [behind {}]", paint(&behind.to_string(), false)))
+                }
+                Some((ahead, behind)) => line.push_str(&format!(
+                    " [ahead {}, behind {}]",
+                    paint(&ahead.to_string(), true),
+                    paint(&behind.to_string(), false)
+                )),
+            }
+        }
+        write!(out, "{}{}", line, end)?;
+    }
+
+    // tracked paths in order, conflicts mixed in with the rest
+    let mut lines: Vec<(&str, String, String)> = Vec::new();
+    for change in &status.changes {
+        let code = format!(
+            "{}{}",
+            paint(&change.staged.to_string(), true),
+            paint(&change.unstaged.to_string(), false)
+        );
+        // a rename shows as `old -> new`, or with -z as the new path then the old
+        let shown = match (&change.from, options.zero) {
+            (Some((from, _)), true) => format!("{}{}{}", change.path, end, from),
+            (Some((from, _)), false) => format!("{} -> {}", path(from), path(&change.path)),
+            (None, _) => path(&change.path),
+        };
+        lines.push((&change.path, code, shown));
+    }
+    for unmerged in &status.unmerged {
+        let code = paint(unmerged_code(unmerged), false);
+        lines.push((&unmerged.path, code, path(&unmerged.path)));
+    }
+    lines.sort_by(|a, b| a.0.cmp(b.0));
+    for (_, code, shown) in lines {
+        write!(out, "{} {}{}", code, shown, end)?;
+    }
+    for p in &status.untracked {
+        write!(out, "{} {}{}", paint("??", false), path(p), end)?;
+    }
+    Ok(())
 }
 
-fn get_files_in_commit() -> Result<HashSet<String>> {
-    let head_hash = get_head_hash()?;
-    let commit = Commit::read_from_hash(&head_hash)?;
+fn write_v2(
+    out: &mut impl Write,
+    status: &Status,
+    options: &Options,
+    quoting: Quoting,
+) -> Result<()> {
+    let end = if options.zero { '\0' } else { '\n' };
+    let path = |p: &str| {
+        if options.zero {
+            p.to_string()
+        } else {
+            quote_path_with(p, quoting)
+        }
+    };
+    let zero_hash = refs::ZERO_HASH;
 
-    let mut files_in_commit = HashSet::new();
+    if options.branch {
+        let branch = &status.branch;
+        write!(
+            out,
+            "# branch.oid {}{}",
+            branch.oid.as_deref().unwrap_or("(initial)"),
+            end
+        )?;
+        write!(
+            out,
+            "# branch.head {}{}",
+            branch.name.as_deref().unwrap_or("(detached)"),
+            end
+        )?;
+        if let Some(upstream) = &branch.upstream {
+            write!(out, "# branch.upstream {}{}", upstream.name, end)?;
+            if let Some((ahead, behind)) = upstream.ahead_behind {
+                write!(out, "# branch.ab +{} -{}{}", ahead, behind, end)?;
+            }
+        }
+    }
 
-    let mut tree_object =
-        Object::try_from(commit.tree.as_str()).context("reading tree object from commit")?;
-    let mut bufread = BufReader::new(&mut tree_object.reader);
-    let tree = Tree::read(&mut bufread).context("reading tree from tree object from commit")?;
-    tree.traverse(&mut files_in_commit)
-        .context("getting files_in_commit of blobs in commit")?;
-    Ok(files_in_commit)
+    let dot = |c: char| if c == ' ' { '.' } else { c };
+    let mut lines: Vec<(&str, String)> = Vec::new();
+    for change in &status.changes {
+        let (head_mode, head_hash) = match &change.head {
+            Some(side) => (side.mode, side.hash.as_str()),
+            None => (0, zero_hash),
+        };
+        let fields = format!(
+            "{}{} N... {:06o} {:06o} {:06o} {} {}",
+            dot(change.staged),
+            dot(change.unstaged),
+            head_mode,
+            change.index.mode,
+            change.worktree_mode,
+            head_hash,
+            change.index.hash
+        );
+        // renames and copies carry their score, and the old path after a tab
+        let line = match &change.from {
+            Some((from, score)) => format!(
+                "2 {} {}{} {}{}{}",
+                fields,
+                change.staged,
+                score * 100 / rename::MAX_SCORE,
+                path(&change.path),
+                if options.zero { '\0' } else { '\t' },
+                path(from)
+            ),
+            None => format!("1 {} {}", fields, path(&change.path)),
+        };
+        lines.push((&change.path, line));
+    }
+    for unmerged in &status.unmerged {
+        let modes = unmerged
+            .stages
+            .each_ref()
+            .map(|s| format!("{:06o}", s.as_ref().map_or(0, |s| s.mode)));
+        let hashes = unmerged
+            .stages
+            .each_ref()
+            .map(|s| s.as_ref().map_or(zero_hash, |s| s.hash.as_str()));
+        lines.push((
+            &unmerged.path,
+            format!(
+                "u {} N... {} {:06o} {} {}",
+                unmerged_code(unmerged),
+                modes.join(" "),
+                unmerged.worktree_mode,
+                hashes.join(" "),
+                path(&unmerged.path)
+            ),
+        ));
+    }
+    lines.sort_by(|a, b| a.0.cmp(b.0));
+    for (_, line) in lines {
+        write!(out, "{}{}", line, end)?;
+    }
+    for p in &status.untracked {
+        write!(out, "? {}{}", path(p), end)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,23 @@
+use anyhow::Result;
+
+use crate::refs::{self, Ref};
+
+pub fn invoke(
+    short: bool,
+    message: &Option<String>,
+    name: &str,
+    target: &Option<String>,
+) -> Result<()> {
+    if let Some(target) = target {
+        return refs::update_symbolic(name, target, message.as_deref());
+    }
+
+    match refs::read(name)? {
+        Some(Ref::Symbolic(target)) if short => {
+            println!("{}", refs::shorten(&target));
+        }
+        Some(Ref::Symbolic(target)) => println!("{}", target),
+        _ => anyhow::bail!("ref {} is not a symbolic ref", name),
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+use crate::{
+    fsmonitor,
+    objects::{
+        index::IndexFile,
+        untracked_cache::{UntrackedCache, HIDE_EMPTY_DIRECTORIES, SHOW_OTHER_DIRECTORIES},
+    },
+    worktree::{self, EntryState},
+};
+
+pub(crate) struct Options {
+    pub refresh: bool,
+    pub quiet: bool,
+    /// `Some(true)` adds an untracked cache to the index, `Some(false)` drops it
+    pub untracked_cache: Option<bool>,
+}
+
+pub fn invoke(options: &Options) -> Result<()> {
+    let mut index = IndexFile::read_or_empty()?;
+    match options.untracked_cache {
+        Some(true) if index.untracked_cache.is_none() => {
+            let flags = SHOW_OTHER_DIRECTORIES | HIDE_EMPTY_DIRECTORIES;
+            index.untracked_cache = Some(UntrackedCache::new(worktree::cache_ident()?, flags));
+        }
+        Some(false) => index.untracked_cache = None,
+        _ => {}
+    }
+    if options.refresh {
+        refresh(&mut index, options.quiet)
+    } else {
+        index.write().context("writing the index")?;
+        Ok(())
+    }
+}
+
+/// `--refresh`: takes the current stat data of files whose contents still match the index, so
+/// later commands can trust stat again, and reports the ones that don't match.
+fn refresh(index: &mut IndexFile, quiet: bool) -> Result<()> {
+    fsmonitor::refresh(index)?;
+    let mut needs_update = false;
+    let mut reported = HashSet::new();
+    let mut refreshed = Vec::new();
+    let states = worktree::check_entries(index)?;
+    let clean: Vec<bool> = states
+        .iter()
+        .map(|s| matches!(s, EntryState::Unchanged | EntryState::Stale(_)))
+        .collect();
+    for (i, (entry, state)) in index.entries.iter().zip(states).enumerate() {
+        let path = entry
+            .entry_path
+            .to_str()
+            .context("path must be valid utf-8")?;
+        if entry.stage() != 0 {
+            needs_update = true;
+            if !quiet && reported.insert(path) {
+                println!("{}: needs merge", path);
+            }
+            continue;
+        }
+        match state {
+            EntryState::Unchanged => {}
+            EntryState::Stale(current) => refreshed.push((i, current)),
+            EntryState::Modified(_) | EntryState::TypeChanged(_) | EntryState::Deleted => {
+                needs_update = true;
+                if !quiet {
+                    println!("{}: needs update", path);
+                }
+            }
+        }
+    }
+
+    for (i, current) in &refreshed {
+        index.entries[*i].refresh_from(current);
+    }
+    fsmonitor::mark_clean(index, &clean);
+    index.write().context("writing the index")?;
+    if needs_update && !quiet {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+
+use crate::{refs, revision};
+
+pub fn invoke(
+    delete: bool,
+    no_deref: bool,
+    message: &Option<String>,
+    name: &str,
+    args: &[String],
+) -> Result<()> {
+    if delete {
+        anyhow::ensure!(
+            args.len() <= 1,
+            "usage: update-ref -d <refname> [<old-val>]"
+        );
+        let old = args.first().map(|old| object_id(old, "old ")).transpose()?;
+        return refs::delete(name, old.as_deref(), no_deref);
+    }
+
+    let (new, old) = match args {
+        [new] => (new, None),
+        [new, old] => (new, Some(old.as_str())),
+        _ => anyhow::bail!("usage: update-ref <refname> <new-val> [<old-val>]"),
+    };
+    let new = object_id(new, "")?;
+    // an empty old value means the same as all zeros: the ref must not exist yet
+    let old = match old {
+        Some("") => Some(refs::ZERO_HASH.to_string()),
+        Some(old) => Some(object_id(old, "old ")?),
+        None => None,
+    };
+    refs::update(
+        name,
+        &new,
+        old.as_deref(),
+        no_deref,
+        message.as_deref().unwrap_or(""),
+    )
+}
+
+/// The id a value on the command line names, which can be any revision, eg `HEAD~1`. All
+/// zeros is kept as it is, standing for no ref at all.
+fn object_id(value: &str, what: &str) -> Result<String> {
+    if value == refs::ZERO_HASH {
+        return Ok(value.to_string());
+    }
+    revision::resolve(value).map_err(|_| anyhow::anyhow!("{}: not a valid {}SHA1", value, what))
+}
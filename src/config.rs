@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use std::{collections::HashMap, env, fs, io::ErrorKind, path::PathBuf};
+
+/// The merged view of the global and repository config files, later files winning.
+///
+/// Keys are stored the way git compares them: section and key lowercased, subsection kept as
+/// written, eg `remote.origin.url` or `core.excludesfile`.
+#[derive(Debug, Default)]
+pub(crate) struct Config {
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let mut config = Config::default();
+        for path in Config::paths() {
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("reading {}", path.display()));
+                }
+            };
+            config
+                .parse(&contents)
+                .with_context(|| format!("bad config file {}", path.display()))?;
+        }
+        Ok(config)
+    }
+
+    fn paths() -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        if let Ok(xdg) = env::var("XDG_CONFIG_HOME") {
+            paths.push(PathBuf::from(xdg).join("git/config"));
+        } else if let Ok(home) = env::var("HOME") {
+            paths.push(PathBuf::from(&home).join(".config/git/config"));
+        }
+        if let Ok(home) = env::var("HOME") {
+            paths.push(PathBuf::from(home).join(".gitconfig"));
+        }
+        paths.push(PathBuf::from(".git/config"));
+        paths
+    }
+
+    fn parse(&mut self, contents: &str) -> Result<()> {
+        let mut section = String::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = strip_comment(line.trim());
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .with_context(|| format!("line {}: unclosed section header", number + 1))?;
+                section = match header.split_once(' ') {
+                    Some((name, sub)) => {
+                        format!("{}.{}", name.to_lowercase(), sub.trim().trim_matches('"'))
+                    }
+                    // the old [section.subsection] syntax
+                    None => match header.split_once('.') {
+                        Some((name, sub)) => format!("{}.{}", name.to_lowercase(), sub),
+                        None => header.to_lowercase(),
+                    },
+                };
+                continue;
+            }
+            anyhow::ensure!(
+                !section.is_empty(),
+                "line {}: key outside of a section",
+                number + 1
+            );
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), unquote(value.trim())),
+                // a key on its own is boolean true
+                None => (line, "true".to_string()),
+            };
+            self.values
+                .entry(format!("{}.{}", section, key.to_lowercase()))
+                .or_default()
+                .push(value);
+        }
+        Ok(())
+    }
+
+    /// The last value set for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .get(&normalize(key))
+            .and_then(|v| v.last())
+            .map(|v| v.as_str())
+    }
+
+    /// Every value set for a multi-valued `key`, in the order they were read.
+    pub fn get_all(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.values
+            .get(&normalize(key))
+            .into_iter()
+            .flatten()
+            .map(|v| v.as_str())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|v| {
+            matches!(
+                v.to_lowercase().as_str(),
+                "true" | "yes" | "on" | "1" | "always"
+            )
+        })
+    }
+
+    /// A path valued key, with a leading `~/` expanded to the home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        let value = self.get(key)?;
+        match (value.strip_prefix("~/"), env::var("HOME")) {
+            (Some(rest), Ok(home)) => Some(PathBuf::from(home).join(rest)),
+            _ => Some(PathBuf::from(value)),
+        }
+    }
+}
+
+/// Lowercases the section and key of `section[.subsection].key`, leaving the subsection alone.
+fn normalize(key: &str) -> String {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    match rest.rsplit_once('.') {
+        Some((sub, name)) => format!("{}.{}.{}", section.to_lowercase(), sub, name.to_lowercase()),
+        None => format!("{}.{}", section.to_lowercase(), rest.to_lowercase()),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => return line[..i].trim_end(),
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {}
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => {}
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Who is making a change, as written in commits and reflogs: `Name <email>`.
+pub fn committer_ident(config: &Config) -> String {
+    let name = env::var("GIT_COMMITTER_NAME")
+        .ok()
+        .or_else(|| config.get("user.name").map(|s| s.to_string()))
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_else(|| "unknown".to_string());
+    let email = env::var("GIT_COMMITTER_EMAIL")
+        .ok()
+        .or_else(|| config.get("user.email").map(|s| s.to_string()))
+        .unwrap_or_else(|| format!("{}@localhost", name));
+    format!("{} <{}>", name, email)
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    attributes::{self, State},
+    config::Config,
+};
+
+/// git looks this far into a file to decide whether it is binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Turns worktree contents into what gets stored for `path`: the `filter` driver's clean
+/// command runs first, then line endings are normalized for text files (`text`, `eol`,
+/// `core.autocrlf`), then `$Id$` is collapsed for files with `ident`.
+pub fn convert_to_git(path: &str, contents: Vec<u8>) -> Result<Vec<u8>> {
+    let attrs = attributes::for_path(path)?;
+    let config = Config::load()?;
+
+    let mut contents = contents;
+    if let Some(State::Value(driver)) = attrs.get("filter") {
+        contents = clean_filter(&config, driver, path, contents)?;
+    }
+
+    let text = match attrs.get("text") {
+        Some(State::Unset) => false,
+        Some(State::Set) => true,
+        Some(State::Value(v)) if v == "auto" => !is_binary(&contents),
+        _ if attrs.contains_key("eol") => true,
+        _ => match config.get("core.autocrlf") {
+            Some(v) if v.eq_ignore_ascii_case("input") => !is_binary(&contents),
+            Some(_) if config.get_bool("core.autocrlf") == Some(true) => !is_binary(&contents),
+            _ => false,
+        },
+    };
+    if text {
+        contents = crlf_to_lf(contents);
+    }
+
+    if attrs.get("ident") == Some(&State::Set) {
+        contents = collapse_ident(&contents);
+    }
+    Ok(contents)
+}
+
+/// Roughly git's `buffer_is_binary`: a NUL in the first few kilobytes.
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+fn crlf_to_lf(contents: Vec<u8>) -> Vec<u8> {
+    if !contents.windows(2).any(|w| w == b"\r\n") {
+        return contents;
+    }
+    let mut out = Vec::with_capacity(contents.len());
+    let mut iter = contents.iter().peekable();
+    while let Some(&b) = iter.next() {
+        if b == b'\r' && iter.peek() == Some(&&b'\n') {
+            continue;
+        }
+        out.push(b);
+    }
+    out
+}
+
+/// `$Id: <anything>$` -> `$Id$`
+fn collapse_ident(contents: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(contents.len());
+    let mut i = 0;
+    while i < contents.len() {
+        if contents[i..].starts_with(b"$Id:") {
+            let end = contents[i + 4..]
+                .iter()
+                .position(|&b| b == b'$' || b == b'\n');
+            if let Some(end) = end {
+                if contents[i + 4 + end] == b'$' {
+                    out.extend_from_slice(b"$Id$");
+                    i += 4 + end + 1;
+                    continue;
+                }
+            }
+        }
+        out.push(contents[i]);
+        i += 1;
+    }
+    out
+}
+
+/// Pipes the contents through `filter.<driver>.clean`, with `%f` replaced by the path.
+fn clean_filter(config: &Config, driver: &str, path: &str, contents: Vec<u8>) -> Result<Vec<u8>> {
+    let Some(command) = config.get(&format!("filter.{}.clean", driver)) else {
+        anyhow::ensure!(
+            config.get_bool(&format!("filter.{}.required", driver)) != Some(true),
+            "{}: clean filter '{}' is required but not configured",
+            path,
+            driver
+        );
+        return Ok(contents);
+    };
+    let command = command.replace("%f", &format!("'{}'", path.replace('\'', "'\\''")));
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running clean filter {}", command))?;
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    // write from another thread so a filter that streams can't deadlock on a full pipe
+    let writer = std::thread::spawn(move || stdin.write_all(&contents));
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("running clean filter {}", command))?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("writing to clean filter panicked"))?
+        .with_context(|| format!("writing to clean filter {}", command))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "{}: clean filter '{}' failed",
+        path,
+        driver
+    );
+    Ok(output.stdout)
+}
@@ -0,0 +1,258 @@
+use anyhow::{Context, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the epoch, right now.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Parses the date forms people actually type to git: unix timestamps (`1700000000` or
+/// `@1700000000`), ISO dates with optional time and offset, and relative ones like `yesterday`,
+/// `now` or `2.weeks.ago`. There is no timezone database here, so dates without an offset are
+/// taken as UTC.
+pub fn parse(input: &str) -> Result<i64> {
+    let input = input.trim();
+    let relative = input.replace('.', " ");
+    let lower = relative.to_lowercase();
+
+    if let Some(secs) = input.strip_prefix('@') {
+        return secs.parse().context("bad timestamp");
+    }
+    if !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()) && input.len() > 8 {
+        return input.parse().context("bad timestamp");
+    }
+    match lower.as_str() {
+        "now" => return Ok(now()),
+        "yesterday" => return Ok(now() - 86400),
+        _ => {}
+    }
+    if let Some(ago) = lower.strip_suffix(" ago") {
+        let mut total = 0;
+        let words: Vec<_> = ago.split_whitespace().collect();
+        anyhow::ensure!(words.len() % 2 == 0, "could not parse date {}", input);
+        for pair in words.chunks(2) {
+            let count: i64 = pair[0]
+                .parse()
+                .with_context(|| format!("could not parse date {}", input))?;
+            let unit = match pair[1].trim_end_matches('s') {
+                "second" | "sec" => 1,
+                "minute" | "min" => 60,
+                "hour" => 3600,
+                "day" => 86400,
+                "week" => 7 * 86400,
+                "month" => 30 * 86400,
+                "year" => 365 * 86400,
+                _ => anyhow::bail!("unknown time unit in {}", input),
+            };
+            total += count * unit;
+        }
+        return Ok(now() - total);
+    }
+
+    parse_iso(input).with_context(|| format!("could not parse date {}", input))
+}
+
+/// `YYYY-MM-DD[( |T)HH:MM[:SS]][ ][(+|-)HHMM|Z]`
+fn parse_iso(input: &str) -> Option<i64> {
+    let (date, rest) = input.split_at(input.find([' ', 'T']).unwrap_or(input.len()));
+    let mut parts = date.split('-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let rest = rest.trim_start_matches([' ', 'T']);
+    let (time, zone) = match rest.find(['+', '-', 'Z', ' ']) {
+        Some(i) => (&rest[..i], rest[i..].trim()),
+        None => (rest, ""),
+    };
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let mut fields = time.split(':').map(|p| p.parse::<i64>().ok());
+        let hours = fields.next()??;
+        let minutes = fields.next()??;
+        let secs = fields.next().unwrap_or(Some(0))?;
+        seconds = hours * 3600 + minutes * 60 + secs;
+    }
+
+    let offset = match zone {
+        "" | "Z" => 0,
+        zone => parse_offset(zone)?,
+    };
+
+    Some(days_from_civil(year, month, day) * 86400 + seconds - offset)
+}
+
+/// `+0130` -> 5400 seconds
+pub fn parse_offset(zone: &str) -> Option<i64> {
+    let (sign, digits) = match zone.as_bytes().first()? {
+        b'+' => (1, &zone[1..]),
+        b'-' => (-1, &zone[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Days since 1970-01-01 for a proleptic gregorian date, from Howard Hinnant's algorithms.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`: (year, month, day).
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A moment as the clock read where it was recorded.
+struct Local {
+    year: i64,
+    month: i64,
+    day: i64,
+    /// into `DAYS`
+    weekday: usize,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+fn local(timestamp: i64, offset: &str) -> Local {
+    let local = timestamp + parse_offset(offset).unwrap_or(0);
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    Local {
+        year,
+        month,
+        day,
+        weekday: days.rem_euclid(7) as usize,
+        hour: secs / 3600,
+        minute: secs % 3600 / 60,
+        second: secs % 60,
+    }
+}
+
+/// Formats like git's default date format, eg `Thu Feb 6 21:03:44 2025 +0100`.
+pub fn format_default(timestamp: i64, offset: &str) -> String {
+    let t = local(timestamp, offset);
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        DAYS[t.weekday],
+        MONTHS[(t.month - 1) as usize],
+        t.day,
+        t.hour,
+        t.minute,
+        t.second,
+        t.year,
+        offset
+    )
+}
+
+/// `Thu, 6 Feb 2025 21:03:44 +0100`
+pub fn format_rfc2822(timestamp: i64, offset: &str) -> String {
+    let t = local(timestamp, offset);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}",
+        DAYS[t.weekday],
+        t.day,
+        MONTHS[(t.month - 1) as usize],
+        t.year,
+        t.hour,
+        t.minute,
+        t.second,
+        offset
+    )
+}
+
+/// `2025-02-06 21:03:44 +0100`, or with `strict` the ISO 8601 `2025-02-06T21:03:44+01:00`.
+pub fn format_iso(timestamp: i64, offset: &str, strict: bool) -> String {
+    let t = local(timestamp, offset);
+    let date = format_short(timestamp, offset);
+    let time = format!("{:02}:{:02}:{:02}", t.hour, t.minute, t.second);
+    if !strict {
+        return format!("{} {} {}", date, time, offset);
+    }
+    let zone = match offset.len() {
+        5 => format!("{}:{}", &offset[..3], &offset[3..]),
+        _ => offset.to_string(),
+    };
+    format!("{}T{}{}", date, time, zone)
+}
+
+/// `2025-02-06`
+pub fn format_short(timestamp: i64, offset: &str) -> String {
+    let t = local(timestamp, offset);
+    format!("{:04}-{:02}-{:02}", t.year, t.month, t.day)
+}
+
+/// How long before `now` it was, eg `3 days ago`, rounded as git rounds.
+pub fn format_relative(timestamp: i64, now: i64) -> String {
+    let units =
+        |count: i64, unit: &str| format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" });
+    let mut diff = now - timestamp;
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+    if diff < 90 {
+        return format!("{} ago", units(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", units(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", units(diff, "hour"));
+    }
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", units(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", units((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", units((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let total_months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", units(years, "year"), units(months, "month"));
+        }
+        return format!("{} ago", units(years, "year"));
+    }
+    format!("{} ago", units((diff + 183) / 365, "year"))
+}
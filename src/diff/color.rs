@@ -0,0 +1,454 @@
+//! Colouring patches: a colour for each kind of line, whitespace errors on added lines, and
+//! `--color-moved`, which tells lines a change moved from lines it added or took away.
+//!
+//! Colours go through `colored`, so whether any are written is its global override, which
+//! the commands set from `--color` and the `color.*` settings. Contents needn't be UTF-8, so
+//! the escapes `colored` puts around a string are put around the bytes instead.
+
+use anyhow::Result;
+use colored::{ColoredString, Colorize};
+use std::{collections::HashMap, io::IsTerminal, io::Write};
+
+use super::is_space;
+
+/// How to colour a piece of text.
+pub(crate) type Paint = fn(&str) -> ColoredString;
+
+pub(crate) const META: Paint = |s| s.bold();
+pub(crate) const FRAG: Paint = |s| s.cyan();
+pub(crate) const OLD: Paint = |s| s.red();
+pub(crate) const NEW: Paint = |s| s.green();
+const WHITESPACE: Paint = |s| s.on_red();
+const OLD_MOVED: Paint = |s| s.magenta().bold();
+const OLD_MOVED_ALTERNATIVE: Paint = |s| s.blue().bold();
+const OLD_MOVED_DIMMED: Paint = |s| s.dimmed();
+const OLD_MOVED_ALTERNATIVE_DIMMED: Paint = |s| s.dimmed().italic();
+const NEW_MOVED: Paint = |s| s.cyan().bold();
+const NEW_MOVED_ALTERNATIVE: Paint = |s| s.yellow().bold();
+const NEW_MOVED_DIMMED: Paint = |s| s.dimmed();
+const NEW_MOVED_ALTERNATIVE_DIMMED: Paint = |s| s.dimmed().italic();
+
+/// A moved block has to have at least this many letters and digits, so that moving a few
+/// braces or blank lines around isn't shown as a move.
+const MIN_ALNUM_COUNT: usize = 20;
+
+/// `--color=<when>`, and what the `color.diff` and `color.ui` settings say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum When {
+    Always,
+    Never,
+    Auto,
+}
+
+impl When {
+    /// The values `--color` takes.
+    pub fn parse(value: &str) -> Result<When> {
+        match value {
+            "always" => Ok(When::Always),
+            "never" => Ok(When::Never),
+            "auto" => Ok(When::Auto),
+            _ => anyhow::bail!(
+                "option `color' expects \"always\", \"auto\", or \"never\", not '{}'",
+                value
+            ),
+        }
+    }
+
+    /// A `color.*` setting, where any true value means `auto`.
+    pub fn from_config(value: &str) -> When {
+        match value.to_lowercase().as_str() {
+            "always" => When::Always,
+            "never" | "false" | "no" | "off" | "0" => When::Never,
+            _ => When::Auto,
+        }
+    }
+
+    /// Whether to colour, `auto` only colouring for a terminal that can show it.
+    pub fn enabled(self) -> bool {
+        match self {
+            When::Always => true,
+            When::Never => false,
+            When::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+        }
+    }
+}
+
+/// `--color-moved` and `diff.colorMoved`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ColorMoved {
+    #[default]
+    No,
+    /// every line that was also added or taken away somewhere else
+    Plain,
+    /// blocks of such lines, big enough to be a real move
+    Blocks,
+    /// blocks, told apart from the block next to them by alternating colours
+    Zebra,
+    /// zebra, dimming all but the first and last lines of each block
+    DimmedZebra,
+}
+
+impl ColorMoved {
+    /// A mode's name, or a boolean as the setting takes, true being the default mode.
+    pub fn parse(value: &str) -> Result<ColorMoved> {
+        match value.to_lowercase().as_str() {
+            "no" | "false" | "off" | "0" => Ok(ColorMoved::No),
+            "default" | "zebra" | "true" | "yes" | "on" | "1" => Ok(ColorMoved::Zebra),
+            "plain" => Ok(ColorMoved::Plain),
+            "blocks" => Ok(ColorMoved::Blocks),
+            "dimmed-zebra" | "dimmed_zebra" => Ok(ColorMoved::DimmedZebra),
+            _ => anyhow::bail!(
+                "color moved setting must be one of 'no', 'default', 'blocks', 'zebra', \
+                 'dimmed-zebra', 'plain'"
+            ),
+        }
+    }
+}
+
+/// Writes `text` in `paint`, if colours are on.
+pub(crate) fn write_painted(out: &mut impl Write, paint: Paint, text: &[u8]) -> Result<()> {
+    let painted = paint("\0").to_string();
+    let (start, end) = painted.split_once('\0').unwrap_or_default();
+    out.write_all(start.as_bytes())?;
+    out.write_all(text)?;
+    out.write_all(end.as_bytes())?;
+    Ok(())
+}
+
+/// Whether colours are being written at all.
+pub(crate) fn enabled() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// a line already written out in full
+    Text,
+    Context,
+    Old,
+    New,
+}
+
+/// Flags for lines `--color-moved` finds moved.
+const MOVED: u8 = 1;
+const ALTERNATIVE: u8 = 2;
+const UNINTERESTING: u8 = 4;
+
+/// A line of a patch, the sign left off a line of the file.
+struct Line {
+    kind: Kind,
+    text: Vec<u8>,
+    /// an added blank line at the end of the file, all of which is a whitespace error
+    blank_at_eof: bool,
+    moved: u8,
+}
+
+/// Writes the lines of patches in their colours. With moved lines to find, every line is held
+/// back until the whole diff is known, as a line may have moved to a file further on.
+pub(crate) struct Painter<'a, W: Write> {
+    out: &'a mut W,
+    moved: ColorMoved,
+    held: Vec<Line>,
+}
+
+impl<'a, W: Write> Painter<'a, W> {
+    pub fn new(out: &'a mut W, moved: ColorMoved) -> Painter<'a, W> {
+        let moved = if enabled() { moved } else { ColorMoved::No };
+        Painter {
+            out,
+            moved,
+            held: Vec::new(),
+        }
+    }
+
+    /// A header line: `diff --git`, the mode and `index` lines and so on.
+    pub fn meta(&mut self, line: &str) -> Result<()> {
+        let mut text = Vec::new();
+        write_painted(&mut text, META, line.as_bytes())?;
+        text.push(b'\n');
+        self.text(&text)
+    }
+
+    /// A hunk header, and the function line found for it.
+    pub fn frag(&mut self, header: &str, function: Option<&[u8]>) -> Result<()> {
+        let mut text = Vec::new();
+        write_painted(&mut text, FRAG, header.as_bytes())?;
+        if let Some(function) = function {
+            text.push(b' ');
+            text.extend_from_slice(function);
+        }
+        text.push(b'\n');
+        self.text(&text)
+    }
+
+    /// Output that is already as it should be.
+    pub fn text(&mut self, text: &[u8]) -> Result<()> {
+        self.line(Kind::Text, text, false)
+    }
+
+    pub fn context(&mut self, line: &[u8]) -> Result<()> {
+        self.line(Kind::Context, line, false)
+    }
+
+    pub fn old(&mut self, line: &[u8]) -> Result<()> {
+        self.line(Kind::Old, line, false)
+    }
+
+    pub fn new_line(&mut self, line: &[u8], blank_at_eof: bool) -> Result<()> {
+        self.line(Kind::New, line, blank_at_eof)
+    }
+
+    /// Writes out what was held back, with the moved lines found.
+    pub fn finish(mut self) -> Result<()> {
+        if self.moved == ColorMoved::No {
+            return Ok(());
+        }
+        mark_moved(&mut self.held, self.moved);
+        if self.moved == ColorMoved::DimmedZebra {
+            dim_moved(&mut self.held);
+        }
+        for line in &self.held {
+            write_line(self.out, line)?;
+        }
+        Ok(())
+    }
+
+    /// A line of the file that lacks a newline is followed by git's marker for that, which
+    /// ends a moved block like any other line.
+    fn line(&mut self, kind: Kind, text: &[u8], blank_at_eof: bool) -> Result<()> {
+        let incomplete = kind != Kind::Text && !text.ends_with(b"\n");
+        let line = Line {
+            kind,
+            text: text.to_vec(),
+            blank_at_eof,
+            moved: 0,
+        };
+        if self.moved == ColorMoved::No {
+            write_line(self.out, &line)?;
+        } else {
+            self.held.push(line);
+        }
+        if incomplete {
+            self.text(b"\n\\ No newline at end of file\n")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_line(out: &mut impl Write, line: &Line) -> Result<()> {
+    let text = line.text.strip_suffix(b"\n").unwrap_or(&line.text);
+    match line.kind {
+        Kind::Text => return Ok(out.write_all(&line.text)?),
+        Kind::Context => {
+            out.write_all(b" ")?;
+            out.write_all(text)?;
+        }
+        Kind::Old => {
+            let paint = match line.moved {
+                0 | ALTERNATIVE => OLD,
+                MOVED => OLD_MOVED,
+                flags if flags == MOVED | UNINTERESTING => OLD_MOVED_DIMMED,
+                flags if flags == MOVED | ALTERNATIVE => OLD_MOVED_ALTERNATIVE,
+                _ => OLD_MOVED_ALTERNATIVE_DIMMED,
+            };
+            write_painted(out, paint, &[b"-", text].concat())?;
+        }
+        Kind::New => {
+            let paint = match line.moved {
+                0 | ALTERNATIVE => NEW,
+                MOVED => NEW_MOVED,
+                flags if flags == MOVED | UNINTERESTING => NEW_MOVED_DIMMED,
+                flags if flags == MOVED | ALTERNATIVE => NEW_MOVED_ALTERNATIVE,
+                _ => NEW_MOVED_ALTERNATIVE_DIMMED,
+            };
+            if line.blank_at_eof {
+                write_painted(out, WHITESPACE, &[b"+", text].concat())?;
+            } else {
+                write_painted(out, paint, b"+")?;
+                write_whitespace_errors(out, text, paint)?;
+            }
+        }
+    }
+    if line.text.ends_with(b"\n") {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes an added line in `paint` but for the whitespace errors git looks for by default:
+/// spaces before a tab in the indent and whitespace at the end. What is left of the indent
+/// around such spaces isn't painted, as in git.
+fn write_whitespace_errors(out: &mut impl Write, text: &[u8], paint: Paint) -> Result<()> {
+    let trailing = text
+        .iter()
+        .rposition(|c| !is_space(*c))
+        .map_or(0, |i| i + 1);
+    let mut written = 0;
+    for (i, &c) in text[..trailing].iter().enumerate() {
+        if c == b' ' {
+            continue;
+        }
+        if c != b'\t' {
+            break;
+        }
+        if written < i {
+            write_painted(out, WHITESPACE, &text[written..i])?;
+            out.write_all(b"\t")?;
+        } else {
+            out.write_all(&text[written..=i])?;
+        }
+        written = i + 1;
+    }
+    if trailing > written {
+        write_painted(out, paint, &text[written..trailing])?;
+    }
+    if trailing < text.len() {
+        write_painted(out, WHITESPACE, &text[trailing..])?;
+    }
+    Ok(())
+}
+
+/// Finds the blocks of added lines that were taken away somewhere else in the same order, and
+/// the other way round, the way git's `mark_color_as_moved` does. Candidate blocks are
+/// followed a line at a time; when none can go on, the block so far is kept if it has enough
+/// to it, and otherwise looked at again from its second line.
+fn mark_moved(lines: &mut [Line], mode: ColorMoved) {
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    let id: Vec<usize> = lines
+        .iter()
+        .map(|line| {
+            let next = ids.len();
+            *ids.entry(&line.text).or_insert(next)
+        })
+        .collect();
+    let mut added: Vec<Vec<usize>> = vec![Vec::new(); ids.len()];
+    let mut deleted: Vec<Vec<usize>> = vec![Vec::new(); ids.len()];
+    for (n, line) in lines.iter().enumerate() {
+        match line.kind {
+            Kind::New => added[id[n]].push(n),
+            Kind::Old => deleted[id[n]].push(n),
+            _ => {}
+        }
+    }
+    let kinds: Vec<Kind> = lines.iter().map(|line| line.kind).collect();
+    let next_line = |n: usize| {
+        let same = n + 1 < kinds.len() && kinds[n + 1] == kinds[n];
+        same.then_some(n + 1)
+    };
+
+    // the lines each candidate block has got to
+    let mut blocks: Vec<usize> = Vec::new();
+    let mut block_length = 0;
+    let mut flipped = false;
+    let mut moved_kind = None;
+    let mut n = 0;
+    while n < lines.len() {
+        let kind = kinds[n];
+        let mut matches = match kind {
+            Kind::New => Some(&deleted[id[n]]),
+            Kind::Old => Some(&added[id[n]]),
+            _ => {
+                flipped = false;
+                None
+            }
+        }
+        .filter(|m| !m.is_empty());
+
+        if !blocks.is_empty() && (matches.is_none() || Some(kind) != moved_kind) {
+            if !adjust_last_block(lines, mode, n, block_length) && block_length > 1 {
+                // there may be another block starting at the block's second line
+                matches = None;
+                n -= block_length;
+            }
+            blocks.clear();
+            block_length = 0;
+            flipped = false;
+        }
+        let Some(matches) = matches else {
+            moved_kind = None;
+            n += 1;
+            continue;
+        };
+        if mode == ColorMoved::Plain {
+            lines[n].moved |= MOVED;
+            n += 1;
+            continue;
+        }
+
+        blocks.retain_mut(|at| match next_line(*at) {
+            Some(next) if id[next] == id[n] => {
+                *at = next;
+                true
+            }
+            _ => false,
+        });
+        if blocks.is_empty() {
+            let contiguous = adjust_last_block(lines, mode, n, block_length);
+            if !contiguous && block_length > 1 {
+                n -= block_length;
+            } else {
+                blocks.extend(matches);
+            }
+            flipped = contiguous && !blocks.is_empty() && moved_kind == Some(kind) && !flipped;
+            moved_kind = (!blocks.is_empty()).then_some(kind);
+            block_length = 0;
+        }
+        if !blocks.is_empty() {
+            block_length += 1;
+            lines[n].moved |= MOVED;
+            if flipped && mode != ColorMoved::Blocks {
+                lines[n].moved |= ALTERNATIVE;
+            }
+        }
+        n += 1;
+    }
+    adjust_last_block(lines, mode, n, block_length);
+}
+
+/// Whether the block of `length` lines before `end` is big enough to be a move, unmarking its
+/// lines if it isn't.
+fn adjust_last_block(lines: &mut [Line], mode: ColorMoved, end: usize, length: usize) -> bool {
+    if mode == ColorMoved::Plain {
+        return length > 0;
+    }
+    let block = &mut lines[end - length..end];
+    let alnum = block
+        .iter()
+        .flat_map(|line| &line.text)
+        .filter(|c| c.is_ascii_alphanumeric())
+        .count();
+    if alnum >= MIN_ALNUM_COUNT {
+        return true;
+    }
+    for line in block {
+        line.moved &= !MOVED;
+    }
+    false
+}
+
+/// Dims the moved lines that aren't at the edge of a block, so the edges stand out.
+fn dim_moved(lines: &mut [Line]) {
+    let is_change = |line: &Line| matches!(line.kind, Kind::Old | Kind::New);
+    let zebra = |line: &Line| line.moved & (MOVED | ALTERNATIVE);
+    for n in 0..lines.len() {
+        let line = &lines[n];
+        if !is_change(line) || line.moved & MOVED == 0 {
+            continue;
+        }
+        let previous = n.checked_sub(1).map(|p| &lines[p]).filter(|l| is_change(l));
+        let next = lines.get(n + 1).filter(|l| is_change(l));
+        let inside = previous.is_some_and(|p| zebra(p) == zebra(line))
+            && next.is_some_and(|n| zebra(n) == zebra(line));
+        let boundary = |other: Option<&Line>| {
+            other.is_some_and(|o| {
+                o.moved & MOVED != 0 && o.moved & ALTERNATIVE != line.moved & ALTERNATIVE
+            })
+        };
+        if inside || !(boundary(previous) || boundary(next)) {
+            lines[n].moved |= UNINTERESTING;
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Histogram diff, patience's cousin: instead of only lines unique on both sides it lines up
+//! around the longest run of matching lines whose rarest line is as rare as possible on the
+//! old side, then does the same on either side of that run. Lines too common to go by send it
+//! back to Myers.
+//!
+//! Line numbers here start at 1 as in git's `xhistogram.c`, so that 0 can mean none.
+
+use std::collections::HashMap;
+
+use super::{myers, Changes, LineDiff};
+
+/// Lines found more often than this on the old side aren't used to line things up.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+pub(crate) struct Histogram;
+
+impl LineDiff for Histogram {
+    fn diff(&self, old: &[u32], new: &[u32]) -> Changes {
+        let mut changes = Changes::none(old.len(), new.len());
+        diff_range(old, new, 1, old.len(), 1, new.len(), &mut changes);
+        changes
+    }
+}
+
+/// A run of matching lines, inclusive at both ends on each side.
+#[derive(Default)]
+struct Region {
+    begin1: usize,
+    end1: usize,
+    begin2: usize,
+    end2: usize,
+}
+
+fn diff_range(
+    old: &[u32],
+    new: &[u32],
+    mut line1: usize,
+    mut count1: usize,
+    mut line2: usize,
+    mut count2: usize,
+    changes: &mut Changes,
+) {
+    loop {
+        if count1 == 0 || count2 == 0 {
+            changes.mark(line1 - 1..line1 - 1 + count1, line2 - 1..line2 - 1 + count2);
+            return;
+        }
+        let Some(lcs) = find_lcs(old, new, line1, count1, line2, count2) else {
+            myers::diff_range(
+                old,
+                new,
+                line1 - 1..line1 - 1 + count1,
+                line2 - 1..line2 - 1 + count2,
+                changes,
+            );
+            return;
+        };
+        if lcs.begin1 == 0 && lcs.begin2 == 0 {
+            // nothing in common
+            changes.mark(line1 - 1..line1 - 1 + count1, line2 - 1..line2 - 1 + count2);
+            return;
+        }
+        diff_range(
+            old,
+            new,
+            line1,
+            lcs.begin1 - line1,
+            line2,
+            lcs.begin2 - line2,
+            changes,
+        );
+        let (end1, end2) = (line1 + count1 - 1, line2 + count2 - 1);
+        (line1, count1) = (lcs.end1 + 1, end1 - lcs.end1);
+        (line2, count2) = (lcs.end2 + 1, end2 - lcs.end2);
+    }
+}
+
+/// How often each line of the old range appears and where.
+struct Index<'a> {
+    old: &'a [u32],
+    new: &'a [u32],
+    line1: usize,
+    end1: usize,
+    line2: usize,
+    end2: usize,
+    /// for each distinct line, its first position and how many times it appears
+    records: Vec<(usize, usize)>,
+    by_line: HashMap<u32, usize>,
+    /// for each position, its record and the next position with the same line, 0 for none
+    record_at: Vec<usize>,
+    next_at: Vec<usize>,
+    /// the rarity of the best run so far, lower is better
+    count: usize,
+    has_common: bool,
+}
+
+impl Index<'_> {
+    fn occurrences(&self, ptr: usize) -> usize {
+        self.records[self.record_at[ptr - self.line1]].1
+    }
+
+    /// Grows runs of matching lines from each place the new side's line `b_ptr` appears on
+    /// the old side, keeping the best in `lcs`. Returns the next new line worth trying.
+    fn try_lcs(&mut self, lcs: &mut Region, b_ptr: usize) -> usize {
+        let mut b_next = b_ptr + 1;
+        let Some(&record) = self.by_line.get(&self.new[b_ptr - 1]) else {
+            return b_next;
+        };
+        let (first, occurrences) = self.records[record];
+        self.has_common = true;
+        if occurrences > self.count {
+            return b_next;
+        }
+
+        let mut a_start = first;
+        loop {
+            let next = self.next_at[a_start - self.line1];
+            let mut b_start = b_ptr;
+            let (mut a_end, mut b_end) = (a_start, b_start);
+            let mut rarest = occurrences;
+            while self.line1 < a_start
+                && self.line2 < b_start
+                && self.old[a_start - 2] == self.new[b_start - 2]
+            {
+                a_start -= 1;
+                b_start -= 1;
+                if rarest > 1 {
+                    rarest = rarest.min(self.occurrences(a_start));
+                }
+            }
+            while a_end < self.end1 && b_end < self.end2 && self.old[a_end] == self.new[b_end] {
+                a_end += 1;
+                b_end += 1;
+                if rarest > 1 {
+                    rarest = rarest.min(self.occurrences(a_end));
+                }
+            }
+
+            if b_next <= b_end {
+                b_next = b_end + 1;
+            }
+            if lcs.end1 - lcs.begin1 < a_end - a_start || rarest < self.count {
+                *lcs = Region {
+                    begin1: a_start,
+                    end1: a_end,
+                    begin2: b_start,
+                    end2: b_end,
+                };
+                self.count = rarest;
+            }
+
+            // the next place the line appears that this run didn't already take in
+            let mut next = next;
+            while next != 0 && next <= a_end {
+                next = self.next_at[next - self.line1];
+            }
+            if next == 0 {
+                return b_next;
+            }
+            a_start = next;
+        }
+    }
+}
+
+/// The run to split the ranges around: zeroes when they have nothing in common, `None` when
+/// the lines they do have in common are too common to go by.
+fn find_lcs(
+    old: &[u32],
+    new: &[u32],
+    line1: usize,
+    count1: usize,
+    line2: usize,
+    count2: usize,
+) -> Option<Region> {
+    let mut index = Index {
+        old,
+        new,
+        line1,
+        end1: line1 + count1 - 1,
+        line2,
+        end2: line2 + count2 - 1,
+        records: Vec::new(),
+        by_line: HashMap::new(),
+        record_at: vec![0; count1],
+        next_at: vec![0; count1],
+        count: MAX_CHAIN_LENGTH + 1,
+        has_common: false,
+    };
+    // from the end, so each record ends up at the line's first position
+    for ptr in (line1..=index.end1).rev() {
+        let at = ptr - line1;
+        match index.by_line.get(&old[ptr - 1]) {
+            Some(&record) => {
+                let (first, occurrences) = &mut index.records[record];
+                index.next_at[at] = *first;
+                *first = ptr;
+                *occurrences += 1;
+                index.record_at[at] = record;
+            }
+            None => {
+                index.by_line.insert(old[ptr - 1], index.records.len());
+                index.record_at[at] = index.records.len();
+                index.records.push((ptr, 1));
+            }
+        }
+    }
+
+    let mut lcs = Region::default();
+    let mut b_ptr = line2;
+    while b_ptr <= index.end2 {
+        b_ptr = index.try_lcs(&mut lcs, b_ptr);
+    }
+    if index.has_common && index.count > MAX_CHAIN_LENGTH {
+        return None;
+    }
+    Some(lcs)
+}
@@ -0,0 +1,187 @@
+//! git's indent heuristic: of the places a run of added or removed lines could be shown, pick
+//! the one whose edges fall where a reader would put them, scored by the indentation and blank
+//! lines around each edge. The weights are git's, tuned by hand against real code.
+
+/// Indentation beyond this counts as this much.
+const MAX_INDENT: isize = 200;
+/// Blank lines beyond this in a row aren't counted.
+const MAX_BLANKS: isize = 20;
+/// How far up from its lowest place a run is tried.
+const MAX_SLIDING: isize = 100;
+
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+/// How much more a difference in indentation weighs than the penalties.
+const INDENT_WEIGHT: isize = 60;
+
+/// Where the run of `size` lines that can slide from ending at `earliest_end` down to ending
+/// at `end` looks best ending.
+pub fn best_end(lines: &[&[u8]], end: usize, size: usize, earliest_end: usize) -> usize {
+    let (end, size) = (end as isize, size as isize);
+    let start = (earliest_end as isize)
+        .max(end - size - 1)
+        .max(end - MAX_SLIDING);
+    let mut best: Option<(isize, Score)> = None;
+    for shift in start..=end {
+        let mut score = Score::default();
+        score.add(&Split::measure(lines, shift));
+        score.add(&Split::measure(lines, shift - size));
+        // later places win ties
+        if best.as_ref().is_none_or(|(_, best)| score.cmp(best) <= 0) {
+            best = Some((shift, score));
+        }
+    }
+    best.map_or(end, |(shift, _)| shift) as usize
+}
+
+/// What is around the boundary just before line `split`.
+struct Split {
+    end_of_file: bool,
+    /// the indentation of the line after the boundary, -1 if it's blank
+    indent: isize,
+    pre_blank: isize,
+    /// the indentation of the nearest line above that isn't blank, -1 if there is none
+    pre_indent: isize,
+    post_blank: isize,
+    post_indent: isize,
+}
+
+impl Split {
+    fn measure(lines: &[&[u8]], split: isize) -> Split {
+        let n = lines.len() as isize;
+        let (end_of_file, indent) = if split >= n {
+            (true, -1)
+        } else {
+            (false, indent_of(lines[split as usize]))
+        };
+
+        let (mut pre_blank, mut pre_indent) = (0, -1);
+        let mut i = split - 1;
+        while i >= 0 {
+            pre_indent = indent_of(lines[i as usize]);
+            if pre_indent != -1 {
+                break;
+            }
+            pre_blank += 1;
+            if pre_blank == MAX_BLANKS {
+                pre_indent = 0;
+                break;
+            }
+            i -= 1;
+        }
+
+        let (mut post_blank, mut post_indent) = (0, -1);
+        let mut i = split + 1;
+        while i < n {
+            post_indent = indent_of(lines[i as usize]);
+            if post_indent != -1 {
+                break;
+            }
+            post_blank += 1;
+            if post_blank == MAX_BLANKS {
+                post_indent = 0;
+                break;
+            }
+            i += 1;
+        }
+
+        Split {
+            end_of_file,
+            indent,
+            pre_blank,
+            pre_indent,
+            post_blank,
+            post_indent,
+        }
+    }
+}
+
+/// The width of a line's leading whitespace with tabs to multiples of 8, or -1 for a blank line.
+fn indent_of(line: &[u8]) -> isize {
+    let mut width = 0;
+    for c in line {
+        match c {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            b'\n' | b'\r' => {}
+            _ => return width,
+        }
+        if width >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+#[derive(Default)]
+struct Score {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+impl Score {
+    fn add(&mut self, split: &Split) {
+        if split.pre_indent == -1 && split.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if split.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+
+        // blank lines after the boundary, counting the one right after it
+        let post_blank = if split.indent == -1 {
+            1 + split.post_blank
+        } else {
+            0
+        };
+        let total_blank = split.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if split.indent != -1 {
+            split.indent
+        } else {
+            split.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || split.pre_indent == -1 || indent == split.pre_indent {
+            return;
+        }
+        self.penalty += if indent > split.pre_indent {
+            if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            }
+        } else if split.post_indent != -1 && split.post_indent > indent {
+            // less indented than what's above but more is below, so likely a block's start
+            if any_blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            }
+        } else if any_blanks {
+            // likely a block's end
+            RELATIVE_DEDENT_WITH_BLANK_PENALTY
+        } else {
+            RELATIVE_DEDENT_PENALTY
+        };
+    }
+
+    /// Negative when `self` is the better score.
+    fn cmp(&self, other: &Score) -> isize {
+        let indents = (self.effective_indent > other.effective_indent) as isize
+            - (self.effective_indent < other.effective_indent) as isize;
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}
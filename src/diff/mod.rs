@@ -0,0 +1,336 @@
+//! Line diffs between two versions of a file, and writing them out as unified patches.
+//!
+//! Lines are compared whole, newline included, so a last line that lost its newline counts as
+//! changed. Which lines changed is worked out as a pair of flags per line, one array for each
+//! side, the way git's xdiff does; the groups of changed lines are then slid to where git
+//! would put them before the hunks are cut.
+
+pub(crate) mod color;
+mod histogram;
+mod indent;
+mod myers;
+pub(crate) mod pairs;
+mod patience;
+pub(crate) mod stat;
+pub(crate) mod unified;
+pub(crate) mod words;
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, io::Read, ops::Range};
+
+use crate::{config::Config, objects::object::Object, regex::Regex};
+use color::ColorMoved;
+use words::WordDiff;
+
+/// Lines of context around each change unless `-U` or `diff.context` say otherwise.
+const DEFAULT_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    /// unchanged lines shown around each change
+    pub context: usize,
+    pub algorithm: Algorithm,
+    /// slide changes to where the indentation around them suggests they begin and end
+    pub indent_heuristic: bool,
+    /// show changes word by word instead of line by line
+    pub word_diff: Option<WordDiff>,
+    /// what a word is, rather than a run of non-whitespace
+    pub word_regex: Option<Regex>,
+    /// colour moved lines apart from other changes
+    pub color_moved: ColorMoved,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            context: DEFAULT_CONTEXT,
+            algorithm: Algorithm::Myers,
+            indent_heuristic: true,
+            word_diff: None,
+            word_regex: None,
+            color_moved: ColorMoved::No,
+        }
+    }
+}
+
+impl Options {
+    /// The defaults as `diff.context`, `diff.algorithm`, `diff.indentHeuristic` and
+    /// `diff.wordRegex` change them.
+    pub fn from_config(config: &Config) -> Result<Options> {
+        let mut options = Options::default();
+        if let Some(context) = config.get("diff.context") {
+            options.context = context
+                .parse()
+                .with_context(|| format!("bad diff.context '{}'", context))?;
+        }
+        if let Some(algorithm) = config.get("diff.algorithm") {
+            options.algorithm = Algorithm::parse(algorithm)?;
+        }
+        if let Some(indent_heuristic) = config.get_bool("diff.indentheuristic") {
+            options.indent_heuristic = indent_heuristic;
+        }
+        if let Some(regex) = config.get("diff.wordregex") {
+            options.word_regex = Some(Regex::new(regex)?);
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    Myers,
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+impl Algorithm {
+    /// The names `--diff-algorithm` and `diff.algorithm` take.
+    pub fn parse(name: &str) -> Result<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "minimal" => Ok(Algorithm::Minimal),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => anyhow::bail!("unknown diff algorithm '{}'", name),
+        }
+    }
+
+    fn engine(self) -> &'static dyn LineDiff {
+        match self {
+            Algorithm::Myers => &myers::Myers { minimal: false },
+            Algorithm::Minimal => &myers::Myers { minimal: true },
+            Algorithm::Patience => &patience::Patience,
+            Algorithm::Histogram => &histogram::Histogram,
+        }
+    }
+}
+
+/// A way of working out which lines changed. Lines come as ids, equal for equal lines.
+pub(crate) trait LineDiff {
+    fn diff(&self, old: &[u32], new: &[u32]) -> Changes;
+}
+
+/// Which lines of each side aren't in the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Changes {
+    pub old: Vec<bool>,
+    pub new: Vec<bool>,
+}
+
+impl Changes {
+    fn none(old_len: usize, new_len: usize) -> Changes {
+        Changes {
+            old: vec![false; old_len],
+            new: vec![false; new_len],
+        }
+    }
+
+    fn mark(&mut self, old: Range<usize>, new: Range<usize>) {
+        self.old[old].fill(true);
+        self.new[new].fill(true);
+    }
+}
+
+/// A file's contents cut into lines, each with its newline if it has one.
+pub(crate) struct Lines<'a> {
+    pub lines: Vec<&'a [u8]>,
+    /// the same number for the same line, on either side
+    pub ids: Vec<u32>,
+}
+
+/// Cuts both sides into lines and numbers them so equal lines compare as equal numbers.
+pub fn lines<'a>(old: &'a [u8], new: &'a [u8]) -> (Lines<'a>, Lines<'a>) {
+    let cut = |data: &'a [u8]| data.split_inclusive(|b| *b == b'\n').collect();
+    number(cut(old), cut(new))
+}
+
+/// Numbers lines already cut, or anything else to be diffed like lines.
+pub fn number<'a>(old: Vec<&'a [u8]>, new: Vec<&'a [u8]>) -> (Lines<'a>, Lines<'a>) {
+    let mut ids: HashMap<&[u8], u32> = HashMap::new();
+    let mut number = |lines: Vec<&'a [u8]>| {
+        let ids = lines
+            .iter()
+            .map(|line| {
+                let next = ids.len() as u32;
+                *ids.entry(line).or_insert(next)
+            })
+            .collect();
+        Lines { lines, ids }
+    };
+    (number(old), number(new))
+}
+
+/// Works out which lines changed between `old` and `new`.
+pub fn diff(old: &Lines, new: &Lines, options: &Options) -> Changes {
+    let mut changes = options.algorithm.engine().diff(&old.ids, &new.ids);
+    compact(
+        old,
+        &mut changes.old,
+        &changes.new,
+        options.indent_heuristic,
+    );
+    compact(
+        new,
+        &mut changes.new,
+        &changes.old,
+        options.indent_heuristic,
+    );
+    changes
+}
+
+/// The whitespace diff and apply look at: spaces, tabs and line endings.
+pub(crate) fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Whether a line is nothing but whitespace, which is all an added blank line at the end of
+/// a file may have.
+pub(crate) fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(|&c| is_space(c))
+}
+
+/// Reads the contents of a blob.
+pub fn read_blob(hash: &str) -> Result<Vec<u8>> {
+    let mut object = Object::try_from(hash)?;
+    let mut contents = Vec::new();
+    object
+        .reader
+        .read_to_end(&mut contents)
+        .with_context(|| format!("reading blob {}", hash))?;
+    Ok(contents)
+}
+
+/// A run of changed lines on one side, `start..end`.
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Group {
+        let mut group = Group { start: 0, end: 0 };
+        group.extend_down(changed);
+        group
+    }
+
+    fn extend_down(&mut self, changed: &[bool]) {
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+    }
+
+    fn extend_up(&mut self, changed: &[bool]) {
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Moves on to the next run, which is empty where two unchanged lines are next to each
+    /// other. False at the end.
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        self.extend_down(changed);
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        self.extend_up(changed);
+        true
+    }
+
+    /// Moves the run down a line, which only changes the diff's look when the first line is
+    /// the same as the one after the run. It may run into the next group and take it in.
+    fn slide_down(&mut self, lines: &[u32], changed: &mut [bool]) -> bool {
+        if self.end == lines.len() || lines[self.start] != lines[self.end] {
+            return false;
+        }
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1;
+        self.extend_down(changed);
+        true
+    }
+
+    fn slide_up(&mut self, lines: &[u32], changed: &mut [bool]) -> bool {
+        if self.start == 0 || lines[self.start - 1] != lines[self.end - 1] {
+            return false;
+        }
+        self.start -= 1;
+        self.end -= 1;
+        changed[self.start] = true;
+        changed[self.end] = false;
+        self.extend_up(changed);
+        true
+    }
+}
+
+/// Slides each run of changed lines on one side as far down as it goes, merging runs that
+/// meet, then back up to line up with a change on the other side if it passed one, or with
+/// `indent_heuristic` to where the indentation around it looks best. This is git's
+/// `xdl_change_compact`, so that where a change could be shown in more than one place it is
+/// shown where git shows it.
+fn compact(side: &Lines, changed: &mut [bool], other: &[bool], indent_heuristic: bool) {
+    let lines = &side.ids[..];
+    let mut group = Group::first(changed);
+    let mut other_group = Group::first(other);
+    loop {
+        if !group.is_empty() {
+            let mut earliest_end;
+            let mut end_matching_other;
+            let mut size;
+            loop {
+                size = group.end - group.start;
+                end_matching_other = None;
+                while group.slide_up(lines, changed) {
+                    other_group.previous(other);
+                }
+                earliest_end = group.end;
+                if !other_group.is_empty() {
+                    end_matching_other = Some(group.end);
+                }
+                while group.slide_down(lines, changed) {
+                    other_group.next(other);
+                    if !other_group.is_empty() {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+            if group.end == earliest_end {
+                // it can't move
+            } else if end_matching_other.is_some() {
+                while other_group.is_empty() {
+                    group.slide_up(lines, changed);
+                    other_group.previous(other);
+                }
+            } else if indent_heuristic {
+                let best_end = indent::best_end(&side.lines, group.end, size, earliest_end);
+                while group.end > best_end {
+                    group.slide_up(lines, changed);
+                    other_group.previous(other);
+                }
+            }
+        }
+        if !group.next(changed) {
+            break;
+        }
+        other_group.next(other);
+    }
+}
@@ -0,0 +1,438 @@
+//! Myers' O(ND) difference algorithm, in linear space by splitting on the middle snake, the
+//! way git's xdiff does it. Lines only one side has are set aside as changes before the search
+//! starts, and so are common lines caught among them, which keeps the search small. Like git's,
+//! an expensive search settles for a good split instead of the best one, so the result isn't
+//! always the shortest possible.
+
+use std::ops::Range;
+
+use super::{Changes, LineDiff};
+
+/// Lines found on the other side this many times or more are candidates for setting aside.
+const MAX_EQUAL_LIMIT: usize = 1024;
+/// How far around a line to look when deciding whether to set it aside.
+const SIMILAR_SCAN_WINDOW: usize = 100;
+/// A common line is set aside when fewer than one in this many of the lines around it are
+/// common too.
+const KEEP_RUN: usize = 4;
+/// The cost at which the search starts looking for a good enough split instead of the best.
+const HEURISTIC_MIN_COST: isize = 256;
+/// Matching lines in a row that make a snake worth splitting on early.
+const SNAKE_COUNT: isize = 20;
+/// How far a snake has to get for every edit so far before it's taken early.
+const HEURISTIC_FACTOR: isize = 4;
+/// The least cost after which the search gives up on the best split.
+const MAX_COST_MIN: isize = 256;
+
+/// git's default algorithm, or with `minimal` the variant that always finds the shortest
+/// diff however long it takes.
+pub(crate) struct Myers {
+    pub minimal: bool,
+}
+
+impl LineDiff for Myers {
+    fn diff(&self, old: &[u32], new: &[u32]) -> Changes {
+        let mut changes = Changes::none(old.len(), new.len());
+
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_range = prefix..old.len() - suffix;
+        let new_range = prefix..new.len() - suffix;
+
+        let old_side = Side::set_aside(old, old_range, new, &mut changes.old);
+        let new_side = Side::set_aside(new, new_range, old, &mut changes.new);
+
+        let diagonals = old_side.lines.len() + new_side.lines.len() + 3;
+        let mut search = Search {
+            old: &old_side,
+            new: &new_side,
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: new_side.lines.len() as isize + 1,
+            max_cost: (bogo_sqrt(diagonals) as isize).max(MAX_COST_MIN),
+            changes: &mut changes,
+        };
+        search.compare(
+            0,
+            old_side.lines.len() as isize,
+            0,
+            new_side.lines.len() as isize,
+            self.minimal,
+        );
+        changes
+    }
+}
+
+/// Diffs part of each side with the default algorithm, for the others to fall back on where
+/// they find nothing to go by.
+pub fn diff_range(
+    old: &[u32],
+    new: &[u32],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    changes: &mut Changes,
+) {
+    let found = Myers { minimal: false }.diff(&old[old_range.clone()], &new[new_range.clone()]);
+    changes.old[old_range].copy_from_slice(&found.old);
+    changes.new[new_range].copy_from_slice(&found.new);
+}
+
+/// The lines of one side that take part in the search, and where each one was.
+struct Side {
+    lines: Vec<u32>,
+    index: Vec<usize>,
+}
+
+impl Side {
+    /// Keeps the lines of `range` worth searching, marking the rest of it changed: lines the
+    /// other side doesn't have, and lines it has too many of that sit among such lines.
+    fn set_aside(lines: &[u32], range: Range<usize>, other: &[u32], changed: &mut [bool]) -> Side {
+        let mut counts = std::collections::HashMap::new();
+        for line in other {
+            *counts.entry(*line).or_insert(0usize) += 1;
+        }
+        let limit = bogo_sqrt(lines.len()).min(MAX_EQUAL_LIMIT);
+        // 0 for no match on the other side, 1 for some and 2 for many
+        let matches: Vec<u8> = range
+            .clone()
+            .map(|i| match counts.get(&lines[i]).copied().unwrap_or(0) {
+                0 => 0,
+                n if n >= limit => 2,
+                _ => 1,
+            })
+            .collect();
+
+        let mut side = Side {
+            lines: Vec::new(),
+            index: Vec::new(),
+        };
+        for (at, i) in range.enumerate() {
+            let keep = matches[at] == 1 || (matches[at] == 2 && !among_unmatched(&matches, at));
+            if keep {
+                side.lines.push(lines[i]);
+                side.index.push(i);
+            } else {
+                changed[i] = true;
+            }
+        }
+        side
+    }
+}
+
+/// Whether a line with many matches is in the middle of lines with none, so it's most likely
+/// a blank line or a brace that happens to be between two changes and is best left out.
+fn among_unmatched(matches: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(SIMILAR_SCAN_WINDOW);
+    let end = (i + SIMILAR_SCAN_WINDOW).min(matches.len() - 1);
+    let scan = |lines: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut many) = (0, 1);
+        for j in lines {
+            match matches[j] {
+                0 => unmatched += 1,
+                2 => many += 1,
+                _ => break,
+            }
+        }
+        (unmatched, many)
+    };
+    let (unmatched_before, many_before) = scan(&mut (start..i).rev());
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (unmatched_after, many_after) = scan(&mut (i + 1..=end));
+    if unmatched_after == 0 {
+        return false;
+    }
+    let unmatched = unmatched_before + unmatched_after;
+    let many = many_before + many_after;
+    many * KEEP_RUN < many + unmatched
+}
+
+/// xdiff's rough square root, a power of two.
+fn bogo_sqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Where to cut a box in two, and whether each half needs its best diff or a good one will do.
+struct Split {
+    old: isize,
+    new: isize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+struct Search<'a> {
+    old: &'a Side,
+    new: &'a Side,
+    /// the furthest `old` line reached on each diagonal, searching from either end
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    /// diagonals go negative, down to minus the `new` lines
+    offset: isize,
+    max_cost: isize,
+    changes: &'a mut Changes,
+}
+
+impl Search<'_> {
+    fn compare(
+        &mut self,
+        mut old_start: isize,
+        mut old_end: isize,
+        mut new_start: isize,
+        mut new_end: isize,
+        minimal: bool,
+    ) {
+        let (a, b) = (&self.old.lines, &self.new.lines);
+        while old_start < old_end
+            && new_start < new_end
+            && a[old_start as usize] == b[new_start as usize]
+        {
+            old_start += 1;
+            new_start += 1;
+        }
+        while old_start < old_end
+            && new_start < new_end
+            && a[old_end as usize - 1] == b[new_end as usize - 1]
+        {
+            old_end -= 1;
+            new_end -= 1;
+        }
+
+        if old_start == old_end {
+            for i in new_start..new_end {
+                self.changes.new[self.new.index[i as usize]] = true;
+            }
+        } else if new_start == new_end {
+            for i in old_start..old_end {
+                self.changes.old[self.old.index[i as usize]] = true;
+            }
+        } else {
+            let split = self.split(old_start, old_end, new_start, new_end, minimal);
+            self.compare(
+                old_start,
+                split.old,
+                new_start,
+                split.new,
+                split.minimal_before,
+            );
+            self.compare(split.old, old_end, split.new, new_end, split.minimal_after);
+        }
+    }
+
+    /// Finds the middle of the shortest edit script through the box by searching from both
+    /// corners at once. Unless `minimal`, an expensive search settles for a long snake, or
+    /// after `max_cost` for whichever end got furthest.
+    fn split(
+        &mut self,
+        old_start: isize,
+        old_end: isize,
+        new_start: isize,
+        new_end: isize,
+        minimal: bool,
+    ) -> Split {
+        let (a, b) = (&self.old.lines, &self.new.lines);
+        let o = self.offset;
+        let at = |k: isize| (k + o) as usize;
+        let (fv, bv) = (&mut self.forward, &mut self.backward);
+
+        let (dmin, dmax) = (old_start - new_end, old_end - new_start);
+        let (fmid, bmid) = (old_start - new_start, old_end - new_end);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        fv[at(fmid)] = old_start;
+        bv[at(bmid)] = old_end;
+        let found = |old: isize, new: isize| Split {
+            old,
+            new,
+            minimal_before: true,
+            minimal_after: true,
+        };
+
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            // widen the diagonals by one, or narrow them where they would leave the box
+            if fmin > dmin {
+                fmin -= 1;
+                fv[at(fmin - 1)] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                fv[at(fmax + 1)] = -1;
+            } else {
+                fmax -= 1;
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = if fv[at(d - 1)] >= fv[at(d + 1)] {
+                    fv[at(d - 1)] + 1
+                } else {
+                    fv[at(d + 1)]
+                };
+                let before = i1;
+                let mut i2 = i1 - d;
+                while i1 < old_end && i2 < new_end && a[i1 as usize] == b[i2 as usize] {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                got_snake |= i1 - before > SNAKE_COUNT;
+                fv[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && bv[at(d)] <= i1 {
+                    return found(i1, i2);
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                bv[at(bmin - 1)] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                bv[at(bmax + 1)] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = if bv[at(d - 1)] < bv[at(d + 1)] {
+                    bv[at(d - 1)]
+                } else {
+                    bv[at(d + 1)] - 1
+                };
+                let before = i1;
+                let mut i2 = i1 - d;
+                while i1 > old_start && i2 > new_start && a[i1 as usize - 1] == b[i2 as usize - 1] {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                got_snake |= before - i1 > SNAKE_COUNT;
+                bv[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= fv[at(d)] {
+                    return found(i1, i2);
+                }
+            }
+
+            if minimal {
+                cost += 1;
+                continue;
+            }
+
+            // a long snake far enough along its diagonal is taken as the split
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best = 0;
+                let mut split = None;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let dd = (d - fmid).abs();
+                    let i1 = fv[at(d)];
+                    let i2 = i1 - d;
+                    let v = (i1 - old_start) + (i2 - new_start) - dd;
+                    if v > HEURISTIC_FACTOR * cost
+                        && v > best
+                        && old_start + SNAKE_COUNT <= i1
+                        && i1 < old_end
+                        && new_start + SNAKE_COUNT <= i2
+                        && i2 < new_end
+                        && (1..=SNAKE_COUNT).all(|k| a[(i1 - k) as usize] == b[(i2 - k) as usize])
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                }
+                if let Some((old, new)) = split {
+                    return Split {
+                        old,
+                        new,
+                        minimal_before: true,
+                        minimal_after: false,
+                    };
+                }
+
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let dd = (d - bmid).abs();
+                    let i1 = bv[at(d)];
+                    let i2 = i1 - d;
+                    let v = (old_end - i1) + (new_end - i2) - dd;
+                    if v > HEURISTIC_FACTOR * cost
+                        && v > best
+                        && old_start < i1
+                        && i1 <= old_end - SNAKE_COUNT
+                        && new_start < i2
+                        && i2 <= new_end - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| a[(i1 + k) as usize] == b[(i2 + k) as usize])
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                }
+                if let Some((old, new)) = split {
+                    return Split {
+                        old,
+                        new,
+                        minimal_before: false,
+                        minimal_after: true,
+                    };
+                }
+            }
+
+            // enough is enough: split where either search got furthest
+            if cost >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = fv[at(d)].min(old_end);
+                    let mut i2 = i1 - d;
+                    if new_end < i2 {
+                        i1 = new_end + d;
+                        i2 = new_end;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                }
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = bv[at(d)].max(old_start);
+                    let mut i2 = i1 - d;
+                    if i2 < new_start {
+                        i1 = new_start + d;
+                        i2 = new_start;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                }
+                return if (old_end + new_end) - bbest < fbest - (old_start + new_start) {
+                    Split {
+                        old: fbest1,
+                        new: fbest - fbest1,
+                        minimal_before: true,
+                        minimal_after: false,
+                    }
+                } else {
+                    Split {
+                        old: bbest1,
+                        new: bbest - bbest1,
+                        minimal_before: false,
+                        minimal_after: true,
+                    }
+                };
+            }
+            cost += 1;
+        }
+    }
+}
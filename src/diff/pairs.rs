@@ -0,0 +1,532 @@
+//! The files that differ between two trees, a tree and the index, or the index and the
+//! worktree, paired up by path the way git's diff queue holds them, and the ways of listing
+//! them: as patches, raw lines, names, or names with their status.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io::Write,
+};
+
+use super::{
+    color::Painter,
+    unified::{self, File, Similarity},
+    Options,
+};
+use crate::{
+    objects::{
+        index::{IndexEntry, IndexFile},
+        object::{self, Kind},
+        tree::Tree,
+    },
+    pathspec::Pathspec,
+    quote::quote_path,
+    refs, rename,
+    worktree::{self, EntryState},
+};
+
+/// A tree entry's mode, eg for a directory in a diff that doesn't recurse.
+const TREE_MODE: u32 = 0o40000;
+/// A gitlink, a commit in a submodule.
+const GITLINK_MODE: u32 = 0o160000;
+
+/// One side of a pair.
+#[derive(Debug, Clone)]
+pub(crate) struct Side {
+    pub path: String,
+    pub mode: u32,
+    /// the null hash for a file in the worktree, whose hash isn't worked out until needed
+    pub hash: String,
+    /// read from the worktree rather than the object store
+    pub in_worktree: bool,
+}
+
+impl Side {
+    pub fn object(path: String, mode: u32, hash: String) -> Side {
+        Side {
+            path,
+            mode,
+            hash,
+            in_worktree: false,
+        }
+    }
+
+    fn worktree(path: String, mode: u32) -> Side {
+        Side {
+            path,
+            mode,
+            hash: refs::ZERO_HASH.to_string(),
+            in_worktree: true,
+        }
+    }
+
+    /// What a side the path is missing from shows in raw output.
+    fn missing(path: String) -> Side {
+        Side::object(path, 0, refs::ZERO_HASH.to_string())
+    }
+}
+
+/// A path that differs, with what it was and what it became.
+#[derive(Debug, Clone)]
+pub(crate) struct FilePair {
+    /// `None` when the file was added
+    pub old: Option<Side>,
+    /// `None` when the file was deleted
+    pub new: Option<Side>,
+    /// the letter `--name-status` shows: `A`, `D`, `M`, `T`, `R`, `C` or `U`
+    pub status: char,
+    /// how alike the sides of an `R` or `C` are, out of `rename::MAX_SCORE`
+    pub score: u32,
+}
+
+impl FilePair {
+    fn new(old: Option<Side>, new: Option<Side>, status: char) -> FilePair {
+        FilePair {
+            old,
+            new,
+            status,
+            score: 0,
+        }
+    }
+
+    /// The path the pair is listed under, the new one for a rename.
+    pub fn path(&self) -> &str {
+        match (&self.old, &self.new) {
+            (_, Some(side)) | (Some(side), None) => &side.path,
+            (None, None) => "",
+        }
+    }
+}
+
+/// How pairs are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Patch,
+    /// `--raw`: modes, hashes and status
+    Raw,
+    NameOnly,
+    NameStatus,
+}
+
+/// The entries of a tree matched by `pathspec`, by path. Unless `recursive`, a subtree is an
+/// entry of its own rather than the files in it.
+pub fn tree_files(
+    tree: &str,
+    recursive: bool,
+    pathspec: &Pathspec,
+) -> Result<BTreeMap<String, Side>> {
+    let mut files = BTreeMap::new();
+    add_tree_files(tree, "", recursive, pathspec, &mut files)?;
+    Ok(files)
+}
+
+fn add_tree_files(
+    tree: &str,
+    prefix: &str,
+    recursive: bool,
+    pathspec: &Pathspec,
+    files: &mut BTreeMap<String, Side>,
+) -> Result<()> {
+    let tree = Tree::read_from_hash(tree).with_context(|| format!("reading tree {}", tree))?;
+    for entry in tree.entries {
+        let path = format!("{}{}", prefix, entry.filename);
+        if entry.kind() == Kind::Tree {
+            if !pathspec.matches_within(&path) {
+                continue;
+            }
+            if recursive {
+                add_tree_files(&entry.hash, &format!("{}/", path), true, pathspec, files)?;
+            } else {
+                files.insert(path.clone(), Side::object(path, TREE_MODE, entry.hash));
+            }
+        } else if pathspec.matches(&path) {
+            let mode = entry.octal_mode();
+            files.insert(path.clone(), Side::object(path, mode, entry.hash));
+        }
+    }
+    Ok(())
+}
+
+/// The merged entries of the index matched by `pathspec`, by path, and the paths with
+/// conflicts.
+pub fn index_files(
+    index: &IndexFile,
+    pathspec: &Pathspec,
+) -> Result<(BTreeMap<String, Side>, BTreeSet<String>)> {
+    let mut files = BTreeMap::new();
+    let mut unmerged = BTreeSet::new();
+    for entry in &index.entries {
+        let path = entry_path(entry)?;
+        if !pathspec.matches(&path) {
+            continue;
+        }
+        if entry.stage() != 0 {
+            unmerged.insert(path);
+        } else {
+            let side = Side::object(path.clone(), entry.mode(), entry.hash.clone());
+            files.insert(path, side);
+        }
+    }
+    Ok((files, unmerged))
+}
+
+fn entry_path(entry: &IndexEntry) -> Result<String> {
+    Ok(entry
+        .entry_path
+        .to_str()
+        .context("path must be valid utf-8")?
+        .to_string())
+}
+
+/// Pairs up the paths that differ between two listings.
+pub fn compare(mut old: BTreeMap<String, Side>, mut new: BTreeMap<String, Side>) -> Vec<FilePair> {
+    let paths: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+    let mut pairs = Vec::new();
+    for path in paths {
+        match (old.remove(&path), new.remove(&path)) {
+            (Some(a), Some(b)) if (a.mode == TREE_MODE) != (b.mode == TREE_MODE) => {
+                // a file and a directory have nothing to compare, one goes and the other comes
+                pairs.push(FilePair::new(Some(a), None, 'D'));
+                pairs.push(FilePair::new(None, Some(b), 'A'));
+            }
+            (Some(a), Some(b)) if a.mode >> 12 != b.mode >> 12 => {
+                pairs.push(FilePair::new(Some(a), Some(b), 'T'));
+            }
+            (Some(a), Some(b)) if a.mode != b.mode || a.hash != b.hash || b.in_worktree => {
+                pairs.push(FilePair::new(Some(a), Some(b), 'M'));
+            }
+            (Some(_), Some(_)) => {}
+            (Some(a), None) => pairs.push(FilePair::new(Some(a), None, 'D')),
+            (None, Some(b)) => pairs.push(FilePair::new(None, Some(b), 'A')),
+            (None, None) => unreachable!("every path comes from one side or the other"),
+        }
+    }
+    pairs
+}
+
+/// Two blobs compared on their own, as `diff <blob> <blob>` does: changed unless they are
+/// the same object with the same mode, whatever their paths.
+pub fn blobs(old: Side, new: Side) -> Vec<FilePair> {
+    let status = if old.mode >> 12 != new.mode >> 12 {
+        'T'
+    } else if old.mode != new.mode || old.hash != new.hash {
+        'M'
+    } else {
+        return Vec::new();
+    };
+    vec![FilePair::new(Some(old), Some(new), status)]
+}
+
+/// The index against a tree, as `diff --cached` and `diff-index --cached` see it. A path with
+/// conflicts is listed as `U` whatever the tree has.
+pub fn tree_to_index(
+    mut tree: BTreeMap<String, Side>,
+    index: &IndexFile,
+    pathspec: &Pathspec,
+) -> Result<Vec<FilePair>> {
+    let (mut files, unmerged) = index_files(index, pathspec)?;
+    let mut conflicts = Vec::new();
+    for path in unmerged {
+        let old = tree
+            .remove(&path)
+            .unwrap_or_else(|| Side::missing(path.clone()));
+        files.remove(&path);
+        conflicts.push(FilePair::new(Some(old), Some(Side::missing(path)), 'U'));
+    }
+    let mut pairs = compare(tree, files);
+    pairs.extend(conflicts);
+    sort(&mut pairs);
+    Ok(pairs)
+}
+
+/// The worktree against the index, as `diff` and `diff-files` see it. A path with conflicts
+/// is listed as `U`, followed by the worktree against the stage of our side.
+pub fn index_to_worktree(index: &IndexFile, pathspec: &Pathspec) -> Result<Vec<FilePair>> {
+    let mut pairs = Vec::new();
+    let mut unmerged_seen = HashSet::new();
+    let entries: Vec<_> = index
+        .entries
+        .iter()
+        .filter(|e| entry_path(e).is_ok_and(|path| pathspec.matches(&path)))
+        .collect();
+    for (i, &entry) in entries.iter().enumerate() {
+        let path = entry_path(entry)?;
+        if entry.stage() != 0 {
+            if !unmerged_seen.insert(path.clone()) {
+                continue;
+            }
+            let ours = entries[i..]
+                .iter()
+                .take_while(|e| e.entry_path == entry.entry_path)
+                .find(|e| e.stage() == 2)
+                .copied();
+            let state = worktree::check_entry(index, ours.unwrap_or(entry))?;
+            let worktree = worktree_side(&path, entry.mode(), &state);
+            let mode = worktree.as_ref().map_or(0, |w| w.mode);
+            pairs.push(FilePair::new(
+                Some(Side::missing(path.clone())),
+                Some(Side::object(
+                    path.clone(),
+                    mode,
+                    refs::ZERO_HASH.to_string(),
+                )),
+                'U',
+            ));
+            if let Some(ours) = ours {
+                let old = Side::object(path, ours.mode(), ours.hash.clone());
+                pairs.push(match worktree {
+                    Some(new) => FilePair::new(Some(old), Some(new), 'M'),
+                    None => FilePair::new(Some(old), None, 'D'),
+                });
+            }
+            continue;
+        }
+
+        let state = worktree::check_entry(index, entry)?;
+        if matches!(state, EntryState::Unchanged | EntryState::Stale(_)) {
+            continue;
+        }
+        let old = Side::object(path.clone(), entry.mode(), entry.hash.clone());
+        let new = worktree_side(&path, entry.mode(), &state);
+        let status = match (&state, &new) {
+            (_, None) => 'D',
+            (EntryState::TypeChanged(_), _) => 'T',
+            _ => 'M',
+        };
+        pairs.push(FilePair::new(Some(old), new, status));
+    }
+    Ok(pairs)
+}
+
+/// The worktree against a tree, as `diff <commit>` and `diff-index` see it. Files whose
+/// index entry matches the worktree are taken from the index, so only the ones that were
+/// changed since need reading.
+pub fn tree_to_worktree(
+    tree: BTreeMap<String, Side>,
+    index: &IndexFile,
+    pathspec: &Pathspec,
+) -> Result<Vec<FilePair>> {
+    let mut files = BTreeMap::new();
+    for entry in &index.entries {
+        let path = entry_path(entry)?;
+        if !pathspec.matches(&path) || files.contains_key(&path) {
+            continue;
+        }
+        let state = worktree::check_entry(index, entry)?;
+        let side = match state {
+            EntryState::Unchanged | EntryState::Stale(_) if entry.stage() == 0 => {
+                Some(Side::object(path.clone(), entry.mode(), entry.hash.clone()))
+            }
+            _ => worktree_side(&path, entry.mode(), &state),
+        };
+        if let Some(side) = side {
+            files.insert(path, side);
+        }
+    }
+    Ok(compare(tree, files))
+}
+
+/// The worktree file an index entry was checked against, `None` when it's gone.
+fn worktree_side(path: &str, mode: u32, state: &EntryState) -> Option<Side> {
+    let mode = match state {
+        EntryState::Deleted => return None,
+        EntryState::Unchanged => mode,
+        EntryState::Stale(current)
+        | EntryState::Modified(current)
+        | EntryState::TypeChanged(current) => current.mode(),
+    };
+    Some(Side::worktree(path.to_string(), mode))
+}
+
+/// Orders pairs by the path they are listed under, conflicts before the other entries for
+/// the same path.
+fn sort(pairs: &mut [FilePair]) {
+    pairs.sort_by(|a, b| {
+        a.path()
+            .cmp(b.path())
+            .then_with(|| (b.status == 'U').cmp(&(a.status == 'U')))
+    });
+}
+
+/// Turns added files that came from deleted ones into renames, and with copies those that
+/// came from changed ones into copies. A file from the worktree is hashed to be compared, and
+/// keeps its hash if it's paired.
+pub fn find_renames(pairs: &mut Vec<FilePair>, options: &rename::Options) -> Result<()> {
+    let (mut sources, mut source_at) = (Vec::new(), Vec::new());
+    let (mut targets, mut target_at) = (Vec::new(), Vec::new());
+    let as_file = |side: &Side| rename::File {
+        path: side.path.clone(),
+        mode: side.mode,
+        hash: side.hash.clone(),
+        in_worktree: side.in_worktree,
+    };
+    for (i, pair) in pairs.iter().enumerate() {
+        match (pair.status, &pair.old, &pair.new) {
+            ('A', None, Some(new)) => {
+                let mut file = as_file(new);
+                if new.in_worktree {
+                    file.hash = worktree::hash_file(&new.path)?;
+                }
+                targets.push(file);
+                target_at.push(i);
+            }
+            ('D' | 'M', Some(old), _) if !old.in_worktree => {
+                sources.push(rename::Source {
+                    file: as_file(old),
+                    deleted: pair.status == 'D',
+                });
+                source_at.push(i);
+            }
+            _ => {}
+        }
+    }
+    if sources.is_empty() || targets.is_empty() {
+        return Ok(());
+    }
+
+    let mut renamed = HashSet::new();
+    for found in rename::detect(&sources, &targets, options)? {
+        let old = pairs[source_at[found.source]].old.clone();
+        if !found.copy {
+            renamed.insert(source_at[found.source]);
+        }
+        let target = &mut pairs[target_at[found.target]];
+        target.status = if found.copy { 'C' } else { 'R' };
+        target.old = old;
+        target.score = found.score;
+        if let Some(new) = &mut target.new {
+            new.hash = targets[found.target].hash.clone();
+        }
+    }
+    let mut i = 0;
+    pairs.retain(|_| {
+        i += 1;
+        !renamed.contains(&(i - 1))
+    });
+    Ok(())
+}
+
+/// Lists `pairs` in `format`. Raw output shows hashes in full unless `abbreviate`, as the
+/// porcelain `diff` does.
+pub fn write(
+    out: &mut impl Write,
+    pairs: &[FilePair],
+    format: Format,
+    abbreviate: bool,
+    options: &Options,
+) -> Result<()> {
+    if format == Format::Patch {
+        // lines may move from one file to another, so the patches are painted together
+        let mut out = Painter::new(out, options.color_moved);
+        for pair in pairs {
+            write_pair_patch(&mut out, pair, options)?;
+        }
+        return out.finish();
+    }
+    for pair in pairs {
+        match format {
+            Format::Patch => unreachable!("patches are written above"),
+            Format::Raw => {
+                let old = pair.old.as_ref();
+                let new = pair.new.as_ref();
+                // a worktree file paired as a rename has a hash but no object to go by
+                let hash = |side: Option<&Side>| {
+                    let hash = side.map_or(refs::ZERO_HASH, |s| s.hash.as_str());
+                    match abbreviate {
+                        false => hash.to_string(),
+                        true => {
+                            object::abbreviate(hash, 7).unwrap_or_else(|_| hash[..7].to_string())
+                        }
+                    }
+                };
+                writeln!(
+                    out,
+                    ":{:06o} {:06o} {} {} {}",
+                    old.map_or(0, |s| s.mode),
+                    new.map_or(0, |s| s.mode),
+                    hash(old),
+                    hash(new),
+                    name_status(pair)
+                )?;
+            }
+            Format::NameOnly => writeln!(out, "{}", quote_path(pair.path()))?,
+            Format::NameStatus => writeln!(out, "{}", name_status(pair))?,
+        }
+    }
+    Ok(())
+}
+
+/// The status and path of a pair, with the score and both paths for a rename or copy.
+fn name_status(pair: &FilePair) -> String {
+    match (pair.status, &pair.old) {
+        ('R' | 'C', Some(old)) => format!(
+            "{}{:03}\t{}\t{}",
+            pair.status,
+            pair.score * 100 / rename::MAX_SCORE,
+            quote_path(&old.path),
+            quote_path(pair.path())
+        ),
+        // git goes by the old path, which only differs for two blobs compared on their own
+        (_, Some(old)) => format!("{}\t{}", pair.status, quote_path(&old.path)),
+        (_, None) => format!("{}\t{}", pair.status, quote_path(pair.path())),
+    }
+}
+
+fn write_pair_patch(
+    out: &mut Painter<impl Write>,
+    pair: &FilePair,
+    options: &Options,
+) -> Result<()> {
+    if pair.status == 'U' {
+        let line = format!("* Unmerged path {}\n", quote_path(pair.path()));
+        return out.text(line.as_bytes());
+    }
+    let old = pair.old.as_ref().map(load).transpose()?;
+    let new = pair.new.as_ref().map(load).transpose()?;
+    let old = old.as_ref().map(|(side, hash, contents)| File {
+        path: &side.path,
+        mode: side.mode,
+        hash,
+        contents,
+    });
+    let new = new.as_ref().map(|(side, hash, contents)| File {
+        path: &side.path,
+        mode: side.mode,
+        hash,
+        contents,
+    });
+    if pair.status == 'T' {
+        // a file that changed type is shown going and coming back
+        unified::write_patch(out, old.as_ref(), None, None, options)?;
+        return unified::write_patch(out, None, new.as_ref(), None, options);
+    }
+    let similarity = match pair.status {
+        'R' | 'C' => Some(Similarity {
+            score: pair.score,
+            copy: pair.status == 'C',
+        }),
+        _ => None,
+    };
+    unified::write_patch(
+        out,
+        old.as_ref(),
+        new.as_ref(),
+        similarity.as_ref(),
+        options,
+    )
+}
+
+/// A side with its hash and contents. A gitlink has no contents here, so git shows the commit
+/// it points at instead.
+pub(super) fn load(side: &Side) -> Result<(&Side, String, Vec<u8>)> {
+    if side.mode == GITLINK_MODE {
+        let contents = format!("Subproject commit {}\n", side.hash).into_bytes();
+        return Ok((side, side.hash.clone(), contents));
+    }
+    if side.in_worktree {
+        let contents = worktree::contents_for_index(&side.path)?;
+        let hash = worktree::blob_hash(contents.clone())?;
+        return Ok((side, hash, contents));
+    }
+    Ok((side, side.hash.clone(), super::read_blob(&side.hash)?))
+}
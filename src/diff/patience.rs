@@ -0,0 +1,149 @@
+//! Patience diff: lines that appear exactly once on each side are lined up first, taking the
+//! longest run of them that is in the same order on both sides, and the gaps between them are
+//! diffed the same way in turn. Where there are no such lines it falls back to Myers.
+
+use std::{collections::HashMap, ops::Range};
+
+use super::{myers, Changes, LineDiff};
+
+pub(crate) struct Patience;
+
+impl LineDiff for Patience {
+    fn diff(&self, old: &[u32], new: &[u32]) -> Changes {
+        let mut changes = Changes::none(old.len(), new.len());
+        diff_range(old, new, 0..old.len(), 0..new.len(), &mut changes);
+        changes
+    }
+}
+
+/// Where a line of the old range turned up in the new one.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Seen {
+    Nowhere,
+    Once(usize),
+    /// more than once on either side
+    Repeated,
+}
+
+struct Entry {
+    old: usize,
+    new: Seen,
+}
+
+fn diff_range(
+    old: &[u32],
+    new: &[u32],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    changes: &mut Changes,
+) {
+    if old_range.is_empty() || new_range.is_empty() {
+        changes.mark(old_range, new_range);
+        return;
+    }
+
+    // the lines of the old range in the order they first appear
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut by_line: HashMap<u32, usize> = HashMap::new();
+    for i in old_range.clone() {
+        match by_line.get(&old[i]) {
+            Some(&e) => entries[e].new = Seen::Repeated,
+            None => {
+                by_line.insert(old[i], entries.len());
+                entries.push(Entry {
+                    old: i,
+                    new: Seen::Nowhere,
+                });
+            }
+        }
+    }
+    let mut any_common = false;
+    for j in new_range.clone() {
+        if let Some(&e) = by_line.get(&new[j]) {
+            any_common = true;
+            entries[e].new = match entries[e].new {
+                Seen::Nowhere => Seen::Once(j),
+                _ => Seen::Repeated,
+            };
+        }
+    }
+    if !any_common {
+        changes.mark(old_range, new_range);
+        return;
+    }
+
+    let common = longest_common_unique(&entries);
+    if common.is_empty() {
+        myers::diff_range(old, new, old_range, new_range, changes);
+        return;
+    }
+
+    // diff the gaps between the unique lines, after taking in the lines either side of each
+    // gap that match anyway
+    let (mut i, mut j) = (old_range.start, new_range.start);
+    let mut next = 0;
+    loop {
+        let (mut gap_end_old, mut gap_end_new) = match common.get(next) {
+            Some(&(a, b)) => (a, b),
+            None => (old_range.end, new_range.end),
+        };
+        if next < common.len() {
+            while gap_end_old > i && gap_end_new > j && old[gap_end_old - 1] == new[gap_end_new - 1]
+            {
+                gap_end_old -= 1;
+                gap_end_new -= 1;
+            }
+        }
+        while i < gap_end_old && j < gap_end_new && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        }
+        if gap_end_old > i || gap_end_new > j {
+            diff_range(old, new, i..gap_end_old, j..gap_end_new, changes);
+        }
+        if next == common.len() {
+            return;
+        }
+        // unique lines next to each other on both sides leave no gap
+        while next + 1 < common.len()
+            && common[next + 1] == (common[next].0 + 1, common[next].1 + 1)
+        {
+            next += 1;
+        }
+        (i, j) = (common[next].0 + 1, common[next].1 + 1);
+        next += 1;
+    }
+}
+
+/// The longest run of lines unique on both sides whose order is the same on both, by patience
+/// sorting on their position in the new range.
+fn longest_common_unique(entries: &[Entry]) -> Vec<(usize, usize)> {
+    let unique: Vec<(usize, usize)> = entries
+        .iter()
+        .filter_map(|e| match e.new {
+            Seen::Once(j) => Some((e.old, j)),
+            _ => None,
+        })
+        .collect();
+    // the top of each pile, and for each line the top of the pile before it when it was placed
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; unique.len()];
+    for (k, &(_, j)) in unique.iter().enumerate() {
+        let pile = piles.partition_point(|&top| unique[top].1 < j);
+        previous[k] = pile.checked_sub(1).map(|p| piles[p]);
+        if pile == piles.len() {
+            piles.push(k);
+        } else {
+            piles[pile] = k;
+        }
+    }
+
+    let mut common = Vec::new();
+    let mut at = piles.last().copied();
+    while let Some(k) = at {
+        common.push(unique[k]);
+        at = previous[k];
+    }
+    common.reverse();
+    common
+}
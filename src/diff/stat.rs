@@ -0,0 +1,545 @@
+//! Summaries of what a diff changed: the `--stat` histogram, `--numstat`, `--shortstat` and
+//! `--dirstat`, laid out the way git's `diff.c` does.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+
+use super::{
+    color,
+    pairs::{self, FilePair},
+    Options,
+};
+use crate::{convert, quote::quote_path, rename};
+
+/// Width of the whole `--stat` line when not given and not writing to a terminal.
+const DEFAULT_WIDTH: usize = 80;
+/// What `--dirstat` leaves out below, in tenths of a percent.
+const DEFAULT_DIRSTAT_PERMILLE: u32 = 30;
+
+/// The widths `--stat` lays its lines out in, each worked out from the files when not given.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct StatWidths {
+    /// the whole line, the terminal's width by default
+    pub width: Option<usize>,
+    pub name_width: Option<usize>,
+    pub graph_width: Option<usize>,
+    /// list only this many files
+    pub count: Option<usize>,
+}
+
+impl StatWidths {
+    /// Parses `--stat=<width>[,<name-width>[,<count>]]`.
+    pub fn parse(value: &str) -> Result<StatWidths> {
+        let mut widths = StatWidths::default();
+        if value.is_empty() {
+            return Ok(widths);
+        }
+        let mut fields = value.split(',').map(|field| {
+            field
+                .parse()
+                .with_context(|| format!("invalid --stat value: {}", value))
+        });
+        widths.width = fields.next().transpose()?;
+        widths.name_width = fields.next().transpose()?;
+        widths.count = fields.next().transpose()?;
+        Ok(widths)
+    }
+}
+
+/// What `--dirstat` measures a change in a file by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DirstatMode {
+    /// bytes removed from or added to the file
+    Changes,
+    /// lines added and removed
+    Lines,
+    /// each changed file counts the same
+    Files,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Dirstat {
+    pub mode: DirstatMode,
+    /// count a directory's changes in its parents too, even once it's shown
+    pub cumulative: bool,
+    /// directories with less of the changes than this, in tenths of a percent, aren't shown
+    pub permille: u32,
+}
+
+impl Default for Dirstat {
+    fn default() -> Self {
+        Dirstat {
+            mode: DirstatMode::Changes,
+            cumulative: false,
+            permille: DEFAULT_DIRSTAT_PERMILLE,
+        }
+    }
+}
+
+impl Dirstat {
+    /// Applies comma separated `--dirstat` parameters, as in `lines,cumulative,10`. A limit
+    /// is a percentage with at most one decimal place counted.
+    pub fn parse(mut self, params: &str) -> Result<Dirstat> {
+        for param in params.split(',').filter(|p| !p.is_empty()) {
+            match param {
+                "changes" => self.mode = DirstatMode::Changes,
+                "lines" => self.mode = DirstatMode::Lines,
+                "files" => self.mode = DirstatMode::Files,
+                "cumulative" => self.cumulative = true,
+                "noncumulative" => self.cumulative = false,
+                _ => {
+                    let (whole, tenths) = param.split_once('.').unwrap_or((param, ""));
+                    let whole: u32 = whole
+                        .parse()
+                        .with_context(|| format!("unknown dirstat parameter '{}'", param))?;
+                    let tenth = match tenths.bytes().next() {
+                        Some(digit) if tenths.bytes().all(|b| b.is_ascii_digit()) => {
+                            (digit - b'0') as u32
+                        }
+                        None => 0,
+                        Some(_) => anyhow::bail!("unknown dirstat parameter '{}'", param),
+                    };
+                    self.permille = whole * 10 + tenth;
+                }
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// What one file pair changed.
+#[derive(Debug, Clone)]
+pub(crate) struct FileStat {
+    /// the path as listed, `old => new` for a rename or copy
+    pub name: String,
+    /// the path on the new side, for `--dirstat`
+    pub path: String,
+    /// lines, or for a binary file the size after
+    pub added: usize,
+    /// lines, or for a binary file the size before
+    pub deleted: usize,
+    pub binary: bool,
+    pub unmerged: bool,
+}
+
+/// Counts what each pair changed.
+pub fn collect(pairs: &[FilePair], options: &Options) -> Result<Vec<FileStat>> {
+    let mut stats = Vec::new();
+    for pair in pairs {
+        // two blobs compared on their own may have different paths too
+        let name = match &pair.old {
+            Some(old) if old.path != pair.path() => rename_name(&old.path, pair.path()),
+            _ => quote_path(pair.path()),
+        };
+        let mut stat = FileStat {
+            name,
+            path: pair.path().to_string(),
+            added: 0,
+            deleted: 0,
+            binary: false,
+            unmerged: pair.status == 'U',
+        };
+        if stat.unmerged {
+            stats.push(stat);
+            continue;
+        }
+        let old = pair.old.as_ref().map(pairs::load).transpose()?;
+        let new = pair.new.as_ref().map(pairs::load).transpose()?;
+        let old_contents = old.as_ref().map_or(&[][..], |(_, _, c)| c);
+        let new_contents = new.as_ref().map_or(&[][..], |(_, _, c)| c);
+        let old_hash = old.as_ref().map(|(_, h, _)| h);
+        let new_hash = new.as_ref().map(|(_, h, _)| h);
+        let same = old_hash == new_hash;
+        // a worktree file only touched since the index saw it hasn't changed at all
+        if same
+            && pair.status == 'M'
+            && pair.old.as_ref().map(|s| s.mode) == pair.new.as_ref().map(|s| s.mode)
+        {
+            continue;
+        }
+        stat.binary = convert::is_binary(old_contents) || convert::is_binary(new_contents);
+        if stat.binary {
+            if !same {
+                stat.deleted = old_contents.len();
+                stat.added = new_contents.len();
+            }
+        } else if !same {
+            let (old_lines, new_lines) = super::lines(old_contents, new_contents);
+            let changes = super::diff(&old_lines, &new_lines, options);
+            stat.deleted = changes.old.iter().filter(|c| **c).count();
+            stat.added = changes.new.iter().filter(|c| **c).count();
+        }
+        stats.push(stat);
+    }
+    Ok(stats)
+}
+
+/// How git shows a rename in a stat: the parts both paths share are written once, as in
+/// `dir/{old => new}/file`. Paths that need quoting are shown whole.
+fn rename_name(old: &str, new: &str) -> String {
+    if quote_path(old) != old || quote_path(new) != new {
+        return format!("{} => {}", quote_path(old), quote_path(new));
+    }
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+
+    // the common prefix, up to and including its last slash
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // the common suffix from a slash on, which may reach back into the prefix's slash
+    let mut suffix = 0;
+    let floor = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len() as isize, b.len() as isize);
+    while i >= floor as isize && j >= floor as isize {
+        // the strings' terminating NULs match too, as in git
+        let x = a.get(i as usize).copied().unwrap_or(0);
+        let y = b.get(j as usize).copied().unwrap_or(0);
+        if x != y {
+            break;
+        }
+        if x == b'/' {
+            suffix = a.len() - i as usize;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let (a_mid, b_mid) = (&old[prefix..prefix + a_mid], &new[prefix..prefix + b_mid]);
+    if prefix + suffix > 0 {
+        format!(
+            "{}{{{} => {}}}{}",
+            &old[..prefix],
+            a_mid,
+            b_mid,
+            &old[old.len() - suffix..]
+        )
+    } else {
+        format!("{} => {}", a_mid, b_mid)
+    }
+}
+
+/// `--numstat`: added and deleted lines and the path, tab separated, with `-` for the counts
+/// of a binary file.
+pub fn write_numstat(out: &mut impl Write, stats: &[FileStat]) -> Result<()> {
+    for stat in stats {
+        if stat.binary {
+            writeln!(out, "-\t-\t{}", stat.name)?;
+        } else {
+            writeln!(out, "{}\t{}\t{}", stat.added, stat.deleted, stat.name)?;
+        }
+    }
+    Ok(())
+}
+
+/// `--stat`: a line for each file with its count of changed lines and a histogram of them
+/// scaled to fit the width, then the totals.
+pub fn write_stat(out: &mut impl Write, stats: &[FileStat], widths: &StatWidths) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let count = widths.count.unwrap_or(stats.len()).min(stats.len());
+    let shown = &stats[..count];
+
+    let mut max_len = 0;
+    let mut max_change = 0;
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    for stat in shown {
+        max_len = max_len.max(text_width(&stat.name));
+        if stat.unmerged {
+            // "Unmerged"
+            bin_width = bin_width.max(8);
+        } else if stat.binary {
+            // "Bin XXX -> YYY bytes"
+            bin_width = bin_width.max(14 + decimal_width(stat.added) + decimal_width(stat.deleted));
+            number_width = 3;
+        } else {
+            max_change = max_change.max(stat.added + stat.deleted);
+        }
+    }
+
+    let mut width = widths.width.unwrap_or_else(terminal_width);
+    number_width = number_width.max(decimal_width(max_change));
+    // at least 6 columns of graph and 10 of name
+    width = width.max(16 + 6 + number_width);
+
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    if let Some(limit) = widths.graph_width.filter(|w| *w > 0) {
+        graph_width = graph_width.min(limit);
+    }
+    let mut name_width = match widths.name_width {
+        Some(w) if w > 0 && w < max_len => w,
+        _ => max_len,
+    };
+    if name_width + number_width + 6 + graph_width > width {
+        let room = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > room {
+            graph_width = room.max(6);
+        }
+        if let Some(limit) = widths.graph_width.filter(|w| *w > 0) {
+            graph_width = graph_width.min(limit);
+        }
+        let rest = width.saturating_sub(number_width + 6 + graph_width);
+        if name_width > rest {
+            name_width = rest;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    for stat in shown {
+        let (prefix, name) = fit_name(&stat.name, name_width);
+        let padding = name_width
+            .saturating_sub(prefix.len())
+            .saturating_sub(text_width(name));
+        write!(
+            out,
+            " {}{}{:padding$} | ",
+            prefix,
+            name,
+            "",
+            padding = padding
+        )?;
+        if stat.unmerged {
+            writeln!(out, "{:>number_width$}", "Unmerged")?;
+            continue;
+        }
+        if stat.binary {
+            write!(out, "{:>number_width$}", "Bin")?;
+            if stat.added != 0 || stat.deleted != 0 {
+                write!(out, " {} -> {} bytes", stat.deleted, stat.added)?;
+            }
+            writeln!(out)?;
+            continue;
+        }
+
+        let (mut add, mut del) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut total = scale_linear(add + del, graph_width, max_change);
+            if total < 2 && add != 0 && del != 0 {
+                total = 2;
+            }
+            if add < del {
+                add = scale_linear(add, graph_width, max_change);
+                del = total - add;
+            } else {
+                del = scale_linear(del, graph_width, max_change);
+                add = total - del;
+            }
+        }
+        let changed = stat.added + stat.deleted;
+        write!(out, "{:>number_width$}", changed)?;
+        if changed != 0 {
+            write!(out, " ")?;
+        }
+        // painted only where there is something to paint, as git does
+        for (count, sign, paint) in [(add, "+", color::NEW), (del, "-", color::OLD)] {
+            if count > 0 {
+                write!(out, "{}", paint(&sign.repeat(count)))?;
+            }
+        }
+        writeln!(out)?;
+    }
+    if count < stats.len() {
+        writeln!(out, " ...")?;
+    }
+    write_shortstat(out, stats)
+}
+
+/// `--shortstat`: only the totals line of `--stat`.
+pub fn write_shortstat(out: &mut impl Write, stats: &[FileStat]) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let files = stats.iter().filter(|s| !s.unmerged).count();
+    let (insertions, deletions) = stats
+        .iter()
+        .filter(|s| !s.unmerged && !s.binary)
+        .fold((0, 0), |(i, d), s| (i + s.added, d + s.deleted));
+    if files == 0 {
+        writeln!(out, " 0 files changed")?;
+        return Ok(());
+    }
+    write!(
+        out,
+        " {} {} changed",
+        files,
+        if files == 1 { "file" } else { "files" }
+    )?;
+    if insertions != 0 || deletions == 0 {
+        let noun = if insertions == 1 {
+            "insertion"
+        } else {
+            "insertions"
+        };
+        write!(out, ", {} {}(+)", insertions, noun)?;
+    }
+    if deletions != 0 || insertions == 0 {
+        let noun = if deletions == 1 {
+            "deletion"
+        } else {
+            "deletions"
+        };
+        write!(out, ", {} {}(-)", deletions, noun)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// `--dirstat`: each directory with at least the given share of the changes, by the share.
+/// Lines come from `stats`; changes and files are worked out from `pairs`.
+pub fn write_dirstat(
+    out: &mut impl Write,
+    pairs: &[FilePair],
+    stats: &[FileStat],
+    dirstat: &Dirstat,
+) -> Result<()> {
+    let mut damages: Vec<(String, usize)> = Vec::new();
+    match dirstat.mode {
+        DirstatMode::Lines => {
+            for stat in stats {
+                let mut damage = stat.added + stat.deleted;
+                if stat.binary {
+                    // bytes aren't lines, so git counts 64 of them as one
+                    damage = damage.div_ceil(64);
+                }
+                damages.push((stat.path.clone(), damage));
+            }
+        }
+        DirstatMode::Changes | DirstatMode::Files => {
+            for pair in pairs.iter().filter(|p| p.status != 'U') {
+                damages.push((pair.path().to_string(), damage(pair, dirstat.mode)?));
+            }
+        }
+    }
+    let changed: usize = damages.iter().map(|(_, d)| d).sum();
+    if changed == 0 {
+        return Ok(());
+    }
+    damages.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut files = &damages[..];
+    gather_dirstat(out, &mut files, changed, "", dirstat)?;
+    Ok(())
+}
+
+/// How much of a file changed for `--dirstat`: bytes removed plus bytes added, or one for
+/// any change when counting files. A change that moves no bytes still counts one.
+fn damage(pair: &FilePair, mode: DirstatMode) -> Result<usize> {
+    if let (Some(old), Some(new)) = (&pair.old, &pair.new) {
+        if !old.in_worktree && !new.in_worktree && old.hash == new.hash {
+            return Ok(0);
+        }
+    }
+    if mode == DirstatMode::Files {
+        return Ok(1);
+    }
+    let old = pair.old.as_ref().map(pairs::load).transpose()?;
+    let new = pair.new.as_ref().map(pairs::load).transpose()?;
+    let damage = match (&old, &new) {
+        (Some((_, old_hash, _)), Some((_, new_hash, _))) if old_hash == new_hash => return Ok(0),
+        (Some((_, _, old)), Some((_, _, new))) => {
+            let (copied, added) = rename::count_changes(old, new);
+            old.len() - copied.min(old.len()) + added
+        }
+        (Some((_, _, old)), None) => old.len(),
+        (None, Some((_, _, new))) => new.len(),
+        (None, None) => 0,
+    };
+    Ok(damage.max(1))
+}
+
+/// Adds up the damage of the files under `base`, taking them off the front of `files`, and
+/// writes the directory if it has enough of it. Like git, the top level isn't shown, nor is
+/// a directory whose changes all come from one directory below it.
+fn gather_dirstat(
+    out: &mut impl Write,
+    files: &mut &[(String, usize)],
+    changed: usize,
+    base: &str,
+    dirstat: &Dirstat,
+) -> Result<usize> {
+    let mut sum = 0;
+    let mut sources = 0;
+    while let Some((name, damage)) = files.first() {
+        let Some(rest) = name.strip_prefix(base) else {
+            break;
+        };
+        match rest.find('/') {
+            Some(slash) => {
+                let dir = name[..base.len() + slash + 1].to_string();
+                sum += gather_dirstat(out, files, changed, &dir, dirstat)?;
+                sources += 1;
+            }
+            None => {
+                sum += damage;
+                *files = &files[1..];
+                sources += 2;
+            }
+        }
+    }
+
+    if !base.is_empty() && sources != 1 && sum != 0 {
+        let permille = sum * 1000 / changed;
+        if permille >= dirstat.permille as usize {
+            writeln!(out, "{:4}.{}% {}", permille / 10, permille % 10, base)?;
+            if !dirstat.cumulative {
+                return Ok(0);
+            }
+        }
+    }
+    Ok(sum)
+}
+
+/// Shortens a name that doesn't fit `width` from the front, to `...` and the rest from a
+/// slash on if there is one.
+fn fit_name(name: &str, width: usize) -> (&'static str, &str) {
+    if text_width(name) <= width {
+        return ("", name);
+    }
+    let room = width.saturating_sub(3);
+    let skip = text_width(name) - room;
+    let start = name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i);
+    let rest = &name[start..];
+    match rest.find('/') {
+        Some(slash) => ("...", &rest[slash..]),
+        None => ("...", rest),
+    }
+}
+
+fn scale_linear(it: usize, width: usize, max_change: usize) -> usize {
+    if it == 0 {
+        return 0;
+    }
+    // at least one column for any change: scale as if one column shorter, then add it
+    1 + it * (width - 1) / max_change
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// The terminal's width from `COLUMNS`, or the default when there is none.
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|c| *c > 0)
+        .unwrap_or(DEFAULT_WIDTH)
+}
@@ -0,0 +1,354 @@
+//! The unified diff format: `diff --git` headers, then hunks of changed lines with context.
+
+use anyhow::Result;
+use std::io::Write;
+
+use super::{color::Painter, is_blank, words, Changes, Lines, Options};
+use crate::{convert, objects::object, quote::quote_path, refs, rename};
+
+/// Longest function name git puts after a hunk header.
+const FUNCTION_NAME_LEN: usize = 80;
+
+/// One side of a file pair.
+pub(crate) struct File<'a> {
+    pub path: &'a str,
+    pub mode: u32,
+    pub hash: &'a str,
+    pub contents: &'a [u8],
+}
+
+/// How alike the sides of a renamed or copied file are.
+pub(crate) struct Similarity {
+    /// out of `rename::MAX_SCORE`
+    pub score: u32,
+    pub copy: bool,
+}
+
+/// Writes the patch taking `old` to `new`, where `None` is a side the file doesn't exist on.
+/// Nothing is written when they are the same. A rename or copy has its `similarity`, and
+/// only shows hunks if the contents changed on the way.
+pub fn write_patch(
+    out: &mut Painter<impl Write>,
+    old: Option<&File>,
+    new: Option<&File>,
+    similarity: Option<&Similarity>,
+    options: &Options,
+) -> Result<()> {
+    let (old_path, new_path) = match (old, new) {
+        (Some(old), Some(new)) => (old.path, new.path),
+        (Some(file), None) | (None, Some(file)) => (file.path, file.path),
+        (None, None) => return Ok(()),
+    };
+    let old_hash = old.map_or(refs::ZERO_HASH, |f| f.hash);
+    let new_hash = new.map_or(refs::ZERO_HASH, |f| f.hash);
+    let old_mode = old.map_or(0, |f| f.mode);
+    let new_mode = new.map_or(0, |f| f.mode);
+    if old_hash == new_hash && old_mode == new_mode && old_path == new_path {
+        return Ok(());
+    }
+
+    let a = quote_path(&format!("a/{}", old_path));
+    let b = quote_path(&format!("b/{}", new_path));
+    out.meta(&format!("diff --git {} {}", a, b))?;
+    match (old, new) {
+        (None, Some(new)) => out.meta(&format!("new file mode {:06o}", new.mode))?,
+        (Some(old), None) => out.meta(&format!("deleted file mode {:06o}", old.mode))?,
+        _ if old_mode != new_mode => {
+            out.meta(&format!("old mode {:06o}", old_mode))?;
+            out.meta(&format!("new mode {:06o}", new_mode))?;
+        }
+        _ => {}
+    }
+    if let Some(similarity) = similarity {
+        let verb = if similarity.copy { "copy" } else { "rename" };
+        out.meta(&format!(
+            "similarity index {}%",
+            similarity.score * 100 / rename::MAX_SCORE
+        ))?;
+        out.meta(&format!("{} from {}", verb, quote_path(old_path)))?;
+        out.meta(&format!("{} to {}", verb, quote_path(new_path)))?;
+    }
+    if old_hash == new_hash {
+        return Ok(());
+    }
+    let (old_abbrev, new_abbrev) = abbreviate_pair(old_hash, new_hash);
+    if old_mode == new_mode {
+        out.meta(&format!(
+            "index {}..{} {:06o}",
+            old_abbrev, new_abbrev, new_mode
+        ))?;
+    } else {
+        out.meta(&format!("index {}..{}", old_abbrev, new_abbrev))?;
+    }
+
+    let old_contents = old.map_or(&[][..], |f| f.contents);
+    let new_contents = new.map_or(&[][..], |f| f.contents);
+    let old_name = if old.is_some() {
+        a
+    } else {
+        "/dev/null".to_string()
+    };
+    let new_name = if new.is_some() {
+        b
+    } else {
+        "/dev/null".to_string()
+    };
+    if convert::is_binary(old_contents) || convert::is_binary(new_contents) {
+        let line = format!("Binary files {} and {} differ\n", old_name, new_name);
+        return out.text(line.as_bytes());
+    }
+    // an empty file added or deleted has no lines to show
+    if old_contents == new_contents {
+        return Ok(());
+    }
+    out.meta(&format!("--- {}", old_name))?;
+    out.meta(&format!("+++ {}", new_name))?;
+    write_hunks(out, old_contents, new_contents, options)
+}
+
+/// Both hashes abbreviated to the same length, the way the `index` line shows them. The null
+/// hash of a missing side and hashes of worktree files not in the object store are cut short
+/// as they are.
+fn abbreviate_pair(old: &str, new: &str) -> (String, String) {
+    let len = [old, new]
+        .iter()
+        .filter(|h| **h != refs::ZERO_HASH)
+        .map(|h| object::abbreviate(h, 7).map_or(7, |a| a.len()))
+        .max()
+        .unwrap_or(7);
+    (old[..len].to_string(), new[..len].to_string())
+}
+
+/// Writes the hunks between two versions of a file.
+pub fn write_hunks(
+    out: &mut Painter<impl Write>,
+    old: &[u8],
+    new: &[u8],
+    options: &Options,
+) -> Result<()> {
+    let (old_lines, new_lines) = super::lines(old, new);
+    let changes = super::diff(&old_lines, &new_lines, options);
+    write_changes(out, &old_lines, &new_lines, &changes, options)
+}
+
+/// A run of changed lines: where it starts on each side and how many lines it covers there.
+pub(crate) struct Edit {
+    pub old: usize,
+    pub old_len: usize,
+    pub new: usize,
+    pub new_len: usize,
+}
+
+pub fn edits(changes: &Changes) -> Vec<Edit> {
+    let (old, new) = (&changes.old, &changes.new);
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        let changed = |c: &[bool], at: usize| at < c.len() && c[at];
+        if !changed(old, i) && !changed(new, j) {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (old_start, new_start) = (i, j);
+        while changed(old, i) {
+            i += 1;
+        }
+        while changed(new, j) {
+            j += 1;
+        }
+        edits.push(Edit {
+            old: old_start,
+            old_len: i - old_start,
+            new: new_start,
+            new_len: j - new_start,
+        });
+    }
+    edits
+}
+
+/// Writes the hunks for lines already diffed. Edits closer together than twice the context
+/// share a hunk.
+pub fn write_changes(
+    out: &mut Painter<impl Write>,
+    old: &Lines,
+    new: &Lines,
+    changes: &Changes,
+    options: &Options,
+) -> Result<()> {
+    let context = options.context;
+    let edits = edits(changes);
+    let blank_at_eof = blank_at_eof(old, new);
+    let mut functions = FunctionFinder::default();
+    let mut rest = &edits[..];
+    while let Some(first) = rest.first() {
+        let mut count = 1;
+        while count < rest.len() {
+            let previous = &rest[count - 1];
+            if rest[count].old - (previous.old + previous.old_len) > 2 * context {
+                break;
+            }
+            count += 1;
+        }
+        let (hunk, after) = rest.split_at(count);
+        rest = after;
+        let last = &hunk[count - 1];
+
+        let old_start = first.old.saturating_sub(context);
+        let new_start = first.new.saturating_sub(context);
+        let trailing = context
+            .min(old.lines.len() - (last.old + last.old_len))
+            .min(new.lines.len() - (last.new + last.new_len));
+        let old_end = last.old + last.old_len + trailing;
+        let new_end = last.new + last.new_len + trailing;
+
+        let (old_header, new_header) = (
+            header_start(old_start, old_end - old_start),
+            header_start(new_start, new_end - new_start),
+        );
+        let header = format!(
+            "@@ -{} +{} @@",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        );
+        out.frag(&header, functions.find(&old.lines, old_start))?;
+
+        let mut at = old_start;
+        for edit in hunk {
+            for line in &old.lines[at..edit.old] {
+                write_context(out, line, options)?;
+            }
+            let removed = &old.lines[edit.old..edit.old + edit.old_len];
+            let added = &new.lines[edit.new..edit.new + edit.new_len];
+            if let Some(mode) = options.word_diff {
+                // the marker for a missing newline has no place among words
+                let text = |lines: &[&[u8]]| {
+                    let mut text = Vec::new();
+                    for line in lines {
+                        text.extend_from_slice(line);
+                        if !line.ends_with(b"\n") {
+                            text.push(b'\n');
+                        }
+                    }
+                    text
+                };
+                words::write_change(out, mode, options, &text(removed), &text(added))?;
+            } else {
+                for line in removed {
+                    out.old(line)?;
+                }
+                // git's line numbers here run one ahead of the line, from the hunk header
+                let old_at = old_header + (edit.old + edit.old_len - old_start);
+                for (i, line) in added.iter().enumerate() {
+                    let new_at = new_header + (edit.new + i - new_start) + 1;
+                    let blank_at_eof = blank_at_eof.is_some_and(|(old_blank, new_blank)| {
+                        old_blank <= old_at && new_blank <= new_at && is_blank(line)
+                    });
+                    out.new_line(line, blank_at_eof)?;
+                }
+            }
+            at = edit.old + edit.old_len;
+        }
+        for line in &old.lines[at..old_end] {
+            write_context(out, line, options)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_context(out: &mut Painter<impl Write>, line: &[u8], options: &Options) -> Result<()> {
+    match options.word_diff {
+        Some(mode) => words::write_context(out, mode, line),
+        None => out.context(line),
+    }
+}
+
+/// Where the blank lines the new side added at its end start on each side, 1-based, if it
+/// added any. These are whitespace errors; blank lines the old side already ended with
+/// aren't.
+fn blank_at_eof(old: &Lines, new: &Lines) -> Option<(usize, usize)> {
+    // only worth looking into when the new side ends with a blank line
+    if !new.lines.last().is_some_and(|line| is_blank(line)) {
+        return None;
+    }
+    let old_blank = count_trailing_blank(&old.lines.concat());
+    let new_blank = count_trailing_blank(&new.lines.concat());
+    if new_blank <= old_blank {
+        return None;
+    }
+    Some((
+        old.lines.len() - old_blank + 1,
+        new.lines.len() - new_blank + 1,
+    ))
+}
+
+/// The blank lines at the end of a file, counted as git's `count_trailing_blank` counts them,
+/// which never gets as far as the first line.
+fn count_trailing_blank(data: &[u8]) -> usize {
+    let mut end = data.len() as isize - 1;
+    if data.last() == Some(&b'\n') {
+        end -= 1;
+    }
+    let mut count = 0;
+    while end > 0 {
+        let mut newline = end;
+        while newline >= 0 && data[newline as usize] != b'\n' {
+            newline -= 1;
+        }
+        if !is_blank(&data[(newline + 1) as usize..=end as usize]) {
+            break;
+        }
+        count += 1;
+        end = newline - 1;
+    }
+    count
+}
+
+/// A hunk header's range: the first line and the count, the count left out when it's one. An
+/// empty range gives the line before it.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// The line a hunk header's range starts at.
+fn header_start(start: usize, len: usize) -> usize {
+    if len == 0 {
+        start
+    } else {
+        start + 1
+    }
+}
+
+/// Finds the line git shows after a hunk header: the closest one above the hunk that starts
+/// with a letter, `_` or `$`. Hunks come in order, so each search stops where the last began.
+#[derive(Default)]
+struct FunctionFinder<'a> {
+    searched_to: usize,
+    found: Option<&'a [u8]>,
+}
+
+impl<'a> FunctionFinder<'a> {
+    fn find(&mut self, lines: &[&'a [u8]], hunk_start: usize) -> Option<&'a [u8]> {
+        let matched = lines[self.searched_to.min(hunk_start)..hunk_start]
+            .iter()
+            .rev()
+            .find(|line| {
+                line.first()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_' || *c == b'$')
+            });
+        if let Some(line) = matched {
+            let line = &line[..line.len().min(FUNCTION_NAME_LEN)];
+            let end = line
+                .iter()
+                .rposition(|c| !c.is_ascii_whitespace())
+                .map_or(0, |i| i + 1);
+            self.found = Some(&line[..end]);
+        }
+        self.searched_to = hunk_start;
+        self.found
+    }
+}
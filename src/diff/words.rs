@@ -0,0 +1,228 @@
+//! `--word-diff`: the lines a change took away and the lines it added, diffed word by word
+//! and shown as one text with the changed words marked.
+//!
+//! Words are runs of non-whitespace, or the matches of a word regex with whatever lies
+//! between them ignored. The words of each side are diffed as lines would be, then the new
+//! side is written out with the old words put in where they were taken away, as git's
+//! `diff_words_show` does.
+
+use anyhow::Result;
+use std::io::Write;
+
+use super::{
+    color::{self, Paint, Painter},
+    is_space, unified, Algorithm, Options,
+};
+use crate::regex::Regex;
+
+/// How changed words are marked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WordDiff {
+    /// `[-old-]{+new+}`
+    Plain,
+    /// only by colour
+    Color,
+    /// a line per piece, starting with its sign, and `~` for the file's newlines
+    Porcelain,
+}
+
+impl WordDiff {
+    /// The modes `--word-diff` takes. `none` turns it off.
+    pub fn parse(mode: &str) -> Result<Option<WordDiff>> {
+        match mode {
+            "plain" => Ok(Some(WordDiff::Plain)),
+            "color" => Ok(Some(WordDiff::Color)),
+            "porcelain" => Ok(Some(WordDiff::Porcelain)),
+            "none" => Ok(None),
+            _ => anyhow::bail!("bad --word-diff argument: {}", mode),
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            WordDiff::Plain => Style {
+                old: ("[-", "-]"),
+                new: ("{+", "+}"),
+                context: ("", ""),
+                newline: "\n",
+            },
+            WordDiff::Color => Style {
+                old: ("", ""),
+                new: ("", ""),
+                context: ("", ""),
+                newline: "\n",
+            },
+            WordDiff::Porcelain => Style {
+                old: ("-", "\n"),
+                new: ("+", "\n"),
+                context: (" ", "\n"),
+                newline: "~\n",
+            },
+        }
+    }
+}
+
+/// What goes around each kind of piece, and what a newline in the text is written as.
+struct Style {
+    old: (&'static str, &'static str),
+    new: (&'static str, &'static str),
+    context: (&'static str, &'static str),
+    newline: &'static str,
+}
+
+/// Writes an unchanged line of a hunk.
+pub fn write_context(out: &mut Painter<impl Write>, mode: WordDiff, line: &[u8]) -> Result<()> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let mut text = Vec::new();
+    if mode == WordDiff::Porcelain {
+        text.push(b' ');
+    }
+    text.extend_from_slice(line);
+    text.push(b'\n');
+    if mode == WordDiff::Porcelain {
+        text.extend_from_slice(b"~\n");
+    }
+    out.text(&text)
+}
+
+/// Writes the lines taken away, `old`, and the lines added in their place, `new`, as one
+/// text with the words that changed marked.
+pub fn write_change(
+    out: &mut Painter<impl Write>,
+    mode: WordDiff,
+    options: &Options,
+    old: &[u8],
+    new: &[u8],
+) -> Result<()> {
+    let style = mode.style();
+    let mut text = Vec::new();
+    if new.is_empty() {
+        write_piece(&mut text, &style, style.old, Some(color::OLD), old)?;
+        return out.text(&text);
+    }
+
+    let regex = options.word_regex.as_ref();
+    let (old_words, new_words) = (words(old, regex), words(new, regex));
+    let (old_lines, new_lines) = super::number(cut(old, &old_words), cut(new, &new_words));
+    let word_options = Options {
+        algorithm: Algorithm::Myers,
+        indent_heuristic: false,
+        ..options.clone()
+    };
+    let changes = super::diff(&old_lines, &new_lines, &word_options);
+
+    // where each run starts and ends in the text, an empty run being placed after the word
+    // before it
+    let span = |words: &[(usize, usize)], first: usize, len: usize| match (len, first) {
+        (0, 0) => (0, 0),
+        (0, _) => (words[first - 1].1, words[first - 1].1),
+        _ => (words[first].0, words[first + len - 1].1),
+    };
+    let mut written = 0;
+    for edit in unified::edits(&changes) {
+        let (old_start, old_end) = span(&old_words, edit.old, edit.old_len);
+        let (new_start, new_end) = span(&new_words, edit.new, edit.new_len);
+        if written != new_start {
+            write_piece(
+                &mut text,
+                &style,
+                style.context,
+                None,
+                &new[written..new_start],
+            )?;
+        }
+        if old_start != old_end {
+            write_piece(
+                &mut text,
+                &style,
+                style.old,
+                Some(color::OLD),
+                &old[old_start..old_end],
+            )?;
+        }
+        if new_start != new_end {
+            write_piece(
+                &mut text,
+                &style,
+                style.new,
+                Some(color::NEW),
+                &new[new_start..new_end],
+            )?;
+        }
+        written = new_end;
+    }
+    if written != new.len() {
+        write_piece(&mut text, &style, style.context, None, &new[written..])?;
+    }
+    out.text(&text)
+}
+
+/// Writes a piece of text a line at a time, each part marked and painted, and each newline
+/// as the style has it.
+fn write_piece(
+    out: &mut Vec<u8>,
+    style: &Style,
+    (prefix, suffix): (&str, &str),
+    paint: Option<Paint>,
+    text: &[u8],
+) -> Result<()> {
+    let mut parts = text.split(|&c| c == b'\n').peekable();
+    while let Some(part) = parts.next() {
+        if !part.is_empty() {
+            let marked = [prefix.as_bytes(), part, suffix.as_bytes()].concat();
+            match paint {
+                Some(paint) => color::write_painted(out, paint, &marked)?,
+                None => out.extend_from_slice(&marked),
+            }
+        }
+        if parts.peek().is_some() {
+            out.extend_from_slice(style.newline.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+fn cut<'a>(text: &'a [u8], words: &[(usize, usize)]) -> Vec<&'a [u8]> {
+    words
+        .iter()
+        .map(|&(start, end)| &text[start..end])
+        .collect()
+}
+
+/// Where the words of `text` start and end. A regex match is cut short at a newline, and
+/// the words end at the first empty match.
+fn words(text: &[u8], regex: Option<&Regex>) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut at = 0;
+    while at < text.len() {
+        let (start, end) = match regex {
+            Some(regex) => {
+                let Some((start, end)) = regex.find(&text[at..]) else {
+                    break;
+                };
+                let (start, mut end) = (at + start, at + end);
+                if let Some(newline) = text[start..end].iter().position(|&c| c == b'\n') {
+                    end = start + newline;
+                }
+                if start >= end {
+                    break;
+                }
+                (start, end)
+            }
+            None => {
+                let Some(start) = text[at..].iter().position(|c| !is_space(*c)) else {
+                    break;
+                };
+                let start = at + start;
+                let end = text[start..]
+                    .iter()
+                    .position(|c| is_space(*c))
+                    .map_or(text.len(), |len| start + len);
+                (start, end)
+            }
+        };
+        words.push((start, end));
+        at = end;
+    }
+    words
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use std::{
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config::Config, objects::index::IndexFile};
+
+/// What the `core.fsmonitor` hook said changed since the index's token.
+enum Changes {
+    Paths(Vec<String>),
+    /// the hook couldn't tell, so anything may have
+    Everything,
+}
+
+/// Asks the `core.fsmonitor` hook what changed since the index was last written and clears
+/// the valid bit of those entries, and the untracked cache of those directories, so they get
+/// looked at. The index gets the hook's new token. Returns whether the hook could account for
+/// everything, in which case what wasn't reported can be trusted without a stat.
+///
+/// Without a hook configured the fsmonitor data is dropped from the index.
+pub fn refresh(index: &mut IndexFile) -> Result<bool> {
+    let config = Config::load()?;
+    let Some(hook) = hook(&config) else {
+        index.fsmonitor_token = None;
+        for entry in &mut index.entries {
+            entry.fsmonitor_valid = false;
+        }
+        return Ok(false);
+    };
+
+    let (token, changes) = match &index.fsmonitor_token {
+        Some(token) => query(&hook, token).unwrap_or_else(|_| (now(), Changes::Everything)),
+        // nothing to ask about yet, the token starts off as the time like git's
+        None => (now(), Changes::Everything),
+    };
+    index.fsmonitor_token = Some(token);
+    let Changes::Paths(paths) = changes else {
+        for entry in &mut index.entries {
+            entry.fsmonitor_valid = false;
+        }
+        return Ok(false);
+    };
+
+    for path in &paths {
+        let path = path.trim_end_matches('/');
+        let below = format!("{}/", path);
+        for entry in &mut index.entries {
+            let entry_path = entry.entry_path.to_str().unwrap_or_default();
+            if entry_path == path || entry_path.starts_with(&below) {
+                entry.fsmonitor_valid = false;
+            }
+        }
+        if let Some(cache) = &mut index.untracked_cache {
+            // the path may be a file or a directory, whose own listing changed as well
+            cache.invalidate_path(path);
+            cache.invalidate_path(&below);
+        }
+    }
+    Ok(true)
+}
+
+/// Marks the entries that were found to match the worktree, so the next `refresh` can skip
+/// them unless the hook reports them.
+pub fn mark_clean(index: &mut IndexFile, clean: &[bool]) {
+    let active = index.fsmonitor_token.is_some();
+    for (entry, clean) in index.entries.iter_mut().zip(clean) {
+        entry.fsmonitor_valid = active && *clean && entry.stage() == 0;
+    }
+}
+
+/// The hook command, unless `core.fsmonitor` is unset or a boolean asking for git's own
+/// daemon, which we don't have.
+fn hook(config: &Config) -> Option<String> {
+    let value = config.get("core.fsmonitor")?;
+    let boolean = matches!(
+        value.to_lowercase().as_str(),
+        "true" | "false" | "yes" | "no" | "on" | "off" | "1" | "0" | ""
+    );
+    if boolean {
+        return None;
+    }
+    config
+        .get_path("core.fsmonitor")
+        .map(|path| path.display().to_string())
+}
+
+/// Runs the hook with protocol version 2: it is given the token and prints a new one, then
+/// the changed paths, all NUL terminated. A path of `/` means everything changed.
+fn query(hook: &str, token: &str) -> Result<(String, Changes)> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", hook))
+        .arg(hook)
+        .arg("2")
+        .arg(token)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("running fsmonitor hook {}", hook))?;
+    anyhow::ensure!(output.status.success(), "fsmonitor hook {} failed", hook);
+
+    let mut fields = output
+        .stdout
+        .split(|b| *b == 0)
+        .map(|f| String::from_utf8_lossy(f).to_string());
+    let token = fields
+        .next()
+        .filter(|t| !t.is_empty())
+        .context("fsmonitor hook gave no token")?;
+    let paths: Vec<String> = fields.filter(|p| !p.is_empty()).collect();
+    if paths.iter().any(|p| p == "/") {
+        return Ok((token, Changes::Everything));
+    }
+    Ok((token, Changes::Paths(paths)))
+}
+
+fn now() -> String {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
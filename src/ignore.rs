@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, wildmatch::wildmatch};
+
+/// One line of an ignore file.
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern {
+    /// the pattern with `!`, the leading `/` and the trailing `/` taken off
+    pattern: String,
+    /// `!pattern`, re-including what an earlier pattern excluded
+    negated: bool,
+    /// only matches directories, from a trailing `/`
+    dir_only: bool,
+    /// matched against the whole path rather than just the file name, from a `/` anywhere
+    /// but at the end
+    anchored: bool,
+    /// the directory of the `.gitignore` the pattern is in, relative to the top and with a
+    /// trailing slash, empty for patterns that apply everywhere
+    base: String,
+    /// where the pattern came from and the line as written, for `check-ignore -v`
+    pub source: String,
+    pub line_number: usize,
+    pub text: String,
+}
+
+impl Pattern {
+    pub fn parse(line: &str) -> Option<Pattern> {
+        let text = line.trim_end_matches(['\n', '\r']);
+        // trailing spaces are ignored unless escaped
+        let mut line = text;
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        Some(Pattern {
+            pattern: line.trim_start_matches('/').to_string(),
+            negated,
+            dir_only,
+            anchored,
+            base: String::new(),
+            source: String::new(),
+            line_number: 0,
+            text: text.to_string(),
+        })
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.anchored {
+            wildmatch(&self.pattern, relative)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.pattern, name)
+        }
+    }
+}
+
+/// The exclude rules for a worktree, checked the way git does: patterns given on the command
+/// line first, then the `.gitignore` files from the deepest directory up, then
+/// `.git/info/exclude`, then `core.excludesFile`. Within each the last matching line wins.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Ignore {
+    command_line: Vec<Pattern>,
+    /// patterns from `.gitignore` files, parents always before their subdirectories
+    per_directory: Vec<Pattern>,
+    global: Vec<Pattern>,
+    /// whether to pick up `.gitignore` files as directories are entered
+    use_gitignore: bool,
+    loaded_dirs: Vec<String>,
+}
+
+impl Ignore {
+    /// What `--exclude-standard` means, and what `status` and `add` use.
+    pub fn standard() -> Result<Ignore> {
+        let mut ignore = Ignore {
+            use_gitignore: true,
+            ..Ignore::default()
+        };
+        if let Some(path) = excludes_file()? {
+            let source = path.display().to_string();
+            ignore.global.extend(read_patterns(&path, "", &source)?);
+        }
+        ignore.global.extend(read_patterns(
+            Path::new(".git/info/exclude"),
+            "",
+            ".git/info/exclude",
+        )?);
+        ignore.load_dir("")?;
+        Ok(ignore)
+    }
+
+    pub fn add_pattern(&mut self, line: &str) {
+        self.command_line.extend(Pattern::parse(line));
+    }
+
+    /// Reads `<dir>/.gitignore` if it hasn't been already. `dir` is relative to the top and
+    /// either empty or ends with a slash.
+    pub fn load_dir(&mut self, dir: &str) -> Result<()> {
+        if !self.use_gitignore || self.loaded_dirs.iter().any(|d| d == dir) {
+            return Ok(());
+        }
+        self.loaded_dirs.push(dir.to_string());
+        let file = format!("{}.gitignore", dir);
+        self.per_directory
+            .extend(read_patterns(Path::new(&file), dir, &file)?);
+        Ok(())
+    }
+
+    /// Loads the `.gitignore` of every directory above `path`, for checking a path without
+    /// walking down to it.
+    pub fn load_parents(&mut self, path: &str) -> Result<()> {
+        let mut end = 0;
+        while let Some(slash) = path[end..].find('/') {
+            end += slash + 1;
+            self.load_dir(&path[..end])?;
+        }
+        Ok(())
+    }
+
+    /// The pattern that decides `path`, which may be a negated one that re-includes it.
+    pub fn matching(&self, path: &str, is_dir: bool) -> Option<&Pattern> {
+        self.command_line
+            .iter()
+            .rev()
+            .chain(self.per_directory.iter().rev())
+            .chain(self.global.iter().rev())
+            .find(|p| p.matches(path, is_dir))
+    }
+
+    /// Whether `path` (relative to the top, without a trailing slash) is ignored, looking only
+    /// at the path itself and not the directories above it.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.matching(path, is_dir).is_some_and(|p| !p.negated)
+    }
+
+    /// Like `is_ignored` but also true when a directory above `path` is ignored, since nothing
+    /// can be re-included from inside an excluded directory. Returns the deciding pattern.
+    pub fn check_path(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>> {
+        self.load_parents(path)?;
+        let mut end = 0;
+        while let Some(slash) = path[end..].find('/') {
+            end += slash;
+            if let Some(pattern) = self.matching(&path[..end], true) {
+                if !pattern.negated {
+                    return Ok(Some(pattern.clone()));
+                }
+            }
+            end += 1;
+        }
+        Ok(self.matching(path, is_dir).cloned())
+    }
+}
+
+/// `core.excludesFile`, defaulting to `$XDG_CONFIG_HOME/git/ignore` or `~/.config/git/ignore`.
+pub fn excludes_file() -> Result<Option<PathBuf>> {
+    let config = Config::load()?;
+    if let Some(path) = config.get_path("core.excludesfile") {
+        return Ok(Some(path));
+    }
+    let path = match (env::var("XDG_CONFIG_HOME"), env::var("HOME")) {
+        (Ok(xdg), _) if !xdg.is_empty() => PathBuf::from(xdg).join("git/ignore"),
+        (_, Ok(home)) => PathBuf::from(home).join(".config/git/ignore"),
+        _ => return Ok(None),
+    };
+    Ok(Some(path))
+}
+
+fn read_patterns(file: &Path, base: &str, source: &str) -> Result<Vec<Pattern>> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+    };
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter_map(|(number, line)| {
+            let mut pattern = Pattern::parse(line)?;
+            pattern.base = base.to_string();
+            pattern.source = source.to_string();
+            pattern.line_number = number + 1;
+            Some(pattern)
+        })
+        .collect())
+}
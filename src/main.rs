@@ -1,11 +1,39 @@
-use commands::{add, cat_file, hash_object, status};
-use std::{fs, path::PathBuf};
+use commands::{
+    add, apply, cat_file, check_ignore, commit_graph, diff_files, diff_index, diff_tree,
+    hash_object, log, ls_files, ls_tree, merge_base, merge_file, reflog, rev_parse, show_ref,
+    status, symbolic_ref, update_index, update_ref,
+};
+use std::{fs, path::PathBuf, process::ExitCode};
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{error::ErrorKind, Args, Parser, Subcommand};
+use diff::{
+    color::{ColorMoved, When},
+    pairs::Format,
+    stat::StatWidths,
+    words::WordDiff,
+};
 
+mod attributes;
 mod commands;
+mod config;
+mod convert;
+mod date;
+mod diff;
+mod fsmonitor;
+mod ignore;
+mod merge;
 mod objects;
+mod patch;
+mod pathspec;
+mod pretty;
+mod quote;
+mod refs;
+mod regex;
+mod rename;
+mod revision;
+mod wildmatch;
+mod worktree;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -19,30 +47,887 @@ struct Cli {
 enum Commands {
     /// Init a git repo
     Init,
+    /// Compute object ids, and optionally write the objects
     HashObject {
         #[arg(short)]
         write: bool,
 
+        /// read the object from stdin
         #[arg(long)]
         stdin: bool,
 
-        file: Option<PathBuf>,
+        /// read file names from stdin, one per line
+        #[arg(long)]
+        stdin_paths: bool,
+
+        /// the type of object to create
+        #[arg(short = 't', value_name = "TYPE", default_value = "blob")]
+        kind: String,
+
+        /// don't check that the content is a valid object of the type
+        #[arg(long)]
+        literally: bool,
+
+        /// hash as if the content were at this path, for attributes and filters
+        #[arg(long)]
+        path: Option<PathBuf>,
+
+        /// hash the contents as they are, without filters
+        #[arg(long)]
+        no_filters: bool,
+
+        files: Vec<PathBuf>,
     },
+    /// Show the type, size or contents of objects
     CatFile {
         #[arg(short)]
         pretty_print: bool,
 
-        hash: String,
+        /// show the type
+        #[arg(short = 't')]
+        show_type: bool,
+
+        /// show the size
+        #[arg(short = 's')]
+        show_size: bool,
+
+        /// exit with 0 if the object exists, 1 if not
+        #[arg(short = 'e')]
+        exists: bool,
+
+        /// print info and contents of objects named on stdin
+        #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        batch: Option<String>,
+
+        /// print info about objects named on stdin
+        #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        batch_check: Option<String>,
+
+        /// with --batch or --batch-check, go through every object instead of reading stdin
+        #[arg(long)]
+        batch_all_objects: bool,
+
+        /// don't flush after every object in batch mode
+        #[arg(long)]
+        buffer: bool,
+
+        /// [<type>] <object>
+        args: Vec<String>,
     },
+    /// Add file contents to the index
     Add {
-        file: PathBuf,
+        /// add files even if they are ignored
+        #[arg(short, long)]
+        force: bool,
+
+        paths: Vec<String>,
+    },
+    /// Show the working tree status
+    Status {
+        /// give the output in the short format
+        #[arg(short, long)]
+        short: bool,
+
+        /// give the output in the long format, the default
+        #[arg(long)]
+        long: bool,
+
+        /// machine readable output, v1 or v2
+        #[arg(long, value_name = "VERSION", num_args = 0..=1, require_equals = true, default_missing_value = "v1")]
+        porcelain: Option<String>,
+
+        /// show the branch and upstream in the short formats
+        #[arg(short, long)]
+        branch: bool,
+
+        /// end entries with NUL, implying --porcelain=v1 if no format is given
+        #[arg(short)]
+        zero: bool,
+
+        /// no, normal or all; attached to -u as in -uno
+        #[arg(short, long, value_name = "MODE", num_args = 0..=1, default_missing_value = "all")]
+        untracked_files: Option<String>,
+
+        /// list files moved in the index as renames, optionally at least <N> alike
+        #[arg(short = 'M', long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        find_renames: Option<String>,
+
+        /// look for copies of files changed in the index too, optionally at least <N> alike
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        find_copies: Option<String>,
+
+        /// don't look for renames, whatever status.renames says
+        #[arg(long, conflicts_with_all = ["find_renames", "find_copies"])]
+        no_renames: bool,
+    },
+    /// Show changes between the worktree, the index, commits and trees, or two blobs
+    Diff {
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// the index against HEAD or the commit given, instead of the worktree
+        #[arg(long, visible_alias = "staged")]
+        cached: bool,
+
+        #[arg(value_name = "COMMIT")]
+        args: Vec<String>,
+
+        #[arg(last = true, value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Compare two trees, or a commit with its parent
+    DiffTree {
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// go into subtrees
+        #[arg(short)]
+        recursive: bool,
+
+        /// show a root commit as all new
+        #[arg(long)]
+        root: bool,
+
+        #[arg(value_name = "TREE-ISH")]
+        args: Vec<String>,
+
+        #[arg(last = true, value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Compare a tree with the worktree or the index
+    DiffIndex {
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// compare with the index instead of the worktree
+        #[arg(long)]
+        cached: bool,
+
+        #[arg(value_name = "TREE-ISH")]
+        args: Vec<String>,
+
+        #[arg(last = true, value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Compare the worktree with the index
+    DiffFiles {
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        #[arg(value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Apply patches to the worktree and/or the index
+    Apply {
+        /// only see whether the patches apply, changing nothing
+        #[arg(long)]
+        check: bool,
+
+        /// apply to the index as well as the worktree
+        #[arg(long)]
+        index: bool,
+
+        /// apply to the index only, leaving the worktree alone
+        #[arg(long)]
+        cached: bool,
+
+        /// merge with the blobs the patch was made from when it doesn't apply
+        #[arg(short = '3', long = "3way")]
+        three_way: bool,
+
+        /// apply the patches in reverse
+        #[arg(short = 'R', long)]
+        reverse: bool,
+
+        /// remove <N> leading components from the paths in the patches
+        #[arg(short = 'p', value_name = "N")]
+        strip: Option<usize>,
+
+        /// make sure at least <N> lines of context match
+        #[arg(short = 'C', value_name = "N")]
+        context: Option<usize>,
+
+        /// allow hunks without context to apply where their headers say
+        #[arg(long)]
+        unidiff_zero: bool,
+
+        /// nowarn, warn, fix, error or error-all for lines adding whitespace errors
+        #[arg(long, value_name = "ACTION")]
+        whitespace: Option<String>,
+
+        /// ignore changes in whitespace when matching context
+        #[arg(long, visible_alias = "ignore-whitespace")]
+        ignore_space_change: bool,
+
+        /// the patch files, stdin for none or `-`
+        #[arg(value_name = "PATCH")]
+        files: Vec<String>,
+    },
+    /// Show the commits reachable from the given ones
+    Log {
+        /// show at most <N> commits
+        #[arg(short = 'n', long = "max-count", value_name = "N")]
+        max_count: Option<usize>,
+
+        /// one line for each commit, the abbreviated hash and the subject
+        #[arg(long)]
+        oneline: bool,
+
+        /// abbreviate the hashes of the commits shown
+        #[arg(long)]
+        abbrev_commit: bool,
+
+        /// oneline, short, medium, full, fuller or a format string with placeholders
+        #[arg(long, visible_alias = "pretty", value_name = "FORMAT")]
+        format: Option<String>,
+
+        /// draw the history beside the commits
+        #[arg(long)]
+        graph: bool,
+
+        /// only commits whose author matches the pattern
+        #[arg(long, value_name = "PATTERN")]
+        author: Vec<String>,
+
+        /// only commits whose message matches the pattern
+        #[arg(long, value_name = "PATTERN")]
+        grep: Vec<String>,
+
+        /// only commits more recent than the date
+        #[arg(long, visible_alias = "after", value_name = "DATE")]
+        since: Option<String>,
+
+        /// only commits older than the date
+        #[arg(long, visible_alias = "before", value_name = "DATE")]
+        until: Option<String>,
+
+        /// show no parent before all its children
+        #[arg(long)]
+        topo_order: bool,
+
+        /// show the oldest commits first
+        #[arg(long)]
+        reverse: bool,
+
+        /// follow only the first parent of merges
+        #[arg(long)]
+        first_parent: bool,
+
+        /// start from all refs and HEAD
+        #[arg(long)]
+        all: bool,
+
+        /// follow the one file given back through renames
+        #[arg(long)]
+        follow: bool,
+
+        #[arg(value_name = "REVISION")]
+        args: Vec<String>,
+
+        #[arg(last = true, value_name = "PATH")]
+        paths: Vec<String>,
+    },
+    /// Find the best common ancestors of commits
+    MergeBase {
+        /// show every best common ancestor, not just one
+        #[arg(short, long)]
+        all: bool,
+
+        /// the common ancestors of all the commits at once, for an octopus merge
+        #[arg(long, conflicts_with_all = ["independent", "is_ancestor", "fork_point"])]
+        octopus: bool,
+
+        /// show the commits that can't be reached from any of the others
+        #[arg(long, conflicts_with_all = ["is_ancestor", "fork_point"])]
+        independent: bool,
+
+        /// exit 0 if the first commit is an ancestor of the second, 1 if not
+        #[arg(long, conflicts_with = "fork_point")]
+        is_ancestor: bool,
+
+        /// where the commit forked from the ref, going by the ref's reflog
+        #[arg(long)]
+        fork_point: bool,
+
+        #[arg(value_name = "COMMIT")]
+        commits: Vec<String>,
+    },
+    /// Merge what two files each did to a common base
+    MergeFile {
+        /// print the result instead of writing it to the current file
+        #[arg(short = 'p', long)]
+        stdout: bool,
+
+        /// show the base's lines in conflicts too
+        #[arg(long, overrides_with = "zdiff3")]
+        diff3: bool,
+
+        /// as --diff3, leaving lines both sides agree on out of conflicts
+        #[arg(long, overrides_with = "diff3")]
+        zdiff3: bool,
+
+        /// resolve conflicts to our side
+        #[arg(long, overrides_with_all = ["theirs", "union"])]
+        ours: bool,
+
+        /// resolve conflicts to their side
+        #[arg(long, overrides_with_all = ["ours", "union"])]
+        theirs: bool,
+
+        /// resolve conflicts to both sides, ours first
+        #[arg(long, overrides_with_all = ["ours", "theirs"])]
+        union: bool,
+
+        /// how long the conflict markers are
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 0,
+            allow_negative_numbers = true
+        )]
+        marker_size: isize,
+
+        /// don't warn about conflicts, which nothing does anyway
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// labels for the current file, the base and the other file, in that order
+        #[arg(short = 'L', value_name = "NAME")]
+        labels: Vec<String>,
+
+        current: String,
+        base: String,
+        other: String,
+    },
+    /// Write and verify the commit-graph
+    CommitGraph {
+        #[command(subcommand)]
+        action: CommitGraphAction,
+    },
+    /// Show whether paths are ignored, and by which pattern
+    CheckIgnore {
+        /// show the source, line and pattern that matched
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// with -v, also show paths that don't match
+        #[arg(short, long)]
+        non_matching: bool,
+
+        /// don't skip files that are tracked
+        #[arg(long)]
+        no_index: bool,
+
+        /// read paths from stdin, one per line
+        #[arg(long)]
+        stdin: bool,
+
+        /// with --stdin, separate input and output with NUL
+        #[arg(short)]
+        zero: bool,
+
+        paths: Vec<String>,
+    },
+    /// Show what is in the index and the worktree
+    LsFiles {
+        /// show tracked files, the default
+        #[arg(short, long)]
+        cached: bool,
+
+        /// show tracked files missing from the worktree
+        #[arg(short, long)]
+        deleted: bool,
+
+        /// show tracked files that changed in the worktree
+        #[arg(short, long)]
+        modified: bool,
+
+        /// show untracked files
+        #[arg(short, long)]
+        others: bool,
+
+        /// show only ignored files
+        #[arg(short, long)]
+        ignored: bool,
+
+        /// use .gitignore and .git/info/exclude
+        #[arg(long)]
+        exclude_standard: bool,
+
+        /// skip untracked files matching this pattern
+        #[arg(short = 'x', long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// show mode, hash and stage
+        #[arg(short, long)]
+        stage: bool,
+
+        /// show only entries with conflicts
+        #[arg(short, long)]
+        unmerged: bool,
+
+        /// end lines with NUL instead of newline
+        #[arg(short)]
+        zero: bool,
+
+        /// show the cached stat data too
+        #[arg(long)]
+        debug: bool,
+    },
+    /// List the contents of a tree object
+    LsTree {
+        /// recurse into subtrees
+        #[arg(short)]
+        recursive: bool,
+
+        /// show trees even when recursing into them
+        #[arg(short = 't')]
+        show_trees: bool,
+
+        /// only show trees, and with -r all of them
+        #[arg(short = 'd')]
+        dirs_only: bool,
+
+        #[arg(long, alias = "name-status")]
+        name_only: bool,
+
+        /// show the size of blobs
+        #[arg(short, long)]
+        long: bool,
+
+        /// abbreviate hashes to a unique prefix of at least this length
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        abbrev: Option<usize>,
+
+        /// end entries with NUL and don't quote paths
+        #[arg(short)]
+        zero: bool,
+
+        tree_ish: String,
+
+        paths: Vec<String>,
+    },
+    /// Update the object name stored in a ref safely
+    UpdateRef {
+        /// delete the ref instead of updating it
+        #[arg(short)]
+        delete: bool,
+
+        /// update the ref itself rather than what it points to
+        #[arg(long)]
+        no_deref: bool,
+
+        /// the reason to record in the reflog
+        #[arg(short)]
+        message: Option<String>,
+
+        #[arg(value_name = "REF")]
+        name: String,
+
+        /// <new-val> [<old-val>], or just [<old-val>] with -d
+        args: Vec<String>,
+    },
+    /// Register file contents in the working tree to the index
+    UpdateIndex {
+        /// refresh the stat data of entries whose contents haven't changed
+        #[arg(long)]
+        refresh: bool,
+
+        /// carry on quietly when files need updating
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// keep a cache of untracked files in the index
+        #[arg(long, overrides_with = "no_untracked_cache")]
+        untracked_cache: bool,
+
+        /// drop the untracked cache from the index
+        #[arg(long, overrides_with = "untracked_cache")]
+        no_untracked_cache: bool,
+    },
+    /// Read or set a symbolic ref
+    SymbolicRef {
+        #[arg(short, long)]
+        short: bool,
+
+        /// the reason to record in the reflog
+        #[arg(short)]
+        message: Option<String>,
+
+        name: String,
+
+        target: Option<String>,
+    },
+    /// List refs and the objects they point to
+    ShowRef {
+        #[arg(long)]
+        head: bool,
+
+        /// also show the peeled object of annotated tags
+        #[arg(short, long)]
+        dereference: bool,
+
+        /// only print the hashes
+        #[arg(short = 's', long)]
+        hash: bool,
+
+        patterns: Vec<String>,
+    },
+    /// Show the history of where a ref has pointed
+    Reflog {
+        /// show or exists, show being the default
+        action: Option<String>,
+
+        #[arg(value_name = "REF")]
+        name: Option<String>,
+    },
+    /// Resolve revision expressions to object names
+    RevParse {
+        /// require exactly one argument that names an object
+        #[arg(long)]
+        verify: bool,
+
+        /// with --verify, exit 1 instead of printing an error
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// abbreviate to a unique prefix of at least this length
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        short: Option<usize>,
+
+        #[arg(long)]
+        abbrev_ref: bool,
+
+        #[arg(long)]
+        symbolic_full_name: bool,
+
+        #[arg(long)]
+        git_dir: bool,
+
+        #[arg(long)]
+        show_toplevel: bool,
+
+        #[arg(long)]
+        is_inside_work_tree: bool,
+
+        revs: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CommitGraphAction {
+    /// Write a commit-graph of the commits given and everything they reach
+    Write {
+        /// start from every ref
+        #[arg(long)]
+        reachable: bool,
+
+        /// start from the commits listed on stdin
+        #[arg(long)]
+        stdin_commits: bool,
+
+        /// keep the commits already in the commit-graph
+        #[arg(long)]
+        append: bool,
+
+        /// write a new layer of a split graph, merging layers unless the strategy is no-merge,
+        /// or replace to write a single layer
+        #[arg(
+            long,
+            value_name = "STRATEGY",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        split: Option<String>,
+    },
+    /// Check the commit-graph against its checksums and the commits it was written from
+    Verify {
+        /// check only the top layer of a split graph
+        #[arg(long)]
+        shallow: bool,
     },
-    Status,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Options `diff` shares with its plumbing.
+#[derive(Args)]
+struct DiffArgs {
+    /// lines of context around each change
+    #[arg(short = 'U', long = "unified", value_name = "N")]
+    unified: Option<usize>,
+
+    /// myers (the default), minimal, patience or histogram
+    #[arg(long, value_name = "ALGORITHM")]
+    diff_algorithm: Option<String>,
+
+    /// shift changes to where the indentation suggests they start and end, the default
+    #[arg(long, overrides_with = "no_indent_heuristic")]
+    indent_heuristic: bool,
+
+    #[arg(long, overrides_with = "indent_heuristic")]
+    no_indent_heuristic: bool,
+
+    /// show a patch, the default for diff
+    #[arg(short, long, overrides_with_all = ["raw", "name_only", "name_status"])]
+    patch: bool,
+
+    /// show modes, hashes and status, the default for the plumbing
+    #[arg(long, overrides_with_all = ["patch", "name_only", "name_status"])]
+    raw: bool,
+
+    /// show only the names of changed files
+    #[arg(long, overrides_with_all = ["patch", "raw", "name_status"])]
+    name_only: bool,
+
+    /// show the names and status of changed files
+    #[arg(long, overrides_with_all = ["patch", "raw", "name_only"])]
+    name_status: bool,
+
+    /// detect renames, optionally at least <N> alike
+    #[arg(short = 'M', long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "", overrides_with = "no_renames")]
+    find_renames: Option<String>,
+
+    /// detect copies as well as renames, optionally at least <N> alike
+    #[arg(short = 'C', long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "", overrides_with = "no_renames")]
+    find_copies: Option<String>,
+
+    /// don't detect renames, whatever diff.renames says
+    #[arg(long, overrides_with_all = ["find_renames", "find_copies"])]
+    no_renames: bool,
+
+    /// show changed lines per file as a histogram, optionally <WIDTH>[,<NAME-WIDTH>[,<COUNT>]]
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    stat: Option<String>,
+
+    /// the width of a --stat line
+    #[arg(long, value_name = "N")]
+    stat_width: Option<usize>,
+
+    /// the width of the names in --stat
+    #[arg(long, value_name = "N")]
+    stat_name_width: Option<usize>,
+
+    /// the width of the histogram in --stat
+    #[arg(long, value_name = "N")]
+    stat_graph_width: Option<usize>,
+
+    /// list only the first <N> files in --stat
+    #[arg(long, value_name = "N")]
+    stat_count: Option<usize>,
 
+    /// show added and deleted lines per file, tab separated
+    #[arg(long)]
+    numstat: bool,
+
+    /// show only the totals of --stat
+    #[arg(long)]
+    shortstat: bool,
+
+    /// show each directory's share of the changes, by changes, lines or files, above a percentage
+    #[arg(long, value_name = "PARAMS", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    dirstat: Option<String>,
+
+    /// --dirstat counting each changed file the same
+    #[arg(long, value_name = "PARAMS", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    dirstat_by_file: Option<String>,
+
+    /// --dirstat counting directories' changes in their parents too
+    #[arg(long)]
+    cumulative: bool,
+
+    /// colour the output: always (without <WHEN>), never or auto
+    #[arg(long, value_name = "WHEN", num_args = 0..=1, require_equals = true, default_missing_value = "always", overrides_with = "no_color")]
+    color: Option<String>,
+
+    #[arg(long, overrides_with = "color")]
+    no_color: bool,
+
+    /// show changed words: plain (the default), color, porcelain or none
+    #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "plain")]
+    word_diff: Option<String>,
+
+    /// what a word is for --word-diff, which it implies
+    #[arg(long, value_name = "REGEX")]
+    word_diff_regex: Option<String>,
+
+    /// --word-diff=color, with words as <REGEX> matches them if given
+    #[arg(long, value_name = "REGEX", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    color_words: Option<String>,
+
+    /// colour lines that were moved: default (zebra), plain, blocks, zebra, dimmed-zebra or no
+    #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "default", overrides_with = "no_color_moved")]
+    color_moved: Option<String>,
+
+    #[arg(long, overrides_with = "color_moved")]
+    no_color_moved: bool,
+}
+
+impl DiffArgs {
+    fn options(&self) -> Result<commands::diff::Options> {
+        Ok(commands::diff::Options {
+            context: self.unified,
+            algorithm: self
+                .diff_algorithm
+                .as_deref()
+                .map(diff::Algorithm::parse)
+                .transpose()?,
+            indent_heuristic: match (self.indent_heuristic, self.no_indent_heuristic) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            format: match (self.patch, self.raw, self.name_only, self.name_status) {
+                (true, _, _, _) => Some(Format::Patch),
+                (_, true, _, _) => Some(Format::Raw),
+                (_, _, true, _) => Some(Format::NameOnly),
+                (_, _, _, true) => Some(Format::NameStatus),
+                _ => None,
+            },
+            renames: match (&self.find_renames, &self.find_copies, self.no_renames) {
+                (_, _, true) => Some(false),
+                (None, None, false) => None,
+                _ => Some(true),
+            },
+            copies: self.find_copies.is_some() && !self.no_renames,
+            rename_score: rename_score(&self.find_renames, &self.find_copies)?,
+            stat: self.stat_widths()?,
+            numstat: self.numstat,
+            shortstat: self.shortstat,
+            dirstat: self.dirstat_params(),
+            color: match (&self.color, self.no_color) {
+                (_, true) => Some(When::Never),
+                (Some(when), _) => Some(When::parse(when)?),
+                (None, false) => None,
+            },
+            word_diff: match (&self.color_words, &self.word_diff) {
+                (Some(_), _) => Some(WordDiff::Color),
+                (None, Some(mode)) => WordDiff::parse(mode)?,
+                // a word regex alone asks for words too
+                (None, None) => self.word_diff_regex.as_ref().map(|_| WordDiff::Plain),
+            },
+            word_regex: match self.color_words.as_deref() {
+                Some(regex) if !regex.is_empty() => Some(regex.to_string()),
+                _ => self.word_diff_regex.clone(),
+            },
+            color_moved: match (&self.color_moved, self.no_color_moved) {
+                (_, true) => Some(ColorMoved::No),
+                (Some(mode), _) => Some(ColorMoved::parse(mode)?),
+                (None, false) => None,
+            },
+        })
+    }
+
+    /// `--stat`, which any of the width options also ask for.
+    fn stat_widths(&self) -> Result<Option<StatWidths>> {
+        let more = [
+            self.stat_width,
+            self.stat_name_width,
+            self.stat_graph_width,
+            self.stat_count,
+        ];
+        if self.stat.is_none() && more.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let mut widths = StatWidths::parse(self.stat.as_deref().unwrap_or(""))?;
+        widths.width = self.stat_width.or(widths.width);
+        widths.name_width = self.stat_name_width.or(widths.name_width);
+        widths.graph_width = self.stat_graph_width;
+        widths.count = self.stat_count.or(widths.count);
+        Ok(Some(widths))
+    }
+
+    /// The `--dirstat` parameters, with `--dirstat-by-file` and `--cumulative` as the ones
+    /// they stand for.
+    fn dirstat_params(&self) -> Option<String> {
+        let mut params = Vec::new();
+        if let Some(p) = &self.dirstat {
+            params.push(p.as_str());
+        }
+        if let Some(p) = &self.dirstat_by_file {
+            params.extend(["files", p.as_str()]);
+        }
+        if self.cumulative {
+            params.push("cumulative");
+        }
+        (!params.is_empty()).then(|| params.join(","))
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse_from(attach_rename_scores(std::env::args().collect())) {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return match e.kind() {
+                ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => ExitCode::SUCCESS,
+                // git exits with 129 on usage errors
+                _ => ExitCode::from(129),
+            };
+        }
+    };
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("fatal: {:#}", e);
+            // like git's die(); commands that fail with 1 on purpose exit themselves
+            ExitCode::from(128)
+        }
+    }
+}
+
+/// The commands whose `-M` and `-C` take a score.
+const RENAME_SCORE_COMMANDS: [&str; 5] =
+    ["diff", "diff-tree", "diff-index", "diff-files", "status"];
+
+/// git takes the score attached to `-M` or `-C`, as in `-M50%`, which clap only reads after
+/// `=`. Only the options of the commands that have them are touched, not what follows `--`.
+fn attach_rename_scores(args: Vec<String>) -> Vec<String> {
+    if !args
+        .get(1)
+        .is_some_and(|command| RENAME_SCORE_COMMANDS.contains(&command.as_str()))
+    {
+        return args;
+    }
+    let mut options = true;
+    args.into_iter()
+        .map(|arg| {
+            options &= arg != "--";
+            match options {
+                true => attach_rename_score(arg),
+                false => arg,
+            }
+        })
+        .collect()
+}
+
+fn attach_rename_score(arg: String) -> String {
+    for flag in ["-M", "-C"] {
+        if let Some(score) = arg.strip_prefix(flag) {
+            if !score.is_empty() && !score.starts_with('=') {
+                return format!("{}={}", flag, score);
+            }
+        }
+    }
+    arg
+}
+
+/// The similarity asked for with `--find-renames=<n>` or `--find-copies=<n>`, the copies'
+/// winning as both set the one score.
+fn rename_score(
+    find_renames: &Option<String>,
+    find_copies: &Option<String>,
+) -> Result<Option<u32>> {
+    match find_copies.as_deref().filter(|s| !s.is_empty()) {
+        Some(score) => Ok(Some(rename::parse_score(score)?)),
+        None => match find_renames.as_deref() {
+            None | Some("") => Ok(None),
+            Some(score) => Ok(Some(rename::parse_score(score)?)),
+        },
+    }
+}
+
+fn run(cli: &Cli) -> Result<()> {
     match &cli.command {
         Commands::Init => {
             //more to be implemnted
@@ -51,15 +936,379 @@ fn main() -> Result<()> {
         Commands::HashObject {
             write,
             stdin,
-            ref file,
+            stdin_paths,
+            kind,
+            literally,
+            path,
+            no_filters,
+            files,
+        } => {
+            let options = hash_object::Options {
+                write: *write,
+                stdin: *stdin,
+                stdin_paths: *stdin_paths,
+                kind: kind.clone(),
+                literally: *literally,
+                path: path.clone(),
+                no_filters: *no_filters,
+            };
+            hash_object::invoke(&options, files)?;
+        }
+        Commands::CatFile {
+            pretty_print,
+            show_type,
+            show_size,
+            exists,
+            batch,
+            batch_check,
+            batch_all_objects,
+            buffer,
+            args,
+        } => {
+            let options = cat_file::Options {
+                pretty_print: *pretty_print,
+                show_type: *show_type,
+                show_size: *show_size,
+                exists: *exists,
+                batch: batch.clone(),
+                batch_check: batch_check.clone(),
+                batch_all_objects: *batch_all_objects,
+                buffer: *buffer,
+            };
+            cat_file::invoke(&options, args)?;
+        }
+        Commands::Add { force, paths } => {
+            let options = add::Options { force: *force };
+            add::invoke(&options, paths)?
+        }
+        Commands::Status {
+            short,
+            long,
+            porcelain,
+            branch,
+            zero,
+            untracked_files,
+            find_renames,
+            find_copies,
+            no_renames,
+        } => {
+            let format = match (porcelain.as_deref(), *short, *long) {
+                (Some("v1") | Some("1"), _, _) => status::Format::PorcelainV1,
+                (Some("v2") | Some("2"), _, _) => status::Format::PorcelainV2,
+                (Some(other), _, _) => anyhow::bail!("unsupported porcelain version '{}'", other),
+                (None, true, _) => status::Format::Short,
+                (None, false, false) if *zero => status::Format::PorcelainV1,
+                (None, _, _) => status::Format::Long,
+            };
+            let untracked = match untracked_files.as_deref() {
+                None | Some("normal") => status::UntrackedMode::Normal,
+                Some("no") => status::UntrackedMode::No,
+                Some("all") => status::UntrackedMode::All,
+                Some(other) => anyhow::bail!("Invalid untracked files mode '{}'", other),
+            };
+            let options = status::Options {
+                format,
+                branch: *branch,
+                zero: *zero,
+                untracked,
+                renames: match (find_renames, find_copies, no_renames) {
+                    (_, _, true) => Some(false),
+                    (None, None, false) => None,
+                    _ => Some(true),
+                },
+                copies: find_copies.is_some(),
+                rename_score: rename_score(find_renames, find_copies)?,
+            };
+            status::invoke(&options)?
+        }
+        Commands::Diff {
+            diff,
+            cached,
+            args,
+            paths,
+        } => {
+            // the command shares its name with the diff module
+            commands::diff::invoke(&diff.options()?, *cached, args, paths)?
+        }
+        Commands::DiffTree {
+            diff,
+            recursive,
+            root,
+            args,
+            paths,
+        } => {
+            let options = diff_tree::Options {
+                recursive: *recursive,
+                root: *root,
+            };
+            diff_tree::invoke(&diff.options()?, &options, args, paths)?
+        }
+        Commands::DiffIndex {
+            diff,
+            cached,
+            args,
+            paths,
+        } => diff_index::invoke(&diff.options()?, *cached, args, paths)?,
+        Commands::DiffFiles { diff, paths } => diff_files::invoke(&diff.options()?, paths)?,
+        Commands::Apply {
+            check,
+            index,
+            cached,
+            three_way,
+            reverse,
+            strip,
+            context,
+            unidiff_zero,
+            whitespace,
+            ignore_space_change,
+            files,
+        } => {
+            let options = apply::Options {
+                check: *check,
+                index: *index,
+                cached: *cached,
+                three_way: *three_way,
+                reverse: *reverse,
+                strip: *strip,
+                context: *context,
+                unidiff_zero: *unidiff_zero,
+                whitespace: whitespace.clone(),
+                ignore_space_change: *ignore_space_change,
+            };
+            apply::invoke(&options, files)?
+        }
+        Commands::Log {
+            max_count,
+            oneline,
+            abbrev_commit,
+            format,
+            graph,
+            author,
+            grep,
+            since,
+            until,
+            topo_order,
+            reverse,
+            first_parent,
+            all,
+            follow,
+            args,
+            paths,
+        } => {
+            let options = log::Options {
+                max_count: *max_count,
+                oneline: *oneline,
+                abbrev_commit: *abbrev_commit,
+                format: format.clone(),
+                graph: *graph,
+                authors: author.clone(),
+                grep: grep.clone(),
+                since: since.clone(),
+                until: until.clone(),
+                topo_order: *topo_order,
+                reverse: *reverse,
+                first_parent: *first_parent,
+                all: *all,
+                follow: *follow,
+            };
+            log::invoke(&options, args, paths)?
+        }
+        Commands::MergeBase {
+            all,
+            octopus,
+            independent,
+            is_ancestor,
+            fork_point,
+            commits,
+        } => {
+            let options = merge_base::Options {
+                all: *all,
+                octopus: *octopus,
+                independent: *independent,
+                is_ancestor: *is_ancestor,
+                fork_point: *fork_point,
+            };
+            merge_base::invoke(&options, commits)?
+        }
+        Commands::MergeFile {
+            stdout,
+            diff3,
+            zdiff3,
+            ours,
+            theirs,
+            union,
+            marker_size,
+            quiet: _,
+            labels,
+            current,
+            base,
+            other,
+        } => {
+            let style = match (diff3, zdiff3) {
+                (true, _) => Some(merge::Style::Diff3),
+                (_, true) => Some(merge::Style::ZealousDiff3),
+                _ => None,
+            };
+            let favor = match (ours, theirs, union) {
+                (true, _, _) => Some(merge::Favor::Ours),
+                (_, true, _) => Some(merge::Favor::Theirs),
+                (_, _, true) => Some(merge::Favor::Union),
+                _ => None,
+            };
+            let options = merge_file::Options {
+                stdout: *stdout,
+                style,
+                favor,
+                marker_size: *marker_size,
+                labels: labels.clone(),
+            };
+            merge_file::invoke(&options, current, base, other)?
+        }
+        Commands::CommitGraph { action } => match action {
+            CommitGraphAction::Write {
+                reachable,
+                stdin_commits,
+                append,
+                split,
+            } => {
+                let options = commit_graph::WriteOptions {
+                    reachable: *reachable,
+                    stdin_commits: *stdin_commits,
+                    append: *append,
+                    split: split.clone(),
+                };
+                commit_graph::write(&options)?
+            }
+            CommitGraphAction::Verify { shallow } => commit_graph::verify(*shallow)?,
+        },
+        Commands::CheckIgnore {
+            verbose,
+            non_matching,
+            no_index,
+            stdin,
+            zero,
+            paths,
+        } => {
+            let options = check_ignore::Options {
+                verbose: *verbose,
+                non_matching: *non_matching,
+                no_index: *no_index,
+                stdin: *stdin,
+                zero: *zero,
+            };
+            check_ignore::invoke(&options, paths)?
+        }
+        Commands::LsFiles {
+            cached,
+            deleted,
+            modified,
+            others,
+            ignored,
+            exclude_standard,
+            exclude,
+            stage,
+            unmerged,
+            zero,
+            debug,
+        } => {
+            let options = ls_files::Options {
+                cached: *cached,
+                deleted: *deleted,
+                modified: *modified,
+                others: *others,
+                ignored: *ignored,
+                exclude_standard: *exclude_standard,
+                exclude: exclude.clone(),
+                stage: *stage,
+                unmerged: *unmerged,
+                zero: *zero,
+                debug: *debug,
+            };
+            ls_files::invoke(&options)?
+        }
+        Commands::LsTree {
+            recursive,
+            show_trees,
+            dirs_only,
+            name_only,
+            long,
+            abbrev,
+            zero,
+            tree_ish,
+            paths,
+        } => {
+            let options = ls_tree::Options {
+                recursive: *recursive,
+                show_trees: *show_trees,
+                dirs_only: *dirs_only,
+                name_only: *name_only,
+                long: *long,
+                abbrev: *abbrev,
+                zero: *zero,
+            };
+            ls_tree::invoke(&options, tree_ish, paths)?
+        }
+        Commands::UpdateRef {
+            delete,
+            no_deref,
+            message,
+            name,
+            args,
+        } => update_ref::invoke(*delete, *no_deref, message, name, args)?,
+        Commands::UpdateIndex {
+            refresh,
+            quiet,
+            untracked_cache,
+            no_untracked_cache,
         } => {
-            hash_object::invoke(file, write)?;
+            let untracked_cache = match (*untracked_cache, *no_untracked_cache) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            let options = update_index::Options {
+                refresh: *refresh,
+                quiet: *quiet,
+                untracked_cache,
+            };
+            update_index::invoke(&options)?
         }
-        Commands::CatFile { pretty_print, hash } => {
-            cat_file::invoke(*pretty_print, &hash)?;
+        Commands::SymbolicRef {
+            short,
+            message,
+            name,
+            target,
+        } => symbolic_ref::invoke(*short, message, name, target)?,
+        Commands::ShowRef {
+            head,
+            dereference,
+            hash,
+            patterns,
+        } => show_ref::invoke(*head, *dereference, *hash, patterns)?,
+        Commands::Reflog { action, name } => reflog::invoke(action, name)?,
+        Commands::RevParse {
+            verify,
+            quiet,
+            short,
+            abbrev_ref,
+            symbolic_full_name,
+            git_dir,
+            show_toplevel,
+            is_inside_work_tree,
+            revs,
+        } => {
+            let options = rev_parse::Options {
+                verify: *verify,
+                quiet: *quiet,
+                short: *short,
+                abbrev_ref: *abbrev_ref,
+                symbolic_full_name: *symbolic_full_name,
+                git_dir: *git_dir,
+                show_toplevel: *show_toplevel,
+                is_inside_work_tree: *is_inside_work_tree,
+            };
+            rev_parse::invoke(&options, revs)?
         }
-        Commands::Add { file } => add::invoke(file)?,
-        Commands::Status => status::invoke()?,
     }
     Ok(())
 }
@@ -0,0 +1,527 @@
+//! Three-way merges of file contents, as git's xdiff `xdl_merge` does them.
+//!
+//! Both sides are diffed against the base. A change only one side made is taken; where both
+//! changed the same lines, the result gets conflict markers around the two versions. Conflicts
+//! are then narrowed to the lines the sides really disagree on, and ones close together are
+//! joined, since a conflict with a few lines in the middle is easier to read than two. The
+//! diff3 styles show the base's lines in each conflict as well, and so leave conflicts as
+//! they are, `zdiff3` only moving lines both sides begin or end with out of them.
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    diff::{self, unified},
+};
+
+/// What the conflict markers call each side and the base.
+pub(crate) struct Labels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+/// How conflicts are shown, as `merge.conflictStyle` names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Style {
+    /// `merge`, just the two sides
+    Merge,
+    /// `diff3`, the base's lines between the sides
+    Diff3,
+    /// `zdiff3`, as `diff3` but with the lines both sides agree on at either end taken out
+    ZealousDiff3,
+}
+
+impl Style {
+    /// The style `merge.conflictStyle` sets, if it does.
+    pub fn configured(config: &Config) -> Result<Option<Style>> {
+        Ok(match config.get("merge.conflictstyle") {
+            None => None,
+            Some("merge") => Some(Style::Merge),
+            Some("diff3") => Some(Style::Diff3),
+            Some("zdiff3") => Some(Style::ZealousDiff3),
+            Some(other) => {
+                anyhow::bail!("unknown style '{}' given for 'merge.conflictstyle'", other)
+            }
+        })
+    }
+}
+
+/// Which side a conflict is resolved to rather than marked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Favor {
+    Ours,
+    Theirs,
+    /// both sides, ours first
+    Union,
+}
+
+pub(crate) struct Options {
+    pub style: Style,
+    pub favor: Option<Favor>,
+    /// how many characters each conflict marker is
+    pub marker_size: usize,
+    /// also join conflicts that any number of lines keep apart, as long as none of them has
+    /// a letter or digit
+    pub join_without_alnum: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            style: Style::Merge,
+            favor: None,
+            marker_size: MARKER_SIZE,
+            join_without_alnum: false,
+        }
+    }
+}
+
+/// The merged contents and how many conflicts are marked in them.
+pub(crate) struct Merged {
+    pub contents: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// Conflicts are joined across fewer unchanged lines than this.
+const JOIN_DISTANCE: isize = 3;
+pub const MARKER_SIZE: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Take {
+    Conflict,
+    Ours,
+    Theirs,
+    /// both sides, for a conflict resolved to their union
+    Both,
+    /// both sides made the same change
+    Same,
+}
+
+/// A stretch of the base that one side or both changed. Lines are counted from 0, on both
+/// sides' results and the base.
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    take: Take,
+    ours: isize,
+    ours_len: isize,
+    theirs: isize,
+    theirs_len: isize,
+    base: isize,
+    base_len: isize,
+}
+
+/// A diff between the base and one side, as `(base, base_len, side, side_len)` runs.
+fn changes(base: &[u8], side: &[u8]) -> Vec<(isize, isize, isize, isize)> {
+    let options = diff::Options {
+        indent_heuristic: false,
+        ..Default::default()
+    };
+    let (old, new) = diff::lines(base, side);
+    let changes = diff::diff(&old, &new, &options);
+    unified::edits(&changes)
+        .into_iter()
+        .map(|e| {
+            (
+                e.old as isize,
+                e.old_len as isize,
+                e.new as isize,
+                e.new_len as isize,
+            )
+        })
+        .collect()
+}
+
+/// Merges what `ours` and `theirs` each did to `base`.
+pub fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &Labels,
+    options: &Options,
+) -> Merged {
+    let ours_changes = changes(base, ours);
+    let theirs_changes = changes(base, theirs);
+    if ours_changes.is_empty() {
+        return Merged {
+            contents: theirs.to_vec(),
+            conflicts: 0,
+        };
+    }
+    if theirs_changes.is_empty() {
+        return Merged {
+            contents: ours.to_vec(),
+            conflicts: 0,
+        };
+    }
+
+    let cut = |data| -> Vec<&[u8]> { diff::lines(data, b"").0.lines };
+    let files = Files {
+        base: cut(base),
+        ours: cut(ours),
+        theirs: cut(theirs),
+    };
+    let mut chunks = line_up(&files, &ours_changes, &theirs_changes);
+    match options.style {
+        // with the base shown, a conflict narrowed to part of it would read wrong
+        Style::Diff3 => {}
+        Style::ZealousDiff3 => trim(&files, &mut chunks),
+        Style::Merge => {
+            refine(&files, &mut chunks);
+            join_close(&files, &mut chunks, options.join_without_alnum);
+        }
+    }
+    if let Some(favor) = options.favor {
+        for chunk in chunks.iter_mut().filter(|c| c.take == Take::Conflict) {
+            chunk.take = match favor {
+                Favor::Ours => Take::Ours,
+                Favor::Theirs => Take::Theirs,
+                Favor::Union => Take::Both,
+            };
+        }
+    }
+
+    let conflicts = chunks.iter().filter(|c| c.take == Take::Conflict).count();
+    Merged {
+        contents: files.write(&chunks, labels, options),
+        conflicts,
+    }
+}
+
+struct Files<'a> {
+    base: Vec<&'a [u8]>,
+    ours: Vec<&'a [u8]>,
+    theirs: Vec<&'a [u8]>,
+}
+
+/// Walks both sides' changes in step, each change becoming a chunk of its own unless it
+/// overlaps one of the other side, when they make a conflict together. Changes that overlap
+/// the chunk before are taken into it.
+fn line_up(
+    files: &Files,
+    mut ours: &[(isize, isize, isize, isize)],
+    mut theirs: &[(isize, isize, isize, isize)],
+) -> Vec<Chunk> {
+    let ours_growth = files.ours.len() as isize - files.base.len() as isize;
+    let theirs_growth = files.theirs.len() as isize - files.base.len() as isize;
+    let mut chunks = Vec::new();
+    while let (Some(&o), Some(&t)) = (ours.first(), theirs.first()) {
+        let (o_base, o_base_len, o_side, o_side_len) = o;
+        let (t_base, t_base_len, t_side, t_side_len) = t;
+        if o_base + o_base_len < t_base {
+            let theirs_at = t_side - t_base + o_base;
+            push(
+                &mut chunks,
+                Chunk {
+                    take: Take::Ours,
+                    base: o_base,
+                    base_len: o_base_len,
+                    ours: o_side,
+                    ours_len: o_side_len,
+                    theirs: theirs_at,
+                    theirs_len: o_base_len,
+                },
+            );
+            ours = &ours[1..];
+            continue;
+        }
+        if t_base + t_base_len < o_base {
+            let ours_at = o_side - o_base + t_base;
+            push(
+                &mut chunks,
+                Chunk {
+                    take: Take::Theirs,
+                    base: t_base,
+                    base_len: t_base_len,
+                    ours: ours_at,
+                    ours_len: t_base_len,
+                    theirs: t_side,
+                    theirs_len: t_side_len,
+                },
+            );
+            theirs = &theirs[1..];
+            continue;
+        }
+        let same = o_base == t_base
+            && o_base_len == t_base_len
+            && o_side_len == t_side_len
+            && files.ours[o_side as usize..(o_side + o_side_len) as usize]
+                == files.theirs[t_side as usize..(t_side + t_side_len) as usize];
+        if !same {
+            // the conflict runs from where the first change starts to where the last ends
+            let start = o_base - t_base;
+            let end = start + o_base_len - t_base_len;
+            let (mut base, mut ours_at, mut theirs_at) = (o_base, o_side, t_side);
+            if start > 0 {
+                base -= start;
+                ours_at -= start;
+            } else {
+                theirs_at += start;
+            }
+            let mut base_len = o_base + o_base_len - base;
+            let mut ours_len = o_side + o_side_len - ours_at;
+            let mut theirs_len = t_side + t_side_len - theirs_at;
+            if end < 0 {
+                base_len -= end;
+                ours_len -= end;
+            } else {
+                theirs_len += end;
+            }
+            push(
+                &mut chunks,
+                Chunk {
+                    take: Take::Conflict,
+                    base,
+                    base_len,
+                    ours: ours_at,
+                    ours_len,
+                    theirs: theirs_at,
+                    theirs_len,
+                },
+            );
+        }
+        let (ours_end, theirs_end) = (o_base + o_base_len, t_base + t_base_len);
+        if ours_end >= theirs_end {
+            theirs = &theirs[1..];
+        }
+        if theirs_end >= ours_end {
+            ours = &ours[1..];
+        }
+    }
+    for &(base, base_len, side, side_len) in ours {
+        push(
+            &mut chunks,
+            Chunk {
+                take: Take::Ours,
+                base,
+                base_len,
+                ours: side,
+                ours_len: side_len,
+                theirs: base + theirs_growth,
+                theirs_len: base_len,
+            },
+        );
+    }
+    for &(base, base_len, side, side_len) in theirs {
+        push(
+            &mut chunks,
+            Chunk {
+                take: Take::Theirs,
+                base,
+                base_len,
+                ours: base + ours_growth,
+                ours_len: base_len,
+                theirs: side,
+                theirs_len: side_len,
+            },
+        );
+    }
+    chunks
+}
+
+/// Adds a chunk, or grows the last one to take it in if they overlap on either side, which
+/// makes a conflict of them unless both take the same side.
+fn push(chunks: &mut Vec<Chunk>, chunk: Chunk) {
+    if let Some(last) = chunks.last_mut() {
+        if chunk.ours <= last.ours + last.ours_len || chunk.theirs <= last.theirs + last.theirs_len
+        {
+            if chunk.take != last.take {
+                last.take = Take::Conflict;
+            }
+            last.base_len = chunk.base + chunk.base_len - last.base;
+            last.ours_len = chunk.ours + chunk.ours_len - last.ours;
+            last.theirs_len = chunk.theirs + chunk.theirs_len - last.theirs;
+            return;
+        }
+    }
+    chunks.push(chunk);
+}
+
+/// Diffs the two sides of each conflict against each other, leaving only the runs where they
+/// differ as conflicts. A conflict whose sides turn out the same is resolved.
+fn refine(files: &Files, chunks: &mut Vec<Chunk>) {
+    let mut refined = Vec::with_capacity(chunks.len());
+    for chunk in chunks.drain(..) {
+        if chunk.take != Take::Conflict || chunk.ours_len == 0 || chunk.theirs_len == 0 {
+            refined.push(chunk);
+            continue;
+        }
+        let ours = files.ours[chunk.ours as usize..(chunk.ours + chunk.ours_len) as usize].concat();
+        let theirs = files.theirs
+            [chunk.theirs as usize..(chunk.theirs + chunk.theirs_len) as usize]
+            .concat();
+        let differences = changes(&ours, &theirs);
+        if differences.is_empty() {
+            refined.push(Chunk {
+                take: Take::Same,
+                ..chunk
+            });
+            continue;
+        }
+        for (ours_at, ours_len, theirs_at, theirs_len) in differences {
+            refined.push(Chunk {
+                ours: chunk.ours + ours_at,
+                ours_len,
+                theirs: chunk.theirs + theirs_at,
+                theirs_len,
+                ..chunk
+            });
+        }
+    }
+    *chunks = refined;
+}
+
+/// Takes the lines both sides of a conflict begin or end with out of it, leaving the rest
+/// as they are.
+fn trim(files: &Files, chunks: &mut [Chunk]) {
+    for chunk in chunks.iter_mut().filter(|c| c.take == Take::Conflict) {
+        while chunk.ours_len > 0
+            && chunk.theirs_len > 0
+            && files.ours[chunk.ours as usize] == files.theirs[chunk.theirs as usize]
+        {
+            chunk.ours += 1;
+            chunk.ours_len -= 1;
+            chunk.theirs += 1;
+            chunk.theirs_len -= 1;
+        }
+        while chunk.ours_len > 0
+            && chunk.theirs_len > 0
+            && files.ours[(chunk.ours + chunk.ours_len - 1) as usize]
+                == files.theirs[(chunk.theirs + chunk.theirs_len - 1) as usize]
+        {
+            chunk.ours_len -= 1;
+            chunk.theirs_len -= 1;
+        }
+    }
+}
+
+/// Joins conflicts that fewer than `JOIN_DISTANCE` of our lines keep apart, or with
+/// `without_alnum` any lines without a letter or digit, those lines going into both sides of
+/// the conflict.
+fn join_close(files: &Files, chunks: &mut Vec<Chunk>, without_alnum: bool) {
+    let mut joined: Vec<Chunk> = Vec::with_capacity(chunks.len());
+    for chunk in chunks.drain(..) {
+        if let Some(last) = joined.last_mut() {
+            let between = (last.ours + last.ours_len) as usize..chunk.ours as usize;
+            let close = between.len() as isize <= JOIN_DISTANCE
+                || without_alnum
+                    && !files.ours[between]
+                        .iter()
+                        .any(|line| line.iter().any(u8::is_ascii_alphanumeric));
+            if last.take == Take::Conflict && chunk.take == Take::Conflict && close {
+                last.ours_len = chunk.ours + chunk.ours_len - last.ours;
+                last.theirs_len = chunk.theirs + chunk.theirs_len - last.theirs;
+                continue;
+            }
+        }
+        joined.push(chunk);
+    }
+    *chunks = joined;
+}
+
+impl Files<'_> {
+    /// The merged contents: our lines between the chunks, and in each what it takes.
+    fn write(&self, chunks: &[Chunk], labels: &Labels, options: &Options) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut written = 0;
+        for chunk in chunks {
+            let ours = chunk.ours as usize..(chunk.ours + chunk.ours_len) as usize;
+            let theirs = chunk.theirs as usize..(chunk.theirs + chunk.theirs_len) as usize;
+            match chunk.take {
+                // the same change is our lines, written with those after it
+                Take::Same => continue,
+                Take::Ours => {
+                    copy(&mut out, &self.ours[written..ours.end], None);
+                }
+                Take::Theirs => {
+                    copy(&mut out, &self.ours[written..ours.start], None);
+                    copy(&mut out, &self.theirs[theirs], None);
+                }
+                Take::Both => {
+                    copy(&mut out, &self.ours[written..ours.start], None);
+                    copy(
+                        &mut out,
+                        &self.ours[ours.clone()],
+                        Some(self.newline(chunk)),
+                    );
+                    copy(&mut out, &self.theirs[theirs], None);
+                }
+                Take::Conflict => {
+                    let newline = self.newline(chunk);
+                    let size = options.marker_size;
+                    copy(&mut out, &self.ours[written..ours.start], None);
+                    marker(&mut out, b'<', size, Some(labels.ours), newline);
+                    copy(&mut out, &self.ours[ours.clone()], Some(newline));
+                    if options.style != Style::Merge {
+                        let base = chunk.base as usize..(chunk.base + chunk.base_len) as usize;
+                        marker(&mut out, b'|', size, Some(labels.base), newline);
+                        copy(&mut out, &self.base[base], Some(newline));
+                    }
+                    marker(&mut out, b'=', size, None, newline);
+                    copy(&mut out, &self.theirs[theirs], Some(newline));
+                    marker(&mut out, b'>', size, Some(labels.theirs), newline);
+                }
+            }
+            written = ours.end;
+        }
+        copy(&mut out, &self.ours[written..], None);
+        out
+    }
+
+    /// How conflict markers and lines added to a conflict end: in CRLF when the lines before
+    /// it on both sides, or their first lines, do, and so does the first line of the base. A
+    /// side that gives nothing to go on is passed over.
+    fn newline(&self, chunk: &Chunk) -> &'static [u8] {
+        let before = |at: isize| (at - 1).max(0) as usize;
+        let mut crlf_lines = None;
+        for side in [
+            crlf(&self.ours, before(chunk.ours)),
+            crlf(&self.theirs, before(chunk.theirs)),
+            crlf(&self.base, 0),
+        ] {
+            crlf_lines = side;
+            if side == Some(false) {
+                break;
+            }
+        }
+        match crlf_lines {
+            Some(true) => b"\r\n",
+            _ => b"\n",
+        }
+    }
+}
+
+/// Whether line `at` ends in CRLF, judged from the line before for a last line without a
+/// newline. `None` when there is nothing to go on.
+fn crlf(lines: &[&[u8]], at: usize) -> Option<bool> {
+    let ends_crlf = |line: &[u8]| line.ends_with(b"\r\n");
+    let line = lines.get(at)?;
+    if at + 1 < lines.len() || line.ends_with(b"\n") {
+        return Some(ends_crlf(line));
+    }
+    if at == 0 {
+        return None;
+    }
+    Some(ends_crlf(lines[at - 1]))
+}
+
+/// Copies lines, ending the last with `newline` if it has none and one is given.
+fn copy(out: &mut Vec<u8>, lines: &[&[u8]], newline: Option<&[u8]>) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if let (Some(newline), Some(last)) = (newline, lines.last()) {
+        if !last.ends_with(b"\n") {
+            out.extend_from_slice(newline);
+        }
+    }
+}
+
+fn marker(out: &mut Vec<u8>, c: u8, size: usize, label: Option<&str>, newline: &[u8]) {
+    out.resize(out.len() + size, c);
+    if let Some(label) = label {
+        out.push(b' ');
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.extend_from_slice(newline);
+}
@@ -3,13 +3,16 @@ use std::{
     io::{BufRead, BufReader},
 };
 
-use crate::objects::object::{self, Kind};
-use anyhow::{Context, Result};
+use crate::objects::{
+    error::{Error, IoContext, Result},
+    object::{self, Kind},
+};
 
 #[derive(Debug)]
 pub(crate) struct Commit {
     pub tree: String,
-    pub parent: String,
+    /// empty for a root commit, more than one for a merge
+    pub parents: Vec<String>,
     pub author: String,
     pub author_email: String,
     pub author_time: String,
@@ -18,64 +21,116 @@ pub(crate) struct Commit {
     pub committer_email: String,
     pub committer_time: String,
     pub committer_offset: String,
+    /// headers after the committer such as `encoding`, `gpgsig` or `mergetag`, in order and
+    /// with continuation lines joined by newlines
+    pub extra_headers: Vec<(String, String)>,
     pub message: String,
 }
 
 impl Commit {
     pub fn read_from_hash(hash: &str) -> Result<Commit> {
         let mut object = object::Object::try_from(hash)?;
-        anyhow::ensure!(object.kind == Kind::Commit);
+        if object.kind != Kind::Commit {
+            return Err(Error::corrupt(hash, "expected a commit"));
+        }
         let mut bufread = BufReader::new(&mut object.reader);
-        Commit::read(&mut bufread)
+        Commit::read(&mut bufread).map_err(|e| e.with_id(hash))
     }
     pub fn read(bufread: &mut impl BufRead) -> Result<Commit> {
-        let mut tree_hash = String::new();
-        bufread.read_line(&mut tree_hash)?;
-        let (_, tree_hash) = tree_hash
-            .split_once(' ')
-            .context("error in formatting in tree hash")?;
-
-        let mut parent_hash = String::new();
-        bufread.read_line(&mut parent_hash)?;
-        let (_, parent_hash) = parent_hash
-            .split_once(' ')
-            .context("error in formatting in parent hash")?;
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers: Vec<(String, String)> = Vec::new();
 
-        let mut author = String::new();
-        bufread.read_line(&mut author)?;
-        let author_vec: Vec<_> = author.split_whitespace().collect();
-        anyhow::ensure!(author_vec.len() == 6, "author entry wrong format");
+        loop {
+            let mut line = String::new();
+            let bytes = bufread
+                .read_line(&mut line)
+                .io_context("reading commit header")?;
+            if bytes == 0 {
+                // a commit with no message doesn't even have the blank line
+                break;
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            if line.is_empty() {
+                break;
+            }
+            if let Some(continuation) = line.strip_prefix(' ') {
+                let (_, value) = extra_headers
+                    .last_mut()
+                    .ok_or_else(|| Error::corrupt("", "continuation line with no header"))?;
+                value.push('\n');
+                value.push_str(continuation);
+                continue;
+            }
 
-        let mut committer = String::new();
-        bufread.read_line(&mut committer)?;
-        let committer_vec: Vec<_> = committer.split_whitespace().collect();
-        anyhow::ensure!(committer_vec.len() == 6, "committer entry wrong format");
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| Error::corrupt("", format!("malformed header line {}", line)))?;
+            match key {
+                "tree" => tree = Some(value.to_string()),
+                "parent" => parents.push(value.to_string()),
+                "author" => author = Some(parse_signature(value, "author")?),
+                "committer" => committer = Some(parse_signature(value, "committer")?),
+                _ => extra_headers.push((key.to_string(), value.to_string())),
+            }
+        }
 
         let mut message = String::new();
-        bufread.read_line(&mut message)?;
-        message.clear();
-        bufread.read_line(&mut message)?;
+        bufread
+            .read_to_string(&mut message)
+            .map_err(|_| Error::corrupt("", "message is not valid utf-8"))?;
+
+        let tree = tree.ok_or_else(|| Error::corrupt("", "error in formatting in tree hash"))?;
+        let (author, author_email, author_time, author_offset) =
+            author.ok_or_else(|| Error::corrupt("", "author entry wrong format"))?;
+        let (committer, committer_email, committer_time, committer_offset) =
+            committer.ok_or_else(|| Error::corrupt("", "committer entry wrong format"))?;
 
         Ok(Commit {
-            tree: tree_hash.trim_end().to_string(),
-            parent: parent_hash.trim_end().to_string(),
-            author: format!("{} {}", &author_vec[1], &author_vec[2]),
-            author_email: author_vec[3].to_string(),
-            author_time: author_vec[4].to_string(),
-            author_offset: author_vec[5].trim_end().to_string(),
-            committer: format!("{} {}", &committer_vec[1], &committer_vec[2]),
-            committer_email: committer_vec[3].to_string(),
-            committer_time: committer_vec[4].to_string(),
-            committer_offset: committer_vec[5].trim_end().to_string(),
+            tree,
+            parents,
+            author,
+            author_email,
+            author_time,
+            author_offset,
+            committer,
+            committer_email,
+            committer_time,
+            committer_offset,
+            extra_headers,
             message,
         })
     }
 }
 
+/// Splits `Name Possibly Several Words <email> 1738872224 +0100` into the name, the email with
+/// its angle brackets, the timestamp and the offset.
+fn parse_signature(value: &str, which: &str) -> Result<(String, String, String, String)> {
+    let wrong = || Error::corrupt("", format!("{} entry wrong format", which));
+    let email_start = value.find('<').ok_or_else(wrong)?;
+    let email_end = value.rfind('>').ok_or_else(wrong)?;
+    if email_end < email_start {
+        return Err(wrong());
+    }
+    let mut time = value[email_end + 1..].split_whitespace();
+    let timestamp = time.next().ok_or_else(wrong)?;
+    let offset = time.next().ok_or_else(wrong)?;
+    Ok((
+        value[..email_start].trim_end().to_string(),
+        value[email_start..=email_end].to_string(),
+        timestamp.to_string(),
+        offset.to_string(),
+    ))
+}
+
 impl Display for Commit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "tree {}", self.tree)?;
-        writeln!(f, "parent {}", self.parent)?;
+        for parent in &self.parents {
+            writeln!(f, "parent {}", parent)?;
+        }
         writeln!(
             f,
             "author {} {} {} {}",
@@ -83,9 +138,13 @@ impl Display for Commit {
         )?;
         writeln!(
             f,
-            "committer {} {} {} {}\n",
+            "committer {} {} {} {}",
             self.committer, self.committer_email, self.committer_time, self.committer_offset
         )?;
+        for (key, value) in &self.extra_headers {
+            writeln!(f, "{} {}", key, value.replace('\n', "\n "))?;
+        }
+        writeln!(f)?;
         write!(f, "{}", self.message)?;
         Ok(())
     }
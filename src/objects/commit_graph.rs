@@ -0,0 +1,867 @@
+//! The commit-graph: the tree, parents, date and generation number of every commit in it, kept
+//! where they can be looked up without inflating any objects.
+//!
+//! `.git/objects/info/commit-graph` holds one file. A split graph is instead a chain of layers
+//! under `commit-graphs/`, listed base first in `commit-graph-chain`, each adding the commits
+//! the layers below don't have. Positions count through the layers from the base, so a parent
+//! can be looked up in whichever layer holds it.
+
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{ErrorKind, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+use crate::objects::{
+    commit::Commit,
+    error::{Error, IoContext, Result},
+};
+
+const GRAPH: &str = ".git/objects/info/commit-graph";
+const GRAPHS: &str = ".git/objects/info/commit-graphs";
+const CHAIN: &str = ".git/objects/info/commit-graphs/commit-graph-chain";
+
+const SIGNATURE: u32 = 0x43475048; // "CGPH"
+const VERSION: u8 = 1;
+const HASH_VERSION: u8 = 1; // sha1
+const HASH_LEN: usize = 20;
+const HEADER_LEN: usize = 8;
+const CHUNK_ENTRY_LEN: usize = 12;
+const FANOUT_LEN: usize = 256 * 4;
+const DATA_LEN: usize = HASH_LEN + 16;
+/// The header, a table of three chunks and its terminator, the fanout and the checksum.
+const MIN_LEN: usize = HEADER_LEN + 4 * CHUNK_ENTRY_LEN + FANOUT_LEN + HASH_LEN;
+
+const OID_FANOUT: u32 = 0x4f494446; // "OIDF"
+const OID_LOOKUP: u32 = 0x4f49444c; // "OIDL"
+const COMMIT_DATA: u32 = 0x43444154; // "CDAT"
+const GENERATION_DATA: u32 = 0x47444132; // "GDA2"
+const GENERATION_OVERFLOW: u32 = 0x47444f32; // "GDO2"
+const EXTRA_EDGES: u32 = 0x45444745; // "EDGE"
+const BASE_GRAPHS: u32 = 0x42415345; // "BASE"
+
+/// A parent slot with no parent in it.
+const PARENT_NONE: u32 = 0x70000000;
+/// Set on the second parent slot of an octopus merge, which then indexes the extra edges.
+const EXTRA_EDGES_NEEDED: u32 = 0x80000000;
+/// Set on the last of a commit's extra edges.
+const LAST_EDGE: u32 = 0x80000000;
+/// Set on a generation offset too big to store inline, which then indexes the overflow chunk.
+const OFFSET_OVERFLOW: u32 = 0x80000000;
+const OFFSET_MAX: u64 = 0x7fffffff;
+const TOPO_LEVEL_MAX: u32 = 0x3fffffff;
+
+/// The generation of a commit the graph doesn't have: after everything that it does.
+pub const GENERATION_INFINITY: u64 = (1 << 63) - 1;
+
+/// How a split write treats the layers already there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Split {
+    /// merge the new layer with those below it that aren't much bigger than it
+    Merge,
+    /// always add a new layer on top
+    NoMerge,
+    /// write everything as a single layer
+    Replace,
+}
+
+/// What the graph knows about a commit.
+#[derive(Debug, Clone)]
+pub(crate) struct GraphCommit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    /// the committer date
+    pub time: i64,
+    /// the corrected commit date if every layer has them, otherwise the topological level;
+    /// either way more than that of any parent
+    pub generation: u64,
+}
+
+struct Layer {
+    data: Vec<u8>,
+    /// the trailing checksum, which names the file of a layer in a chain
+    checksum: String,
+    commits: u32,
+    /// the commits in the layers below this one
+    base: u32,
+    /// how many layers the file says are below it
+    base_count: u8,
+    fanout: usize,
+    oids: usize,
+    commit_data: usize,
+    generations: Option<usize>,
+    overflow: Option<usize>,
+    extra_edges: Option<usize>,
+    base_graphs: Option<usize>,
+}
+
+pub(crate) struct CommitGraph {
+    /// the base layer first
+    layers: Vec<Layer>,
+    /// whether it was read from a chain rather than the single file
+    split: bool,
+    /// whether every layer has corrected commit dates to give as generations
+    corrected: bool,
+}
+
+fn be32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn be64(data: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn corrupt(reason: impl Into<String>) -> Error {
+    Error::corrupt("commit-graph", reason)
+}
+
+impl Layer {
+    fn parse(data: Vec<u8>) -> Result<Layer> {
+        if data.len() < MIN_LEN {
+            return Err(corrupt("commit-graph file is too small"));
+        }
+        let signature = be32(&data, 0);
+        if signature != SIGNATURE {
+            return Err(corrupt(format!(
+                "commit-graph signature {:X} does not match signature {:X}",
+                signature, SIGNATURE
+            )));
+        }
+        if data[4] != VERSION {
+            return Err(corrupt(format!(
+                "commit-graph version {:X} does not match version {:X}",
+                data[4], VERSION
+            )));
+        }
+        if data[5] != HASH_VERSION {
+            return Err(corrupt(format!(
+                "commit-graph hash version {:X} does not match version {:X}",
+                data[5], HASH_VERSION
+            )));
+        }
+
+        let chunk_count = data[6] as usize;
+        let table_end = HEADER_LEN + (chunk_count + 1) * CHUNK_ENTRY_LEN;
+        if table_end > data.len() - HASH_LEN {
+            return Err(corrupt("commit-graph chunk lookup table entry missing"));
+        }
+        let mut chunks = HashMap::new();
+        for nth in 0..chunk_count {
+            let entry = HEADER_LEN + nth * CHUNK_ENTRY_LEN;
+            let id = be32(&data, entry);
+            let start = be64(&data, entry + 4) as usize;
+            let end = be64(&data, entry + CHUNK_ENTRY_LEN + 4) as usize;
+            if start < table_end || end < start || end > data.len() - HASH_LEN {
+                return Err(corrupt(format!(
+                    "commit-graph improper chunk offset {:08x}",
+                    start
+                )));
+            }
+            chunks.insert(id, (start, end - start));
+        }
+
+        let fanout = match chunks.get(&OID_FANOUT) {
+            Some(&(start, FANOUT_LEN)) => start,
+            _ => {
+                return Err(corrupt(
+                    "commit-graph required OID fanout chunk is missing or corrupted",
+                ))
+            }
+        };
+        let commits = be32(&data, fanout + FANOUT_LEN - 4);
+        let sized = |id: u32, each: usize| match chunks.get(&id) {
+            Some(&(start, len)) if len == commits as usize * each => Some(start),
+            _ => None,
+        };
+        let oids = sized(OID_LOOKUP, HASH_LEN).ok_or_else(|| {
+            corrupt("commit-graph required OID lookup chunk is missing or corrupted")
+        })?;
+        let commit_data = sized(COMMIT_DATA, DATA_LEN).ok_or_else(|| {
+            corrupt("commit-graph required commit data chunk is missing or corrupted")
+        })?;
+
+        let checksum = hex::encode(&data[data.len() - HASH_LEN..]);
+        Ok(Layer {
+            base_count: data[7],
+            generations: sized(GENERATION_DATA, 4),
+            overflow: chunks.get(&GENERATION_OVERFLOW).map(|&(start, _)| start),
+            extra_edges: chunks.get(&EXTRA_EDGES).map(|&(start, _)| start),
+            base_graphs: chunks
+                .get(&BASE_GRAPHS)
+                .filter(|&&(_, len)| len == data[7] as usize * HASH_LEN)
+                .map(|&(start, _)| start),
+            data,
+            checksum,
+            commits,
+            base: 0,
+            fanout,
+            oids,
+            commit_data,
+        })
+    }
+
+    fn oid(&self, index: u32) -> &[u8] {
+        let at = self.oids + index as usize * HASH_LEN;
+        &self.data[at..at + HASH_LEN]
+    }
+
+    fn fanout(&self, byte: usize) -> u32 {
+        be32(&self.data, self.fanout + byte * 4)
+    }
+
+    /// The index within this layer of the commit with this raw id.
+    fn find(&self, raw: &[u8]) -> Option<u32> {
+        let first = raw[0] as usize;
+        let mut low = if first == 0 {
+            0
+        } else {
+            self.fanout(first - 1)
+        };
+        let mut high = self.fanout(first).min(self.commits);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.oid(middle).cmp(raw) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    fn checksum_is_valid(&self) -> bool {
+        let end = self.data.len() - HASH_LEN;
+        Sha1::digest(&self.data[..end]).as_slice() == &self.data[end..]
+    }
+}
+
+impl CommitGraph {
+    /// The graph of the repository, from the single file if there is one and otherwise from
+    /// the chain of layers, or None if there's neither.
+    pub fn load() -> Result<Option<CommitGraph>> {
+        match fs::read(GRAPH) {
+            Ok(data) => return Ok(Some(CommitGraph::new(vec![Layer::parse(data)?], false))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).io_context("reading the commit-graph"),
+        }
+        let chain = match fs::read_to_string(CHAIN) {
+            Ok(chain) => chain,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).io_context("reading the commit-graph chain"),
+        };
+        let mut layers: Vec<Layer> = Vec::new();
+        for (nth, name) in chain.lines().enumerate() {
+            let path = format!("{}/graph-{}.graph", GRAPHS, name);
+            let data = fs::read(&path).io_context(format!("unable to find {}", path))?;
+            let mut layer = Layer::parse(data)?;
+            let matches_chain = layer.base_count as usize == nth
+                && (nth == 0
+                    || layer.base_graphs.is_some_and(|start| {
+                        layers.iter().enumerate().all(|(i, below)| {
+                            let at = start + i * HASH_LEN;
+                            hex::encode(&layer.data[at..at + HASH_LEN]) == below.checksum
+                        })
+                    }));
+            if !matches_chain {
+                return Err(corrupt("commit-graph chain does not match"));
+            }
+            layer.base = layers.iter().map(|below| below.commits).sum();
+            layers.push(layer);
+        }
+        if layers.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(CommitGraph::new(layers, true)))
+    }
+
+    fn new(layers: Vec<Layer>, split: bool) -> CommitGraph {
+        let corrected = layers.iter().all(|layer| layer.generations.is_some());
+        CommitGraph {
+            layers,
+            split,
+            corrected,
+        }
+    }
+
+    /// How many commits there are in all the layers.
+    pub fn len(&self) -> u32 {
+        self.layers
+            .last()
+            .map_or(0, |layer| layer.base + layer.commits)
+    }
+
+    /// Whether the generations are corrected commit dates, rather than topological levels.
+    pub fn has_corrected_dates(&self) -> bool {
+        self.corrected
+    }
+
+    /// The position of a commit counting through the layers from the base.
+    pub fn position(&self, hash: &str) -> Option<u32> {
+        let raw = hex::decode(hash).ok().filter(|raw| raw.len() == HASH_LEN)?;
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.find(&raw).map(|index| layer.base + index))
+    }
+
+    fn layer(&self, position: u32) -> Result<(&Layer, u32)> {
+        self.layers
+            .iter()
+            .find(|layer| position >= layer.base && position < layer.base + layer.commits)
+            .map(|layer| (layer, position - layer.base))
+            .ok_or_else(|| corrupt(format!("invalid commit position {}", position)))
+    }
+
+    pub fn hash_at(&self, position: u32) -> Result<String> {
+        let (layer, index) = self.layer(position)?;
+        Ok(hex::encode(layer.oid(index)))
+    }
+
+    /// What the graph has for a commit, or None if it doesn't have it.
+    pub fn lookup(&self, hash: &str) -> Result<Option<GraphCommit>> {
+        match self.position(hash) {
+            Some(position) => Ok(Some(self.commit_at(position)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The generation of a commit, `GENERATION_INFINITY` if the graph doesn't have it.
+    pub fn generation(&self, hash: &str) -> Result<u64> {
+        match self.position(hash) {
+            Some(position) => {
+                let (layer, index) = self.layer(position)?;
+                self.generation_at(layer, index)
+            }
+            None => Ok(GENERATION_INFINITY),
+        }
+    }
+
+    pub fn commit_at(&self, position: u32) -> Result<GraphCommit> {
+        let (layer, index) = self.layer(position)?;
+        let data = &layer.data;
+        let at = layer.commit_data + index as usize * DATA_LEN;
+
+        let mut parents = Vec::new();
+        let first = be32(data, at + HASH_LEN);
+        if first != PARENT_NONE {
+            parents.push(self.hash_at(first)?);
+        }
+        let second = be32(data, at + HASH_LEN + 4);
+        if second != PARENT_NONE {
+            if second & EXTRA_EDGES_NEEDED == 0 {
+                parents.push(self.hash_at(second)?);
+            } else {
+                let edges = layer
+                    .extra_edges
+                    .ok_or_else(|| corrupt("commit-graph has no extra edges chunk"))?;
+                let mut edge = edges + (second & !EXTRA_EDGES_NEEDED) as usize * 4;
+                loop {
+                    if edge + 4 > data.len() - HASH_LEN {
+                        return Err(corrupt("commit-graph extra edges out of bounds"));
+                    }
+                    let parent = be32(data, edge);
+                    parents.push(self.hash_at(parent & !LAST_EDGE)?);
+                    if parent & LAST_EDGE != 0 {
+                        break;
+                    }
+                    edge += 4;
+                }
+            }
+        }
+
+        Ok(GraphCommit {
+            tree: hex::encode(&data[at..at + HASH_LEN]),
+            parents,
+            time: commit_time(data, at),
+            generation: self.generation_at(layer, index)?,
+        })
+    }
+
+    fn generation_at(&self, layer: &Layer, index: u32) -> Result<u64> {
+        let data = &layer.data;
+        let at = layer.commit_data + index as usize * DATA_LEN;
+        let (Some(generations), true) = (layer.generations, self.corrected) else {
+            return Ok((be32(data, at + HASH_LEN + 8) >> 2) as u64);
+        };
+        let time = commit_time(data, at) as u64;
+        let offset = be32(data, generations + index as usize * 4);
+        if offset & OFFSET_OVERFLOW == 0 {
+            return Ok(time + offset as u64);
+        }
+        let overflow = layer
+            .overflow
+            .map(|start| start + (offset & !OFFSET_OVERFLOW) as usize * 8)
+            .filter(|&at| at + 8 <= data.len() - HASH_LEN)
+            .ok_or_else(|| corrupt("commit-graph overflow generation data is too small"))?;
+        Ok(time + be64(data, overflow))
+    }
+
+    /// Checks the layers against their checksums and the commits in them against the objects
+    /// they came from, giving a message for each problem found. With `shallow` only the top
+    /// layer is checked.
+    pub fn verify(&self, shallow: bool) -> Result<Vec<String>> {
+        let mut problems = Vec::new();
+        for layer in self.layers.iter().rev() {
+            self.verify_layer(layer, &mut problems)?;
+            if shallow {
+                break;
+            }
+        }
+        Ok(problems)
+    }
+
+    fn verify_layer(&self, layer: &Layer, problems: &mut Vec<String>) -> Result<()> {
+        if !layer.checksum_is_valid() {
+            problems
+                .push("the commit-graph file has incorrect checksum and is likely corrupt".into());
+        }
+        let mut structural = false;
+        let mut fanout = 0;
+        for index in 0..layer.commits {
+            let oid = layer.oid(index);
+            if index > 0 && layer.oid(index - 1) >= oid {
+                problems.push(format!(
+                    "commit-graph has incorrect OID order: {} then {}",
+                    hex::encode(layer.oid(index - 1)),
+                    hex::encode(oid)
+                ));
+                structural = true;
+            }
+            while oid[0] as usize > fanout {
+                if layer.fanout(fanout) != index {
+                    problems.push(format!(
+                        "commit-graph has incorrect fanout value: fanout[{}] = {} != {}",
+                        fanout,
+                        layer.fanout(fanout),
+                        index
+                    ));
+                    structural = true;
+                }
+                fanout += 1;
+            }
+        }
+        while fanout < 256 {
+            if layer.fanout(fanout) != layer.commits {
+                problems.push(format!(
+                    "commit-graph has incorrect fanout value: fanout[{}] = {} != {}",
+                    fanout,
+                    layer.fanout(fanout),
+                    layer.commits
+                ));
+                structural = true;
+            }
+            fanout += 1;
+        }
+        if structural {
+            return Ok(());
+        }
+
+        for index in 0..layer.commits {
+            let hash = hex::encode(layer.oid(index));
+            let graph = match self.commit_at(layer.base + index) {
+                Ok(graph) => graph,
+                Err(_) => {
+                    problems.push(format!("failed to parse commit {} from commit-graph", hash));
+                    continue;
+                }
+            };
+            let Ok(odb) = Commit::read_from_hash(&hash) else {
+                problems.push(format!(
+                    "failed to parse commit {} from object database for commit-graph",
+                    hash
+                ));
+                continue;
+            };
+            if graph.tree != odb.tree {
+                problems.push(format!(
+                    "root tree OID for commit {} in commit-graph is {} != {}",
+                    hash, graph.tree, odb.tree
+                ));
+            }
+
+            let mut max_generation = 0;
+            for (nth, parent) in graph.parents.iter().enumerate() {
+                let Some(odb_parent) = odb.parents.get(nth) else {
+                    problems.push(format!(
+                        "commit-graph parent list for commit {} is too long",
+                        hash
+                    ));
+                    break;
+                };
+                if parent != odb_parent {
+                    problems.push(format!(
+                        "commit-graph parent for {} is {} != {}",
+                        hash, parent, odb_parent
+                    ));
+                }
+                max_generation = max_generation.max(self.generation(parent)?);
+            }
+            if odb.parents.len() > graph.parents.len() {
+                problems.push(format!(
+                    "commit-graph parent list for commit {} terminates early",
+                    hash
+                ));
+            }
+
+            // a topological level stops counting at its maximum
+            if !self.corrected && max_generation == TOPO_LEVEL_MAX as u64 {
+                max_generation -= 1;
+            }
+            if graph.generation < max_generation + 1 {
+                problems.push(format!(
+                    "commit-graph generation for commit {} is {} < {}",
+                    hash,
+                    graph.generation,
+                    max_generation + 1
+                ));
+            }
+            let odb_time = odb.committer_time.parse::<i64>().unwrap_or(0);
+            if graph.time != odb_time {
+                problems.push(format!(
+                    "commit date for commit {} in commit-graph is {} != {}",
+                    hash, graph.time, odb_time
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The 34 bit committer date, whose top two bits share a word with the topological level.
+fn commit_time(data: &[u8], at: usize) -> i64 {
+    let high = (be32(data, at + HASH_LEN + 8) & 3) as i64;
+    let low = be32(data, at + HASH_LEN + 12) as i64;
+    (high << 32) | low
+}
+
+/// A commit to be written, with what's worked out for it along the way.
+struct Entry {
+    tree: String,
+    parents: Vec<String>,
+    time: i64,
+}
+
+/// Writes a graph of `commits` and every commit they reach, if there are any. Without `split`
+/// that's the single file, replacing any chain. A split write adds only the commits the chain
+/// doesn't have yet, as a new layer or merged with the layers below it as `split` says.
+pub fn write(commits: &[String], split: Option<Split>) -> Result<()> {
+    let existing = match split {
+        Some(_) => CommitGraph::load()?,
+        None => None,
+    };
+    let in_existing = |hash: &str| {
+        existing
+            .as_ref()
+            .is_some_and(|graph| graph.position(hash).is_some())
+    };
+
+    let mut entries: HashMap<String, Entry> = HashMap::new();
+    let mut pending: Vec<String> = commits.to_vec();
+    while let Some(hash) = pending.pop() {
+        if entries.contains_key(&hash) || in_existing(&hash) {
+            continue;
+        }
+        let commit = Commit::read_from_hash(&hash)?;
+        pending.extend(commit.parents.iter().cloned());
+        entries.insert(
+            hash,
+            Entry {
+                tree: commit.tree,
+                parents: commit.parents,
+                time: commit.committer_time.parse().unwrap_or(0),
+            },
+        );
+    }
+    if entries.is_empty() && split != Some(Split::Replace) {
+        return Ok(());
+    }
+
+    // the layers kept as they are below the new one; the rest are merged into it
+    let layer_count = existing.as_ref().map_or(0, |graph| graph.layers.len());
+    let mut kept = layer_count;
+    if let Some(graph) = &existing {
+        match split {
+            Some(Split::Replace) => kept = 0,
+            Some(Split::Merge) => {
+                let mut count = entries.len() as u64;
+                while kept > 0 && graph.layers[kept - 1].commits as u64 <= 2 * count {
+                    count += graph.layers[kept - 1].commits as u64;
+                    kept -= 1;
+                }
+            }
+            _ => {}
+        }
+        for layer in &graph.layers[kept..] {
+            for index in 0..layer.commits {
+                let commit = graph.commit_at(layer.base + index)?;
+                entries.insert(
+                    hex::encode(layer.oid(index)),
+                    Entry {
+                        tree: commit.tree,
+                        parents: commit.parents,
+                        time: commit.time,
+                    },
+                );
+            }
+        }
+    }
+    let base_layers: &[Layer] = existing.as_ref().map_or(&[], |graph| &graph.layers[..kept]);
+    let base_commits: u32 = base_layers.iter().map(|layer| layer.commits).sum();
+
+    let mut hashes: Vec<&String> = entries.keys().collect();
+    hashes.sort();
+    let positions: HashMap<&str, u32> = hashes
+        .iter()
+        .enumerate()
+        .map(|(index, hash)| (hash.as_str(), base_commits + index as u32))
+        .collect();
+    let position = |hash: &str| -> Result<u32> {
+        match positions.get(hash) {
+            Some(&position) => Ok(position),
+            None => existing
+                .as_ref()
+                .and_then(|graph| graph.position(hash))
+                .filter(|&position| position < base_commits)
+                .ok_or_else(|| corrupt(format!("missing parent {} for commit-graph", hash))),
+        }
+    };
+    let generations = generations(&hashes, &entries, existing.as_ref())?;
+
+    let mut counts = [0u32; 256];
+    for hash in &hashes {
+        counts[u8::from_str_radix(&hash[..2], 16).unwrap_or(0) as usize] += 1;
+    }
+    let mut fanout = Vec::with_capacity(FANOUT_LEN);
+    let mut total = 0;
+    for count in counts {
+        total += count;
+        fanout.extend_from_slice(&total.to_be_bytes());
+    }
+    let mut oids = Vec::with_capacity(hashes.len() * HASH_LEN);
+    let mut commit_data = Vec::with_capacity(hashes.len() * DATA_LEN);
+    let mut generation_data = Vec::with_capacity(hashes.len() * 4);
+    let mut overflow = Vec::new();
+    let mut extra_edges = Vec::new();
+    for hash in &hashes {
+        let entry = &entries[*hash];
+        let (level, corrected) = generations[hash.as_str()];
+        oids.extend_from_slice(&hex::decode(hash).map_err(|_| corrupt("invalid commit id"))?);
+        commit_data
+            .extend_from_slice(&hex::decode(&entry.tree).map_err(|_| corrupt("invalid tree id"))?);
+        let first = match entry.parents.first() {
+            Some(parent) => position(parent)?,
+            None => PARENT_NONE,
+        };
+        let second = match entry.parents.len() {
+            0 | 1 => PARENT_NONE,
+            2 => position(&entry.parents[1])?,
+            _ => EXTRA_EDGES_NEEDED | (extra_edges.len() / 4) as u32,
+        };
+        if entry.parents.len() > 2 {
+            let last = entry.parents.len() - 1;
+            for (nth, parent) in entry.parents.iter().enumerate().skip(1) {
+                let mut edge = position(parent)?;
+                if nth == last {
+                    edge |= LAST_EDGE;
+                }
+                extra_edges.extend_from_slice(&edge.to_be_bytes());
+            }
+        }
+        let time = entry.time.max(0) as u64;
+        commit_data.extend_from_slice(&first.to_be_bytes());
+        commit_data.extend_from_slice(&second.to_be_bytes());
+        commit_data.extend_from_slice(&((level << 2) | ((time >> 32) as u32 & 3)).to_be_bytes());
+        commit_data.extend_from_slice(&(time as u32).to_be_bytes());
+
+        let offset = corrected - time;
+        let offset = if offset > OFFSET_MAX {
+            overflow.extend_from_slice(&offset.to_be_bytes());
+            OFFSET_OVERFLOW | (overflow.len() / 8 - 1) as u32
+        } else {
+            offset as u32
+        };
+        generation_data.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    let mut chunks = vec![
+        (OID_FANOUT, fanout),
+        (OID_LOOKUP, oids),
+        (COMMIT_DATA, commit_data),
+        (GENERATION_DATA, generation_data),
+    ];
+    if !overflow.is_empty() {
+        chunks.push((GENERATION_OVERFLOW, overflow));
+    }
+    if !extra_edges.is_empty() {
+        chunks.push((EXTRA_EDGES, extra_edges));
+    }
+    if !base_layers.is_empty() {
+        let mut bases = Vec::new();
+        for layer in base_layers {
+            bases.extend_from_slice(&layer.data[layer.data.len() - HASH_LEN..]);
+        }
+        chunks.push((BASE_GRAPHS, bases));
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&SIGNATURE.to_be_bytes());
+    data.extend_from_slice(&[
+        VERSION,
+        HASH_VERSION,
+        chunks.len() as u8,
+        base_layers.len() as u8,
+    ]);
+    let mut offset = (HEADER_LEN + (chunks.len() + 1) * CHUNK_ENTRY_LEN) as u64;
+    for (id, chunk) in &chunks {
+        data.extend_from_slice(&id.to_be_bytes());
+        data.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        data.extend_from_slice(chunk);
+    }
+    let checksum = Sha1::digest(&data);
+    data.extend_from_slice(&checksum);
+    let checksum = hex::encode(checksum);
+
+    if split.is_none() {
+        write_file(GRAPH, &data)?;
+        // the single file is read first anyway, so a chain would only go stale
+        remove(CHAIN)?;
+        expire(&[])?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(GRAPHS).io_context("creating the commit-graphs directory")?;
+    let mut chain: Vec<String> = base_layers
+        .iter()
+        .map(|layer| layer.checksum.clone())
+        .collect();
+    if let Some(graph) = &existing {
+        if !graph.split && kept == 1 {
+            // the single file becomes the base of the chain
+            fs::rename(GRAPH, format!("{}/graph-{}.graph", GRAPHS, chain[0]))
+                .io_context("moving the commit-graph into the chain")?;
+        }
+    }
+    write_file(&format!("{}/graph-{}.graph", GRAPHS, checksum), &data)?;
+    chain.push(checksum);
+    let mut contents = String::new();
+    for name in &chain {
+        contents.push_str(name);
+        contents.push('\n');
+    }
+    write_file(CHAIN, contents.as_bytes())?;
+    remove(GRAPH)?;
+    expire(&chain)?;
+    Ok(())
+}
+
+/// The topological level and corrected commit date of each commit to be written, working out
+/// those of the commits they reach in the layers kept below as well.
+fn generations(
+    hashes: &[&String],
+    entries: &HashMap<String, Entry>,
+    existing: Option<&CommitGraph>,
+) -> Result<HashMap<String, (u32, u64)>> {
+    let mut done: HashMap<String, (u32, u64)> = HashMap::new();
+    let mut below: HashMap<String, GraphCommit> = HashMap::new();
+    for hash in hashes {
+        let mut stack = vec![hash.to_string()];
+        while let Some(hash) = stack.last().cloned() {
+            if done.contains_key(&hash) {
+                stack.pop();
+                continue;
+            }
+            let (parents, time) = match entries.get(&hash) {
+                Some(entry) => (entry.parents.clone(), entry.time),
+                None => {
+                    if !below.contains_key(&hash) {
+                        let commit = existing
+                            .and_then(|graph| graph.lookup(&hash).transpose())
+                            .transpose()?
+                            .ok_or_else(|| corrupt(format!("missing commit {}", hash)))?;
+                        below.insert(hash.clone(), commit);
+                    }
+                    (below[&hash].parents.clone(), below[&hash].time)
+                }
+            };
+            let missing: Vec<String> = parents
+                .iter()
+                .filter(|parent| !done.contains_key(*parent))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            let time = time.max(0) as u64;
+            let (mut level, mut corrected) = (0, time);
+            for parent in &parents {
+                let (parent_level, parent_corrected) = done[parent];
+                level = level.max(parent_level);
+                corrected = corrected.max(parent_corrected + 1);
+            }
+            done.insert(hash, ((level + 1).min(TOPO_LEVEL_MAX), corrected));
+            stack.pop();
+        }
+    }
+    Ok(done)
+}
+
+/// Writes a file through a temporary one next to it, read-only as git leaves them.
+fn write_file(path: &str, data: &[u8]) -> Result<()> {
+    let temp = format!("{}.lock", path);
+    let mut file = fs::File::create_new(&temp).io_context(format!(
+        "Unable to create '{}': File exists. Another git process seems to be running in this \
+         repository",
+        temp
+    ))?;
+    let written = file
+        .write_all(data)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::set_permissions(&temp, fs::Permissions::from_mode(0o444)))
+        .and_then(|_| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written.io_context(format!("writing {}", path))
+}
+
+fn remove(path: &str) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).io_context(format!("removing {}", path))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes the layer files the chain no longer lists.
+fn expire(chain: &[String]) -> Result<()> {
+    let dir = match fs::read_dir(GRAPHS) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).io_context("reading the commit-graphs directory"),
+    };
+    for file in dir {
+        let file = file.io_context("reading the commit-graphs directory")?;
+        let name = file.file_name().to_string_lossy().to_string();
+        let Some(checksum) = name
+            .strip_prefix("graph-")
+            .and_then(|rest| rest.strip_suffix(".graph"))
+        else {
+            continue;
+        };
+        if !chain.iter().any(|kept| kept == checksum) {
+            remove(&Path::new(GRAPHS).join(&name).to_string_lossy())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::{fmt::Display, io};
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+/// Errors coming out of the object database, the index, and the tree and commit parsers.
+///
+/// Commands still use `anyhow`, but wrap these so `main` can pick an exit code by looking for
+/// one of them in the error chain.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// no object (or file) matches this id
+    NotFound(String),
+    /// an abbreviated id matches more than one object
+    Ambiguous(String),
+    /// not something that can name an object, eg too short or not hex
+    InvalidId(String),
+    /// a type name other than blob, tree or commit
+    UnknownKind(String),
+    /// the data is there but not in the format it should be in
+    Corrupt {
+        id: String,
+        reason: String,
+    },
+    Io {
+        context: String,
+        source: io::Error,
+    },
+}
+
+impl Error {
+    pub fn corrupt(id: impl Into<String>, reason: impl Into<String>) -> Error {
+        Error::Corrupt {
+            id: id.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Fills in the id of a `Corrupt` error raised by a parser that only saw a reader.
+    pub fn with_id(self, id: &str) -> Error {
+        match self {
+            Error::Corrupt { id: old, reason } if old.is_empty() => Error::Corrupt {
+                id: id.to_string(),
+                reason,
+            },
+            other => other,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound(id) => write!(f, "not a valid object name {}", id),
+            Error::Ambiguous(id) => write!(f, "short object ID {} is ambiguous", id),
+            Error::InvalidId(id) => write!(f, "invalid object name {}", id),
+            Error::UnknownKind(kind) => write!(f, "invalid object type \"{}\"", kind),
+            Error::Corrupt { id, reason } if id.is_empty() => {
+                write!(f, "corrupt object: {}", reason)
+            }
+            Error::Corrupt { id, reason } => write!(f, "object {} is corrupt: {}", id, reason),
+            Error::Io { context, .. } => write!(f, "{}", context),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io {
+            context: "i/o error".to_string(),
+            source,
+        }
+    }
+}
+
+/// Like `anyhow::Context` but for io results inside the object modules.
+pub(crate) trait IoContext<T> {
+    fn io_context(self, context: impl Into<String>) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            context: context.into(),
+            source,
+        })
+    }
+}
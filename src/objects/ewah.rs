@@ -0,0 +1,73 @@
+//! The EWAH compressed bitmaps some index extensions are made of.
+//!
+//! On disk: the number of bits, the number of 64-bit words, the words, then the position of
+//! the last marker word, all big endian. Each marker word holds a run of identical words (bit
+//! 0 the value, bits 1-32 the length) followed by a count of literal words (bits 33-63).
+
+use crate::objects::error::{Error, Result};
+
+/// Reads a bitmap off the front of `data`, returning which bits are set and how many bytes it
+/// took up.
+pub fn read(data: &[u8]) -> Result<(Vec<bool>, usize)> {
+    let truncated = || Error::corrupt("index", "ewah bitmap is truncated");
+    let u32_at = |at: usize| -> Result<u32> {
+        let bytes = data.get(at..at + 4).ok_or_else(truncated)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let bit_size = u32_at(0)? as usize;
+    let word_count = u32_at(4)? as usize;
+    let words_end = 8 + word_count * 8;
+    let words: Vec<u64> = data
+        .get(8..words_end)
+        .ok_or_else(truncated)?
+        .chunks_exact(8)
+        .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+        .collect();
+    // the position of the last marker only matters to git for appending
+    u32_at(words_end)?;
+
+    let mut bits = Vec::with_capacity(bit_size);
+    let mut i = 0;
+    while i < words.len() {
+        let marker = words[i];
+        let run_bit = marker & 1 == 1;
+        let run_len = ((marker >> 1) & 0xffff_ffff) as usize;
+        let literals = (marker >> 33) as usize;
+        bits.extend(std::iter::repeat_n(run_bit, run_len * 64));
+        for literal in words.get(i + 1..i + 1 + literals).ok_or_else(truncated)? {
+            bits.extend((0..64).map(|b| literal >> b & 1 == 1));
+        }
+        i += 1 + literals;
+    }
+    if bits.len() < bit_size {
+        return Err(Error::corrupt(
+            "index",
+            "ewah bitmap is shorter than its size",
+        ));
+    }
+    bits.truncate(bit_size);
+    Ok((bits, words_end + 4))
+}
+
+/// Appends the bitmap for `bits` to `out`. Everything goes in as literal words behind a single
+/// marker, which any reader handles, and the size stops at the last set bit like git's.
+pub fn write(bits: &[bool], out: &mut Vec<u8>) {
+    let bit_size = bits.iter().rposition(|b| *b).map_or(0, |last| last + 1);
+    let literals: Vec<u64> = bits[..bit_size]
+        .chunks(64)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |word, (b, set)| word | (*set as u64) << b)
+        })
+        .collect();
+
+    out.extend_from_slice(&(bit_size as u32).to_be_bytes());
+    out.extend_from_slice(&(literals.len() as u32 + 1).to_be_bytes());
+    out.extend_from_slice(&((literals.len() as u64) << 33).to_be_bytes());
+    for literal in literals {
+        out.extend_from_slice(&literal.to_be_bytes());
+    }
+    out.extend_from_slice(&0u32.to_be_bytes());
+}
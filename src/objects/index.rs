@@ -1,50 +1,217 @@
-use std::os::macos::fs::MetadataExt;
-use std::os::unix::ffi::OsStringExt;
-use std::os::unix::fs::PermissionsExt;
+use sha1::{Digest, Sha1};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::{
     ffi::OsString,
     fs,
-    io::{BufRead, BufReader, Read},
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
     path::PathBuf,
 };
 
-use anyhow::{Context, Result};
+use crate::objects::{
+    error::{Error, IoContext, Result},
+    ewah,
+    untracked_cache::UntrackedCache,
+};
 
 #[derive(Debug)]
 pub struct IndexFile {
+    #[allow(dead_code)]
     pub signature: String,
     pub version: u32,
     pub entries: Vec<IndexEntry>,
+    /// the mtime of `.git/index` when it was read, `None` if there wasn't one
+    pub timestamp: Option<(u32, u32)>,
+    /// the `UNTR` extension
+    pub untracked_cache: Option<UntrackedCache>,
+    /// what to ask the fsmonitor hook for changes since, from the `FSMN` extension
+    pub fsmonitor_token: Option<String>,
 }
 impl IndexFile {
+    /// The index, or an empty one if the repository doesn't have one yet.
+    pub fn read_or_empty() -> Result<IndexFile> {
+        match fs::metadata(".git/index") {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(IndexFile {
+                signature: "DIRC".to_string(),
+                version: 2,
+                entries: Vec::new(),
+                timestamp: None,
+                untracked_cache: None,
+                fsmonitor_token: None,
+            }),
+            _ => IndexFile::read_from_index(),
+        }
+    }
+
+    /// Writes the entries back to `.git/index` through `.git/index.lock`, sorted by path and
+    /// stage the way git expects. Of the extensions only the untracked cache and fsmonitor
+    /// data are kept, git rebuilds the others as needed.
+    pub fn write(&mut self) -> Result<()> {
+        self.entries.sort_by(|a, b| {
+            (a.entry_path.as_os_str().as_bytes(), a.stage())
+                .cmp(&(b.entry_path.as_os_str().as_bytes(), b.stage()))
+        });
+        let extended = self.entries.iter().any(|e| e.extended_flags != 0);
+        self.version = if extended {
+            3
+        } else {
+            self.version.clamp(2, 3)
+        };
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"DIRC");
+        data.extend_from_slice(&self.version.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            entry.write_to(&mut data);
+        }
+        if let Some(cache) = &self.untracked_cache {
+            let mut extension = Vec::new();
+            cache.write_to(&mut extension);
+            write_extension(b"UNTR", &extension, &mut data);
+        }
+        if let Some(token) = &self.fsmonitor_token {
+            // version 2: the token, then a bitmap of the entries the hook can't vouch for
+            let mut extension = Vec::new();
+            extension.extend_from_slice(&2u32.to_be_bytes());
+            extension.extend_from_slice(token.as_bytes());
+            extension.push(0);
+            let dirty: Vec<bool> = self.entries.iter().map(|e| !e.fsmonitor_valid).collect();
+            let mut bitmap = Vec::new();
+            ewah::write(&dirty, &mut bitmap);
+            extension.extend_from_slice(&(bitmap.len() as u32).to_be_bytes());
+            extension.extend_from_slice(&bitmap);
+            write_extension(b"FSMN", &extension, &mut data);
+        }
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        let mut lock = fs::File::create_new(".git/index.lock").io_context(
+            "Unable to create '.git/index.lock': File exists. Another git process seems to be \
+             running in this repository",
+        )?;
+        let written = lock
+            .write_all(&data)
+            .and_then(|_| lock.sync_all())
+            .and_then(|_| fs::rename(".git/index.lock", ".git/index"));
+        if written.is_err() {
+            let _ = fs::remove_file(".git/index.lock");
+        }
+        written.io_context("writing index")
+    }
     pub fn read_from_index() -> Result<IndexFile> {
-        let index = fs::File::open(".git/index").context("opening index file")?;
+        let index = fs::File::open(".git/index").io_context("opening index file")?;
+        let metadata = index
+            .metadata()
+            .io_context("reading metadata of index file")?;
+        let timestamp = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
         let mut bufread = BufReader::new(index);
         let mut signature = [0u8; 4];
-        bufread.read_exact(&mut signature)?;
+        bufread
+            .read_exact(&mut signature)
+            .map_err(|_| Error::corrupt("index", "file is too short"))?;
         let signature = String::from_utf8_lossy(&signature);
 
-        anyhow::ensure!(signature == "DIRC", "wrong signature, should be D I R C");
+        if signature != "DIRC" {
+            return Err(Error::corrupt(
+                "index",
+                "wrong signature, should be D I R C",
+            ));
+        }
 
-        let version = read_u32(&mut bufread).context("reading version")?;
+        let version = read_u32(&mut bufread, "reading version")?;
+        if !(2..=3).contains(&version) {
+            return Err(Error::corrupt(
+                "index",
+                format!("index version {} is not supported", version),
+            ));
+        }
 
-        let entries_number = read_u32(&mut bufread).context("reading version")?;
+        let entries_number = read_u32(&mut bufread, "reading number of entries")?;
 
         let mut entries = Vec::new();
         for _ in 0..entries_number {
-            let entry = IndexEntry::read_from_index(&mut bufread)?;
+            let entry =
+                IndexEntry::read_from_index(&mut bufread).map_err(|e| e.with_id("index"))?;
             entries.push(entry);
         }
 
-        Ok(IndexFile {
+        let mut index = IndexFile {
             signature: signature.to_string(),
             version,
             entries,
-        })
+            timestamp: Some(timestamp),
+            untracked_cache: None,
+            fsmonitor_token: None,
+        };
+
+        // extensions fill the rest, up to the checksum
+        let mut rest = Vec::new();
+        bufread
+            .read_to_end(&mut rest)
+            .io_context("reading index extensions")?;
+        let mut extensions = &rest[..rest.len().saturating_sub(20)];
+        while extensions.len() >= 8 {
+            let signature = &extensions[..4];
+            let len = u32::from_be_bytes(extensions[4..8].try_into().unwrap()) as usize;
+            let data = extensions
+                .get(8..8 + len)
+                .ok_or_else(|| Error::corrupt("index", "extension is truncated"))?;
+            match signature {
+                b"UNTR" => index.untracked_cache = Some(UntrackedCache::read(data)?),
+                b"FSMN" => index.read_fsmonitor(data)?,
+                _ => {}
+            }
+            extensions = &extensions[8 + len..];
+        }
+        Ok(index)
+    }
+
+    fn read_fsmonitor(&mut self, data: &[u8]) -> Result<()> {
+        let truncated = || Error::corrupt("index", "fsmonitor extension is truncated");
+        let version = u32::from_be_bytes(data.get(..4).ok_or_else(truncated)?.try_into().unwrap());
+        let (token, rest) = match version {
+            // version 1 has a timestamp in nanoseconds where later ones have a token
+            1 => {
+                let bytes = data.get(4..12).ok_or_else(truncated)?;
+                let nanos = u64::from_be_bytes(bytes.try_into().unwrap());
+                (nanos.to_string(), &data[12..])
+            }
+            2 => {
+                let end = data[4..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .ok_or_else(truncated)?
+                    + 4;
+                (
+                    String::from_utf8_lossy(&data[4..end]).to_string(),
+                    &data[end + 1..],
+                )
+            }
+            v => {
+                return Err(Error::corrupt(
+                    "index",
+                    format!("fsmonitor extension version {} is not supported", v),
+                ))
+            }
+        };
+        let (dirty, _) = ewah::read(rest.get(4..).ok_or_else(truncated)?)?;
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            entry.fsmonitor_valid = !dirty.get(i).copied().unwrap_or(false);
+        }
+        self.fsmonitor_token = Some(token);
+        Ok(())
+    }
+
+    /// Whether `entry` could have changed after its stat data was taken without the stat data
+    /// showing it, because the file was modified in the same moment the index was written.
+    pub fn is_racy(&self, entry: &IndexEntry) -> bool {
+        self.timestamp
+            .is_some_and(|t| (entry.mtime_seconds, entry.mtime_nanoseconds) >= t)
     }
 }
 
-#[derive(Debug, Hash, Eq)]
+#[derive(Debug, Eq)]
 pub(crate) struct IndexEntry {
     //https://git-scm.com/docs/index-format
     pub ctime_seconds: u32,
@@ -61,46 +228,67 @@ pub(crate) struct IndexEntry {
     pub file_size: u32, //says truncated?
     pub hash: String,
     pub flags: u16,
+    /// only present in version 3 indexes, when bit 14 of `flags` is set
+    pub extended_flags: u16,
     pub entry_path: PathBuf,
+    /// the fsmonitor hook hasn't reported a change since the entry was found up to date, so
+    /// the worktree needn't be looked at
+    pub fsmonitor_valid: bool,
 }
 
 impl IndexEntry {
     fn read_from_index(index: &mut impl BufRead) -> Result<IndexEntry> {
-        let ctime_seconds = read_u32(index).context("reading ctime seconds")?;
-        let ctime_nanoseconds = read_u32(index).context("reading ctime nanoseconds")?;
-        let mtime_seconds = read_u32(index).context("reading  mtime_seconds")?;
-        let mtime_nanoseconds = read_u32(index).context("reading  mtime_nanoseconds")?;
-        let device = read_u32(index).context("reading  device")?;
-        let inode = read_u32(index).context("reading  inode")?;
-        let mode = read_u32(index).context("reading  mode")?;
+        let ctime_seconds = read_u32(index, "reading ctime seconds")?;
+        let ctime_nanoseconds = read_u32(index, "reading ctime nanoseconds")?;
+        let mtime_seconds = read_u32(index, "reading  mtime_seconds")?;
+        let mtime_nanoseconds = read_u32(index, "reading  mtime_nanoseconds")?;
+        let device = read_u32(index, "reading  device")?;
+        let inode = read_u32(index, "reading  inode")?;
+        let mode = read_u32(index, "reading  mode")?;
 
         let object_type = (mode >> 12) as u8;
         let unix_permission = (mode & 0x1ff) as u16;
 
-        let user_id = read_u32(index).context("reading  user_id")?;
-        let group_id = read_u32(index).context("reading  group_id")?;
-        let file_size = read_u32(index).context("reading  group_id")?;
+        let user_id = read_u32(index, "reading  user_id")?;
+        let group_id = read_u32(index, "reading  group_id")?;
+        let file_size = read_u32(index, "reading  file_size")?;
 
         let mut hash_buffer = [0u8; 20];
-        index.read_exact(&mut hash_buffer).context("reading hash")?;
-        let hash = hex::encode(&hash_buffer);
+        index
+            .read_exact(&mut hash_buffer)
+            .map_err(|_| Error::corrupt("", "truncated while reading hash"))?;
+        let hash = hex::encode(hash_buffer);
         let mut flags_buffer = [0u8; 2];
         index
             .read_exact(&mut flags_buffer)
-            .context("reading flags")?;
+            .map_err(|_| Error::corrupt("", "truncated while reading flags"))?;
         let flags = u16::from_be_bytes(flags_buffer);
+        let mut extended_flags = 0;
+        let mut extended_len = 0;
+        if flags & 0x4000 != 0 {
+            let mut extended_buffer = [0u8; 2];
+            index
+                .read_exact(&mut extended_buffer)
+                .map_err(|_| Error::corrupt("", "truncated while reading extended flags"))?;
+            extended_flags = u16::from_be_bytes(extended_buffer);
+            extended_len = 2;
+        }
 
         let mut entry_path = Vec::new();
-        let entry_path_bytes = index.read_until(0, &mut entry_path)?;
+        let entry_path_bytes = index
+            .read_until(0, &mut entry_path)
+            .io_context("reading entry path")?;
 
-        let padding = 8 - ((entry_path_bytes + 20 + 2) % 8); // "1-8 nul bytes as necessary to pad the entry to a multiple of eight bytes while keeping the name NUL-terminated."
+        let padding = 8 - ((entry_path_bytes + 20 + 2 + extended_len) % 8); // "1-8 nul bytes as necessary to pad the entry to a multiple of eight bytes while keeping the name NUL-terminated."
 
         if padding < 8 {
             // if padding is equal to 8 that means we have no padding ie padding above
             // was 8 - 0, 0 being the amount of bytes that are not divisible by 8 so no padding
             // because divisible by 8
             for _ in 0..padding {
-                index.read(&mut [0u8]).context("reading padding")?;
+                index
+                    .read_exact(&mut [0u8])
+                    .map_err(|_| Error::corrupt("", "truncated while reading padding"))?;
             }
         }
         //zeros
@@ -122,41 +310,128 @@ impl IndexEntry {
             file_size,
             hash,
             flags,
+            extended_flags,
             entry_path,
+            fsmonitor_valid: false,
         })
     }
 
+    fn write_to(&self, data: &mut Vec<u8>) {
+        let start = data.len();
+        for n in [
+            self.ctime_seconds,
+            self.ctime_nanoseconds,
+            self.mtime_seconds,
+            self.mtime_nanoseconds,
+            self.device,
+            self.inode,
+            self.mode(),
+            self.user_id,
+            self.group_id,
+            self.file_size,
+        ] {
+            data.extend_from_slice(&n.to_be_bytes());
+        }
+        data.extend_from_slice(&hex::decode(&self.hash).unwrap_or_else(|_| vec![0; 20]));
+
+        // the low 12 bits hold the length of the name, capped for long ones
+        let path = self.entry_path.as_os_str().as_bytes();
+        let mut flags = (self.flags & 0x3000) | path.len().min(0xfff) as u16;
+        if self.extended_flags != 0 {
+            flags |= 0x4000;
+        }
+        data.extend_from_slice(&flags.to_be_bytes());
+        if self.extended_flags != 0 {
+            data.extend_from_slice(&self.extended_flags.to_be_bytes());
+        }
+        data.extend_from_slice(path);
+
+        // NUL terminated and padded to a multiple of eight
+        let len = data.len() - start;
+        data.resize(start + (len + 8) / 8 * 8, 0);
+    }
+
+    /// The mode as git writes it in trees and `ls-files --stage`, eg 0o100644.
+    pub fn mode(&self) -> u32 {
+        ((self.object_type as u32) << 12) | self.unix_permission as u32
+    }
+
+    /// 0 for a normal entry, 1-3 for the base, ours and theirs sides of a conflict.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0x3
+    }
+
+    /// Takes the stat data of `current`, leaving the hash, path and flags alone.
+    pub fn refresh_from(&mut self, current: &IndexEntry) {
+        self.ctime_seconds = current.ctime_seconds;
+        self.ctime_nanoseconds = current.ctime_nanoseconds;
+        self.mtime_seconds = current.mtime_seconds;
+        self.mtime_nanoseconds = current.mtime_nanoseconds;
+        self.device = current.device;
+        self.inode = current.inode;
+        self.user_id = current.user_id;
+        self.group_id = current.group_id;
+        self.file_size = current.file_size;
+    }
+
+    /// An entry for contents that aren't in the worktree, such as a side of a conflict, so it
+    /// has no stat data.
+    pub fn without_stat(path: PathBuf, hash: &str, mode: u32, stage: u16) -> IndexEntry {
+        IndexEntry {
+            ctime_seconds: 0,
+            ctime_nanoseconds: 0,
+            mtime_seconds: 0,
+            mtime_nanoseconds: 0,
+            device: 0,
+            inode: 0,
+            object_type: (mode >> 12) as u8,
+            unix_permission: (mode & 0o777) as u16,
+            user_id: 0,
+            group_id: 0,
+            file_size: 0,
+            hash: hash.into(),
+            flags: stage << 12,
+            extended_flags: 0,
+            entry_path: path,
+            fsmonitor_valid: false,
+        }
+    }
+
     pub fn from_path(path: PathBuf, hash: &str, flags: u16) -> Result<IndexEntry> {
-        let metadata = fs::metadata(&path)?;
+        let metadata = fs::symlink_metadata(&path)
+            .io_context(format!("reading metadata of {}", path.display()))?;
         Ok(IndexEntry {
-            ctime_seconds: metadata.st_ctime() as u32,
-            ctime_nanoseconds: metadata.st_ctime_nsec() as u32,
-            mtime_seconds: metadata.st_mtime() as u32,
-            mtime_nanoseconds: metadata.st_mtime_nsec() as u32,
-            device: metadata.st_dev() as u32,
-            inode: metadata.st_ino() as u32,
+            ctime_seconds: metadata.ctime() as u32,
+            ctime_nanoseconds: metadata.ctime_nsec() as u32,
+            mtime_seconds: metadata.mtime() as u32,
+            mtime_nanoseconds: metadata.mtime_nsec() as u32,
+            device: metadata.dev() as u32,
+            inode: metadata.ino() as u32,
             object_type: match &metadata {
                 //there is also something called a gitlink? not sure what
                 //that is
-                md if md.is_symlink() => 0b1010 as u8,
-                _ => 0b1000 as u8, // for reg file
+                md if md.is_symlink() => 0b1010_u8,
+                _ => 0b1000_u8, // for reg file
             },
             unix_permission: {
                 if metadata.is_symlink() {
                     0
                 } else {
+                    // git only records whether the owner can execute
                     match metadata.permissions().mode() {
-                        mode if mode == 0o755 => 0o755 as u16,
-                        _ => 0o644 as u16,
+                        mode if mode & 0o100 != 0 => 0o755_u16,
+                        _ => 0o644_u16,
                     }
                 }
             },
-            user_id: metadata.st_uid(),
-            group_id: metadata.st_gid(),
-            file_size: metadata.st_size() as u32,
+            user_id: metadata.uid(),
+            group_id: metadata.gid(),
+            file_size: metadata.size() as u32,
             hash: hash.into(),
             flags,
+            extended_flags: 0,
             entry_path: path,
+            fsmonitor_valid: false,
         })
     }
 }
@@ -178,9 +453,17 @@ impl PartialEq for IndexEntry {
     }
 }
 
-fn read_u32(bufread: &mut impl BufRead) -> Result<u32> {
+fn write_extension(signature: &[u8; 4], extension: &[u8], data: &mut Vec<u8>) {
+    data.extend_from_slice(signature);
+    data.extend_from_slice(&(extension.len() as u32).to_be_bytes());
+    data.extend_from_slice(extension);
+}
+
+fn read_u32(bufread: &mut impl BufRead, what: &str) -> Result<u32> {
     let mut buffer = [0u8; 4];
-    bufread.read_exact(&mut buffer)?;
+    bufread
+        .read_exact(&mut buffer)
+        .map_err(|_| Error::corrupt("", format!("truncated while {}", what)))?;
     let number = u32::from_be_bytes(buffer);
     Ok(number)
 }
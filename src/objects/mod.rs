@@ -1,4 +1,9 @@
 pub(crate) mod commit;
+pub(crate) mod commit_graph;
+pub(crate) mod error;
+pub(crate) mod ewah;
 pub(crate) mod index;
 pub(crate) mod object;
+pub(crate) mod tag;
 pub(crate) mod tree;
+pub(crate) mod untracked_cache;
@@ -1,28 +1,44 @@
-use anyhow::{Context, Result};
+use crate::objects::error::{Error, IoContext, Result};
 use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use sha1::{Digest, Sha1};
 use std::{
     ffi::CStr,
+    fmt::Display,
     fs,
-    io::{BufRead, BufReader, Read, Write},
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    path::PathBuf,
 };
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Kind {
     Blob,
     Tree,
     Commit,
+    Tag,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Kind::Blob => "blob",
+            Kind::Tree => "tree",
+            Kind::Commit => "commit",
+            Kind::Tag => "tag",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 impl TryFrom<&str> for Kind {
-    type Error = anyhow::Error;
+    type Error = Error;
 
-    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+    fn try_from(value: &str) -> Result<Self> {
         match value {
             "blob" => Ok(Kind::Blob),
             "tree" => Ok(Kind::Tree),
             "commit" => Ok(Kind::Commit),
-            _ => anyhow::bail!("unknown object type"),
+            "tag" => Ok(Kind::Tag),
+            _ => Err(Error::UnknownKind(value.to_string())),
         }
     }
 }
@@ -37,123 +53,201 @@ pub(crate) struct Object {
 }
 
 impl TryFrom<&str> for Object {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut path_iter = fs::read_dir(format!(".git/objects/{}", &value[..2]))?
-            .into_iter()
-            .filter(|e| {
-                // get back result from the read_dir so have to unwrap
-                let Ok(e) = e else {
-                    return false;
-                };
-                let file_name = e.file_name();
-                //have to convert to str to use starts with, ok bc must be valid utf-8 for git
-                //anyway
-                let Some(file_name) = file_name.to_str() else {
-                    return false;
-                };
-                file_name.starts_with(&value[2..])
-            });
-
-        // double ?? because read_dir returns a result, and .next() returns an option so you get
-        // Result<Option<>>
-        let path = path_iter.next().context("error in getting path")??;
-        anyhow::ensure!(path_iter.next().is_none(), "not a unique hash");
-
-        let file = fs::File::open(path.path()).context("opening the file to read the contents")?;
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        let id = expand_id(value)?;
+        let path = loose_path(&id);
+
+        let file = fs::File::open(path).io_context("opening the file to read the contents")?;
         let z = ZlibDecoder::new(file);
         let mut buf_read = BufReader::new(z);
         let mut header = Vec::new();
         buf_read
             .read_until(0u8, &mut header)
-            .context("reading header")?;
-        let header = CStr::from_bytes_with_nul(&header).expect("must be a nul byte at the end");
-        let header = header.to_str().context("header must be valid utf-8")?;
-        let Some((blob_type, size)) = header.split_once(" ") else {
-            anyhow::bail!("wrong format of file");
+            .map_err(|e| Error::corrupt(&id, format!("reading header: {}", e)))?;
+        let header = CStr::from_bytes_with_nul(&header)
+            .map_err(|_| Error::corrupt(&id, "header is not nul terminated"))?;
+        let header = header
+            .to_str()
+            .map_err(|_| Error::corrupt(&id, "header must be valid utf-8"))?;
+        let Some((blob_type, size)) = header.split_once(' ') else {
+            return Err(Error::corrupt(
+                &id,
+                "header is not in the form <type> <size>",
+            ));
         };
 
-        let size: u64 = size.parse().context("invalid size")?;
+        let size: u64 = size
+            .parse()
+            .map_err(|_| Error::corrupt(&id, format!("invalid size {}", size)))?;
 
         Ok(Object {
-            kind: blob_type.trim().try_into().context("error parsing kind")?,
+            kind: blob_type
+                .trim()
+                .try_into()
+                .map_err(|e: Error| Error::corrupt(&id, e.to_string()))?,
             len: size,
             reader: Box::new(buf_read.take(size)),
         })
     }
 }
 
-impl Object {
-    pub fn write(&mut self) -> Result<String> {
-        let mut buffer = Vec::new();
-        let bytes = self
-            .reader
-            .read_to_end(&mut buffer)
-            .context("reading file to buffer")?;
+/// Turns a full or abbreviated (at least 4 hex digits) object id into the full id of the one
+/// object it names.
+pub fn expand_id(value: &str) -> Result<String> {
+    // git will not look up anything shorter than 4 hex digits
+    if value.len() < 4 || value.len() > 40 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::InvalidId(value.to_string()));
+    }
+    let value = value.to_ascii_lowercase();
 
-        let file = fs::File::create_new("temp").context("wrting temp file")?;
-        let mut hash_writer = HashWriter {
-            hasher: Sha1::new(),
-            writer: ZlibEncoder::new(file, Compression::default()),
+    let dir = match fs::read_dir(format!(".git/objects/{}", &value[..2])) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(Error::NotFound(value)),
+        Err(e) => return Err(e).io_context("reading the objects directory"),
+    };
+    let mut path_iter = dir.filter(|e| {
+        // get back result from the read_dir so have to unwrap
+        let Ok(e) = e else {
+            return false;
+        };
+        let file_name = e.file_name();
+        //have to convert to str to use starts with, ok bc must be valid utf-8 for git
+        //anyway
+        let Some(file_name) = file_name.to_str() else {
+            return false;
         };
+        file_name.starts_with(&value[2..])
+    });
+
+    // read_dir yields results and .next() an option so you get Option<Result<>>
+    let path = path_iter
+        .next()
+        .ok_or_else(|| Error::NotFound(value.clone()))?
+        .io_context("reading the objects directory")?;
+    if path_iter.next().is_some() {
+        return Err(Error::Ambiguous(value));
+    }
+    Ok(format!(
+        "{}{}",
+        &value[..2],
+        path.file_name().to_string_lossy()
+    ))
+}
 
-        match self.kind {
-            Kind::Blob => {
-                write!(hash_writer, "blob {}\0", bytes)
-                    .context("writing  header to the file and hashing")?;
-                hash_writer
-                    .write_all(&buffer)
-                    .context("writing content to file and hashing")?;
+/// Where the loose object with the full id `hash` lives.
+pub fn loose_path(hash: &str) -> PathBuf {
+    PathBuf::from(format!(".git/objects/{}/{}", &hash[..2], &hash[2..]))
+}
+
+/// The ids of every loose object, sorted.
+pub fn all_ids() -> Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for dir in fs::read_dir(".git/objects").io_context("reading the objects directory")? {
+        let dir = dir.io_context("reading the objects directory")?;
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        // skips info/ and pack/
+        if prefix.len() != 2 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        for file in fs::read_dir(dir.path()).io_context("reading the objects directory")? {
+            let file = file.io_context("reading the objects directory")?;
+            let rest = file.file_name().to_string_lossy().to_string();
+            if rest.len() == 38 && rest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                ids.push(format!("{}{}", prefix, rest));
             }
-            Kind::Tree => todo!(),
-            Kind::Commit => todo!(),
         }
+    }
+    ids.sort();
+    Ok(ids)
+}
 
-        let result = hash_writer.hasher.finalize();
-        let file = hash_writer
-            .writer
-            .finish()
-            .context("finishing the compression")?;
-
-        let hash = format!("{:x}", result);
-        fs::create_dir_all(format!(".git/objects/{}", &hash[..2]))
-            .context("creating the dir for the compressed file")?;
-        fs::rename(
-            "temp",
-            format!(".git/objects/{}/{}", &hash[..2], &hash[2..]),
-        )
-        .context("rename the file")?;
-
-        Ok(hash)
+/// The shortest prefix of `hash`, at least `min_len` long, that names only that object.
+pub fn abbreviate(hash: &str, min_len: usize) -> Result<String> {
+    let mut len = min_len.clamp(4, 40);
+    while len < hash.len() {
+        match expand_id(&hash[..len]) {
+            Err(Error::Ambiguous(_)) => len += 1,
+            Err(e) => return Err(e),
+            Ok(_) => break,
+        }
+    }
+    Ok(hash[..len.min(hash.len())].to_string())
+}
+
+impl Object {
+    pub fn write(&mut self) -> Result<String> {
+        let mut buffer = Vec::new();
+        self.reader
+            .read_to_end(&mut buffer)
+            .io_context("reading file to buffer")?;
+        write_typed(&self.kind.to_string(), &buffer)
     }
 
     pub fn hash(&mut self) -> Result<String> {
         let mut buffer = Vec::new();
-        let bytes = self
-            .reader
+        self.reader
             .read_to_end(&mut buffer)
-            .context("reading file to buffer")?;
+            .io_context("reading file to buffer")?;
+        hash_typed(&self.kind.to_string(), &buffer)
+    }
+}
 
-        let mut hash_writer = HashWriter {
-            hasher: Sha1::new(),
-            writer: std::io::sink(),
-        };
+/// Writes `contents` as a loose object with `kind` in its header, which needn't be one of the
+/// kinds git knows, for `hash-object --literally`.
+pub fn write_typed(kind: &str, contents: &[u8]) -> Result<String> {
+    // a name of our own in the objects dir so two writers don't trample each other and the
+    // rename below stays on one filesystem
+    let temp = format!(".git/objects/tmp_obj_{}", std::process::id());
+    let file = fs::File::create(&temp).io_context("wrting temp file")?;
+    let mut hash_writer = HashWriter {
+        hasher: Sha1::new(),
+        writer: ZlibEncoder::new(file, Compression::default()),
+    };
 
-        match self.kind {
-            Kind::Blob => {
-                write!(hash_writer, "blob {}\0", bytes).context("hashing header")?;
-                hash_writer.write_all(&buffer).context("hashing content")?;
-            }
-            Kind::Tree => todo!(),
-            Kind::Commit => todo!(),
-        }
+    write!(hash_writer, "{} {}\0", kind, contents.len())
+        .io_context("writing  header to the file and hashing")?;
+    hash_writer
+        .write_all(contents)
+        .io_context("writing content to file and hashing")?;
 
-        let result = hash_writer.hasher.finalize();
+    let result = hash_writer.hasher.finalize();
+    hash_writer
+        .writer
+        .finish()
+        .io_context("finishing the compression")?;
 
-        let hash = format!("{:x}", result);
-        Ok(hash)
+    let hash = format!("{:x}", result);
+    let path = loose_path(&hash);
+    if path.exists() {
+        // objects are immutable, so the copy already there is just as good
+        fs::remove_file(&temp).io_context("removing the temp file")?;
+        return Ok(hash);
     }
+    fs::create_dir_all(format!(".git/objects/{}", &hash[..2]))
+        .io_context("creating the dir for the compressed file")?;
+    fs::rename(&temp, path).io_context("rename the file")?;
+
+    Ok(hash)
+}
+
+/// The id `contents` would have as an object with `kind` in its header.
+pub fn hash_typed(kind: &str, contents: &[u8]) -> Result<String> {
+    let mut hash_writer = HashWriter {
+        hasher: Sha1::new(),
+        writer: std::io::sink(),
+    };
+
+    write!(hash_writer, "{} {}\0", kind, contents.len()).io_context("hashing header")?;
+    hash_writer
+        .write_all(contents)
+        .io_context("hashing content")?;
+
+    let result = hash_writer.hasher.finalize();
+
+    let hash = format!("{:x}", result);
+    Ok(hash)
 }
 
 struct HashWriter<W> {
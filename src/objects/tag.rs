@@ -0,0 +1,85 @@
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader},
+};
+
+use crate::objects::{
+    error::{Error, IoContext, Result},
+    object::{Kind, Object},
+};
+
+/// An annotated tag object, which names another object and carries its own message.
+#[derive(Debug)]
+pub(crate) struct Tag {
+    pub object: String,
+    pub kind: Kind,
+    pub tag: String,
+    /// `Name <email> timestamp offset`, missing on some very old tags
+    pub tagger: Option<String>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn read_from_hash(hash: &str) -> Result<Tag> {
+        let mut object = Object::try_from(hash)?;
+        if object.kind != Kind::Tag {
+            return Err(Error::corrupt(hash, "expected a tag"));
+        }
+        let mut bufread = BufReader::new(&mut object.reader);
+        Tag::read(&mut bufread).map_err(|e| e.with_id(hash))
+    }
+
+    pub fn read(bufread: &mut impl BufRead) -> Result<Tag> {
+        let mut object = None;
+        let mut kind = None;
+        let mut tag = None;
+        let mut tagger = None;
+
+        loop {
+            let mut line = String::new();
+            let bytes = bufread
+                .read_line(&mut line)
+                .io_context("reading tag header")?;
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            if bytes == 0 || line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| Error::corrupt("", format!("malformed header line {}", line)))?;
+            match key {
+                "object" => object = Some(value.to_string()),
+                "type" => kind = Some(Kind::try_from(value)?),
+                "tag" => tag = Some(value.to_string()),
+                "tagger" => tagger = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        let mut message = String::new();
+        bufread
+            .read_to_string(&mut message)
+            .map_err(|_| Error::corrupt("", "message is not valid utf-8"))?;
+
+        Ok(Tag {
+            object: object.ok_or_else(|| Error::corrupt("", "tag has no object line"))?,
+            kind: kind.ok_or_else(|| Error::corrupt("", "tag has no type line"))?,
+            tag: tag.ok_or_else(|| Error::corrupt("", "tag has no tag line"))?,
+            tagger,
+            message,
+        })
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "object {}", self.object)?;
+        writeln!(f, "type {}", self.kind)?;
+        writeln!(f, "tag {}", self.tag)?;
+        if let Some(tagger) = &self.tagger {
+            writeln!(f, "tagger {}", tagger)?;
+        }
+        writeln!(f)?;
+        write!(f, "{}", self.message)
+    }
+}
@@ -1,7 +1,9 @@
-use crate::objects::object::{Kind, Object};
-use anyhow::{Context, Result};
+use crate::objects::{
+    error::{Error, IoContext, Result},
+    object::{Kind, Object},
+};
 use std::{
-    collections::HashSet,
+    collections::BTreeMap,
     fmt::Display,
     io::{BufRead, BufReader},
 };
@@ -11,81 +13,107 @@ pub(crate) struct Tree {
 }
 
 impl Tree {
+    pub fn read_from_hash(hash: &str) -> Result<Tree> {
+        let mut object = Object::try_from(hash)?;
+        if object.kind != Kind::Tree {
+            return Err(Error::corrupt(hash, "expected a tree"));
+        }
+        let mut bufread = BufReader::new(&mut object.reader);
+        Tree::read(&mut bufread).map_err(|e| e.with_id(hash))
+    }
+
     pub fn read(bufread: &mut impl BufRead) -> Result<Tree> {
         let mut entries = Vec::new();
-        while let Ok(entry) = TreeEntry::read(bufread) {
+        // an empty tree is valid, eg the tree of a commit with no files
+        while let Some(entry) = TreeEntry::read(bufread)? {
             entries.push(entry);
         }
-        if entries.len() == 0 {
-            anyhow::bail!("this has no tree entries")
-        }
         Ok(Tree { entries })
     }
-    pub fn traverse(self, blobs: &mut HashSet<String>) -> Result<()> {
+
+    /// Everything below this tree that isn't itself a tree, keyed by its path from the root,
+    /// with `prefix` in front of every path.
+    pub fn flatten(self, prefix: &str, files: &mut BTreeMap<String, TreeEntry>) -> Result<()> {
         for entry in self.entries {
-            match entry {
-                entry if entry.mode != 40000 => {
-                    blobs.insert(entry.hash.clone());
-                }
-                entry if entry.mode == 40000 => {
-                    let mut tree: Object = entry.hash.as_str().try_into()?;
-                    anyhow::ensure!(tree.kind == Kind::Tree, "error in formatting");
-                    let mut bufread = BufReader::new(&mut tree.reader);
-                    let tree = Tree::read(&mut bufread).context("creating tree")?;
-                    tree.traverse(blobs)?;
-                }
-                _ => anyhow::bail!("this should never be called"),
+            let path = format!("{}{}", prefix, entry.filename);
+            if entry.kind() == Kind::Tree {
+                let tree = Tree::read_from_hash(&entry.hash)?;
+                tree.flatten(&format!("{}/", path), files)?;
+            } else {
+                files.insert(path, entry);
             }
         }
         Ok(())
     }
 }
+#[derive(Debug, Clone)]
 pub(crate) struct TreeEntry {
-    mode: u32,
-    filename: String,
-    hash: String,
+    pub mode: u32,
+    pub filename: String,
+    pub hash: String,
 }
 impl TreeEntry {
-    pub fn read(bufread: &mut impl BufRead) -> Result<TreeEntry> {
+    /// What the entry points to. Executables and symlinks are blobs too, and a gitlink
+    /// (submodule) is a commit in another repository.
+    pub fn kind(&self) -> Kind {
+        match self.mode {
+            40000 => Kind::Tree,
+            160000 => Kind::Commit,
+            _ => Kind::Blob,
+        }
+    }
+
+    /// The mode as a number, eg 0o100644, where `mode` holds the octal digits as written.
+    pub fn octal_mode(&self) -> u32 {
+        u32::from_str_radix(&self.mode.to_string(), 8).unwrap_or(0)
+    }
+
+    /// Reads the next entry, or `None` once the tree is exhausted.
+    pub fn read(bufread: &mut impl BufRead) -> Result<Option<TreeEntry>> {
         let mut mode_and_filename = Vec::new();
         let bytes_read = bufread
             .read_until(0, &mut mode_and_filename)
-            .context("reading mode and file name")?;
-        anyhow::ensure!(bytes_read > 1, "end of file");
+            .io_context("reading mode and file name")?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        if mode_and_filename.last() != Some(&0) {
+            return Err(Error::corrupt("", "tree entry name is not nul terminated"));
+        }
 
         let mode_and_filename =
             String::from_utf8_lossy(&mode_and_filename[..mode_and_filename.len() - 1]);
         let (mode, filename) = mode_and_filename
             .split_once(' ')
-            .context("splitting mode and filename")?;
+            .ok_or_else(|| Error::corrupt("", "tree entry has no mode"))?;
         let mut hash = [0u8; 20];
-        bufread.read_exact(&mut hash).context("reading hash")?;
+        bufread
+            .read_exact(&mut hash)
+            .map_err(|_| Error::corrupt("", "tree entry hash is truncated"))?;
         let hash = hex::encode(hash);
 
         let tree_entry = TreeEntry {
-            mode: mode.parse().context("mode not a number")?,
+            mode: mode
+                .parse()
+                .map_err(|_| Error::corrupt("", format!("mode {} is not a number", mode)))?,
             filename: filename.to_string(),
             hash,
         };
 
-        Ok(tree_entry)
+        Ok(Some(tree_entry))
     }
 }
 
 impl Display for TreeEntry {
+    /// The `<mode> <type> <hash>\t<name>` line `cat-file -p` and `ls-tree` print.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let file_type = match self.mode {
-            100644 => "blob",
-            100755 => "executable",
-            40000 => "tree",
-            120000 => "symlink",
-            160000 => "gitlink",
-            _ => "",
-        };
         write!(
             f,
-            "{:06} {} {} {}",
-            self.mode, file_type, self.hash, self.filename
+            "{:06} {} {}\t{}",
+            self.mode,
+            self.kind(),
+            self.hash,
+            self.filename
         )
     }
 }
@@ -0,0 +1,364 @@
+//! The `UNTR` index extension: what was untracked in each directory the last time it was read,
+//! with the directory's stat data so unchanged directories don't have to be read again.
+
+use std::{fs::Metadata, os::unix::fs::MetadataExt};
+
+use crate::objects::{
+    error::{Error, Result},
+    ewah,
+};
+
+/// `dir_flags` bits, as in git's `struct dir_struct`
+pub const SHOW_OTHER_DIRECTORIES: u32 = 0x2;
+pub const HIDE_EMPTY_DIRECTORIES: u32 = 0x4;
+
+/// Stat data laid out the way index entries keep it, ctime through size.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct StatData {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub device: u32,
+    pub inode: u32,
+    pub user_id: u32,
+    pub group_id: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    const LEN: usize = 36;
+
+    pub fn from_metadata(metadata: &Metadata) -> StatData {
+        StatData {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            device: metadata.dev() as u32,
+            inode: metadata.ino() as u32,
+            user_id: metadata.uid(),
+            group_id: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+
+    fn read(data: &[u8]) -> StatData {
+        let n = |i: usize| u32::from_be_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+        StatData {
+            ctime: (n(0), n(1)),
+            mtime: (n(2), n(3)),
+            device: n(4),
+            inode: n(5),
+            user_id: n(6),
+            group_id: n(7),
+            size: n(8),
+        }
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        for n in [
+            self.ctime.0,
+            self.ctime.1,
+            self.mtime.0,
+            self.mtime.1,
+            self.device,
+            self.inode,
+            self.user_id,
+            self.group_id,
+            self.size,
+        ] {
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// An exclude file outside the worktree, `.git/info/exclude` or `core.excludesFile`. The hash
+/// is `None` when the file doesn't exist.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ExcludeFile {
+    pub stat: StatData,
+    pub hash: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct UntrackedCache {
+    /// where and on what system the cache was made, NUL terminated; directory mtimes can't be
+    /// trusted anywhere else
+    pub ident: Vec<u8>,
+    pub info_exclude: ExcludeFile,
+    pub excludes_file: ExcludeFile,
+    /// which kind of listing the cache holds, `SHOW_OTHER_DIRECTORIES | HIDE_EMPTY_DIRECTORIES`
+    /// for `-unormal` and 0 for `-uall`
+    pub dir_flags: u32,
+    /// the name of the per-directory ignore file
+    pub exclude_per_dir: String,
+    pub root: Option<CachedDir>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CachedDir {
+    /// the name within its parent, empty for the top
+    pub name: String,
+    /// untracked files, and untracked directories with a trailing slash
+    pub untracked: Vec<String>,
+    /// the directories that were looked into
+    pub dirs: Vec<CachedDir>,
+    /// `untracked` is what is in the directory as of `stat`
+    pub valid: bool,
+    /// only read far enough to find out whether anything in it is untracked
+    pub check_only: bool,
+    pub stat: StatData,
+    /// the blob hash of the directory's `.gitignore`
+    pub exclude_hash: Option<String>,
+}
+
+impl CachedDir {
+    pub fn new(name: &str) -> CachedDir {
+        CachedDir {
+            name: name.to_string(),
+            ..CachedDir::default()
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.valid = false;
+        self.check_only = false;
+        self.untracked.clear();
+    }
+}
+
+impl UntrackedCache {
+    pub fn new(ident: Vec<u8>, dir_flags: u32) -> UntrackedCache {
+        UntrackedCache {
+            ident,
+            info_exclude: ExcludeFile::default(),
+            excludes_file: ExcludeFile::default(),
+            dir_flags,
+            exclude_per_dir: ".gitignore".to_string(),
+            root: None,
+        }
+    }
+
+    /// Forgets what was cached for the directory holding `path`, for when the path is added to
+    /// or removed from the index or changes in the worktree. A trailing slash means `path` is
+    /// a directory, which is forgotten too. When untracked directories are shown as `dir/`
+    /// every directory above changes as well, so they all go.
+    pub fn invalidate_path(&mut self, path: &str) {
+        let ancestors_too = self.dir_flags & SHOW_OTHER_DIRECTORIES != 0;
+        let Some(mut dir) = self.root.as_mut() else {
+            return;
+        };
+        let mut components: Vec<&str> = path.split('/').collect();
+        // the last component is the file, or empty after a directory's slash
+        components.pop();
+        for component in components {
+            if ancestors_too {
+                dir.invalidate();
+            }
+            match dir.dirs.iter().position(|d| d.name == component) {
+                Some(i) => dir = &mut dir.dirs[i],
+                None => {
+                    // the directory was never looked into, so its parent has to be re-read
+                    // to find it
+                    dir.invalidate();
+                    return;
+                }
+            }
+        }
+        dir.invalidate();
+    }
+
+    pub fn read(data: &[u8]) -> Result<UntrackedCache> {
+        let mut reader = Reader { data, at: 0 };
+        let ident_len = reader.varint()? as usize;
+        let ident = reader.take(ident_len)?.to_vec();
+        let info_exclude_stat = StatData::read(reader.take(StatData::LEN)?);
+        let excludes_file_stat = StatData::read(reader.take(StatData::LEN)?);
+        let dir_flags = u32::from_be_bytes(reader.take(4)?.try_into().unwrap());
+        let info_exclude = ExcludeFile {
+            stat: info_exclude_stat,
+            hash: reader.hash()?,
+        };
+        let excludes_file = ExcludeFile {
+            stat: excludes_file_stat,
+            hash: reader.hash()?,
+        };
+        let exclude_per_dir = reader.string()?;
+        let mut cache = UntrackedCache {
+            ident,
+            info_exclude,
+            excludes_file,
+            dir_flags,
+            exclude_per_dir,
+            root: None,
+        };
+
+        let dir_count = reader.varint()? as usize;
+        if dir_count == 0 {
+            return Ok(cache);
+        }
+        // the directories come depth first, each with how many of the following are its
+        // children; the rest of their data comes after all of them, in the same order
+        let mut flat = Vec::with_capacity(dir_count);
+        for _ in 0..dir_count {
+            let untracked_count = reader.varint()? as usize;
+            let child_count = reader.varint()? as usize;
+            let mut dir = CachedDir::new(&reader.string()?);
+            for _ in 0..untracked_count {
+                dir.untracked.push(reader.string()?);
+            }
+            flat.push((dir, child_count));
+        }
+        let valid = reader.bitmap()?;
+        let check_only = reader.bitmap()?;
+        let hash_valid = reader.bitmap()?;
+        let is_set = |bits: &[bool], i: usize| bits.get(i).copied().unwrap_or(false);
+        for (i, (dir, _)) in flat.iter_mut().enumerate() {
+            dir.valid = is_set(&valid, i);
+            dir.check_only = is_set(&check_only, i);
+            if dir.valid {
+                dir.stat = StatData::read(reader.take(StatData::LEN)?);
+            }
+        }
+        for (i, (dir, _)) in flat.iter_mut().enumerate() {
+            if is_set(&hash_valid, i) {
+                dir.exclude_hash = Some(hex::encode(reader.take(20)?));
+            }
+        }
+
+        let mut flat = flat.into_iter();
+        cache.root = Some(build_tree(&mut flat)?);
+        if flat.next().is_some() {
+            return Err(Error::corrupt(
+                "index",
+                "untracked cache has directories outside the top",
+            ));
+        }
+        Ok(cache)
+    }
+
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        write_varint(self.ident.len() as u64, out);
+        out.extend_from_slice(&self.ident);
+        self.info_exclude.stat.write_to(out);
+        self.excludes_file.stat.write_to(out);
+        out.extend_from_slice(&self.dir_flags.to_be_bytes());
+        write_hash(self.info_exclude.hash.as_deref(), out);
+        write_hash(self.excludes_file.hash.as_deref(), out);
+        out.extend_from_slice(self.exclude_per_dir.as_bytes());
+        out.push(0);
+
+        let Some(root) = &self.root else {
+            write_varint(0, out);
+            return;
+        };
+        let mut flat = Vec::new();
+        flatten(root, &mut flat);
+        write_varint(flat.len() as u64, out);
+        for dir in &flat {
+            write_varint(dir.untracked.len() as u64, out);
+            write_varint(dir.dirs.len() as u64, out);
+            for name in std::iter::once(&dir.name).chain(&dir.untracked) {
+                out.extend_from_slice(name.as_bytes());
+                out.push(0);
+            }
+        }
+        let bits = |f: fn(&CachedDir) -> bool| flat.iter().map(|d| f(d)).collect::<Vec<_>>();
+        ewah::write(&bits(|d| d.valid), out);
+        ewah::write(&bits(|d| d.check_only), out);
+        ewah::write(&bits(|d| d.exclude_hash.is_some()), out);
+        for dir in flat.iter().filter(|d| d.valid) {
+            dir.stat.write_to(out);
+        }
+        for dir in &flat {
+            if let Some(hash) = &dir.exclude_hash {
+                write_hash(Some(hash), out);
+            }
+        }
+        out.push(0);
+    }
+}
+
+fn build_tree(flat: &mut impl Iterator<Item = (CachedDir, usize)>) -> Result<CachedDir> {
+    let (mut dir, child_count) = flat
+        .next()
+        .ok_or_else(|| Error::corrupt("index", "untracked cache is missing directories"))?;
+    for _ in 0..child_count {
+        dir.dirs.push(build_tree(flat)?);
+    }
+    Ok(dir)
+}
+
+fn flatten<'a>(dir: &'a CachedDir, flat: &mut Vec<&'a CachedDir>) {
+    flat.push(dir);
+    for child in &dir.dirs {
+        flatten(child, flat);
+    }
+}
+
+fn write_hash(hash: Option<&str>, out: &mut Vec<u8>) {
+    match hash.and_then(|h| hex::decode(h).ok()) {
+        Some(bytes) => out.extend_from_slice(&bytes),
+        None => out.extend_from_slice(&[0; 20]),
+    }
+}
+
+/// git's variable width integers: seven bits a byte, most significant first, each
+/// continuation adding one so no number has two encodings.
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    out.extend_from_slice(&bytes);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let bytes = self
+            .data
+            .get(self.at..self.at + len)
+            .ok_or_else(|| Error::corrupt("index", "untracked cache is truncated"))?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut byte = self.take(1)?[0];
+        let mut value = (byte & 0x7f) as u64;
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let rest = &self.data[self.at.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| Error::corrupt("index", "untracked cache name is not nul terminated"))?;
+        let name = String::from_utf8_lossy(&rest[..len]).to_string();
+        self.at += len + 1;
+        Ok(name)
+    }
+
+    /// A hash, `None` for the null hash.
+    fn hash(&mut self) -> Result<Option<String>> {
+        let bytes = self.take(20)?;
+        Ok((bytes != [0; 20]).then(|| hex::encode(bytes)))
+    }
+
+    fn bitmap(&mut self) -> Result<Vec<bool>> {
+        let (bits, len) = ewah::read(&self.data[self.at.min(self.data.len())..])?;
+        self.at += len;
+        Ok(bits)
+    }
+}
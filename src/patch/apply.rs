@@ -0,0 +1,442 @@
+//! Applying a file's patch to its contents, as git's `apply_fragments` does.
+//!
+//! Each hunk is looked for where its header says, then ever further above and below. A hunk
+//! that starts at the top of the file, or has no context after its changes, must match there.
+//! With `-C` a hunk that doesn't match loses context lines until it does or it is down to the
+//! number asked for. Lines an earlier hunk wrote can't be matched again.
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{
+    whitespace::{self, Action, Report},
+    BinaryPatch, FilePatch, Hunk,
+};
+use crate::{diff, objects::object, refs::ZERO_HASH, worktree};
+
+pub(crate) struct Options {
+    /// trust hunks without context to be where their headers say
+    pub unidiff_zero: bool,
+    /// context lines that must match, `-C`; all of them when `None`
+    pub context: Option<usize>,
+    /// let runs of whitespace in context lines match any other whitespace
+    pub ignore_space_change: bool,
+}
+
+/// A line of the contents being patched.
+struct Line {
+    text: Vec<u8>,
+    /// written by an earlier hunk
+    patched: bool,
+}
+
+/// A line of a hunk's before or after.
+struct HunkLine {
+    text: Vec<u8>,
+    /// a context line, on both sides
+    common: bool,
+}
+
+/// What `patch` makes of `contents`.
+pub fn apply(
+    patch: &FilePatch,
+    contents: Vec<u8>,
+    options: &Options,
+    report: &mut Report,
+) -> Result<Vec<u8>> {
+    if let Some(binary) = &patch.binary {
+        return apply_binary(patch, binary, contents);
+    }
+    let mut image: Vec<Line> = contents
+        .split_inclusive(|&c| c == b'\n')
+        .map(|text| Line {
+            text: text.to_vec(),
+            patched: false,
+        })
+        .collect();
+    for hunk in &patch.hunks {
+        if !apply_hunk(&mut image, hunk, patch.crlf_in_old, options, report) {
+            bail!("patch failed: {}:{}", patch.name(), hunk.old_start);
+        }
+    }
+    Ok(image.into_iter().flat_map(|line| line.text).collect())
+}
+
+/// Applies a hunk where it matches. A CR at the end of a line is whitespace to fix unless
+/// `cr_at_eol`.
+fn apply_hunk(
+    image: &mut Vec<Line>,
+    hunk: &Hunk,
+    cr_at_eol: bool,
+    options: &Options,
+    report: &mut Report,
+) -> bool {
+    // added lines are only fixed once the patch is known to have errors
+    let fix = report.action == Action::Fix && report.errors > 0;
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    let mut blank_lines_at_end = 0;
+    let mut first_blank_line = 0;
+    for line in &hunk.lines {
+        let common = line.sign == b' ';
+        if line.sign != b'+' {
+            before.push(HunkLine {
+                text: line.text.clone(),
+                common,
+            });
+        }
+        if line.sign != b'-' {
+            let text = match line.sign == b'+' && fix {
+                true => {
+                    let (text, fixed) = whitespace::fix(&line.text, cr_at_eol);
+                    report.fixed += fixed as usize;
+                    text
+                }
+                false => line.text.clone(),
+            };
+            after.push(HunkLine { text, common });
+        }
+        let blank = diff::is_blank(&line.text);
+        if line.sign == b'+' && blank {
+            if blank_lines_at_end == 0 {
+                first_blank_line = line.line_number;
+            }
+            blank_lines_at_end += 1;
+        } else if !(common && blank && !line.text.is_empty()) {
+            blank_lines_at_end = 0;
+        }
+    }
+
+    let leading = hunk.lines.iter().take_while(|l| l.sign == b' ').count();
+    let trailing = hunk
+        .lines
+        .iter()
+        .rev()
+        .take_while(|l| l.sign == b' ')
+        .count();
+    let (mut left_leading, mut left_trailing) = (leading, trailing);
+    let mut match_beginning = hunk.old_start == 0 || (hunk.old_start == 1 && !options.unidiff_zero);
+    let mut match_end = !options.unidiff_zero && trailing == 0;
+    let mut position = hunk.new_start.saturating_sub(1) as isize;
+    let limit = options.context.unwrap_or(usize::MAX);
+    let fix = (report.action == Action::Fix).then_some(cr_at_eol);
+    let at = loop {
+        let found = find(
+            image,
+            &mut before,
+            &mut after,
+            position,
+            (match_beginning, match_end),
+            options,
+            fix,
+        );
+        if found.is_some() || (left_leading <= limit && left_trailing <= limit) {
+            break found;
+        }
+        if match_beginning || match_end {
+            match_beginning = false;
+            match_end = false;
+            continue;
+        }
+        // take a context line off the end that has more, or both if they have the same
+        if left_leading >= left_trailing {
+            before.remove(0);
+            after.remove(0);
+            position -= 1;
+            left_leading -= 1;
+        }
+        if left_trailing > left_leading {
+            before.pop();
+            after.pop();
+            left_trailing -= 1;
+        }
+    };
+    let Some(at) = at else {
+        return false;
+    };
+
+    if blank_lines_at_end > 0 && before.len() + at >= image.len() && report.action != Action::Nowarn
+    {
+        report.record(whitespace::BLANK_AT_EOF, b"+", first_blank_line);
+        if report.action == Action::Fix {
+            after.truncate(after.len().saturating_sub(blank_lines_at_end));
+        }
+    }
+    if left_leading != leading || left_trailing != trailing {
+        eprintln!(
+            "Context reduced to ({}/{}) to apply fragment at {}",
+            left_leading,
+            left_trailing,
+            at + 1
+        );
+    }
+    let end = (at + before.len()).min(image.len());
+    image.splice(
+        at..end,
+        after.into_iter().map(|line| Line {
+            text: line.text,
+            patched: true,
+        }),
+    );
+    true
+}
+
+/// Where the hunk's before matches, trying `position` first and then a line further down and
+/// up in turn. `fix` is set with `--whitespace=fix`, to whether a CR at the end of a line stays.
+fn find(
+    image: &[Line],
+    before: &mut [HunkLine],
+    after: &mut Vec<HunkLine>,
+    position: isize,
+    (match_beginning, match_end): (bool, bool),
+    options: &Options,
+    fix: Option<bool>,
+) -> Option<usize> {
+    if before.len() > image.len() {
+        return None;
+    }
+    // a hunk that lost its first line of context at the top of the file goes to the end
+    let position = if match_beginning {
+        0
+    } else if match_end {
+        image.len() - before.len()
+    } else if position < 0 {
+        image.len()
+    } else {
+        (position as usize).min(image.len())
+    };
+    let (mut backwards, mut forwards) = (position, position);
+    let mut current = position;
+    let mut i = 0;
+    loop {
+        if (!match_beginning || current == 0)
+            && matches(image, before, after, current, match_end, options, fix)
+        {
+            return Some(current);
+        }
+        loop {
+            if backwards == 0 && forwards == image.len() {
+                return None;
+            }
+            if i % 2 == 1 {
+                if backwards == 0 {
+                    i += 1;
+                    continue;
+                }
+                backwards -= 1;
+                current = backwards;
+            } else {
+                if forwards == image.len() {
+                    i += 1;
+                    continue;
+                }
+                forwards += 1;
+                current = forwards;
+            }
+            break;
+        }
+        i += 1;
+    }
+}
+
+/// Whether the hunk's before matches the contents at `at`: exactly, or when asked to, with
+/// whitespace ignored or fixed on both sides. A match that needed either takes the context
+/// lines as they matched into the hunk's after.
+fn matches(
+    image: &[Line],
+    before: &mut [HunkLine],
+    after: &mut Vec<HunkLine>,
+    at: usize,
+    match_end: bool,
+    options: &Options,
+    fix: Option<bool>,
+) -> bool {
+    // with --whitespace=fix the before may run past the end, when the hunk takes away blank
+    // lines at the end that aren't there any more
+    let limit = if before.len() + at <= image.len() {
+        if match_end && before.len() + at != image.len() {
+            return false;
+        }
+        before.len()
+    } else if fix.is_some() {
+        image.len() - at
+    } else {
+        return false;
+    };
+    let target = &image[at..at + limit];
+    if target
+        .iter()
+        .zip(before.iter())
+        .any(|(line, want)| line.patched || !same_but_whitespace(&line.text, &want.text))
+    {
+        return false;
+    }
+    if limit == before.len() {
+        if target
+            .iter()
+            .zip(before.iter())
+            .all(|(l, w)| l.text == w.text)
+        {
+            return true;
+        }
+    } else if before[..limit].iter().all(|l| diff::is_blank(&l.text)) {
+        return false;
+    }
+
+    let beyond_end = || before[limit..].iter().map(|l| l.text.clone());
+    let matched: Vec<Vec<u8>> = if options.ignore_space_change {
+        if !target
+            .iter()
+            .zip(before.iter())
+            .all(|(l, w)| fuzzy_match(&l.text, &w.text))
+            || !beyond_end().all(|l| diff::is_blank(&l))
+        {
+            return false;
+        }
+        target
+            .iter()
+            .map(|l| l.text.clone())
+            .chain(beyond_end())
+            .collect()
+    } else if let Some(cr_at_eol) = fix {
+        let mut fixed = Vec::new();
+        for (line, want) in target.iter().zip(before.iter()) {
+            let want = whitespace::fix(&want.text, cr_at_eol).0;
+            if whitespace::fix(&line.text, cr_at_eol).0 != want {
+                return false;
+            }
+            fixed.push(want);
+        }
+        for line in beyond_end() {
+            let line = whitespace::fix(&line, cr_at_eol).0;
+            if !diff::is_blank(&line) {
+                return false;
+            }
+            fixed.push(line);
+        }
+        fixed
+    } else {
+        return false;
+    };
+    take_context(before, after, matched);
+    true
+}
+
+/// Puts the lines the hunk's before matched in its place, and the context lines among them in
+/// the hunk's after. Context lines of the after past the end of the before are dropped.
+fn take_context(before: &mut [HunkLine], after: &mut Vec<HunkLine>, matched: Vec<Vec<u8>>) {
+    for (line, text) in before.iter_mut().zip(matched) {
+        line.text = text;
+    }
+    let mut context = before.iter().filter(|l| l.common).map(|l| l.text.clone());
+    let mut updated = Vec::with_capacity(after.len());
+    for line in after.drain(..) {
+        if !line.common {
+            updated.push(line);
+        } else if let Some(text) = context.next() {
+            updated.push(HunkLine { text, common: true });
+        }
+    }
+    *after = updated;
+}
+
+/// Whether two lines are the same once all whitespace is taken out, which any match needs.
+fn same_but_whitespace(a: &[u8], b: &[u8]) -> bool {
+    let words = |line: &[u8]| -> Vec<u8> {
+        line.iter()
+            .copied()
+            .filter(|&c| !diff::is_space(c))
+            .collect()
+    };
+    words(a) == words(b)
+}
+
+/// Whether two lines are the same but for how long their runs of whitespace are, git's
+/// `fuzzy_matchlines`. Line endings don't count, but whitespace on one side only does.
+fn fuzzy_match(a: &[u8], b: &[u8]) -> bool {
+    let trim = |line: &[u8]| -> usize {
+        let mut end = line.len();
+        while end > 0 && matches!(line[end - 1], b'\r' | b'\n') {
+            end -= 1;
+        }
+        end
+    };
+    let (a, b) = (&a[..trim(a)], &b[..trim(b)]);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if diff::is_space(a[i]) {
+            if !diff::is_space(b[j]) {
+                return false;
+            }
+            while i < a.len() && diff::is_space(a[i]) {
+                i += 1;
+            }
+            while j < b.len() && diff::is_space(b[j]) {
+                j += 1;
+            }
+        } else {
+            if a[i] != b[j] {
+                return false;
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    i == a.len() && j == b.len()
+}
+
+/// A binary patch only applies to exactly the blob it was made from, named in full on its
+/// `index` line. The new blob is taken from the repository if it is there, otherwise made
+/// with the patch's data and checked against the id.
+fn apply_binary(patch: &FilePatch, binary: &BinaryPatch, contents: Vec<u8>) -> Result<Vec<u8>> {
+    let name = patch.name();
+    let (Some(old_id), Some(new_id)) = (&patch.old_id, &patch.new_id) else {
+        bail!(
+            "cannot apply binary patch to '{}' without full index line",
+            name
+        );
+    };
+    if old_id.len() != 40 || new_id.len() != 40 {
+        bail!(
+            "cannot apply binary patch to '{}' without full index line",
+            name
+        );
+    }
+    if patch.old_name.is_some() {
+        let id = worktree::blob_hash(contents.clone())?;
+        if id != *old_id {
+            bail!(
+                "the patch applies to '{}' ({}), which does not match the current contents.",
+                name,
+                id
+            );
+        }
+    } else if !contents.is_empty() {
+        bail!(
+            "the patch applies to an empty '{}' but it is not empty",
+            name
+        );
+    }
+    if new_id == ZERO_HASH {
+        return Ok(Vec::new());
+    }
+    if object::loose_path(new_id).exists() {
+        return diff::read_blob(new_id);
+    }
+    let hunk = binary
+        .forward
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing binary patch data for '{}'", name))?;
+    let result = hunk
+        .apply(&contents)
+        .ok_or_else(|| anyhow!("binary patch does not apply to '{}'", name))?;
+    let id = worktree::blob_hash(result.clone())?;
+    if id != *new_id {
+        bail!(
+            "binary patch to '{}' creates incorrect result (expecting {}, got {})",
+            name,
+            new_id,
+            id
+        );
+    }
+    Ok(result)
+}
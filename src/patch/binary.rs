@@ -0,0 +1,125 @@
+//! The data of git's binary patches: zlib deflated, then written out in base 85 a line at a
+//! time, each line starting with a letter for how many bytes it holds. A hunk is either the
+//! whole new contents or a delta against the old, in the format packs use.
+
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+/// The digits of git's base 85, in order.
+const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+#[derive(Debug, Clone)]
+pub(crate) enum BinaryHunk {
+    /// the contents after the patch
+    Literal(Vec<u8>),
+    /// instructions to build the contents after out of those before
+    Delta(Vec<u8>),
+}
+
+impl BinaryHunk {
+    /// What the hunk makes of `old`, or `None` if it doesn't fit `old`.
+    pub fn apply(&self, old: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            BinaryHunk::Literal(data) => Some(data.clone()),
+            BinaryHunk::Delta(delta) => apply_delta(old, delta),
+        }
+    }
+}
+
+/// Decodes one line of base 85 onto `data`: a length letter, `A`-`Z` for 1 to 26 bytes and
+/// `a`-`z` for 27 to 52, then five digits for every four bytes, the last four padded.
+pub fn decode_line(line: &[u8], data: &mut Vec<u8>) -> Option<()> {
+    let line = line.strip_suffix(b"\n")?;
+    if line.len() < 6 || (line.len() - 1) % 5 != 0 {
+        return None;
+    }
+    let len = match line[0] {
+        c @ b'A'..=b'Z' => (c - b'A' + 1) as usize,
+        c @ b'a'..=b'z' => (c - b'a' + 27) as usize,
+        _ => return None,
+    };
+    let room = (line.len() - 1) / 5 * 4;
+    // the padding never takes up a whole group
+    if room < len || len + 4 <= room {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(room);
+    for group in line[1..].chunks(5) {
+        let mut value: u32 = 0;
+        for &digit in group {
+            let digit = BASE85.iter().position(|&d| d == digit)? as u32;
+            value = value.checked_mul(85)?.checked_add(digit)?;
+        }
+        bytes.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&bytes[..len]);
+    Some(())
+}
+
+/// Inflates the data of a hunk, which must come to the size its header gave.
+pub fn inflate(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    let mut inflated = Vec::with_capacity(size);
+    ZlibDecoder::new(data).read_to_end(&mut inflated).ok()?;
+    (inflated.len() == size).then_some(inflated)
+}
+
+/// Builds the target of a delta out of `base`: the sizes of the base and the target, then
+/// instructions to copy a range of the base or to insert the bytes that follow.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut at = 0;
+    if delta_size(delta, &mut at)? != base.len() {
+        return None;
+    }
+    let size = delta_size(delta, &mut at)?;
+    let mut target = Vec::with_capacity(size);
+    while at < delta.len() {
+        let op = delta[at];
+        at += 1;
+        if op & 0x80 != 0 {
+            // which bytes of the offset and size follow is in the low seven bits
+            let mut read = |bits: std::ops::Range<u8>| -> Option<usize> {
+                let mut value = 0;
+                for (i, bit) in bits.enumerate() {
+                    if op & (1 << bit) != 0 {
+                        value |= (*delta.get(at)? as usize) << (8 * i);
+                        at += 1;
+                    }
+                }
+                Some(value)
+            };
+            let offset = read(0..4)?;
+            let len = match read(4..7)? {
+                0 => 0x10000,
+                len => len,
+            };
+            target.extend_from_slice(base.get(offset..offset.checked_add(len)?)?);
+        } else if op != 0 {
+            let len = op as usize;
+            target.extend_from_slice(delta.get(at..at + len)?);
+            at += len;
+        } else {
+            return None;
+        }
+    }
+    (target.len() == size).then_some(target)
+}
+
+/// A size at the start of a delta: seven bits a byte, least significant first, the top bit
+/// saying another byte follows.
+fn delta_size(delta: &[u8], at: &mut usize) -> Option<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*at)?;
+        *at += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+        if shift > 56 {
+            return None;
+        }
+    }
+}
@@ -0,0 +1,718 @@
+//! Reading patches: the unified diffs `diff -u` writes, and git's, whose extended headers say
+//! what else happens to a file (renames, copies, mode changes, files appearing and going away)
+//! and whose binary patches carry the new contents themselves.
+//!
+//! The parse follows git's `apply.c`. Anything that isn't a header is skipped, a hunk must
+//! have exactly the lines its header counts, and names lose the leading components `-p` asks
+//! to strip.
+
+pub(crate) mod apply;
+pub(crate) mod binary;
+pub(crate) mod whitespace;
+
+use anyhow::{anyhow, bail, Result};
+use std::mem;
+
+use binary::BinaryHunk;
+
+/// What a patch does to one file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FilePatch {
+    /// the path before, `None` for a file the patch creates
+    pub old_name: Option<String>,
+    /// the path after, `None` for a file the patch deletes
+    pub new_name: Option<String>,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    pub is_new: bool,
+    pub is_delete: bool,
+    pub is_rename: bool,
+    pub is_copy: bool,
+    /// the blob ids on the `index` line, as abbreviated as they are there
+    pub old_id: Option<String>,
+    pub new_id: Option<String>,
+    pub hunks: Vec<Hunk>,
+    /// set for a binary patch, which has no hunks
+    pub binary: Option<BinaryPatch>,
+    /// a line of the old contents ends in CRLF, so a CR there isn't trailing whitespace
+    pub crlf_in_old: bool,
+}
+
+/// A run of changed lines and the context around them.
+#[derive(Debug, Clone)]
+pub(crate) struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HunkLine {
+    /// `b' '`, `b'-'` or `b'+'`
+    pub sign: u8,
+    /// the line with its newline, unless a `\ No newline at end of file` followed it
+    pub text: Vec<u8>,
+    /// where the line is in the patch, counting from 1
+    pub line_number: usize,
+}
+
+/// The data of a `GIT binary patch`. Both hunks are missing for a patch that only says
+/// `Binary files ... differ`, which only applies if the new blob is already in the repository.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BinaryPatch {
+    /// turns the old contents into the new
+    pub forward: Option<BinaryHunk>,
+    /// turns the new contents back into the old
+    pub reverse: Option<BinaryHunk>,
+}
+
+impl FilePatch {
+    /// The name messages about the patch use.
+    pub fn name(&self) -> &str {
+        self.old_name
+            .as_deref()
+            .or(self.new_name.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Turns the patch around, for `-R`.
+    pub fn reverse(&mut self) {
+        mem::swap(&mut self.old_name, &mut self.new_name);
+        mem::swap(&mut self.old_mode, &mut self.new_mode);
+        mem::swap(&mut self.is_new, &mut self.is_delete);
+        mem::swap(&mut self.old_id, &mut self.new_id);
+        for hunk in &mut self.hunks {
+            mem::swap(&mut hunk.old_start, &mut hunk.new_start);
+            mem::swap(&mut hunk.old_len, &mut hunk.new_len);
+            for line in &mut hunk.lines {
+                line.sign = match line.sign {
+                    b'-' => b'+',
+                    b'+' => b'-',
+                    sign => sign,
+                };
+            }
+        }
+        if let Some(binary) = &mut self.binary {
+            mem::swap(&mut binary.forward, &mut binary.reverse);
+        }
+    }
+
+    /// Whether the patch changes any lines, as opposed to only renaming or changing the mode.
+    pub fn changes_contents(&self) -> bool {
+        self.binary.is_some()
+            || self
+                .hunks
+                .iter()
+                .any(|h| h.lines.iter().any(|l| l.sign != b' '))
+    }
+}
+
+/// Reads every file's patch out of `input`, stripping `strip` leading components from the
+/// names. Without `strip`, git's `a/` and `b/` are stripped, and a traditional patch whose
+/// names have no directories in them is taken to have nothing to strip.
+pub fn parse(input: &[u8], strip: Option<usize>) -> Result<Vec<FilePatch>> {
+    let mut parser = Parser {
+        lines: input.split_inclusive(|&c| c == b'\n').collect(),
+        at: 0,
+        strip: strip.unwrap_or(1),
+        strip_known: strip.is_some(),
+    };
+    let mut patches = Vec::new();
+    while let Some(mut patch) = parser.header()? {
+        parser.body(&mut patch)?;
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+struct Parser<'a> {
+    lines: Vec<&'a [u8]>,
+    /// the next line to read, counting from 0
+    at: usize,
+    strip: usize,
+    /// `strip` was asked for or guessed, and is no longer guessed at
+    strip_known: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self, ahead: usize) -> Option<&'a [u8]> {
+        self.lines.get(self.at + ahead).copied()
+    }
+
+    /// Skips to the next file's header and reads it.
+    fn header(&mut self) -> Result<Option<FilePatch>> {
+        while let Some(line) = self.line(0) {
+            if line.starts_with(b"@@ -") {
+                bail!(
+                    "patch fragment without header at line {}: {}",
+                    self.at + 1,
+                    text(line)
+                );
+            }
+            if let Some(names) = line.strip_prefix(b"diff --git ") {
+                self.at += 1;
+                return self.git_header(&text(names)).map(Some);
+            }
+            let next =
+                |ahead, prefix: &[u8]| self.line(ahead).is_some_and(|l| l.starts_with(prefix));
+            if line.starts_with(b"--- ") && next(1, b"+++ ") && next(2, b"@@ -") {
+                return self.traditional_header().map(Some);
+            }
+            self.at += 1;
+        }
+        Ok(None)
+    }
+
+    /// The extended header lines after `diff --git`, up to the first that isn't one.
+    fn git_header(&mut self, names: &str) -> Result<FilePatch> {
+        let default_name = header_name(names, self.strip);
+        let mut patch = FilePatch::default();
+        while let Some(line) = self.line(0) {
+            let line = text(line);
+            let number = self.at + 1;
+            // names in rename and copy lines have no a/ or b/ to strip
+            let strip = self.strip.saturating_sub(1);
+            if let Some(name) = line.strip_prefix("--- ") {
+                verify_name(
+                    &mut patch.old_name,
+                    name,
+                    patch.is_new,
+                    "old",
+                    self.strip,
+                    number,
+                )?;
+            } else if let Some(name) = line.strip_prefix("+++ ") {
+                verify_name(
+                    &mut patch.new_name,
+                    name,
+                    patch.is_delete,
+                    "new",
+                    self.strip,
+                    number,
+                )?;
+            } else if let Some(mode) = line.strip_prefix("old mode ") {
+                patch.old_mode = Some(parse_mode(mode, number)?);
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                patch.new_mode = Some(parse_mode(mode, number)?);
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                patch.is_delete = true;
+                patch.new_name = None;
+                patch.old_mode = Some(parse_mode(mode, number)?);
+            } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                patch.is_new = true;
+                patch.old_name = None;
+                patch.new_mode = Some(parse_mode(mode, number)?);
+            } else if let Some(name) = line.strip_prefix("copy from ") {
+                patch.is_copy = true;
+                patch.old_name = find_name(name, strip, false);
+            } else if let Some(name) = line.strip_prefix("copy to ") {
+                patch.is_copy = true;
+                patch.new_name = find_name(name, strip, false);
+            } else if let Some(name) = line
+                .strip_prefix("rename from ")
+                .or_else(|| line.strip_prefix("rename old "))
+            {
+                patch.is_rename = true;
+                patch.old_name = find_name(name, strip, false);
+            } else if let Some(name) = line
+                .strip_prefix("rename to ")
+                .or_else(|| line.strip_prefix("rename new "))
+            {
+                patch.is_rename = true;
+                patch.new_name = find_name(name, strip, false);
+            } else if line.starts_with("similarity index ")
+                || line.starts_with("dissimilarity index ")
+            {
+            } else if let Some(ids) = line.strip_prefix("index ") {
+                let (ids, mode) = match ids.split_once(' ') {
+                    Some((ids, mode)) => (ids, Some(mode)),
+                    None => (ids, None),
+                };
+                let Some((old, new)) = ids.split_once("..") else {
+                    break;
+                };
+                patch.old_id = Some(old.to_string());
+                patch.new_id = Some(new.to_string());
+                if let Some(mode) = mode {
+                    patch.old_mode = Some(parse_mode(mode, number)?);
+                }
+            } else {
+                break;
+            }
+            self.at += 1;
+        }
+
+        if patch.old_name.is_none() && patch.new_name.is_none() {
+            let Some(name) = default_name else {
+                bail!(
+                    "git diff header lacks filename information when removing {} leading \
+                     pathname component{} (line {})",
+                    self.strip,
+                    if self.strip == 1 { "" } else { "s" },
+                    self.at
+                );
+            };
+            if !patch.is_new {
+                patch.old_name = Some(name.clone());
+            }
+            if !patch.is_delete {
+                patch.new_name = Some(name);
+            }
+        }
+        if (patch.new_name.is_none() && !patch.is_delete)
+            || (patch.old_name.is_none() && !patch.is_new)
+        {
+            bail!(
+                "git diff header lacks filename information (line {})",
+                self.at
+            );
+        }
+        Ok(patch)
+    }
+
+    /// The `---` and `+++` lines of a patch without git's headers. A side that is
+    /// `/dev/null` makes the file new or deleted.
+    fn traditional_header(&mut self) -> Result<FilePatch> {
+        let first = text(self.lines[self.at]);
+        let second = text(self.lines[self.at + 1]);
+        let (first, second) = (&first["--- ".len()..], &second["+++ ".len()..]);
+        self.at += 2;
+        if !self.strip_known {
+            let guess = |name: &str| match is_dev_null(name) {
+                true => None,
+                false => find_name(name, 0, true).filter(|n| !n.contains('/')),
+            };
+            if let (Some(_), Some(_)) | (None, Some(_)) = (guess(first), guess(second)) {
+                self.strip = 0;
+                self.strip_known = true;
+            }
+        }
+        let mut patch = FilePatch::default();
+        let name = if is_dev_null(first) {
+            patch.is_new = true;
+            patch.new_name = traditional_name(second, None, self.strip);
+            patch.new_name.clone()
+        } else if is_dev_null(second) {
+            patch.is_delete = true;
+            patch.old_name = traditional_name(first, None, self.strip);
+            patch.old_name.clone()
+        } else {
+            let first_name = traditional_name(first, None, self.strip);
+            let name = traditional_name(second, first_name.as_deref(), self.strip);
+            // diff -N gives a file that isn't there the epoch for its date
+            if has_epoch_timestamp(first) {
+                patch.is_new = true;
+                patch.new_name = name.clone();
+            } else if has_epoch_timestamp(second) {
+                patch.is_delete = true;
+                patch.old_name = name.clone();
+            } else {
+                patch.old_name = name.clone();
+                patch.new_name = name.clone();
+            }
+            name
+        };
+        if name.is_none() {
+            bail!("unable to find filename in patch at line {}", self.at);
+        }
+        Ok(patch)
+    }
+
+    /// The hunks, or binary data, after a header.
+    fn body(&mut self, patch: &mut FilePatch) -> Result<()> {
+        match self.line(0) {
+            Some(b"GIT binary patch\n") => {
+                self.at += 1;
+                let Some(forward) = self.binary_hunk()? else {
+                    bail!("unrecognized binary patch at line {}", self.at);
+                };
+                let reverse = self.binary_hunk()?;
+                patch.binary = Some(BinaryPatch {
+                    forward: Some(forward),
+                    reverse,
+                });
+                return Ok(());
+            }
+            Some(line)
+                if (line.starts_with(b"Binary files ") || line.starts_with(b"Files "))
+                    && line.ends_with(b" differ\n") =>
+            {
+                self.at += 1;
+                patch.binary = Some(BinaryPatch::default());
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        while self.line(0).is_some_and(|l| l.starts_with(b"@@ -")) {
+            patch.hunks.push(self.hunk()?);
+        }
+        let (old_lines, new_lines) = patch.hunks.iter().fold((0, 0), |(old, new), h| {
+            let count = |sign| h.lines.iter().filter(|l| l.sign == sign).count();
+            (
+                old + count(b'-') + count(b' '),
+                new + count(b'+') + count(b' '),
+            )
+        });
+        if patch.is_new && old_lines > 0 {
+            bail!("new file {} depends on old contents", patch.name());
+        }
+        if patch.is_delete && new_lines > 0 {
+            bail!("deleted file {} still has contents", patch.name());
+        }
+        Ok(())
+    }
+
+    /// A hunk, which must have exactly the lines its header counts on each side.
+    fn hunk(&mut self) -> Result<Hunk> {
+        let corrupt = |at: usize| anyhow!("corrupt patch at line {}", at + 1);
+        let (old_start, old_len, new_start, new_len) =
+            hunk_header(&text(self.lines[self.at])).ok_or_else(|| corrupt(self.at))?;
+        self.at += 1;
+
+        let (mut old_left, mut new_left) = (old_len, new_len);
+        let mut lines: Vec<HunkLine> = Vec::new();
+        while old_left > 0 || new_left > 0 {
+            let line = self
+                .line(0)
+                .filter(|l| l.ends_with(b"\n"))
+                .ok_or_else(|| corrupt(self.at))?;
+            let (sign, text) = match line[0] {
+                // newer GNU diff leaves out the space of an empty context line
+                b'\n' => (b' ', line),
+                sign @ (b' ' | b'-' | b'+') => (sign, &line[1..]),
+                b'\\' if line.len() >= 12 && line.starts_with(b"\\ ") => {
+                    no_newline(&mut lines);
+                    self.at += 1;
+                    continue;
+                }
+                _ => return Err(corrupt(self.at)),
+            };
+            let left = |n: usize| n.checked_sub(1).ok_or_else(|| corrupt(self.at));
+            if sign != b'+' {
+                old_left = left(old_left)?;
+            }
+            if sign != b'-' {
+                new_left = left(new_left)?;
+            }
+            lines.push(HunkLine {
+                sign,
+                text: text.to_vec(),
+                line_number: self.at + 1,
+            });
+            self.at += 1;
+        }
+        // the last line of a file without a newline is only marked after the counts run out
+        if self
+            .line(0)
+            .is_some_and(|l| l.len() > 12 && l.starts_with(b"\\ "))
+        {
+            no_newline(&mut lines);
+            self.at += 1;
+        }
+        if lines.iter().all(|l| l.sign == b' ') {
+            return Err(corrupt(self.at - 1));
+        }
+        Ok(Hunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+            lines,
+        })
+    }
+
+    /// A `literal` or `delta` hunk of base 85 lines, ended by an empty line.
+    fn binary_hunk(&mut self) -> Result<Option<BinaryHunk>> {
+        let Some(header) = self.line(0) else {
+            return Ok(None);
+        };
+        let header = text(header);
+        let (literal, size) = if let Some(size) = header.strip_prefix("literal ") {
+            (true, size)
+        } else if let Some(size) = header.strip_prefix("delta ") {
+            (false, size)
+        } else {
+            return Ok(None);
+        };
+        let corrupt = |at: usize, line: &[u8]| {
+            anyhow!("corrupt binary patch at line {}: {}", at + 1, text(line))
+        };
+        let size: usize = size
+            .parse()
+            .map_err(|_| corrupt(self.at, header.as_bytes()))?;
+        self.at += 1;
+
+        let mut data = Vec::new();
+        loop {
+            let line = self.line(0).unwrap_or_default();
+            if line == b"\n" {
+                self.at += 1;
+                break;
+            }
+            binary::decode_line(line, &mut data).ok_or_else(|| corrupt(self.at, line))?;
+            self.at += 1;
+        }
+        let data = binary::inflate(&data, size).ok_or_else(|| corrupt(self.at - 1, b""))?;
+        Ok(Some(if literal {
+            BinaryHunk::Literal(data)
+        } else {
+            BinaryHunk::Delta(data)
+        }))
+    }
+}
+
+/// A `\ No newline at end of file` takes the newline off the line before it.
+fn no_newline(lines: &mut [HunkLine]) {
+    if let Some(line) = lines.last_mut() {
+        if line.text.ends_with(b"\n") {
+            line.text.pop();
+        }
+    }
+}
+
+/// A line as text, without its newline.
+fn text(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
+
+/// `@@ -<start>[,<len>] +<start>[,<len>] @@`, a missing length being 1.
+fn hunk_header(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (old, rest) = rest.split_once(" +")?;
+    let (new, _) = rest.split_once(" @@")?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(old)?;
+    let (new_start, new_len) = range(new)?;
+    Some((old_start, old_len, new_start, new_len))
+}
+
+fn parse_mode(mode: &str, line: usize) -> Result<u32> {
+    u32::from_str_radix(mode.trim_end(), 8)
+        .map_err(|_| anyhow!("invalid mode on line {}: {}", line, mode))
+}
+
+/// Checks a `---` or `+++` line against what the header already said the name is, or takes
+/// the name from it if nothing did.
+fn verify_name(
+    name: &mut Option<String>,
+    line: &str,
+    null: bool,
+    side: &str,
+    strip: usize,
+    number: usize,
+) -> Result<()> {
+    match name {
+        None if !null => *name = find_name(line, strip, true),
+        Some(name) => {
+            if null {
+                bail!(
+                    "git apply: bad git-diff - expected /dev/null, got {} on line {}",
+                    name,
+                    number
+                );
+            }
+            if find_name(line, strip, true).as_ref() != Some(name) {
+                bail!(
+                    "git apply: bad git-diff - inconsistent {} filename on line {}",
+                    side,
+                    number
+                );
+            }
+        }
+        None => {
+            if !is_dev_null(line) {
+                bail!(
+                    "git apply: bad git-diff - expected /dev/null on line {}",
+                    number
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_dev_null(name: &str) -> bool {
+    name.strip_prefix("/dev/null")
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+}
+
+/// The name in a header line, quoted or not, with `strip` leading components taken off. On
+/// `---` and `+++` lines the name ends at a tab.
+fn find_name(line: &str, strip: usize, until_tab: bool) -> Option<String> {
+    if line.starts_with('"') {
+        if let Some((name, _)) = unquote(line) {
+            return strip_components(&name, strip).map(squash_slashes);
+        }
+    }
+    let name = match until_tab {
+        true => line.split('\t').next().unwrap_or(line),
+        false => line,
+    };
+    strip_components(name, strip).map(squash_slashes)
+}
+
+/// The name on a `---` or `+++` line without git's headers, where a date may follow a tab.
+/// Given the name on the other line, that is kept if this one only adds to its end, as in
+/// `file.orig`.
+fn traditional_name(line: &str, other: Option<&str>, strip: usize) -> Option<String> {
+    let Some(name) = find_name(line, strip, true) else {
+        return other.map(str::to_string);
+    };
+    match other {
+        Some(other) if other.len() < name.len() && name.starts_with(other) => {
+            Some(other.to_string())
+        }
+        _ => Some(name),
+    }
+}
+
+/// Whether the date after the name on a `---` or `+++` line is the epoch, in whatever time
+/// zone: `1970-01-01` or, west of Greenwich, `1969-12-31`, then `hh:mm:00[.000] +hhmm`.
+fn has_epoch_timestamp(line: &str) -> bool {
+    let Some((_, stamp)) = line.rsplit_once('\t') else {
+        return false;
+    };
+    let (epoch_hour, time) = match (
+        stamp.strip_prefix("1969-12-31 "),
+        stamp.strip_prefix("1970-01-01 "),
+    ) {
+        (Some(time), _) => (24, time),
+        (_, Some(time)) => (0, time),
+        _ => return false,
+    };
+    let Some((time, zone)) = time.split_once(' ') else {
+        return false;
+    };
+    let time = match time.split_once('.') {
+        Some((time, fraction)) if !fraction.is_empty() && fraction.bytes().all(|c| c == b'0') => {
+            time
+        }
+        Some(_) => return false,
+        None => time,
+    };
+    let number = |digits: &str| digits.parse::<i64>().ok().filter(|_| digits.len() == 2);
+    let (Some(hour), Some(minute), Some("00")) = (
+        time.get(..2).and_then(number),
+        time.get(3..5).and_then(number),
+        time.get(6..8).filter(|_| time.len() == 8),
+    ) else {
+        return false;
+    };
+    let (sign, zone) = match (zone.strip_prefix('+'), zone.strip_prefix('-')) {
+        (Some(zone), _) => (1, zone),
+        (_, Some(zone)) => (-1, zone),
+        _ => return false,
+    };
+    let zone = zone.replacen(':', "", 1);
+    let (Some(zone_hours), Some(zone_minutes), 4) = (
+        zone.get(..2).and_then(number),
+        zone.get(2..4).and_then(number),
+        zone.len(),
+    ) else {
+        return false;
+    };
+    hour * 60 + minute - sign * (zone_hours * 60 + zone_minutes) == epoch_hour * 60
+}
+
+/// The name on the `diff --git` line, which is only known when both sides name the same
+/// path, as they do unless the file was renamed or copied.
+fn header_name(names: &str, strip: usize) -> Option<String> {
+    let (first, second) = if names.starts_with('"') {
+        let (first, rest) = unquote(names)?;
+        let rest = rest.trim_start_matches([' ', '\t']);
+        let second = match rest.starts_with('"') {
+            true => unquote(rest)?.0,
+            false => rest.to_string(),
+        };
+        (first, second)
+    } else if let Some(quote) = names.find(" \"") {
+        (names[..quote].to_string(), unquote(&names[quote + 1..])?.0)
+    } else {
+        // unquoted names may have spaces, so try each split for two that are the same
+        let first = strip_components(names, strip)?;
+        return first
+            .char_indices()
+            .filter(|&(_, c)| c == ' ' || c == '\t')
+            .find(|&(i, _)| strip_components(&first[i + 1..], strip) == Some(&first[..i]))
+            .map(|(i, _)| squash_slashes(&first[..i]));
+    };
+    let first = strip_components(&first, strip)?;
+    (Some(first) == strip_components(&second, strip)).then(|| squash_slashes(first))
+}
+
+/// Takes `strip` leading components off a path, or `None` if it doesn't have that many, or
+/// nothing is left.
+fn strip_components(name: &str, strip: usize) -> Option<&str> {
+    let mut rest = name;
+    for _ in 0..strip {
+        let slash = rest.find('/')?;
+        rest = &rest[slash + 1..];
+    }
+    (!rest.is_empty()).then_some(rest)
+}
+
+fn squash_slashes(name: &str) -> String {
+    let mut squashed = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c != '/' || !squashed.ends_with('/') {
+            squashed.push(c);
+        }
+    }
+    squashed
+}
+
+/// Undoes the C-style quoting git gives names with unusual characters, returning the name and
+/// whatever follows the closing quote. `None` if `quoted` isn't a quoted name.
+fn unquote(quoted: &str) -> Option<(String, &str)> {
+    let bytes = quoted.as_bytes();
+    if bytes.first() != Some(&b'"') {
+        return None;
+    }
+    let mut name = Vec::new();
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                return Some((
+                    String::from_utf8_lossy(&name).into_owned(),
+                    &quoted[i + 1..],
+                ))
+            }
+            b'\\' => {
+                i += 1;
+                let c = match *bytes.get(i)? {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => 0x0b,
+                    b'f' => 0x0c,
+                    b'r' => b'\r',
+                    c @ (b'"' | b'\\') => c,
+                    c @ b'0'..=b'3' => {
+                        let digits = bytes.get(i + 1..i + 3)?;
+                        if !digits.iter().all(|d| (b'0'..=b'7').contains(d)) {
+                            return None;
+                        }
+                        i += 2;
+                        ((c - b'0') << 6) | ((digits[0] - b'0') << 3) | (digits[1] - b'0')
+                    }
+                    _ => return None,
+                };
+                name.push(c);
+            }
+            c => name.push(c),
+        }
+        i += 1;
+    }
+    None
+}
@@ -0,0 +1,217 @@
+//! Whitespace errors in the lines a patch adds, by git's default `core.whitespace` rules:
+//! whitespace at the end of a line, a space before a tab in the indent, and blank lines added
+//! at the end of the file. `apply` warns about them, fixes them or refuses the patch.
+
+use anyhow::{bail, Result};
+
+use crate::diff::is_space;
+
+pub const BLANK_AT_EOL: u8 = 1;
+pub const SPACE_BEFORE_TAB: u8 = 2;
+pub const BLANK_AT_EOF: u8 = 4;
+
+/// What `--whitespace` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Nowarn,
+    Warn,
+    Fix,
+    Error,
+}
+
+impl Action {
+    /// The values `--whitespace` and `apply.whitespace` take, each with how many errors are
+    /// shown before the rest are only counted, 0 for all of them.
+    pub fn parse(value: &str) -> Result<(Action, usize)> {
+        Ok(match value {
+            "nowarn" => (Action::Nowarn, 5),
+            "warn" => (Action::Warn, 5),
+            "fix" | "strip" => (Action::Fix, 5),
+            "error" => (Action::Error, 5),
+            "error-all" => (Action::Error, 0),
+            _ => bail!("unrecognized whitespace option '{}'", value),
+        })
+    }
+}
+
+/// Counts the errors found in a patch and shows the first few.
+pub(crate) struct Report {
+    pub action: Action,
+    /// errors after this many are only counted, unless it is 0
+    squelch: usize,
+    /// the patch file as messages name it
+    pub input: String,
+    pub errors: usize,
+    /// added lines that had their errors fixed
+    pub fixed: usize,
+}
+
+impl Report {
+    pub fn new(action: Action, squelch: usize) -> Report {
+        Report {
+            action,
+            squelch,
+            input: String::new(),
+            errors: 0,
+            fixed: 0,
+        }
+    }
+
+    /// Counts the errors of a line and, unless there have been too many, shows them with the
+    /// line.
+    pub fn record(&mut self, errors: u8, line: &[u8], line_number: usize) {
+        if errors == 0 {
+            return;
+        }
+        self.errors += 1;
+        if self.squelch != 0 && self.squelch < self.errors {
+            return;
+        }
+        eprintln!("{}:{}: {}.", self.input, line_number, describe(errors));
+        eprintln!("{}", String::from_utf8_lossy(line));
+    }
+
+    /// The warnings after all patches are read, and whether `--whitespace=error` refuses them.
+    pub fn finish(&self, applied: bool) -> bool {
+        let lines = |n: usize| match n {
+            1 => "1 line adds".to_string(),
+            n => format!("{} lines add", n),
+        };
+        if self.errors == 0 {
+            return false;
+        }
+        if self.squelch != 0 && self.squelch < self.errors {
+            let squelched = self.errors - self.squelch;
+            eprintln!(
+                "warning: squelched {} whitespace error{}",
+                squelched,
+                if squelched == 1 { "" } else { "s" }
+            );
+        }
+        if self.action == Action::Error {
+            eprintln!("error: {} whitespace errors.", lines(self.errors));
+            return true;
+        }
+        if self.fixed > 0 && applied {
+            eprintln!(
+                "warning: {} line{} applied after fixing whitespace errors.",
+                self.fixed,
+                if self.fixed == 1 { "" } else { "s" }
+            );
+        } else {
+            eprintln!("warning: {} whitespace errors.", lines(self.errors));
+        }
+        false
+    }
+}
+
+fn describe(errors: u8) -> String {
+    let mut parts = Vec::new();
+    if errors & BLANK_AT_EOL != 0 {
+        parts.push("trailing whitespace");
+    }
+    if errors & BLANK_AT_EOF != 0 {
+        parts.push("new blank line at EOF");
+    }
+    if errors & SPACE_BEFORE_TAB != 0 {
+        parts.push("space before tab in indent");
+    }
+    parts.join(", ")
+}
+
+/// The errors in a line, its newline aside, and its CR too if `cr_at_eol`.
+pub fn check(line: &[u8], cr_at_eol: bool) -> u8 {
+    let mut line = line.strip_suffix(b"\n").unwrap_or(line);
+    if cr_at_eol {
+        line = line.strip_suffix(b"\r").unwrap_or(line);
+    }
+    let mut errors = 0;
+    let trailing = line
+        .iter()
+        .rposition(|&c| !is_space(c))
+        .map_or(0, |i| i + 1);
+    if trailing < line.len() {
+        errors |= BLANK_AT_EOL;
+    }
+    let mut written = 0;
+    for (i, &c) in line[..trailing].iter().enumerate() {
+        if c == b' ' {
+            continue;
+        }
+        if c != b'\t' {
+            break;
+        }
+        if written < i {
+            errors |= SPACE_BEFORE_TAB;
+        }
+        written = i + 1;
+    }
+    errors
+}
+
+/// The line with its errors fixed, as git's `ws_fix_copy`: whitespace at the end taken off,
+/// and spaces that come before a tab in the indent dropped. A CR before the newline is kept
+/// only if `cr_at_eol`. Also whether anything was fixed.
+pub fn fix(line: &[u8], cr_at_eol: bool) -> (Vec<u8>, bool) {
+    let mut len = line.len();
+    let mut newline = false;
+    let mut cr = false;
+    let mut fixed = false;
+    if line.ends_with(b"\n") {
+        newline = true;
+        len -= 1;
+        if line[..len].ends_with(b"\r") {
+            cr = cr_at_eol;
+            len -= 1;
+        }
+    }
+    if len > 0 && is_space(line[len - 1]) {
+        while len > 0 && is_space(line[len - 1]) {
+            len -= 1;
+        }
+        fixed = true;
+    }
+
+    let mut out = Vec::with_capacity(line.len());
+    let mut rest = &line[..len];
+    let mut last_tab = None;
+    let mut space_before_tab = false;
+    let mut last_space = None;
+    for (i, &c) in rest.iter().enumerate() {
+        match c {
+            b'\t' => {
+                last_tab = Some(i);
+                space_before_tab |= last_space.is_some();
+            }
+            b' ' => last_space = Some(i),
+            _ => break,
+        }
+    }
+    if let (true, Some(last_tab)) = (space_before_tab, last_tab) {
+        // the indent up to its last tab loses its spaces, bar any run of 8 that is a tab
+        let mut spaces = 0;
+        for &c in &rest[..=last_tab] {
+            if c != b' ' {
+                spaces = 0;
+                out.push(c);
+            } else {
+                spaces += 1;
+                if spaces == 8 {
+                    out.push(b'\t');
+                    spaces = 0;
+                }
+            }
+        }
+        out.resize(out.len() + spaces, b' ');
+        rest = &rest[last_tab + 1..];
+        fixed = true;
+    }
+    out.extend_from_slice(rest);
+    if cr {
+        out.push(b'\r');
+    }
+    if newline {
+        out.push(b'\n');
+    }
+    (out, fixed)
+}
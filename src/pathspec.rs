@@ -0,0 +1,68 @@
+use crate::wildmatch::fnmatch;
+
+/// Paths given on the command line to limit what a command looks at.
+///
+/// A plain path names itself and everything under it. One with glob characters is matched
+/// against whole paths, with `*` crossing slashes, so `*.c` finds C files at any depth. An
+/// empty pathspec, or `.`, matches everything.
+#[derive(Debug, Default)]
+pub(crate) struct Pathspec {
+    pub items: Vec<String>,
+}
+
+impl Pathspec {
+    pub fn new(args: &[impl AsRef<str>]) -> Pathspec {
+        Pathspec {
+            items: args.iter().map(|a| normalize(a.as_ref())).collect(),
+        }
+    }
+
+    /// Whether `path` is matched, by any item or by an empty pathspec.
+    pub fn matches(&self, path: &str) -> bool {
+        self.items.is_empty() || self.items.iter().any(|item| item_matches(item, path))
+    }
+
+    /// Whether anything in the directory `dir` could be matched, so it is worth looking in.
+    pub fn matches_within(&self, dir: &str) -> bool {
+        self.items.is_empty()
+            || self.items.iter().any(|item| {
+                item_matches(item, dir)
+                    || has_glob(item)
+                    || item
+                        .strip_prefix(dir)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+}
+
+/// Whether one pathspec item matches `path`.
+pub fn item_matches(item: &str, path: &str) -> bool {
+    if item.is_empty() || path == item {
+        return true;
+    }
+    if path
+        .strip_prefix(item)
+        .is_some_and(|rest| rest.starts_with('/'))
+    {
+        return true;
+    }
+    has_glob(item) && fnmatch(item, path)
+}
+
+pub fn has_glob(item: &str) -> bool {
+    item.contains(['*', '?', '[', '\\'])
+}
+
+/// `./a/b/` -> `a/b`, and `.` -> the empty item that matches everything.
+fn normalize(arg: &str) -> String {
+    let mut item = arg;
+    while let Some(rest) = item.strip_prefix("./") {
+        item = rest;
+    }
+    let item = item.trim_end_matches('/');
+    if item == "." {
+        String::new()
+    } else {
+        item.to_string()
+    }
+}
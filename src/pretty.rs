@@ -0,0 +1,453 @@
+//! Showing commits as `git log` does: the built-in formats `--pretty` names, and `--format`
+//! strings with `%` placeholders.
+
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::{
+    date,
+    objects::{commit::Commit, object},
+    refs::{self, Ref},
+    revision,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// `<hash> <subject>` on one line
+    Oneline,
+    /// the author and the subject
+    Short,
+    /// the author, the date and the whole message, the default
+    Medium,
+    /// the author, the committer and the whole message
+    Full,
+    /// the author and committer with their dates, and the whole message
+    Fuller,
+    /// a `--format` string
+    User(String),
+}
+
+/// A format and how commits shown with it are set apart.
+#[derive(Debug, Clone)]
+pub(crate) struct Pretty {
+    pub format: Format,
+    /// whether each commit's text ends with a newline, rather than having one between it
+    /// and the next
+    pub terminated: bool,
+}
+
+impl Pretty {
+    /// Parses a `--pretty` or `--format` value: a format's name, `format:<string>` for text
+    /// separated by newlines, `tformat:<string>` for text ended by them, or a string with a
+    /// placeholder in it (or none at all), which is taken as a `tformat:`.
+    pub fn parse(value: &str) -> Result<Pretty> {
+        let (format, terminated) = match value {
+            "oneline" => (Format::Oneline, true),
+            "short" => (Format::Short, false),
+            "medium" => (Format::Medium, false),
+            "full" => (Format::Full, false),
+            "fuller" => (Format::Fuller, false),
+            _ => {
+                if let Some(string) = value.strip_prefix("format:") {
+                    (Format::User(string.to_string()), false)
+                } else if let Some(string) = value.strip_prefix("tformat:") {
+                    (Format::User(string.to_string()), true)
+                } else if value.is_empty() || value.contains('%') {
+                    (Format::User(value.to_string()), true)
+                } else {
+                    anyhow::bail!("invalid --pretty format: {}", value)
+                }
+            }
+        };
+        Ok(Pretty { format, terminated })
+    }
+
+    /// Whether the format shows nothing at all, so no line is given to a commit.
+    pub fn is_empty(&self) -> bool {
+        self.format == Format::User(String::new())
+    }
+
+    /// Whether the format shows the refs pointing at commits, which are worth loading then.
+    pub fn wants_decorations(&self) -> bool {
+        matches!(&self.format, Format::User(string) if string.contains("%d") || string.contains("%D"))
+    }
+}
+
+/// The names of the refs pointing at each commit, for `%d` and `%D`.
+#[derive(Debug, Default)]
+pub(crate) struct Decorations {
+    /// (ref name, name as shown) by commit, the last ref in name order first
+    names: HashMap<String, Vec<(String, String)>>,
+    /// the branch HEAD is on, if it isn't detached
+    head_branch: Option<String>,
+}
+
+impl Decorations {
+    pub fn load() -> Result<Decorations> {
+        let mut decorations = Decorations::default();
+        for (name, hash) in refs::list()? {
+            let shown = if let Some(tag) = name.strip_prefix("refs/tags/") {
+                format!("tag: {}", tag)
+            } else if name.starts_with("refs/heads/") || name.starts_with("refs/remotes/") {
+                refs::shorten(&name).to_string()
+            } else {
+                name.clone()
+            };
+            // an annotated tag decorates the commit it points at too
+            let peeled = revision::peel_tags(&hash)?;
+            if peeled != hash {
+                decorations.add(&peeled, &name, &shown);
+            }
+            decorations.add(&hash, &name, &shown);
+        }
+        if let Some(head) = refs::resolve("HEAD")? {
+            decorations.add(&head, "HEAD", "HEAD");
+        }
+        if let Some(Ref::Symbolic(branch)) = refs::read("HEAD")? {
+            decorations.head_branch = Some(branch);
+        }
+        Ok(decorations)
+    }
+
+    fn add(&mut self, hash: &str, name: &str, shown: &str) {
+        let names = self.names.entry(hash.to_string()).or_default();
+        names.insert(0, (name.to_string(), shown.to_string()));
+    }
+
+    /// The refs pointing at `hash` as `log` lists them, eg `HEAD -> main, tag: v1.0`.
+    pub fn list(&self, hash: &str) -> String {
+        let Some(names) = self.names.get(hash) else {
+            return String::new();
+        };
+        let current = self.head_branch.as_deref().filter(|branch| {
+            branch.starts_with("refs/heads/") && names.iter().any(|(name, _)| name == branch)
+        });
+        let mut shown = Vec::new();
+        for (name, label) in names {
+            if Some(name.as_str()) == current {
+                continue;
+            }
+            match current {
+                Some(branch) if name == "HEAD" => {
+                    shown.push(format!("HEAD -> {}", refs::shorten(branch)))
+                }
+                _ => shown.push(label.clone()),
+            }
+        }
+        shown.join(", ")
+    }
+}
+
+/// What a commit's message is made of, as the placeholders see it.
+struct Message<'a> {
+    /// the first paragraph, with leading blank lines skipped
+    subject: Vec<&'a str>,
+    /// everything after the first paragraph and the blank lines after it
+    body: &'a str,
+}
+
+impl<'a> Message<'a> {
+    fn parse(message: &'a str) -> Message<'a> {
+        let mut rest = message;
+        let mut subject = Vec::new();
+        let mut started = false;
+        while !rest.is_empty() {
+            let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+            let line = rest[..end].trim_end();
+            if line.is_empty() {
+                if started {
+                    break;
+                }
+            } else {
+                started = true;
+                subject.push(line);
+            }
+            rest = &rest[end..];
+        }
+        while let Some(line) = rest.split_inclusive('\n').next() {
+            if !line.trim().is_empty() {
+                break;
+            }
+            rest = &rest[line.len()..];
+        }
+        Message {
+            subject,
+            body: rest,
+        }
+    }
+
+    fn subject(&self) -> String {
+        self.subject.join(" ")
+    }
+}
+
+/// The text shown for a commit after the `commit <hash>` line of the built-in formats, or in
+/// place of everything with a format string. `parents` are the ones the walk kept.
+pub(crate) fn message(
+    pretty: &Pretty,
+    hash: &str,
+    commit: &Commit,
+    parents: &[String],
+    decorations: &Decorations,
+) -> Result<Vec<u8>> {
+    let format = match &pretty.format {
+        Format::User(string) => return expand(string, hash, commit, parents, decorations),
+        Format::Oneline => return Ok(Message::parse(&commit.message).subject().into_bytes()),
+        format => format,
+    };
+
+    let mut text = String::new();
+    if parents.len() > 1 {
+        text.push_str("Merge:");
+        for parent in parents {
+            text.push(' ');
+            text.push_str(&object::abbreviate(parent, 7)?);
+        }
+        text.push('\n');
+    }
+    let author = format!("{} {}", commit.author, commit.author_email);
+    let committer = format!("{} {}", commit.committer, commit.committer_email);
+    let author_date = date::format_default(
+        commit.author_time.parse().unwrap_or(0),
+        &commit.author_offset,
+    );
+    let committer_date = date::format_default(
+        commit.committer_time.parse().unwrap_or(0),
+        &commit.committer_offset,
+    );
+    match format {
+        Format::Short => text.push_str(&format!("Author: {}\n", author)),
+        Format::Medium => {
+            text.push_str(&format!("Author: {}\nDate:   {}\n", author, author_date));
+        }
+        Format::Full => {
+            text.push_str(&format!("Author: {}\nCommit: {}\n", author, committer));
+        }
+        _ => {
+            text.push_str(&format!(
+                "Author:     {}\nAuthorDate: {}\nCommit:     {}\nCommitDate: {}\n",
+                author, author_date, committer, committer_date
+            ));
+        }
+    }
+    text.push('\n');
+
+    let mut started = false;
+    for line in commit.message.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if !started {
+                continue;
+            }
+            if *format == Format::Short {
+                break;
+            }
+        }
+        started = true;
+        text.push_str("    ");
+        text.push_str(&expand_tabs(line));
+        text.push('\n');
+    }
+    text.truncate(text.trim_end().len());
+    text.push('\n');
+    Ok(text.into_bytes())
+}
+
+/// Tabs replaced with spaces up to the next multiple of eight columns.
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = 8 - column % 8;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+/// Fills in the placeholders of a format string. One that isn't known is left as it is.
+fn expand(
+    format: &str,
+    hash: &str,
+    commit: &Commit,
+    parents: &[String],
+    decorations: &Decorations,
+) -> Result<Vec<u8>> {
+    let message = Message::parse(&commit.message);
+    let mut out: Vec<u8> = Vec::new();
+    let mut rest = format;
+    while let Some(at) = rest.find('%') {
+        out.extend(&rest.as_bytes()[..at]);
+        rest = &rest[at + 1..];
+
+        // `%+x` puts a newline before x if it isn't empty, `% x` a space, and `%-x` takes
+        // away the newlines before it if it is
+        let magic = rest
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '+' | '-' | ' '))
+            .filter(|_| rest.len() > 1);
+        let placeholder = if magic.is_some() { &rest[1..] } else { rest };
+        let before = out.len();
+        let Some(used) = placeholder_into(
+            &mut out,
+            placeholder,
+            hash,
+            commit,
+            parents,
+            &message,
+            decorations,
+        )?
+        else {
+            out.push(b'%');
+            continue;
+        };
+        rest = &placeholder[used..];
+        match magic {
+            Some('-') if out.len() == before => {
+                while out.last() == Some(&b'\n') {
+                    out.pop();
+                }
+            }
+            Some('+') if out.len() != before => out.insert(before, b'\n'),
+            Some(' ') if out.len() != before => out.insert(before, b' '),
+            _ => {}
+        }
+    }
+    out.extend(rest.as_bytes());
+    Ok(out)
+}
+
+/// Writes what the placeholder at the start of `spec` stands for, saying how much of `spec`
+/// it took, or None if it isn't one.
+fn placeholder_into(
+    out: &mut Vec<u8>,
+    spec: &str,
+    hash: &str,
+    commit: &Commit,
+    parents: &[String],
+    message: &Message,
+    decorations: &Decorations,
+) -> Result<Option<usize>> {
+    let mut chars = spec.chars();
+    let Some(first) = chars.next() else {
+        return Ok(None);
+    };
+    let text = match first {
+        'H' => hash.to_string(),
+        'h' => object::abbreviate(hash, 7)?,
+        'T' => commit.tree.clone(),
+        't' => object::abbreviate(&commit.tree, 7)?,
+        'P' => parents.join(" "),
+        'p' => parents
+            .iter()
+            .map(|parent| object::abbreviate(parent, 7))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(" "),
+        'a' | 'c' => {
+            let Some(field) = chars.next() else {
+                return Ok(None);
+            };
+            let (name, email, time, offset) = if first == 'a' {
+                (
+                    &commit.author,
+                    &commit.author_email,
+                    &commit.author_time,
+                    &commit.author_offset,
+                )
+            } else {
+                (
+                    &commit.committer,
+                    &commit.committer_email,
+                    &commit.committer_time,
+                    &commit.committer_offset,
+                )
+            };
+            let email = email.trim_start_matches('<').trim_end_matches('>');
+            let timestamp = time.parse().unwrap_or(0);
+            let text = match field {
+                'n' | 'N' => name.clone(),
+                'e' | 'E' => email.to_string(),
+                'l' | 'L' => email.split('@').next().unwrap_or_default().to_string(),
+                'd' => date::format_default(timestamp, offset),
+                'D' => date::format_rfc2822(timestamp, offset),
+                'r' => date::format_relative(timestamp, date::now()),
+                't' => time.clone(),
+                'i' => date::format_iso(timestamp, offset, false),
+                'I' => date::format_iso(timestamp, offset, true),
+                's' => date::format_short(timestamp, offset),
+                _ => return Ok(None),
+            };
+            out.extend(text.as_bytes());
+            return Ok(Some(2));
+        }
+        'd' => {
+            let list = decorations.list(hash);
+            if list.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", list)
+            }
+        }
+        'D' => decorations.list(hash),
+        's' => message.subject(),
+        'f' => sanitize(&message.subject()),
+        'b' => message.body.to_string(),
+        'B' => commit.message.clone(),
+        'n' => "\n".to_string(),
+        '%' => "%".to_string(),
+        'x' => {
+            let digits = spec
+                .get(1..3)
+                .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()));
+            let Some(byte) = digits.and_then(|d| u8::from_str_radix(d, 16).ok()) else {
+                return Ok(None);
+            };
+            out.push(byte);
+            return Ok(Some(3));
+        }
+        'C' => {
+            // colours are only written to a terminal, which log output here never goes to
+            for name in ["red", "green", "blue", "reset"] {
+                if spec[1..].starts_with(name) {
+                    return Ok(Some(1 + name.len()));
+                }
+            }
+            return Ok(None);
+        }
+        _ => return Ok(None),
+    };
+    out.extend(text.as_bytes());
+    Ok(Some(1))
+}
+
+/// The subject made fit for a file name, as `%f` and `format-patch` make it: runs of other
+/// characters become one `-`, and no `.` or `-` is left at the end.
+fn sanitize(subject: &str) -> String {
+    let mut sanitized = String::new();
+    let mut space = 2;
+    let mut chars = subject.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if space == 1 {
+                sanitized.push('-');
+            }
+            space = 0;
+            sanitized.push(c);
+            if c == '.' {
+                while chars.peek() == Some(&'.') {
+                    chars.next();
+                }
+            }
+        } else {
+            space |= 1;
+        }
+    }
+    sanitized.truncate(sanitized.trim_end_matches(['.', '-']).len());
+    sanitized
+}
@@ -0,0 +1,79 @@
+use crate::config::Config;
+
+/// What makes a path quoted, beyond control characters, quotes and backslashes which always
+/// do.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Quoting {
+    /// bytes outside ASCII, escaped in octal; `core.quotePath`, on unless set to false
+    pub non_ascii: bool,
+    /// spaces, which are kept but put the path in quotes, as status's short format does
+    pub spaces: bool,
+}
+
+impl Default for Quoting {
+    fn default() -> Quoting {
+        Quoting {
+            non_ascii: true,
+            spaces: false,
+        }
+    }
+}
+
+impl Quoting {
+    pub fn from_config(config: &Config) -> Quoting {
+        Quoting {
+            non_ascii: config.get_bool("core.quotePath").unwrap_or(true),
+            ..Default::default()
+        }
+    }
+}
+
+/// Quotes a path the way git does with `core.quotePath` on: in double quotes with C escapes
+/// when it has control characters, quotes, backslashes or anything outside ASCII.
+pub fn quote_path(path: &str) -> String {
+    quote_path_with(path, Quoting::default())
+}
+
+/// `quote_path` with the choices of `quoting`.
+pub fn quote_path_with(path: &str, quoting: Quoting) -> String {
+    let must_escape = |b: u8| b < 0x20 || b == b'"' || b == b'\\' || b == 0x7f;
+    let needs_quoting = path
+        .bytes()
+        .any(|b| must_escape(b) || (b >= 0x80 && quoting.non_ascii));
+    if !needs_quoting {
+        return match quoting.spaces && path.contains(' ') {
+            true => format!("\"{}\"", path),
+            false => path.to_string(),
+        };
+    }
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        if !c.is_ascii() {
+            match quoting.non_ascii {
+                true => {
+                    let mut bytes = [0; 4];
+                    for b in c.encode_utf8(&mut bytes).bytes() {
+                        quoted.push_str(&format!("\\{:03o}", b));
+                    }
+                }
+                false => quoted.push(c),
+            }
+            continue;
+        }
+        match c {
+            '\x07' => quoted.push_str("\\a"),
+            '\x08' => quoted.push_str("\\b"),
+            '\t' => quoted.push_str("\\t"),
+            '\n' => quoted.push_str("\\n"),
+            '\x0b' => quoted.push_str("\\v"),
+            '\x0c' => quoted.push_str("\\f"),
+            '\r' => quoted.push_str("\\r"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if must_escape(c as u8) => quoted.push_str(&format!("\\{:03o}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,334 @@
+pub(crate) mod packed;
+pub(crate) mod reflog;
+
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use packed::PackedRefs;
+
+/// git gives up following symbolic refs after this many hops, see `SYMREF_MAXDEPTH`
+const MAX_DEPTH: usize = 5;
+
+pub(crate) const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Ref {
+    /// points straight at an object
+    Direct(String),
+    /// `ref: <name>`, like HEAD on a branch
+    Symbolic(String),
+}
+
+/// Reads a single ref without following it, loose files taking priority over packed-refs.
+pub fn read(name: &str) -> Result<Option<Ref>> {
+    let path = Path::new(".git").join(name);
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let contents = contents.trim_end();
+            if let Some(target) = contents.strip_prefix("ref:") {
+                return Ok(Some(Ref::Symbolic(target.trim().to_string())));
+            }
+            anyhow::ensure!(is_hash(contents), "ref {} is not a valid hash", name);
+            return Ok(Some(Ref::Direct(contents.to_string())));
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::IsADirectory => {}
+        // a path component being a file, eg refs/heads/a when asking for refs/heads/a/b
+        Err(e) if e.kind() == ErrorKind::NotADirectory => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading ref {}", name)),
+    }
+
+    let packed = PackedRefs::read()?;
+    Ok(packed.get(name).map(|r| Ref::Direct(r.hash.clone())))
+}
+
+/// Follows symbolic refs down to the ref that holds a hash. Returns the name of that last ref
+/// along with its hash, which is `None` for an unborn branch (HEAD pointing at a branch with no
+/// commits yet) or a ref that does not exist.
+pub fn resolve_name(name: &str) -> Result<(String, Option<String>)> {
+    let mut name = name.to_string();
+    for _ in 0..=MAX_DEPTH {
+        match read(&name)? {
+            Some(Ref::Direct(hash)) => return Ok((name, Some(hash))),
+            Some(Ref::Symbolic(target)) => name = target,
+            None => return Ok((name, None)),
+        }
+    }
+    anyhow::bail!("too many levels of symbolic refs at {}", name)
+}
+
+/// Follows `name` to the hash it ends up at, if any.
+pub fn resolve(name: &str) -> Result<Option<String>> {
+    Ok(resolve_name(name)?.1)
+}
+
+/// The full name a short one like `main` or `v1.0` refers to, trying the places git searches
+/// in the same order: as given, then under `refs/`, `refs/tags/`, `refs/heads/`,
+/// `refs/remotes/` and finally `refs/remotes/<name>/HEAD`.
+pub fn dwim(name: &str) -> Result<Option<String>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for candidate in candidates {
+        // only HEAD and its friends live directly in .git, next to files like config and index
+        // that aren't refs, so names there must be all capitals and underscores
+        if !candidate.starts_with("refs/") && !is_root_ref_name(&candidate) {
+            continue;
+        }
+        // a file that doesn't hold a ref just isn't this candidate
+        if read(&candidate).ok().flatten().is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `name` could be a ref directly in `.git`, like `HEAD` or `ORIG_HEAD`.
+fn is_root_ref_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+}
+
+/// Every ref under `refs/` as (name, hash), loose and packed merged and sorted by name.
+/// Symbolic refs are resolved; ones that dangle are left out.
+pub fn list() -> Result<Vec<(String, String)>> {
+    let mut names = std::collections::BTreeSet::new();
+    collect_loose(Path::new(".git/refs"), &mut names)?;
+    let packed = PackedRefs::read()?;
+    names.extend(packed.refs.keys().cloned());
+
+    let mut refs = Vec::new();
+    for name in names {
+        if let Some(hash) = resolve(&name)? {
+            refs.push((name, hash));
+        }
+    }
+    Ok(refs)
+}
+
+fn collect_loose(dir: &Path, names: &mut std::collections::BTreeSet<String>) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_loose(&path, names)?;
+        } else if path.extension().is_none_or(|ext| ext != "lock") {
+            let name = path
+                .strip_prefix(".git")
+                .expect("walking under .git")
+                .to_str()
+                .context("ref names must be utf-8")?;
+            names.insert(name.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Points `name` at `new`, checking under the lock that it currently holds `old`. An `old` of
+/// all zeros means the ref must not exist yet, `None` skips the check. Unless `no_deref`,
+/// symbolic refs are followed and the ref at the end is the one written.
+///
+/// The move is recorded in the reflog of the ref written with `message`, and also in HEAD's
+/// when that is the branch HEAD is on.
+pub fn update(
+    name: &str,
+    new: &str,
+    old: Option<&str>,
+    no_deref: bool,
+    message: &str,
+) -> Result<()> {
+    anyhow::ensure!(is_hash(new), "{}: not a valid SHA1", new);
+    let target = if no_deref {
+        name.to_string()
+    } else {
+        resolve_name(name)?.0
+    };
+    check_name(&target)?;
+    let head = resolve_name("HEAD")?.0;
+
+    let lock = Lock::acquire(Path::new(".git").join(&target))?;
+    let previous = verify_old(&target, old)?;
+
+    let mut file = lock.file()?;
+    writeln!(file, "{}", new).with_context(|| format!("writing {}.lock", target))?;
+    lock.commit()?;
+
+    reflog::append(&target, previous.as_deref(), new, message)?;
+    if target != "HEAD" && target == head {
+        reflog::append("HEAD", previous.as_deref(), new, message)?;
+    }
+    Ok(())
+}
+
+/// Removes `name` both as a loose file and from packed-refs, with the same `old` check as
+/// `update`.
+pub fn delete(name: &str, old: Option<&str>, no_deref: bool) -> Result<()> {
+    let target = if no_deref {
+        name.to_string()
+    } else {
+        resolve_name(name)?.0
+    };
+
+    let path = Path::new(".git").join(&target);
+    let lock = Lock::acquire(&path)?;
+    verify_old(&target, old)?;
+
+    PackedRefs::remove(&target)?;
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("deleting {}", target)),
+    }
+    drop(lock);
+    reflog::remove(&target)
+}
+
+/// Makes `name` a symbolic ref to `target`, eg `HEAD` to `refs/heads/main`. With a `message`
+/// the switch goes into the reflog of `name` when both ends point at commits, like checkout
+/// does.
+pub fn update_symbolic(name: &str, target: &str, message: Option<&str>) -> Result<()> {
+    check_name(target)?;
+    anyhow::ensure!(
+        target.starts_with("refs/"),
+        "refusing to point {} outside of refs/",
+        name
+    );
+    let previous = resolve(name)?;
+    let lock = Lock::acquire(Path::new(".git").join(name))?;
+    let mut file = lock.file()?;
+    writeln!(file, "ref: {}", target).with_context(|| format!("writing {}.lock", name))?;
+    lock.commit()?;
+
+    if let (Some(message), Some(new)) = (message, resolve(target)?) {
+        reflog::append(name, previous.as_deref(), &new, message)?;
+    }
+    Ok(())
+}
+
+/// Checks `old` against what `name` holds now and returns the current value.
+fn verify_old(name: &str, old: Option<&str>) -> Result<Option<String>> {
+    let current = match read(name)? {
+        Some(Ref::Direct(hash)) => Some(hash),
+        Some(Ref::Symbolic(_)) => resolve(name)?,
+        None => None,
+    };
+    let Some(old) = old else {
+        return Ok(current);
+    };
+    match &current {
+        None if old == ZERO_HASH => {}
+        None => anyhow::bail!("cannot lock ref '{}': unable to resolve reference", name),
+        Some(current) if old == ZERO_HASH => {
+            anyhow::bail!(
+                "cannot lock ref '{}': reference already exists ({})",
+                name,
+                current
+            )
+        }
+        Some(current) if current != old => anyhow::bail!(
+            "cannot lock ref '{}': is at {} but expected {}",
+            name,
+            current,
+            old
+        ),
+        Some(_) => {}
+    }
+    Ok(current)
+}
+
+/// `refs/heads/main` -> `main`, the way git shows names to people.
+pub fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+pub fn is_hash(s: &str) -> bool {
+    s.len() == 40 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The rules from `git check-ref-format` that matter for names we write.
+pub fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && name != "@"
+        && !name
+            .bytes()
+            .any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b))
+        && name
+            .split('/')
+            .all(|part| !part.starts_with('.') && !part.ends_with(".lock"));
+    anyhow::ensure!(valid, "'{}' is not a valid ref name", name);
+    Ok(())
+}
+
+/// A `<file>.lock` taken with `O_EXCL`. Writing goes to the lock file, `commit` renames it over
+/// the real file, and dropping it without committing removes it again.
+pub(crate) struct Lock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl Lock {
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Lock> {
+        let path = path.as_ref().to_path_buf();
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+        }
+        let file = fs::File::create_new(&lock_path).with_context(|| {
+            format!(
+                "Unable to create '{}': File exists or could not be created. Another git \
+                 process seems to be running in this repository",
+                lock_path.display()
+            )
+        })?;
+        Ok(Lock {
+            path,
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    pub fn file(&self) -> Result<&fs::File> {
+        self.file.as_ref().context("lock already released")
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        self.file()?.sync_all().context("syncing lock file")?;
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("renaming {}", self.lock_path.display()))?;
+        // only now is there no lock file left for drop to remove
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fs, io::ErrorKind, io::Write};
+
+use crate::refs::Lock;
+
+const PACKED_REFS: &str = ".git/packed-refs";
+
+#[derive(Debug, Clone)]
+pub(crate) struct PackedRef {
+    pub hash: String,
+    /// the object an annotated tag points to, from the `^` line after it
+    pub peeled: Option<String>,
+}
+
+/// The contents of `.git/packed-refs`, kept sorted by name like git writes it.
+#[derive(Debug, Default)]
+pub(crate) struct PackedRefs {
+    pub refs: BTreeMap<String, PackedRef>,
+}
+
+impl PackedRefs {
+    pub fn read() -> Result<PackedRefs> {
+        let contents = match fs::read_to_string(PACKED_REFS) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(PackedRefs::default()),
+            Err(e) => return Err(e).context("reading packed-refs"),
+        };
+        PackedRefs::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<PackedRefs> {
+        let mut refs = BTreeMap::new();
+        let mut last: Option<String> = None;
+
+        for line in contents.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                // the header with the traits, eg "# pack-refs with: peeled fully-peeled sorted"
+                continue;
+            }
+            if let Some(peeled) = line.strip_prefix('^') {
+                let name = last
+                    .as_ref()
+                    .context("peeled line in packed-refs without a ref before it")?;
+                let entry: &mut PackedRef = refs.get_mut(name).expect("inserted just before");
+                entry.peeled = Some(peeled.trim().to_string());
+                continue;
+            }
+            let (hash, name) = line
+                .split_once(' ')
+                .with_context(|| format!("unexpected line in packed-refs: {}", line))?;
+            anyhow::ensure!(
+                hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit()),
+                "bad hash in packed-refs: {}",
+                line
+            );
+            refs.insert(
+                name.to_string(),
+                PackedRef {
+                    hash: hash.to_string(),
+                    peeled: None,
+                },
+            );
+            last = Some(name.to_string());
+        }
+
+        Ok(PackedRefs { refs })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackedRef> {
+        self.refs.get(name)
+    }
+
+    /// Drops `name` and rewrites the file through `packed-refs.lock`, used when deleting a ref
+    /// so the packed copy does not show through afterwards.
+    pub fn remove(name: &str) -> Result<()> {
+        let lock = Lock::acquire(PACKED_REFS)?;
+        let mut packed = PackedRefs::read()?;
+        if packed.refs.remove(name).is_none() {
+            return Ok(());
+        }
+
+        let mut contents = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for (name, entry) in packed.refs.iter() {
+            contents.push_str(&format!("{} {}\n", entry.hash, name));
+            if let Some(peeled) = &entry.peeled {
+                contents.push_str(&format!("^{}\n", peeled));
+            }
+        }
+        let mut file = lock.file()?;
+        file.write_all(contents.as_bytes())
+            .context("writing packed-refs.lock")?;
+        lock.commit()
+    }
+}
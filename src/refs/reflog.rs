@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use std::{
+    fmt::Display,
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{self, Config},
+    date,
+    refs::{self, ZERO_HASH},
+};
+
+/// One line of `.git/logs/<ref>`:
+/// `<old> <new> <name> <<email>> <timestamp> <offset>\t<message>`
+#[derive(Debug, Clone)]
+pub(crate) struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    /// `Name <email>`
+    pub committer: String,
+    pub timestamp: i64,
+    pub offset: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Result<ReflogEntry> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = header
+            .split_once(' ')
+            .context("reflog entry has no new value")?;
+        let (new, rest) = rest
+            .split_once(' ')
+            .context("reflog entry has no identity")?;
+        let end_of_email = rest.rfind('>').context("reflog entry has no email")?;
+        let committer = &rest[..=end_of_email];
+        let mut time = rest[end_of_email + 1..].split_whitespace();
+        let timestamp = time
+            .next()
+            .context("reflog entry has no timestamp")?
+            .parse()
+            .context("reflog timestamp is not a number")?;
+        let offset = time.next().unwrap_or("+0000");
+
+        Ok(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            committer: committer.to_string(),
+            timestamp,
+            offset: offset.to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+impl Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.old, self.new, self.committer, self.timestamp, self.offset
+        )?;
+        // git leaves the tab out along with an empty message
+        if !self.message.is_empty() {
+            write!(f, "\t{}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+fn log_path(name: &str) -> PathBuf {
+    Path::new(".git/logs").join(name)
+}
+
+/// Whether updates to `name` get logged, following git's default of
+/// `core.logAllRefUpdates=true` for repositories with a worktree.
+pub fn should_log(name: &str) -> Result<bool> {
+    if log_path(name).exists() {
+        return Ok(true);
+    }
+    let config = Config::load()?;
+    let enabled = match config.get("core.logallrefupdates") {
+        Some(v) if v.eq_ignore_ascii_case("always") => return Ok(true),
+        Some(_) => config.get_bool("core.logallrefupdates").unwrap_or(true),
+        None => true,
+    };
+    Ok(enabled
+        && (name == "HEAD"
+            || name.starts_with("refs/heads/")
+            || name.starts_with("refs/remotes/")
+            || name.starts_with("refs/notes/")))
+}
+
+/// Appends an entry for `name` moving from `old` (`None` when it did not exist) to `new`.
+pub fn append(name: &str, old: Option<&str>, new: &str, message: &str) -> Result<()> {
+    if !should_log(name)? {
+        return Ok(());
+    }
+    let config = Config::load()?;
+    let entry = ReflogEntry {
+        old: old.unwrap_or(ZERO_HASH).to_string(),
+        new: new.to_string(),
+        committer: config::committer_ident(&config),
+        timestamp: date::now(),
+        offset: "+0000".to_string(),
+        // the message has to stay on one line
+        message: message.lines().collect::<Vec<_>>().join(" "),
+    };
+
+    let path = log_path(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    writeln!(file, "{}", entry).with_context(|| format!("appending to {}", path.display()))?;
+    Ok(())
+}
+
+/// All entries for `name`, oldest first as they are in the file.
+pub fn read(name: &str) -> Result<Vec<ReflogEntry>> {
+    let contents = match fs::read_to_string(log_path(name)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading reflog of {}", name)),
+    };
+    contents
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(ReflogEntry::parse)
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("parsing reflog of {}", name))
+}
+
+pub fn exists(name: &str) -> bool {
+    log_path(name).is_file()
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    match fs::remove_file(log_path(name)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("removing reflog of {}", name)),
+    }
+}
+
+/// What goes between the braces of `<ref>@{...}`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Selector {
+    /// `@{n}`, the value n moves ago
+    Nth(usize),
+    /// `@{date}`, the value the ref had at that time
+    Date(i64),
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Selector> {
+        if let Ok(n) = selector.parse() {
+            return Ok(Selector::Nth(n));
+        }
+        Ok(Selector::Date(date::parse(selector)?))
+    }
+}
+
+/// Looks up the hash `name` had according to its reflog.
+pub fn lookup(name: &str, selector: &Selector) -> Result<String> {
+    let entries = read(name)?;
+    let shown = refs::shorten(name);
+    anyhow::ensure!(!entries.is_empty(), "log for '{}' is empty", shown);
+
+    let hash = match *selector {
+        Selector::Nth(n) if n < entries.len() => entries[entries.len() - 1 - n].new.clone(),
+        // one past the end is where the oldest entry started from
+        Selector::Nth(n) if n == entries.len() && entries[0].old != ZERO_HASH => {
+            entries[0].old.clone()
+        }
+        Selector::Nth(_) => {
+            anyhow::bail!("log for '{}' only has {} entries", shown, entries.len())
+        }
+        Selector::Date(time) => match entries.iter().rev().find(|e| e.timestamp <= time) {
+            Some(entry) => entry.new.clone(),
+            None => {
+                eprintln!(
+                    "warning: log for '{}' only goes back to {}",
+                    shown,
+                    date::format_rfc2822(entries[0].timestamp, &entries[0].offset)
+                );
+                // a log started by creating the ref has nothing older than its first value
+                match entries[0].old.as_str() {
+                    ZERO_HASH => entries[0].new.clone(),
+                    old => old.to_string(),
+                }
+            }
+        },
+    };
+    anyhow::ensure!(hash != ZERO_HASH, "log for '{}' has no value there", shown);
+    Ok(hash)
+}
+
+/// Resolves `<ref>@{<n>|<date>}`, returning `None` when `spec` is not in that form. An empty
+/// ref means the branch HEAD is on.
+pub fn resolve_spec(spec: &str) -> Result<Option<String>> {
+    let Some((name, selector)) = spec
+        .strip_suffix('}')
+        .and_then(|spec| spec.rsplit_once("@{"))
+    else {
+        return Ok(None);
+    };
+    let selector = Selector::parse(selector)?;
+    let name = if name.is_empty() {
+        refs::resolve_name("HEAD")?.0
+    } else {
+        refs::dwim(name)?.with_context(|| format!("unknown ref {}", name))?
+    };
+    lookup(&name, &selector).map(Some)
+}
@@ -0,0 +1,512 @@
+//! POSIX extended regular expressions, enough for the word regexes `diff` takes.
+//!
+//! Patterns are compiled the way git compiles them, `REG_EXTENDED | REG_NEWLINE`: `.` and
+//! negated brackets don't match a newline, and `^` and `$` match at the start and end of every
+//! line. Matching is leftmost-longest as POSIX asks, so `a|ab` matches all of `ab`; the
+//! compiled program is run over the text a step at a time, all alternatives at once.
+//! Bracket expressions take ranges and `[:class:]` names, and `\w`, `\W`, `\s` and `\S` are
+//! taken as GNU takes them. Basic patterns, which `log --grep` takes by default, are rewritten
+//! into extended ones first.
+
+use anyhow::Result;
+
+/// A compiled pattern.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+/// A set of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Class([u64; 4]);
+
+impl Class {
+    const EMPTY: Class = Class([0; 4]);
+
+    fn byte(c: u8) -> Class {
+        let mut class = Class::EMPTY;
+        class.insert(c);
+        class
+    }
+
+    fn from_fn(f: impl Fn(u8) -> bool) -> Class {
+        let mut class = Class::EMPTY;
+        for c in 0..=255u8 {
+            if f(c) {
+                class.insert(c);
+            }
+        }
+        class
+    }
+
+    fn insert(&mut self, c: u8) {
+        self.0[c as usize / 64] |= 1 << (c % 64);
+    }
+
+    fn contains(&self, c: u8) -> bool {
+        self.0[c as usize / 64] & (1 << (c % 64)) != 0
+    }
+
+    fn union(&mut self, other: &Class) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a |= b;
+        }
+    }
+
+    /// Everything else, less the newline, which nothing negated matches.
+    fn negate(&self) -> Class {
+        let mut class = Class(self.0.map(|bits| !bits));
+        class.0[0] &= !(1 << b'\n');
+        class
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Class(Class),
+    LineStart,
+    LineEnd,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Class(Class),
+    LineStart,
+    LineEnd,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// The most a bound like `{m,n}` may ask for, as in glibc.
+const MAX_REPEAT: u32 = 255;
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex> {
+        let mut parser = Parser {
+            pattern: pattern.as_bytes(),
+            at: 0,
+        };
+        let node = parser.alternation()?;
+        if parser.at < parser.pattern.len() {
+            // only an unopened `)` stops the parse early
+            anyhow::bail!("invalid regex '{}': unmatched ) or \\)", pattern);
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+        Ok(Regex { program })
+    }
+
+    /// Compiles a POSIX basic pattern, where `\(`, `\{`, `\|`, `\+` and `\?` are the operators
+    /// and the bare characters stand for themselves, as GNU reads them.
+    pub fn basic(pattern: &str) -> Result<Regex> {
+        let bytes = pattern.as_bytes();
+        let mut extended = Vec::with_capacity(bytes.len());
+        let mut at = 0;
+        while at < bytes.len() {
+            let c = bytes[at];
+            at += 1;
+            match c {
+                b'\\' if at < bytes.len() => {
+                    let escaped = bytes[at];
+                    at += 1;
+                    if !b"(){}|+?".contains(&escaped) {
+                        extended.push(b'\\');
+                    }
+                    extended.push(escaped);
+                }
+                b'(' | b')' | b'{' | b'}' | b'|' | b'+' | b'?' => extended.extend([b'\\', c]),
+                // a star with nothing before it to repeat is an ordinary character
+                b'*' if matches!(extended.last(), None | Some(b'(' | b'|' | b'^'))
+                    && !extended.ends_with(b"\\(")
+                    && !extended.ends_with(b"\\|") =>
+                {
+                    extended.extend(b"\\*")
+                }
+                b'[' => {
+                    // copied whole, so that nothing inside is taken as an operator
+                    let start = at - 1;
+                    let mut end = at;
+                    if bytes.get(end) == Some(&b'^') {
+                        end += 1;
+                    }
+                    if bytes.get(end) == Some(&b']') {
+                        end += 1;
+                    }
+                    while end < bytes.len() && bytes[end] != b']' {
+                        if bytes[end] == b'['
+                            && matches!(bytes.get(end + 1), Some(b':' | b'.' | b'='))
+                        {
+                            let close = [bytes[end + 1], b']'];
+                            match bytes[end + 2..].windows(2).position(|w| w == close) {
+                                Some(offset) => end += offset + 4,
+                                None => end = bytes.len(),
+                            }
+                        } else {
+                            end += 1;
+                        }
+                    }
+                    end = (end + 1).min(bytes.len());
+                    extended.extend(&bytes[start..end]);
+                    at = end;
+                }
+                c => extended.push(c),
+            }
+        }
+        Regex::new(&String::from_utf8_lossy(&extended))
+    }
+
+    /// Where the leftmost, and then longest, match in `text` starts and ends.
+    pub fn find(&self, text: &[u8]) -> Option<(usize, usize)> {
+        (0..=text.len()).find_map(|start| self.longest_at(text, start).map(|end| (start, end)))
+    }
+
+    /// The end of the longest match starting at `start`.
+    fn longest_at(&self, text: &[u8], start: usize) -> Option<usize> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut longest = None;
+        self.add_thread(&mut current, 0, text, start);
+        let mut at = start;
+        loop {
+            for i in 0..current.list.len() {
+                match &self.program[current.list[i]] {
+                    Inst::Match => longest = Some(at),
+                    Inst::Class(class) if at < text.len() && class.contains(text[at]) => {
+                        self.add_thread(&mut next, current.list[i] + 1, text, at + 1);
+                    }
+                    _ => {}
+                }
+            }
+            if next.list.is_empty() {
+                return longest;
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+            at += 1;
+        }
+    }
+
+    /// Adds the thread at `pc`, following jumps and the anchors that hold at `at` so the list
+    /// only has instructions that look at a byte, and matches.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, text: &[u8], at: usize) {
+        if threads.seen[pc] {
+            return;
+        }
+        threads.seen[pc] = true;
+        match self.program[pc] {
+            Inst::Jump(to) => self.add_thread(threads, to, text, at),
+            Inst::Split(a, b) => {
+                self.add_thread(threads, a, text, at);
+                self.add_thread(threads, b, text, at);
+            }
+            Inst::LineStart => {
+                if at == 0 || text[at - 1] == b'\n' {
+                    self.add_thread(threads, pc + 1, text, at);
+                }
+            }
+            Inst::LineEnd => {
+                if at == text.len() || text[at] == b'\n' {
+                    self.add_thread(threads, pc + 1, text, at);
+                }
+            }
+            Inst::Class(_) | Inst::Match => threads.list.push(pc),
+        }
+    }
+}
+
+struct Threads {
+    list: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            list: Vec::new(),
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Class(class) => program.push(Inst::Class(*class)),
+        Node::LineStart => program.push(Inst::LineStart),
+        Node::LineEnd => program.push(Inst::LineEnd),
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, program)),
+        Node::Alternate(nodes) => {
+            let mut jumps = Vec::new();
+            for (i, node) in nodes.iter().enumerate() {
+                if i + 1 == nodes.len() {
+                    compile(node, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(node, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.at).copied()
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "invalid regex '{}': {}",
+            String::from_utf8_lossy(self.pattern),
+            message
+        )
+    }
+
+    fn alternation(&mut self) -> Result<Node> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some(b'|') {
+            self.at += 1;
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Node> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == b'|' || c == b')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.repetitions(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        let c = self.pattern[self.at];
+        self.at += 1;
+        Ok(match c {
+            b'.' => Node::Class(Class::byte(b'\n').negate()),
+            b'^' => Node::LineStart,
+            b'$' => Node::LineEnd,
+            b'[' => Node::Class(self.bracket()?),
+            b'(' => {
+                let node = self.alternation()?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error("unmatched ( or \\("));
+                }
+                self.at += 1;
+                node
+            }
+            b'*' | b'+' | b'?' => return Err(self.error("invalid preceding regular expression")),
+            b'\\' => {
+                let Some(escaped) = self.peek() else {
+                    return Err(self.error("trailing backslash"));
+                };
+                self.at += 1;
+                Node::Class(match escaped {
+                    b'w' => word_class(),
+                    b'W' => word_class().negate(),
+                    b's' => Class::from_fn(is_space),
+                    b'S' => Class::from_fn(is_space).negate(),
+                    c => Class::byte(c),
+                })
+            }
+            c => Node::Class(Class::byte(c)),
+        })
+    }
+
+    fn repetitions(&mut self, mut node: Node) -> Result<Node> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'*') => (0, None),
+                Some(b'+') => (1, None),
+                Some(b'?') => (0, Some(1)),
+                Some(b'{') => match self.bound()? {
+                    Some(bound) => bound,
+                    None => return Ok(node),
+                },
+                _ => return Ok(node),
+            };
+            // past the operator, or the closing brace of a bound
+            self.at += 1;
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    /// Reads `{m}`, `{m,}` or `{m,n}` up to its closing brace, which is left to be skipped.
+    /// A brace that doesn't start a bound is an ordinary character.
+    fn bound(&mut self) -> Result<Option<(u32, Option<u32>)>> {
+        let number = |parser: &mut Parser, at: &mut usize| -> Option<u32> {
+            let start = *at;
+            while parser.pattern.get(*at).is_some_and(u8::is_ascii_digit) {
+                *at += 1;
+            }
+            std::str::from_utf8(&parser.pattern[start..*at])
+                .ok()?
+                .parse()
+                .ok()
+        };
+        let mut at = self.at + 1;
+        let Some(min) = number(self, &mut at) else {
+            return Ok(None);
+        };
+        let max = if self.pattern.get(at) == Some(&b',') {
+            at += 1;
+            number(self, &mut at)
+        } else {
+            Some(min)
+        };
+        if self.pattern.get(at) != Some(&b'}') {
+            return Err(self.error("unmatched { or \\{"));
+        }
+        if max.is_some_and(|max| max < min) || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(self.error("invalid content of \\{\\}"));
+        }
+        self.at = at;
+        Ok(Some((min, max)))
+    }
+
+    /// Reads a bracket expression after its `[`, through its `]`.
+    fn bracket(&mut self) -> Result<Class> {
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.at += 1;
+        }
+        let mut class = Class::EMPTY;
+        let mut first = true;
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unmatched [, [^, [:, [., or [="));
+            };
+            if c == b']' && !first {
+                self.at += 1;
+                break;
+            }
+            first = false;
+            if c == b'[' && self.pattern.get(self.at + 1) == Some(&b':') {
+                let rest = &self.pattern[self.at + 2..];
+                let Some(end) = rest.windows(2).position(|w| w == b":]") else {
+                    return Err(self.error("unmatched [, [^, [:, [., or [="));
+                };
+                let name = String::from_utf8_lossy(&rest[..end]).into_owned();
+                class.union(
+                    &named_class(&name).ok_or_else(|| self.error("invalid character class"))?,
+                );
+                self.at += 2 + end + 2;
+                continue;
+            }
+            self.at += 1;
+            let is_range = self.peek() == Some(b'-')
+                && self
+                    .pattern
+                    .get(self.at + 1)
+                    .is_some_and(|&end| end != b']');
+            if is_range {
+                let end = self.pattern[self.at + 1];
+                if end < c {
+                    return Err(self.error("invalid range end"));
+                }
+                (c..=end).for_each(|c| class.insert(c));
+                self.at += 2;
+            } else {
+                class.insert(c);
+            }
+        }
+        Ok(if negated { class.negate() } else { class })
+    }
+}
+
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c)
+}
+
+fn word_class() -> Class {
+    Class::from_fn(|c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+fn named_class(name: &str) -> Option<Class> {
+    let test: fn(u8) -> bool = match name {
+        "alnum" => |c| c.is_ascii_alphanumeric(),
+        "alpha" => |c| c.is_ascii_alphabetic(),
+        "blank" => |c| c == b' ' || c == b'\t',
+        "cntrl" => |c| c.is_ascii_control(),
+        "digit" => |c| c.is_ascii_digit(),
+        "graph" => |c| c.is_ascii_graphic(),
+        "lower" => |c| c.is_ascii_lowercase(),
+        "print" => |c| c.is_ascii_graphic() || c == b' ',
+        "punct" => |c| c.is_ascii_punctuation(),
+        "space" => is_space,
+        "upper" => |c| c.is_ascii_uppercase(),
+        "xdigit" => |c| c.is_ascii_hexdigit(),
+        _ => return None,
+    };
+    Some(Class::from_fn(test))
+}
@@ -0,0 +1,336 @@
+//! Rename and copy detection, pairing files that went away (or, for copies, any file that was
+//! there before) with files that appeared, the way git's diffcore-rename does.
+//!
+//! Identical contents pair up first. What is left is scored by how much of the contents the
+//! two files share: both are cut into chunks at newlines or every 64 bytes, and the bytes in
+//! chunks that appear in both count as copied.
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, io::Read, path::Path};
+
+use crate::{config::Config, convert, objects::object::Object, worktree};
+
+/// Scores are out of this, so 50% is 30000.
+pub const MAX_SCORE: u32 = 60000;
+/// What `-M` and `-C` use without a number.
+pub const DEFAULT_SCORE: u32 = MAX_SCORE / 2;
+/// Past this many sources times targets only identical contents are paired, like
+/// `diff.renameLimit` squared.
+const DEFAULT_LIMIT: usize = 1000;
+
+/// Chunk hashes are taken modulo this prime.
+const HASHBASE: u32 = 107927;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Options {
+    /// the least similarity for a pair to count, out of `MAX_SCORE`
+    pub min_score: u32,
+    /// also pair files that appeared with sources that are still there
+    pub copies: bool,
+    /// more sources times targets than this square only get exact matching
+    pub limit: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            min_score: DEFAULT_SCORE,
+            copies: false,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl Options {
+    /// What `<command>.renames`, falling back to `diff.renames`, asks for: `None` when rename
+    /// detection is off. Renames are on when neither is set.
+    pub fn from_config(config: &Config, command: &str) -> Option<Options> {
+        let key = [format!("{}.renames", command), "diff.renames".to_string()]
+            .into_iter()
+            .find(|key| config.get(key).is_some());
+        let mut options = Options::default();
+        if let Some(limit) = config
+            .get(&format!("{}.renamelimit", command))
+            .or_else(|| config.get("diff.renamelimit"))
+            .and_then(|v| v.parse().ok())
+        {
+            options.limit = limit;
+        }
+        match key.as_deref().and_then(|key| config.get(key)) {
+            None => Some(options),
+            Some(v) if v.eq_ignore_ascii_case("copies") || v.eq_ignore_ascii_case("copy") => {
+                options.copies = true;
+                Some(options)
+            }
+            Some(_) if config.get_bool(key.as_deref().unwrap()) == Some(true) => Some(options),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Parses the number after `-M` or `-C`: digits on their own are a fraction, so `5` and `50%`
+/// are both half.
+pub fn parse_score(value: &str) -> Result<u32> {
+    let parse = || -> Option<u32> {
+        if let Some(percent) = value.strip_suffix('%') {
+            let percent: f64 = percent.parse().ok()?;
+            return Some((percent / 100.0 * MAX_SCORE as f64) as u32);
+        }
+        let (whole, fraction) = value.split_once('.').unwrap_or(("", value));
+        if !whole.is_empty() && whole.parse::<u32>().ok()? >= 1 {
+            return Some(MAX_SCORE);
+        }
+        if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let fraction: f64 = format!("0.{}", fraction).parse().ok()?;
+        Some((fraction * MAX_SCORE as f64) as u32)
+    };
+    parse()
+        .map(|score| score.min(MAX_SCORE))
+        .with_context(|| format!("invalid rename score '{}'", value))
+}
+
+/// One side of a possible pair.
+#[derive(Debug, Clone)]
+pub(crate) struct File {
+    pub path: String,
+    pub mode: u32,
+    pub hash: String,
+    /// read from the worktree, as its blob isn't in the object store
+    pub in_worktree: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Source {
+    pub file: File,
+    /// gone on the other side, so it can be renamed; one that is still there can only be
+    /// copied
+    pub deleted: bool,
+}
+
+/// A target found to come from a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Match {
+    /// indexes into the sources and targets given to `detect`
+    pub source: usize,
+    pub target: usize,
+    pub score: u32,
+    /// the source lives on, either because it wasn't deleted or because a later target takes
+    /// it as a rename
+    pub copy: bool,
+}
+
+/// Pairs `targets` with `sources`. Each target gets at most one source, and each deleted
+/// source is renamed at most once; with `copies` a source can also be copied any number of
+/// times, otherwise only deleted sources are considered at all.
+pub fn detect(sources: &[Source], targets: &[File], options: &Options) -> Result<Vec<Match>> {
+    let candidates: Vec<usize> = (0..sources.len())
+        .filter(|&i| options.copies || sources[i].deleted)
+        .collect();
+    let mut matched: Vec<Option<(usize, u32)>> = vec![None; targets.len()];
+
+    // identical contents first, preferring a source with the same file name
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for &i in &candidates {
+        if is_regular(sources[i].file.mode) {
+            by_hash.entry(&sources[i].file.hash).or_default().push(i);
+        }
+    }
+    let mut renamed = vec![false; sources.len()];
+    for (t, target) in targets.iter().enumerate() {
+        if !is_regular(target.mode) {
+            continue;
+        }
+        let Some(found) = by_hash.get(target.hash.as_str()) else {
+            continue;
+        };
+        let same_name = |i: &&usize| basename(&sources[**i].file.path) == basename(&target.path);
+        let unused = |i: &&usize| sources[**i].deleted && !renamed[**i];
+        let mut best = found
+            .iter()
+            .filter(unused)
+            .find(same_name)
+            .or_else(|| found.iter().find(unused));
+        if options.copies {
+            best = best
+                .or_else(|| found.iter().find(same_name))
+                .or_else(|| found.first());
+        }
+        if let Some(&i) = best {
+            renamed[i] |= sources[i].deleted;
+            matched[t] = Some((i, MAX_SCORE));
+        }
+    }
+
+    // then by similarity, for what is left, best scores first
+    let open_targets: Vec<usize> = (0..targets.len())
+        .filter(|&t| matched[t].is_none() && is_regular(targets[t].mode))
+        .collect();
+    let open_sources: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&i| is_regular(sources[i].file.mode) && (options.copies || !renamed[i]))
+        .collect();
+    let within_limit = open_targets.len() * open_sources.len() <= options.limit * options.limit;
+    if within_limit && !open_targets.is_empty() && !open_sources.is_empty() {
+        let mut contents = ContentCache::default();
+        let mut scored = Vec::new();
+        for &t in &open_targets {
+            for &i in &open_sources {
+                let score =
+                    contents.similarity(&sources[i].file, &targets[t], options.min_score)?;
+                if score >= options.min_score {
+                    scored.push((score, t, i));
+                }
+            }
+        }
+        // highest score first, then the order git would visit them in
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        // renames of deleted sources get first pick, so a copy doesn't steal a rename's target
+        for rename_pass in [true, false] {
+            for &(score, t, i) in &scored {
+                if matched[t].is_some() {
+                    continue;
+                }
+                let renames = sources[i].deleted && !renamed[i];
+                if rename_pass && !renames {
+                    continue;
+                }
+                if !rename_pass && !options.copies {
+                    break;
+                }
+                renamed[i] |= renames;
+                matched[t] = Some((i, score));
+            }
+        }
+    }
+
+    // a deleted source paired more than once is renamed by its last target, like git
+    let mut last_target = vec![None; sources.len()];
+    for (t, found) in matched.iter().enumerate() {
+        if let Some((i, _)) = found {
+            last_target[*i] = Some(t);
+        }
+    }
+    let matches = matched
+        .into_iter()
+        .enumerate()
+        .filter_map(|(t, found)| {
+            let (i, score) = found?;
+            Some(Match {
+                source: i,
+                target: t,
+                score,
+                copy: !sources[i].deleted || last_target[i] != Some(t),
+            })
+        })
+        .collect();
+    Ok(matches)
+}
+
+/// Symlinks and submodules only pair with identical ones of their own kind, which git leaves
+/// to exact matching; only regular files are scored.
+fn is_regular(mode: u32) -> bool {
+    mode >> 12 == 0o10
+}
+
+fn basename(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path)
+}
+
+/// Blob contents and their chunk counts, read once however many pairs they are in.
+#[derive(Default)]
+struct ContentCache {
+    spans: HashMap<String, (usize, HashMap<u32, usize>)>,
+}
+
+impl ContentCache {
+    fn spans(&mut self, file: &File) -> Result<&(usize, HashMap<u32, usize>)> {
+        let hash = &file.hash;
+        if !self.spans.contains_key(hash) {
+            let contents = if file.in_worktree {
+                worktree::contents_for_index(&file.path)?
+            } else {
+                let mut object = Object::try_from(hash.as_str())?;
+                let mut contents = Vec::new();
+                object
+                    .reader
+                    .read_to_end(&mut contents)
+                    .with_context(|| format!("reading blob {}", hash))?;
+                contents
+            };
+            self.spans
+                .insert(hash.to_string(), (contents.len(), hash_chunks(&contents)));
+        }
+        Ok(&self.spans[hash])
+    }
+
+    /// How much of the larger of the two files is made of chunks they share, out of
+    /// `MAX_SCORE`.
+    fn similarity(&mut self, source: &File, target: &File, min_score: u32) -> Result<u32> {
+        let (source_size, _) = *self.spans(source)?;
+        let (target_size, _) = *self.spans(target)?;
+        let max_size = source_size.max(target_size) as u64;
+        let delta_size = source_size.abs_diff(target_size) as u64;
+        // too different in size for enough of the bigger one to be shared
+        if max_size * ((MAX_SCORE - min_score) as u64) < delta_size * (MAX_SCORE as u64) {
+            return Ok(0);
+        }
+        if max_size == 0 {
+            // two empty files are as alike as files get, but git doesn't pair them by score
+            return Ok(0);
+        }
+        let source_spans = &self.spans[&source.hash].1;
+        let target_spans = &self.spans[&target.hash].1;
+        let copied: usize = source_spans
+            .iter()
+            .map(|(hash, count)| (*count).min(target_spans.get(hash).copied().unwrap_or(0)))
+            .sum();
+        Ok((copied as u64 * MAX_SCORE as u64 / max_size) as u32)
+    }
+}
+
+/// The bytes of `old` that are still in `new`, and the bytes of `new` that weren't in `old`,
+/// counted by chunks the way scores are.
+pub fn count_changes(old: &[u8], new: &[u8]) -> (usize, usize) {
+    let (old_spans, new_spans) = (hash_chunks(old), hash_chunks(new));
+    let mut copied = 0;
+    let mut added = 0;
+    for (hash, &count) in &new_spans {
+        let kept = old_spans.get(hash).copied().unwrap_or(0).min(count);
+        copied += kept;
+        added += count - kept;
+    }
+    (copied, added)
+}
+
+/// Bytes per chunk hash, chunks ending at a newline or after 64 bytes. A carriage return
+/// before a newline is left out of text so line endings don't count as changes. As in git,
+/// what follows the last chunk without reaching a newline isn't counted.
+fn hash_chunks(contents: &[u8]) -> HashMap<u32, usize> {
+    let text = !convert::is_binary(contents);
+    let mut spans = HashMap::new();
+    let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0usize);
+    let mut bytes = contents.iter().copied().peekable();
+    while let Some(c) = bytes.next() {
+        if text && c == b'\r' && bytes.peek() == Some(&b'\n') {
+            continue;
+        }
+        let old = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old >> 25);
+        accum1 = accum1.wrapping_add(c as u32);
+        len += 1;
+        if len < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *spans.entry(hash).or_default() += len;
+        (accum1, accum2, len) = (0, 0, 0);
+    }
+    spans
+}
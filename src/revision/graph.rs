@@ -0,0 +1,574 @@
+//! The text drawing of history that `log --graph` puts to the left of each commit.
+//!
+//! This follows git's graph.c closely, so that the lines come out the same. Every commit shown
+//! is given a column, and lines for the parents still to be shown run down the columns to the
+//! right of it. Between commits the drawing goes through states: a commit with three or more
+//! parents first widens the columns around it, then comes its own line, the lines branching
+//! off a merge, and last the lines that have come to share a column collapsing towards the
+//! left. When output for a commit runs out before those are done, the rest is drawn before
+//! the next commit, and a skipped drawing is marked with `...`.
+
+use anyhow::Result;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Padding,
+    Skip,
+    PreCommit,
+    Commit,
+    PostMerge,
+    Collapsing,
+}
+
+/// The branch lines between one commit and the next, drawn `\` or `/` as they move.
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+pub(crate) struct Graph {
+    /// the commit being drawn, and those of its parents that will be shown
+    commit: Option<String>,
+    parents: Vec<String>,
+    /// how wide the widest line for this commit is, to pad the others to
+    width: usize,
+    /// which row of the widening before an octopus merge is next
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    /// the column of the commit, and of the one before it
+    commit_index: usize,
+    prev_commit_index: usize,
+    /// for a merge, 0 when its first parent's line goes off to the left, 1 when it is
+    /// directly below; None until worked out
+    merge_layout: Option<usize>,
+    /// how many columns a merge adds to the right of the commit, -1 when the first new edge
+    /// joins the column left of it at once
+    edges_added: isize,
+    prev_edges_added: isize,
+    /// the commits whose lines the columns carry before this commit, and after it
+    columns: Vec<String>,
+    new_columns: Vec<String>,
+    /// for each character position on the line, the new column the line there is heading to
+    mapping: Vec<Option<usize>>,
+    old_mapping: Vec<Option<usize>>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            commit: None,
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: Some(0),
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+        }
+    }
+
+    /// Moves on to the next commit shown, given its parents that will also be shown.
+    pub fn update(&mut self, commit: &str, parents: Vec<String>) {
+        self.commit = Some(commit.to_string());
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // the previous commit's drawing wasn't finished, so mark the gap
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    fn num_parents(&self) -> usize {
+        self.parents.len()
+    }
+
+    fn update_columns(&mut self) {
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+
+        let max_new_columns = self.columns.len() + self.num_parents();
+        self.mapping = vec![None; 2 * max_new_columns];
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let commit = self.commit.clone().unwrap();
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                commit.clone()
+            } else {
+                self.columns[i].clone()
+            };
+
+            if column == commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = None;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(&parent, Some(i));
+                }
+                // the commit itself takes up room even with no parents to draw
+                if self.num_parents() == 0 {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&column, None);
+            }
+        }
+
+        while self.mapping.len() > 1 && self.mapping.last() == Some(&None) {
+            self.mapping.pop();
+        }
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns.iter().position(|c| c == commit)
+    }
+
+    /// Gives `commit` a column after this one unless it has one, and says where the line
+    /// leading to it from the old column `index` (or from this commit) should go.
+    fn insert_into_new_columns(&mut self, commit: &str, index: Option<usize>) {
+        let i = match self.find_new_column(commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit.to_string());
+                self.new_columns.len() - 1
+            }
+        };
+
+        let mapping_index;
+        match (index, self.merge_layout) {
+            (Some(index), None) if self.num_parents() > 1 => {
+                // the first parent of a merge decides how the merge is laid out, by whether
+                // its line goes off to the left
+                let dist = index as isize - i as isize;
+                let shift = if dist > 1 { 2 * dist - 3 } else { 1 };
+                let layout = if dist > 0 { 0 } else { 1 };
+                self.merge_layout = Some(layout);
+                self.edges_added = self.num_parents() as isize + layout as isize - 2;
+                mapping_index = self.width as isize + (layout as isize - 1) * shift;
+                self.width += 2 * layout;
+            }
+            _ if self.edges_added > 0
+                && self.width >= 2
+                && self.mapping[self.width - 2] == Some(i) =>
+            {
+                // an edge the merge added joins the last existing column straight away
+                mapping_index = self.width as isize - 2;
+                self.edges_added = -1;
+            }
+            _ => {
+                mapping_index = self.width as isize;
+                self.width += 2;
+            }
+        }
+        self.mapping[mapping_index as usize] = Some(i);
+    }
+
+    fn merge_layout(&self) -> usize {
+        self.merge_layout.unwrap_or(0)
+    }
+
+    /// How many of an octopus merge's parents are joined to it by dashes.
+    fn num_dashed_parents(&self) -> isize {
+        self.num_parents() as isize + self.merge_layout() as isize - 3
+    }
+
+    fn num_expansion_rows(&self) -> isize {
+        self.num_dashed_parents() * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.num_parents() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && (self.expansion_row as isize) < self.num_expansion_rows()
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    /// Whether every line in the mapping is already where it is heading, or just right of it.
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping
+            .iter()
+            .enumerate()
+            .all(|(i, target)| target.is_none_or(|target| target == i / 2))
+    }
+
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    /// The next line of the drawing, and whether it was the commit's own.
+    fn next_line(&mut self) -> (String, bool) {
+        let mut line = String::new();
+        let mut commit_line = false;
+        match self.state {
+            State::Padding => self.padding(&mut line),
+            State::Skip => self.skip(&mut line),
+            State::PreCommit => self.pre_commit(&mut line),
+            State::Commit => {
+                self.commit_line(&mut line);
+                commit_line = true;
+            }
+            State::PostMerge => self.post_merge(&mut line),
+            State::Collapsing => self.collapsing(&mut line),
+        }
+        self.pad(&mut line);
+        (line, commit_line)
+    }
+
+    fn pad(&self, line: &mut String) {
+        let width = line.chars().count();
+        if width < self.width {
+            line.extend(std::iter::repeat_n(' ', self.width - width));
+        }
+    }
+
+    fn padding(&self, line: &mut String) {
+        for _ in &self.new_columns {
+            line.push_str("| ");
+        }
+    }
+
+    fn skip(&mut self, line: &mut String) {
+        line.push_str("...");
+        if self.needs_pre_commit_line() {
+            self.update_state(State::PreCommit);
+        } else {
+            self.update_state(State::Commit);
+        }
+    }
+
+    /// Widens the lines right of an octopus merge, two rows for each dashed parent.
+    fn pre_commit(&mut self, line: &mut String) {
+        let commit = self.commit.as_deref().unwrap();
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if column == commit {
+                seen_this = true;
+                line.push('|');
+                line.extend(std::iter::repeat_n(' ', self.expansion_row));
+            } else if seen_this && self.expansion_row == 0 {
+                // carry on the `\` lines a merge just before left
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if seen_this {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    fn commit_line(&mut self, line: &mut String) {
+        let commit = self.commit.clone().unwrap();
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                &commit
+            } else {
+                &self.columns[i]
+            };
+
+            if *column == commit {
+                seen_this = true;
+                line.push('*');
+                if self.num_parents() > 2 {
+                    // the dashes over to an octopus merge's parents
+                    let dashed = self.num_dashed_parents();
+                    for i in 0..dashed {
+                        line.push('-');
+                        line.push(if i == dashed - 1 { '.' } else { '-' });
+                    }
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen_this && self.edges_added == 1 {
+                // right of a merge, a line the previous merge sent off to the right carries on
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping.get(2 * i + 1) == Some(&Some(i))
+                && self
+                    .mapping
+                    .get(2 * i)
+                    .copied()
+                    .flatten()
+                    .is_none_or(|m| m < i)
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        if self.num_parents() > 1 {
+            self.update_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    /// The lines branching off a merge to its parents.
+    fn post_merge(&mut self, line: &mut String) {
+        let commit = self.commit.clone().unwrap();
+        let first_parent = self.parents[0].clone();
+        let mut parent_column: Option<usize> = None;
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                commit.clone()
+            } else {
+                self.columns[i].clone()
+            };
+
+            if column == commit {
+                seen_this = true;
+                let mut index = self.merge_layout();
+                for j in 0..self.num_parents() {
+                    line.push(MERGE_CHARS[index]);
+                    if index == 2 {
+                        if self.edges_added > 0 || j + 1 < self.num_parents() {
+                            line.push(' ');
+                        }
+                    } else {
+                        index += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout() != 0 || i + 1 != self.commit_index {
+                    line.push(if parent_column.is_some() { '_' } else { ' ' });
+                }
+            }
+
+            if column == first_parent {
+                parent_column = Some(i);
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    /// Moves each line one step towards its column, crossing at most one other line, which
+    /// may take a run of `_` to get to.
+    fn collapsing(&mut self, line: &mut String) {
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        let size = self.old_mapping.len();
+        self.mapping.clear();
+        self.mapping.resize(size, None);
+
+        let mut used_horizontal = false;
+        let mut horizontal_edge: Option<usize> = None;
+        let mut horizontal_edge_target: Option<usize> = None;
+        for i in 0..size {
+            let Some(target) = self.old_mapping[i] else {
+                continue;
+            };
+            // lines only ever move to the left
+            if target * 2 == i {
+                self.mapping[i] = Some(target);
+            } else if self.mapping[i - 1].is_none() {
+                self.mapping[i - 1] = Some(target);
+                if horizontal_edge.is_none() {
+                    horizontal_edge = Some(i);
+                    horizontal_edge_target = Some(target);
+                    let mut j = target * 2 + 3;
+                    while j + 2 < i {
+                        self.mapping[j] = Some(target);
+                        j += 2;
+                    }
+                }
+            } else if self.mapping[i - 1] == Some(target) {
+                // joins the line to its left, which is going to the same place
+            } else {
+                // crosses over the line to its left
+                self.mapping[i - 2] = Some(target);
+                if horizontal_edge.is_none() {
+                    horizontal_edge_target = Some(target);
+                    horizontal_edge = Some(i - 1);
+                    let mut j = target * 2 + 3;
+                    while j + 2 < i {
+                        self.mapping[j] = Some(target);
+                        j += 2;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping.clone_from(&self.mapping);
+        if self.mapping.last() == Some(&None) {
+            self.mapping.pop();
+        }
+
+        for i in 0..self.mapping.len() {
+            match self.mapping[i] {
+                None => line.push(' '),
+                Some(target) if target * 2 == i => line.push('|'),
+                Some(target)
+                    if Some(target) == horizontal_edge_target
+                        && Some(i) != horizontal_edge.map(|edge| edge - 1) =>
+                {
+                    // only the first segment carries on into the next line
+                    if i != target * 2 + 3 {
+                        self.mapping[i] = None;
+                    }
+                    used_horizontal = true;
+                    line.push('_');
+                }
+                Some(_) => {
+                    if used_horizontal && horizontal_edge.is_some_and(|edge| i < edge) {
+                        self.mapping[i] = None;
+                    }
+                    line.push('/');
+                }
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+
+    /// A line that leaves every branch line as it is, to go beside more text for a commit.
+    fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+        let commit = self.commit.as_deref().unwrap();
+        let mut line = String::new();
+        for column in &self.columns {
+            line.push('|');
+            if column == commit && self.num_parents() > 2 {
+                line.extend(std::iter::repeat_n(' ', (self.num_parents() - 2) * 2));
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad(&mut line);
+        self.prev_state = State::Padding;
+        line
+    }
+
+    /// Writes the drawing up to and including the commit's own line, without ending that.
+    pub fn show_commit(&mut self, out: &mut impl Write) -> Result<()> {
+        if self.is_commit_finished() {
+            return self.show_padding(out);
+        }
+        loop {
+            let (line, commit_line) = self.next_line();
+            out.write_all(line.as_bytes())?;
+            if commit_line || self.is_commit_finished() {
+                return Ok(());
+            }
+            out.write_all(b"\n")?;
+        }
+    }
+
+    /// Writes the next line of the drawing without ending it, to go before a line of text.
+    pub fn show_oneline(&mut self, out: &mut impl Write) -> Result<()> {
+        let (line, _) = self.next_line();
+        out.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn show_padding(&mut self, out: &mut impl Write) -> Result<()> {
+        let line = self.padding_line();
+        out.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes what is left of the drawing for this commit, lines between but not after.
+    pub fn show_remainder(&mut self, out: &mut impl Write) -> Result<()> {
+        while !self.is_commit_finished() {
+            let (line, _) = self.next_line();
+            out.write_all(line.as_bytes())?;
+            if !self.is_commit_finished() {
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a commit's text with the drawing before each line but the first, which goes
+    /// after the commit's own line, and then the rest of the drawing.
+    pub fn show_commit_msg(&mut self, out: &mut impl Write, text: &[u8]) -> Result<()> {
+        let mut rest = text;
+        while !rest.is_empty() {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(rest.len(), |i| i + 1);
+            out.write_all(&rest[..end])?;
+            rest = &rest[end..];
+            if !rest.is_empty() {
+                self.show_oneline(out)?;
+            }
+        }
+
+        let newline_terminated = text.last() == Some(&b'\n');
+        if !self.is_commit_finished() {
+            if !newline_terminated {
+                out.write_all(b"\n")?;
+            }
+            self.show_remainder(out)?;
+            if newline_terminated {
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+pub(crate) mod graph;
+pub(crate) mod reach;
+pub(crate) mod walk;
+
+use anyhow::{Context, Result};
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    config::Config,
+    objects::{
+        commit::Commit,
+        commit_graph::CommitGraph,
+        error::Error,
+        index::{IndexEntry, IndexFile},
+        object::{self, Kind, Object},
+        tag::Tag,
+        tree::Tree,
+    },
+    refs::{self, reflog},
+};
+
+/// What a revision argument names: one object, or a set of commits given as a range.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Revision {
+    Single(String),
+    /// `A..B`, commits reachable from `to` but not from `from`
+    Range {
+        from: String,
+        to: String,
+    },
+    /// `A...B`, commits reachable from either but not both
+    Symmetric {
+        left: String,
+        right: String,
+    },
+}
+
+/// Parses a revision argument, which may be a range. Empty ends of a range mean HEAD.
+pub fn parse(spec: &str) -> Result<Revision> {
+    // a path after a colon can contain dots of its own, eg HEAD:../x
+    if !spec.contains(':') {
+        if let Some((left, right)) = spec.split_once("...") {
+            return Ok(Revision::Symmetric {
+                left: resolve(or_head(left))?,
+                right: resolve(or_head(right))?,
+            });
+        }
+        if let Some((from, to)) = spec.split_once("..") {
+            return Ok(Revision::Range {
+                from: resolve(or_head(from))?,
+                to: resolve(or_head(to))?,
+            });
+        }
+    }
+    Ok(Revision::Single(resolve(spec)?))
+}
+
+fn or_head(side: &str) -> &str {
+    if side.is_empty() {
+        "HEAD"
+    } else {
+        side
+    }
+}
+
+/// Resolves a single revision expression to the full id of the object it names.
+///
+/// Understands ref names (looked up in git's DWIM order), full and abbreviated hashes, `@`,
+/// reflog selectors (`main@{2}`, `@{yesterday}`), the `^`, `^N`, `~N`, `^{type}` and `^{}`
+/// suffixes, `rev:path` to look up a path in a tree, and `:path` / `:N:path` for the index.
+pub fn resolve(spec: &str) -> Result<String> {
+    if let Some(rest) = spec.strip_prefix(':') {
+        return resolve_index_path(rest);
+    }
+    if let Some(colon) = top_level_colon(spec) {
+        let tree = peel(&resolve(&spec[..colon])?, Kind::Tree)?;
+        return resolve_tree_path(&tree, &spec[colon + 1..]);
+    }
+
+    let ops_start = find_outside_braces(spec, |c| c == '^' || c == '~').unwrap_or(spec.len());
+    let (base, mut ops) = spec.split_at(ops_start);
+    let mut hash = resolve_base(base)?;
+
+    while !ops.is_empty() {
+        let op = ops.as_bytes()[0];
+        ops = &ops[1..];
+
+        if op == b'^' && ops.starts_with('{') {
+            let end = ops
+                .find('}')
+                .with_context(|| format!("missing }} in {}", spec))?;
+            hash = match &ops[1..end] {
+                "" => peel_tags(&hash)?,
+                "object" => hash,
+                kind => peel(&hash, Kind::try_from(kind)?)?,
+            };
+            ops = &ops[end + 1..];
+            continue;
+        }
+
+        let digits = ops.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let n: usize = if digits == 0 {
+            1
+        } else {
+            ops[..digits]
+                .parse()
+                .with_context(|| format!("bad number in {}", spec))?
+        };
+        ops = &ops[digits..];
+
+        hash = match op {
+            b'~' => {
+                for _ in 0..n {
+                    hash = nth_parent(&hash, 1, spec)?;
+                }
+                hash
+            }
+            // ^0 is the commit itself, peeled from a tag if need be
+            _ if n == 0 => peel(&hash, Kind::Commit)?,
+            _ => nth_parent(&hash, n, spec)?,
+        };
+    }
+
+    Ok(hash)
+}
+
+fn resolve_base(base: &str) -> Result<String> {
+    if base.contains("@{") {
+        if let Some(hash) = reflog::resolve_spec(base)? {
+            return Ok(hash);
+        }
+    }
+    let base = if base == "@" { "HEAD" } else { base };
+    if base.is_empty() {
+        anyhow::bail!(Error::InvalidId(base.to_string()));
+    }
+    if refs::is_hash(base) {
+        return Ok(base.to_ascii_lowercase());
+    }
+    if let Some(name) = refs::dwim(base)? {
+        return refs::resolve(&name)?
+            .with_context(|| format!("ref {} does not point to anything", name));
+    }
+    match object::expand_id(base) {
+        Ok(hash) => Ok(hash),
+        Err(Error::InvalidId(_)) | Err(Error::NotFound(_)) => {
+            Err(Error::NotFound(base.to_string()).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn nth_parent(hash: &str, n: usize, spec: &str) -> Result<String> {
+    let commit = Commit::read_from_hash(&peel(hash, Kind::Commit)?)?;
+    commit
+        .parents
+        .get(n - 1)
+        .cloned()
+        .ok_or_else(|| Error::NotFound(spec.to_string()).into())
+}
+
+/// The kind of object `hash` names.
+pub fn kind_of(hash: &str) -> Result<Kind> {
+    Ok(Object::try_from(hash)?.kind)
+}
+
+/// Follows annotated tags until reaching something that isn't one.
+pub fn peel_tags(hash: &str) -> Result<String> {
+    let mut hash = hash.to_string();
+    while kind_of(&hash)? == Kind::Tag {
+        hash = Tag::read_from_hash(&hash)?.object;
+    }
+    Ok(hash)
+}
+
+/// Follows `hash` to an object of kind `want`: tags are dereferenced, and commits give their
+/// tree when a tree is wanted.
+pub fn peel(hash: &str, want: Kind) -> Result<String> {
+    let mut hash = hash.to_string();
+    loop {
+        let kind = kind_of(&hash)?;
+        if kind == want {
+            return Ok(hash);
+        }
+        hash = match kind {
+            Kind::Tag => Tag::read_from_hash(&hash)?.object,
+            Kind::Commit if want == Kind::Tree => Commit::read_from_hash(&hash)?.tree,
+            _ => anyhow::bail!("{} is a {}, not a {}", hash, kind, want),
+        };
+    }
+}
+
+/// The hash of the entry at `path` inside `tree`, an empty path meaning the tree itself.
+pub fn resolve_tree_path(tree: &str, path: &str) -> Result<String> {
+    Ok(tree_path_entry(tree, path)?.0)
+}
+
+/// The hash and mode of the entry at `path` inside `tree`.
+fn tree_path_entry(tree: &str, path: &str) -> Result<(String, u32)> {
+    let (mut hash, mut mode) = (tree.to_string(), 0o40000);
+    for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let missing = || format!("path '{}' does not exist in '{}'", path, tree);
+        let entry = Tree::read_from_hash(&hash)
+            .with_context(missing)?
+            .entries
+            .into_iter()
+            .find(|e| e.filename == component)
+            .with_context(missing)?;
+        mode = entry.octal_mode();
+        hash = entry.hash;
+    }
+    Ok((hash, mode))
+}
+
+/// `path` or `N:path`, looked up in the index at stage N (0 if not given).
+fn resolve_index_path(spec: &str) -> Result<String> {
+    Ok(index_path_entry(spec)?.hash)
+}
+
+fn index_path_entry(spec: &str) -> Result<IndexEntry> {
+    let (stage, path) = match spec.split_once(':') {
+        Some((stage, path)) if stage.len() == 1 && stage.as_bytes()[0].is_ascii_digit() => {
+            (stage.parse::<u16>()?, path)
+        }
+        _ => (0, spec),
+    };
+    let path = path.trim_start_matches("./");
+    let index = IndexFile::read_from_index()?;
+    index
+        .entries
+        .into_iter()
+        .find(|e| e.stage() == stage && e.entry_path == Path::new(path))
+        .with_context(|| format!("path '{}' is not in the index at stage {}", path, stage))
+}
+
+/// The path and mode of the object `rev:path` or `:path` names, as its tree or the index
+/// has them. `None` for revisions that don't go through a path.
+pub fn path_of(spec: &str) -> Result<Option<(String, u32)>> {
+    if let Some(rest) = spec.strip_prefix(':') {
+        let entry = index_path_entry(rest)?;
+        let path = entry.entry_path.to_string_lossy().into_owned();
+        return Ok(Some((path, entry.mode())));
+    }
+    let Some(colon) = top_level_colon(spec) else {
+        return Ok(None);
+    };
+    let tree = peel(&resolve(&spec[..colon])?, Kind::Tree)?;
+    let path = &spec[colon + 1..];
+    let (_, mode) = tree_path_entry(&tree, path)?;
+    let path = path.split('/').filter(|c| !c.is_empty() && *c != ".");
+    Ok(Some((path.collect::<Vec<_>>().join("/"), mode)))
+}
+
+/// The first colon that isn't inside `@{...}`, since reflog dates can have times in them.
+fn top_level_colon(spec: &str) -> Option<usize> {
+    find_outside_braces(spec, |c| c == ':')
+}
+
+fn find_outside_braces(spec: &str, matches: impl Fn(char) -> bool) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if depth == 0 && matches(c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The repository's commit-graph, unless there isn't one or `core.commitGraph` turns it off.
+/// One that can't be read is left alone, as the objects still have everything it would give.
+pub fn commit_graph() -> Result<Option<CommitGraph>> {
+    if Config::load()?.get_bool("core.commitGraph") == Some(false) {
+        return Ok(None);
+    }
+    Ok(CommitGraph::load().unwrap_or(None))
+}
+
+/// Every commit reachable from `start`, itself included.
+pub fn ancestors(start: &[String]) -> Result<HashSet<String>> {
+    reach::Reach::new()?.ancestors(start)
+}
+
+/// The best common ancestors of two commits, newest first: those reachable from both that
+/// aren't themselves ancestors of another common one.
+pub fn merge_bases(a: &str, b: &str) -> Result<Vec<String>> {
+    let a = peel(a, Kind::Commit)?;
+    let b = peel(b, Kind::Commit)?;
+    reach::Reach::new()?.merge_bases(&a, &[b])
+}
@@ -0,0 +1,342 @@
+//! Questions about which commits reach which, answered the way git's commit-reach.c does:
+//! walking down from the commits at once, newest generation first, and stopping as soon as
+//! only commits reachable from all of them are left. With a commit-graph the generation
+//! numbers also cut off the parts of history that can't matter.
+
+use anyhow::Result;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::objects::{
+    commit::Commit,
+    commit_graph::{CommitGraph, GENERATION_INFINITY},
+};
+
+const PARENT1: u8 = 1 << 0;
+const PARENT2: u8 = 1 << 1;
+/// Reachable from a commit already found to be common, so not a best common ancestor.
+const STALE: u8 = 1 << 2;
+const RESULT: u8 = 1 << 3;
+
+struct Info {
+    parents: Vec<String>,
+    time: i64,
+    generation: u64,
+}
+
+/// A queued commit: the highest generation first, then the newest, then the one queued first.
+#[derive(PartialEq, Eq)]
+struct Queued {
+    generation: u64,
+    time: i64,
+    order: u64,
+    hash: String,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.generation
+            .cmp(&other.generation)
+            .then_with(|| self.time.cmp(&other.time))
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub(crate) struct Reach {
+    graph: Option<CommitGraph>,
+    commits: HashMap<String, Info>,
+}
+
+impl Reach {
+    pub fn new() -> Result<Reach> {
+        Ok(Reach {
+            graph: super::commit_graph()?,
+            commits: HashMap::new(),
+        })
+    }
+
+    /// The parents, date and generation of a commit, from the commit-graph if it has it.
+    fn info(&mut self, hash: &str) -> Result<&Info> {
+        if !self.commits.contains_key(hash) {
+            let graphed = match &self.graph {
+                Some(graph) => graph.lookup(hash)?,
+                None => None,
+            };
+            let info = match graphed {
+                Some(commit) => Info {
+                    parents: commit.parents,
+                    time: commit.time,
+                    generation: commit.generation,
+                },
+                None => {
+                    let commit = Commit::read_from_hash(hash)?;
+                    Info {
+                        time: commit.committer_time.parse().unwrap_or(0),
+                        parents: commit.parents,
+                        generation: GENERATION_INFINITY,
+                    }
+                }
+            };
+            self.commits.insert(hash.to_string(), info);
+        }
+        Ok(&self.commits[hash])
+    }
+
+    fn generation(&mut self, hash: &str) -> Result<u64> {
+        Ok(self.info(hash)?.generation)
+    }
+
+    fn time(&self, hash: &str) -> i64 {
+        self.commits[hash].time
+    }
+
+    /// Every commit reachable from `start`, itself included.
+    pub fn ancestors(&mut self, start: &[String]) -> Result<HashSet<String>> {
+        let mut seen = HashSet::new();
+        let mut pending: Vec<String> = start.to_vec();
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            pending.extend(self.info(&hash)?.parents.iter().cloned());
+        }
+        Ok(seen)
+    }
+
+    /// The best common ancestors of `one` and any of `twos`, newest first: those that aren't
+    /// reachable from another common ancestor.
+    pub fn merge_bases(&mut self, one: &str, twos: &[String]) -> Result<Vec<String>> {
+        if twos.iter().any(|two| two == one) {
+            return Ok(vec![one.to_string()]);
+        }
+        let (common, flags) = self.paint_down_to_common(one, twos, 0)?;
+        let mut bases = Vec::new();
+        for hash in common {
+            if flags[&hash] & STALE == 0 {
+                self.insert_by_date(&mut bases, hash);
+            }
+        }
+        if bases.len() < 2 {
+            return Ok(bases);
+        }
+        let independent = self.remove_redundant(bases)?;
+        let mut bases = Vec::new();
+        for hash in independent {
+            self.insert_by_date(&mut bases, hash);
+        }
+        Ok(bases)
+    }
+
+    /// The merge bases of all the commits at once, as an octopus merge of them would use:
+    /// those of the first two, then those of each of them and the third, and so on. May hold
+    /// duplicates and bases reachable from others.
+    pub fn octopus_merge_bases(&mut self, commits: &[String]) -> Result<Vec<String>> {
+        let Some((first, rest)) = commits.split_first() else {
+            return Ok(Vec::new());
+        };
+        let mut bases = vec![first.clone()];
+        for commit in rest {
+            let mut next = Vec::new();
+            for base in &bases {
+                next.extend(self.merge_bases(commit, std::slice::from_ref(base))?);
+            }
+            bases = next;
+        }
+        Ok(bases)
+    }
+
+    /// The commits given less duplicates and any another one can reach, in the order given.
+    pub fn independent(&mut self, commits: &[String]) -> Result<Vec<String>> {
+        let mut unique: Vec<String> = Vec::new();
+        for commit in commits {
+            if !unique.contains(commit) {
+                unique.push(commit.clone());
+            }
+        }
+        self.remove_redundant(unique)
+    }
+
+    /// Whether `commit` can be reached from any of `references`. A commit of a higher
+    /// generation than all of them can't be, without walking anything.
+    pub fn is_ancestor(&mut self, commit: &str, references: &[String]) -> Result<bool> {
+        let mut max_generation = 0;
+        for reference in references {
+            max_generation = max_generation.max(self.generation(reference)?);
+        }
+        let generation = self.generation(commit)?;
+        if generation > max_generation {
+            return Ok(false);
+        }
+        let (_, flags) = self.paint_down_to_common(commit, references, generation)?;
+        Ok(flags[commit] & PARENT2 != 0)
+    }
+
+    /// Where `commit` forked from a branch that has pointed at each of `history` in turn:
+    /// their one merge base, as long as the branch was once there. Commits rebased out of the
+    /// branch since are still found in its history, which a plain merge base would miss.
+    pub fn fork_point(&mut self, commit: &str, history: &[String]) -> Result<Option<String>> {
+        let bases = self.merge_bases(commit, history)?;
+        Ok(match bases.as_slice() {
+            [base] if history.contains(base) => Some(base.clone()),
+            _ => None,
+        })
+    }
+
+    /// How many commits `ours` can reach that `theirs` can't, and the other way around.
+    pub fn ahead_behind(&mut self, ours: &str, theirs: &str) -> Result<(usize, usize)> {
+        let mut flags: HashMap<String, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut order = 0;
+        for (hash, side) in [(ours, PARENT1), (theirs, PARENT2)] {
+            *flags.entry(hash.to_string()).or_default() |= side;
+            self.push(&mut queue, &mut order, hash, false)?;
+        }
+
+        while let Some(Queued { hash, .. }) = queue.pop() {
+            let side = flags[&hash];
+            for parent in self.info(&hash)?.parents.clone() {
+                let parent_flags = flags.entry(parent.clone()).or_default();
+                if *parent_flags & side == side {
+                    continue;
+                }
+                *parent_flags |= side;
+                self.push(&mut queue, &mut order, &parent, false)?;
+            }
+            // once the generations say nothing left can reach what has been walked, whatever
+            // both sides reach from here on doesn't count for either
+            let done = queue.iter().all(|queued| {
+                flags[&queued.hash] == PARENT1 | PARENT2 && queued.generation != GENERATION_INFINITY
+            });
+            if done {
+                break;
+            }
+        }
+        let count = |side| flags.values().filter(|&&f| f == side).count();
+        Ok((count(PARENT1), count(PARENT2)))
+    }
+
+    fn push(
+        &mut self,
+        queue: &mut BinaryHeap<Queued>,
+        order: &mut u64,
+        hash: &str,
+        by_date: bool,
+    ) -> Result<()> {
+        let info = self.info(hash)?;
+        queue.push(Queued {
+            generation: if by_date { 0 } else { info.generation },
+            time: info.time,
+            order: *order,
+            hash: hash.to_string(),
+        });
+        *order += 1;
+        Ok(())
+    }
+
+    /// Walks down from `one` and `twos` marking what each reaches, until every commit left to
+    /// walk is reachable from a common ancestor already found or is older in generation than
+    /// `min_generation`. Gives the common ancestors found, newest first, and the marks.
+    fn paint_down_to_common(
+        &mut self,
+        one: &str,
+        twos: &[String],
+        min_generation: u64,
+    ) -> Result<(Vec<String>, HashMap<String, u8>)> {
+        let by_date = min_generation == 0
+            && !self
+                .graph
+                .as_ref()
+                .is_some_and(CommitGraph::has_corrected_dates);
+        let mut flags: HashMap<String, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut order = 0;
+        let mut result = Vec::new();
+
+        flags.insert(one.to_string(), PARENT1);
+        if twos.is_empty() {
+            return Ok((vec![one.to_string()], flags));
+        }
+        self.push(&mut queue, &mut order, one, by_date)?;
+        for two in twos {
+            *flags.entry(two.clone()).or_default() |= PARENT2;
+            self.push(&mut queue, &mut order, two, by_date)?;
+        }
+
+        while queue.iter().any(|queued| flags[&queued.hash] & STALE == 0) {
+            let Queued { hash, .. } = queue.pop().unwrap();
+            if self.generation(&hash)? < min_generation {
+                break;
+            }
+            let mut marks = flags[&hash] & (PARENT1 | PARENT2 | STALE);
+            if marks == PARENT1 | PARENT2 {
+                let hash_flags = flags.get_mut(&hash).unwrap();
+                if *hash_flags & RESULT == 0 {
+                    *hash_flags |= RESULT;
+                    self.insert_by_date(&mut result, hash.clone());
+                }
+                // what a common ancestor reaches is common but never the best
+                marks |= STALE;
+            }
+            for parent in self.info(&hash)?.parents.clone() {
+                let parent_flags = flags.entry(parent.clone()).or_default();
+                if *parent_flags & marks == marks {
+                    continue;
+                }
+                *parent_flags |= marks;
+                self.push(&mut queue, &mut order, &parent, by_date)?;
+            }
+        }
+        Ok((result, flags))
+    }
+
+    /// Drops the commits reachable from another one in the list.
+    fn remove_redundant(&mut self, commits: Vec<String>) -> Result<Vec<String>> {
+        let mut redundant = vec![false; commits.len()];
+        for i in 0..commits.len() {
+            if redundant[i] {
+                continue;
+            }
+            let mut min_generation = self.generation(&commits[i])?;
+            let mut others = Vec::new();
+            let mut indexes = Vec::new();
+            for (j, other) in commits.iter().enumerate() {
+                if i == j || redundant[j] {
+                    continue;
+                }
+                min_generation = min_generation.min(self.generation(other)?);
+                others.push(other.clone());
+                indexes.push(j);
+            }
+            let (_, flags) = self.paint_down_to_common(&commits[i], &others, min_generation)?;
+            if flags[&commits[i]] & PARENT2 != 0 {
+                redundant[i] = true;
+            }
+            for (other, j) in others.iter().zip(indexes) {
+                if flags[other] & PARENT1 != 0 {
+                    redundant[j] = true;
+                }
+            }
+        }
+        Ok(commits
+            .into_iter()
+            .zip(redundant)
+            .filter(|(_, redundant)| !redundant)
+            .map(|(hash, _)| hash)
+            .collect())
+    }
+
+    /// Inserts before the first commit that is older, so equal dates keep the order added.
+    fn insert_by_date(&self, list: &mut Vec<String>, hash: String) {
+        let time = self.time(&hash);
+        let at = list
+            .iter()
+            .position(|other| self.time(other) < time)
+            .unwrap_or(list.len());
+        list.insert(at, hash);
+    }
+}
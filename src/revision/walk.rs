@@ -0,0 +1,949 @@
+//! Walking the commit graph the way `git log` does.
+//!
+//! Commits come out newest first by committer date, a parent only after the child that led to
+//! it was taken. Excluded tips (`^A`, `A..B`) make the walk limited: everything is walked up
+//! front until only excluded commits are left to look at, which is also how `--topo-order`
+//! gets a whole list to sort. With a commit-graph, `--topo-order` instead counts in-degrees
+//! only as deep as the generation numbers say the walk has come, and commits are only
+//! inflated once they are shown or matched against. With a pathspec, history is simplified as
+//! git simplifies it by default: a commit that leaves the paths as one of its parents had them
+//! is dropped, and a merge is followed only down a parent it took the paths from unchanged.
+//!
+//! `--follow` doesn't simplify. It shows the commits that aren't merges and change the one path
+//! it follows, and when one of them added the path as a rename, follows the file it came from
+//! for the rest of the walk.
+
+use anyhow::Result;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use crate::{
+    config::Config,
+    diff::pairs,
+    objects::{
+        commit::Commit,
+        commit_graph::{CommitGraph, GENERATION_INFINITY},
+        tree::Tree,
+    },
+    pathspec::Pathspec,
+    regex::Regex,
+    rename,
+};
+
+/// Reached from a tip, queued or already walked.
+const SEEN: u8 = 1 << 0;
+/// Reachable from an excluded tip.
+const UNINTERESTING: u8 = 1 << 1;
+/// Has the paths the pathspec looks at as a parent had them.
+const TREESAME: u8 = 1 << 2;
+const SHOWN: u8 = 1 << 3;
+/// Its parents have been looked at.
+const ADDED: u8 = 1 << 4;
+/// Queued to have its parents looked at ahead of a topological walk.
+const EXPLORED: u8 = 1 << 5;
+/// Queued to be counted in its parents' in-degrees for a topological walk.
+const INDEGREE: u8 = 1 << 6;
+
+/// How many more commits to walk once only excluded ones are queued, in case an interesting
+/// one turns up behind a commit with a skewed date.
+const SLOP: usize = 5;
+
+#[derive(Debug, Default)]
+pub(crate) struct Options {
+    /// `--topo-order`, no parent before all its children, and lines of history kept together
+    pub topo_order: bool,
+    /// `--reverse`, oldest first, after the rest of the limits
+    pub reverse: bool,
+    /// `--first-parent`, follow only the first parent of merges
+    pub first_parent: bool,
+    /// `-n`, stop after this many commits
+    pub max_count: Option<usize>,
+    /// `--since`, stop at commits older than this
+    pub since: Option<i64>,
+    /// `--until`, leave out commits newer than this
+    pub until: Option<i64>,
+    /// `--author`, any one must match the author's name and email
+    pub authors: Vec<Regex>,
+    /// `--grep`, any one must match the message
+    pub grep: Vec<Regex>,
+    /// only commits changing these paths, with the history simplified to them
+    pub pathspec: Pathspec,
+    /// `--follow`, the one path of the pathspec is followed back through renames, without
+    /// simplifying history
+    pub follow: bool,
+    /// replace parents that were left out with their nearest shown ancestors, as `--graph`
+    /// needs to draw lines that connect
+    pub rewrite_parents: bool,
+}
+
+struct Node {
+    /// read only when needed if the commit-graph has the rest
+    commit: Option<Commit>,
+    tree: String,
+    time: i64,
+    generation: u64,
+    /// the parents the commit has
+    commit_parents: Vec<String>,
+    /// the commit's parents, less the ones simplification cut off or rewritten to the
+    /// commits that are shown in their place
+    parents: Vec<String>,
+    flags: u8,
+}
+
+/// A queued commit: the newest first, and among equal dates the one queued first.
+#[derive(PartialEq, Eq)]
+struct Queued {
+    time: i64,
+    order: u64,
+    hash: String,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.time
+            .cmp(&other.time)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A commit queued by generation: the highest first, then the newest, then the one queued
+/// first.
+#[derive(PartialEq, Eq)]
+struct ByGeneration {
+    generation: u64,
+    time: i64,
+    order: u64,
+    hash: String,
+}
+
+impl Ord for ByGeneration {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.generation
+            .cmp(&other.generation)
+            .then_with(|| self.time.cmp(&other.time))
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for ByGeneration {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A topological walk that doesn't need all of history first. A commit is ready once every
+/// child that will be shown has been, which is known as soon as the in-degrees are counted
+/// down to its generation: nothing of a lower generation can be its child.
+struct TopoWalk {
+    min_generation: u64,
+    /// commits to process the parents of, so simplification and exclusion are known before
+    /// in-degrees are counted through them
+    explore: BinaryHeap<ByGeneration>,
+    /// commits to count in their parents' in-degrees
+    counting: BinaryHeap<ByGeneration>,
+    /// one more than the number of children yet to be shown, 0 once shown itself
+    indegree: HashMap<String, isize>,
+    /// the commits ready to be shown, taken last in first out to keep to a line of history
+    ready: Vec<String>,
+}
+
+pub(crate) struct Walk {
+    options: Options,
+    graph: Option<CommitGraph>,
+    nodes: HashMap<String, Node>,
+    queue: BinaryHeap<Queued>,
+    queued: u64,
+    /// whether the excluded tips require walking everything up front
+    limited: bool,
+    /// the walk `--topo-order` takes when there are generation numbers
+    topo: Option<TopoWalk>,
+    /// what's left to give out, once a limited walk has worked it out
+    list: Option<VecDeque<String>>,
+    /// the whole walk, oldest first, for `--reverse`
+    reversed: Option<VecDeque<String>>,
+    remaining: Option<usize>,
+}
+
+impl Walk {
+    /// Starts a walk from commits given as (hash, excluded).
+    pub fn new(options: Options, tips: &[(String, bool)]) -> Result<Walk> {
+        let graph = super::commit_graph()?.filter(|graph| graph.len() > 0);
+        let incremental = options.topo_order && graph.is_some();
+        let mut walk = Walk {
+            limited: options.topo_order && !incremental,
+            topo: None,
+            remaining: options.max_count,
+            options,
+            graph,
+            nodes: HashMap::new(),
+            queue: BinaryHeap::new(),
+            queued: 0,
+            list: None,
+            reversed: None,
+        };
+        for (hash, excluded) in tips {
+            walk.load(hash)?;
+            if *excluded {
+                walk.nodes.get_mut(hash).unwrap().flags |= UNINTERESTING;
+                walk.mark_parents_uninteresting(hash);
+                walk.limited |= !incremental;
+            }
+        }
+        let mut start: Vec<&String> = Vec::new();
+        for (hash, _) in tips {
+            let node = walk.nodes.get_mut(hash).unwrap();
+            if node.flags & SEEN == 0 {
+                node.flags |= SEEN;
+                start.push(hash);
+            }
+        }
+        // stable, so tips with equal dates stay in the order given
+        start.sort_by_key(|hash| std::cmp::Reverse(walk.nodes[*hash].time));
+        if incremental {
+            let start: Vec<String> = start.into_iter().cloned().collect();
+            walk.start_topo_walk(&start)?;
+        } else {
+            for hash in start {
+                walk.push(hash);
+            }
+        }
+        Ok(walk)
+    }
+
+    /// The next commit to show, or None when the walk is done.
+    pub fn next(&mut self) -> Result<Option<String>> {
+        if !self.options.reverse {
+            return self.next_unreversed();
+        }
+        if self.reversed.is_none() {
+            let mut reversed = VecDeque::new();
+            while let Some(hash) = self.next_unreversed()? {
+                reversed.push_front(hash);
+            }
+            self.reversed = Some(reversed);
+        }
+        Ok(self.reversed.as_mut().unwrap().pop_front())
+    }
+
+    /// A commit given out, which is always read by then.
+    pub fn commit(&self, hash: &str) -> &Commit {
+        self.nodes[hash].commit.as_ref().unwrap()
+    }
+
+    /// The parents of a commit given out, as simplified and rewritten.
+    pub fn parents(&self, hash: &str) -> &[String] {
+        &self.nodes[hash].parents
+    }
+
+    /// Whether a commit would be shown if the walk came to it, as `--graph` asks of parents to
+    /// know which lines to draw.
+    pub fn is_shown(&mut self, hash: &str) -> Result<bool> {
+        self.load(hash)?;
+        Ok(self.action(hash)? == Action::Show)
+    }
+
+    fn next_unreversed(&mut self) -> Result<Option<String>> {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        if self.limited && self.list.is_none() {
+            let list = self.limit()?;
+            self.list = Some(if self.options.topo_order {
+                self.sort_topologically(list)
+            } else {
+                list
+            });
+        }
+        loop {
+            let hash = match (&mut self.list, &mut self.topo) {
+                (Some(list), _) => list.pop_front(),
+                (None, Some(topo)) => topo.ready.pop().inspect(|hash| {
+                    topo.indegree.insert(hash.clone(), 0);
+                }),
+                (None, None) => self.queue.pop().map(|queued| queued.hash),
+            };
+            let Some(hash) = hash else {
+                return Ok(None);
+            };
+            if !self.limited {
+                if self
+                    .options
+                    .since
+                    .is_some_and(|since| self.nodes[&hash].time < since)
+                {
+                    continue;
+                }
+                if self.topo.is_some() {
+                    self.expand_topo_walk(&hash)?;
+                } else {
+                    self.process_parents(&hash)?;
+                }
+            }
+            if self.action(&hash)? == Action::Ignore {
+                continue;
+            }
+            if self.options.follow && !self.follow(&hash)? {
+                continue;
+            }
+            if self.wants_ancestry() {
+                self.rewrite_parents(&hash)?;
+            }
+            if let Some(remaining) = &mut self.remaining {
+                *remaining -= 1;
+            }
+            self.read(&hash)?;
+            self.nodes.get_mut(&hash).unwrap().flags |= SHOWN;
+            return Ok(Some(hash));
+        }
+    }
+
+    fn load(&mut self, hash: &str) -> Result<()> {
+        if self.nodes.contains_key(hash) {
+            return Ok(());
+        }
+        let graphed = match &self.graph {
+            Some(graph) => graph.lookup(hash)?,
+            None => None,
+        };
+        let node = match graphed {
+            Some(graphed) => Node {
+                commit: None,
+                tree: graphed.tree,
+                time: graphed.time,
+                generation: graphed.generation,
+                parents: graphed.parents.clone(),
+                commit_parents: graphed.parents,
+                flags: 0,
+            },
+            None => {
+                let commit = Commit::read_from_hash(hash)?;
+                Node {
+                    tree: commit.tree.clone(),
+                    time: commit.committer_time.parse().unwrap_or(0),
+                    generation: GENERATION_INFINITY,
+                    parents: commit.parents.clone(),
+                    commit_parents: commit.parents.clone(),
+                    commit: Some(commit),
+                    flags: 0,
+                }
+            }
+        };
+        self.nodes.insert(hash.to_string(), node);
+        Ok(())
+    }
+
+    /// Reads the whole of a loaded commit, if the commit-graph gave the rest.
+    fn read(&mut self, hash: &str) -> Result<()> {
+        let node = self.nodes.get_mut(hash).unwrap();
+        if node.commit.is_none() {
+            node.commit = Some(Commit::read_from_hash(hash)?);
+        }
+        Ok(())
+    }
+
+    fn flags(&self, hash: &str) -> u8 {
+        self.nodes.get(hash).map_or(0, |node| node.flags)
+    }
+
+    fn push(&mut self, hash: &str) {
+        if self.topo.is_some() {
+            // the topological walk keeps queues of its own
+            return;
+        }
+        self.queue.push(Queued {
+            time: self.nodes[hash].time,
+            order: self.queued,
+            hash: hash.to_string(),
+        });
+        self.queued += 1;
+    }
+
+    fn prunes(&self) -> bool {
+        !self.options.follow && !self.options.pathspec.items.is_empty()
+    }
+
+    fn wants_ancestry(&self) -> bool {
+        self.prunes() && self.options.rewrite_parents
+    }
+
+    /// Marks everything already loaded behind an excluded commit as excluded too; what isn't
+    /// loaded yet is marked as the walk reaches it.
+    fn mark_parents_uninteresting(&mut self, hash: &str) {
+        let mut pending = vec![hash.to_string()];
+        while let Some(hash) = pending.pop() {
+            let parents = match self.nodes.get(&hash) {
+                Some(node) => node.commit_parents.clone(),
+                None => continue,
+            };
+            for parent in parents {
+                if let Some(node) = self.nodes.get_mut(&parent) {
+                    if node.flags & UNINTERESTING == 0 {
+                        node.flags |= UNINTERESTING;
+                        pending.push(parent);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queues the parents of a commit not yet seen, simplifying an interesting commit first
+    /// so that only the parents it keeps are walked.
+    fn process_parents(&mut self, hash: &str) -> Result<()> {
+        let node = self.nodes.get_mut(hash).unwrap();
+        if node.flags & ADDED != 0 {
+            return Ok(());
+        }
+        node.flags |= ADDED;
+
+        if node.flags & UNINTERESTING != 0 {
+            for parent in node.parents.clone() {
+                self.load(&parent)?;
+                self.nodes.get_mut(&parent).unwrap().flags |= UNINTERESTING;
+                self.mark_parents_uninteresting(&parent);
+                let node = self.nodes.get_mut(&parent).unwrap();
+                if node.flags & SEEN == 0 {
+                    node.flags |= SEEN;
+                    self.push(&parent);
+                }
+            }
+            return Ok(());
+        }
+
+        self.simplify(hash)?;
+        for parent in self.nodes[hash].parents.clone() {
+            self.load(&parent)?;
+            let node = self.nodes.get_mut(&parent).unwrap();
+            if node.flags & SEEN == 0 {
+                node.flags |= SEEN;
+                self.push(&parent);
+            }
+            if self.options.first_parent {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks a commit TREESAME when it doesn't change the paths, and when a merge has the
+    /// paths as one of its interesting parents had them, cuts its other parents off.
+    fn simplify(&mut self, hash: &str) -> Result<()> {
+        if !self.prunes() {
+            return Ok(());
+        }
+        let node = &self.nodes[hash];
+        let tree = node.tree.clone();
+        let parents = node.parents.clone();
+        if parents.is_empty() {
+            if same_trees(None, Some(&tree), "", &self.options.pathspec)? {
+                self.nodes.get_mut(hash).unwrap().flags |= TREESAME;
+            }
+            return Ok(());
+        }
+
+        let mut relevant_parents = 0;
+        let (mut relevant_change, mut irrelevant_change) = (false, false);
+        for (nth, parent) in parents.iter().enumerate() {
+            if nth == 1 && self.options.first_parent {
+                break;
+            }
+            self.load(parent)?;
+            let relevant = self.flags(parent) & UNINTERESTING == 0;
+            if relevant {
+                relevant_parents += 1;
+            }
+            let parent_tree = self.nodes[parent].tree.clone();
+            if same_trees(Some(&parent_tree), Some(&tree), "", &self.options.pathspec)? {
+                if !relevant {
+                    // keep the merge's other lines, an excluded side can't explain it
+                    continue;
+                }
+                let node = self.nodes.get_mut(hash).unwrap();
+                node.parents = vec![parent.clone()];
+                node.flags |= TREESAME;
+                return Ok(());
+            }
+            if relevant {
+                relevant_change = true;
+            } else {
+                irrelevant_change = true;
+            }
+        }
+
+        let node = self.nodes.get_mut(hash).unwrap();
+        let changed = if relevant_parents > 0 {
+            relevant_change
+        } else {
+            irrelevant_change
+        };
+        if changed {
+            node.flags &= !TREESAME;
+        } else {
+            node.flags |= TREESAME;
+        }
+        Ok(())
+    }
+
+    fn action(&mut self, hash: &str) -> Result<Action> {
+        let node = &self.nodes[hash];
+        if node.flags & (SHOWN | UNINTERESTING) != 0 {
+            return Ok(Action::Ignore);
+        }
+        if self.options.until.is_some_and(|until| node.time > until) {
+            return Ok(Action::Ignore);
+        }
+        if !self.options.authors.is_empty() || !self.options.grep.is_empty() {
+            self.read(hash)?;
+            if !self.matches(self.commit(hash)) {
+                return Ok(Action::Ignore);
+            }
+        }
+        let node = &self.nodes[hash];
+        if self.prunes() && node.flags & TREESAME != 0 {
+            // a merge between lines that are drawn holds the graph together
+            let relevant = node
+                .parents
+                .iter()
+                .filter(|parent| self.flags(parent) & UNINTERESTING == 0)
+                .count();
+            if !self.wants_ancestry() || relevant < 2 {
+                return Ok(Action::Ignore);
+            }
+        }
+        Ok(Action::Show)
+    }
+
+    /// Whether a commit that isn't a merge changes the path `--follow` is on, as it is diffed
+    /// against its parent. If all it did there was add the path as a rename, the file it was
+    /// renamed from is followed from here on.
+    fn follow(&mut self, hash: &str) -> Result<bool> {
+        let node = &self.nodes[hash];
+        if node.parents.len() > 1 {
+            return Ok(false);
+        }
+        let tree = node.tree.clone();
+        let parent_tree = match node.parents.first().cloned() {
+            Some(parent) => {
+                self.load(&parent)?;
+                Some(self.nodes[&parent].tree.clone())
+            }
+            None => None,
+        };
+        let pathspec = &self.options.pathspec;
+        let old = match &parent_tree {
+            Some(parent_tree) => pairs::tree_files(parent_tree, true, pathspec)?,
+            None => Default::default(),
+        };
+        let changes = pairs::compare(old, pairs::tree_files(&tree, true, pathspec)?);
+        let followed = &pathspec.items[0];
+        if let ([added], Some(parent_tree)) = (&changes[..], &parent_tree) {
+            if added.status == 'A' && added.path() == followed {
+                if let Some(source) = renamed_from(parent_tree, &tree, followed)? {
+                    self.options.pathspec = Pathspec::new(&[source]);
+                }
+            }
+        }
+        Ok(!changes.is_empty())
+    }
+
+    fn matches(&self, commit: &Commit) -> bool {
+        let Options { authors, grep, .. } = &self.options;
+        let author = format!("{} {}", commit.author, commit.author_email);
+        (authors.is_empty()
+            || authors
+                .iter()
+                .any(|re| re.find(author.as_bytes()).is_some()))
+            && (grep.is_empty()
+                || grep
+                    .iter()
+                    .any(|re| re.find(commit.message.as_bytes()).is_some()))
+    }
+
+    /// Replaces each parent that won't be shown, because it didn't change the paths, with its
+    /// nearest ancestor that will be, dropping ones that lead nowhere.
+    fn rewrite_parents(&mut self, hash: &str) -> Result<()> {
+        let mut parents = Vec::new();
+        for parent in self.nodes[hash].parents.clone() {
+            if let Some(parent) = self.rewrite_one(parent)? {
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+        self.nodes.get_mut(hash).unwrap().parents = parents;
+        Ok(())
+    }
+
+    fn rewrite_one(&mut self, mut hash: String) -> Result<Option<String>> {
+        loop {
+            self.load(&hash)?;
+            if !self.limited {
+                self.process_parents(&hash)?;
+            }
+            let node = &self.nodes[&hash];
+            if node.flags & UNINTERESTING != 0 || node.flags & TREESAME == 0 {
+                return Ok(Some(hash));
+            }
+            if node.parents.is_empty() {
+                return Ok(None);
+            }
+            match self.one_relevant_parent(&node.parents) {
+                Some(parent) => hash = parent,
+                None => return Ok(Some(hash)),
+            }
+        }
+    }
+
+    /// The parent a TREESAME commit was compared to: the only one, the first if only first
+    /// parents are followed, or the only interesting one of a merge.
+    fn one_relevant_parent(&self, parents: &[String]) -> Option<String> {
+        if self.options.first_parent || parents.len() == 1 {
+            return parents.first().cloned();
+        }
+        let mut relevant = parents
+            .iter()
+            .filter(|parent| self.flags(parent) & UNINTERESTING == 0);
+        match (relevant.next(), relevant.next()) {
+            (Some(parent), None) => Some(parent.clone()),
+            _ => None,
+        }
+    }
+
+    fn topo(&mut self) -> &mut TopoWalk {
+        self.topo.as_mut().unwrap()
+    }
+
+    fn by_generation(&mut self, hash: &str) -> ByGeneration {
+        let node = &self.nodes[hash];
+        let queued = ByGeneration {
+            generation: node.generation,
+            time: node.time,
+            order: self.queued,
+            hash: hash.to_string(),
+        };
+        self.queued += 1;
+        queued
+    }
+
+    fn explore(&mut self, hash: &str) {
+        let node = self.nodes.get_mut(hash).unwrap();
+        if node.flags & EXPLORED == 0 {
+            node.flags |= EXPLORED;
+            let queued = self.by_generation(hash);
+            self.topo().explore.push(queued);
+        }
+    }
+
+    fn count(&mut self, hash: &str) {
+        let node = self.nodes.get_mut(hash).unwrap();
+        if node.flags & INDEGREE == 0 {
+            node.flags |= INDEGREE;
+            let queued = self.by_generation(hash);
+            self.topo().counting.push(queued);
+        }
+    }
+
+    /// Counts the in-degrees down to the lowest generation of the tips, and makes ready the
+    /// tips no other tip leads to, the first given to come out first.
+    fn start_topo_walk(&mut self, start: &[String]) -> Result<()> {
+        self.topo = Some(TopoWalk {
+            min_generation: GENERATION_INFINITY,
+            explore: BinaryHeap::new(),
+            counting: BinaryHeap::new(),
+            indegree: HashMap::new(),
+            ready: Vec::new(),
+        });
+        for hash in start {
+            self.explore(hash);
+            self.count(hash);
+            let generation = self.nodes[hash].generation;
+            let topo = self.topo();
+            topo.min_generation = topo.min_generation.min(generation);
+            topo.indegree.insert(hash.clone(), 1);
+        }
+        let min_generation = self.topo().min_generation;
+        self.count_to_depth(min_generation)?;
+
+        let topo = self.topo();
+        for hash in start.iter().rev() {
+            if topo.indegree[hash] == 1 {
+                topo.ready.push(hash.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes the parents of the next commit to explore, as the walk itself would have.
+    fn explore_step(&mut self) -> Result<()> {
+        let Some(ByGeneration { hash, .. }) = self.topo().explore.pop() else {
+            return Ok(());
+        };
+        if self
+            .options
+            .since
+            .is_some_and(|since| self.nodes[&hash].time < since)
+        {
+            self.nodes.get_mut(&hash).unwrap().flags |= UNINTERESTING;
+        }
+        self.process_parents(&hash)?;
+        if self.flags(&hash) & UNINTERESTING != 0 {
+            self.mark_parents_uninteresting(&hash);
+        }
+        for parent in self.nodes[&hash].parents.clone() {
+            self.load(&parent)?;
+            self.explore(&parent);
+        }
+        Ok(())
+    }
+
+    fn explore_to_depth(&mut self, generation: u64) -> Result<()> {
+        while self
+            .topo()
+            .explore
+            .peek()
+            .is_some_and(|queued| queued.generation >= generation)
+        {
+            self.explore_step()?;
+        }
+        Ok(())
+    }
+
+    /// Counts the next commit in its parents' in-degrees, once it and everything above it
+    /// has been explored.
+    fn count_step(&mut self) -> Result<()> {
+        let Some(ByGeneration {
+            hash, generation, ..
+        }) = self.topo().counting.pop()
+        else {
+            return Ok(());
+        };
+        self.explore_to_depth(generation)?;
+        for parent in self.nodes[&hash].parents.clone() {
+            self.load(&parent)?;
+            let degree = self.topo().indegree.entry(parent.clone()).or_insert(0);
+            *degree = if *degree == 0 { 2 } else { *degree + 1 };
+            self.count(&parent);
+            if self.options.first_parent {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn count_to_depth(&mut self, generation: u64) -> Result<()> {
+        while self
+            .topo()
+            .counting
+            .peek()
+            .is_some_and(|queued| queued.generation >= generation)
+        {
+            self.count_step()?;
+        }
+        Ok(())
+    }
+
+    /// Takes a commit shown off its parents' in-degrees, counting deeper first if a parent
+    /// is of a lower generation than any counted yet.
+    fn expand_topo_walk(&mut self, hash: &str) -> Result<()> {
+        self.process_parents(hash)?;
+        for parent in self.nodes[hash].parents.clone() {
+            if self.flags(&parent) & UNINTERESTING != 0 {
+                continue;
+            }
+            self.load(&parent)?;
+            let generation = self.nodes[&parent].generation;
+            if generation < self.topo().min_generation {
+                self.topo().min_generation = generation;
+                self.count_to_depth(generation)?;
+            }
+            let topo = self.topo();
+            let degree = topo.indegree.entry(parent.clone()).or_insert(0);
+            *degree -= 1;
+            if *degree == 1 {
+                topo.ready.push(parent);
+            }
+            if self.options.first_parent {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks until only excluded commits are left, giving the interesting ones in the order
+    /// they were reached. Commits older than `--since` count as excluded.
+    fn limit(&mut self) -> Result<VecDeque<String>> {
+        let mut list = VecDeque::new();
+        let mut slop = SLOP;
+        let mut date = i64::MAX;
+        while let Some(Queued { hash, .. }) = self.queue.pop() {
+            if self
+                .options
+                .since
+                .is_some_and(|since| self.nodes[&hash].time < since)
+            {
+                self.nodes.get_mut(&hash).unwrap().flags |= UNINTERESTING;
+            }
+            self.process_parents(&hash)?;
+            if self.flags(&hash) & UNINTERESTING != 0 {
+                self.mark_parents_uninteresting(&hash);
+                slop = self.still_interesting(date, slop);
+                if slop > 0 {
+                    continue;
+                }
+                break;
+            }
+            if self
+                .options
+                .until
+                .is_some_and(|until| self.nodes[&hash].time > until)
+            {
+                continue;
+            }
+            date = self.nodes[&hash].time;
+            list.push_back(hash);
+        }
+        Ok(list)
+    }
+
+    fn still_interesting(&self, date: i64, slop: usize) -> usize {
+        let Some(next) = self.queue.peek() else {
+            return 0;
+        };
+        if date <= next.time {
+            return SLOP;
+        }
+        if self
+            .queue
+            .iter()
+            .any(|queued| self.flags(&queued.hash) & UNINTERESTING == 0)
+        {
+            return SLOP;
+        }
+        slop - 1
+    }
+
+    /// Orders the list so that no commit comes before all its children have, keeping to the
+    /// line of history being followed for as long as it can, as git's graph order does.
+    fn sort_topologically(&self, list: VecDeque<String>) -> VecDeque<String> {
+        let mut indegree: HashMap<&str, usize> =
+            list.iter().map(|hash| (hash.as_str(), 1)).collect();
+        for hash in &list {
+            for parent in &self.nodes[hash].parents {
+                if let Some(degree) = indegree.get_mut(parent.as_str()) {
+                    *degree += 1;
+                }
+            }
+        }
+        // the tips go on a stack so that the first one given comes off first
+        let mut stack: Vec<&str> = list
+            .iter()
+            .map(String::as_str)
+            .filter(|hash| indegree[hash] == 1)
+            .collect();
+        stack.reverse();
+
+        let mut sorted = VecDeque::new();
+        while let Some(hash) = stack.pop() {
+            for parent in &self.nodes[hash].parents {
+                if let Some(degree) = indegree.get_mut(parent.as_str()) {
+                    if *degree == 0 {
+                        continue;
+                    }
+                    *degree -= 1;
+                    if *degree == 1 {
+                        stack.push(parent);
+                    }
+                }
+            }
+            indegree.insert(hash, 0);
+            sorted.push_back(hash.to_string());
+        }
+        sorted
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Show,
+    Ignore,
+}
+
+/// The file `path`, added between two trees, was renamed or copied from, if rename detection
+/// as the config has it finds one. Only `path` is looked for among the files that appeared.
+fn renamed_from(old: &str, new: &str, path: &str) -> Result<Option<String>> {
+    let Some(options) = rename::Options::from_config(&Config::load()?, "diff") else {
+        return Ok(None);
+    };
+    let everything = Pathspec::default();
+    let mut changes = pairs::compare(
+        pairs::tree_files(old, true, &everything)?,
+        pairs::tree_files(new, true, &everything)?,
+    );
+    changes.retain(|pair| pair.status != 'A' || pair.path() == path);
+    pairs::find_renames(&mut changes, &options)?;
+    Ok(changes
+        .into_iter()
+        .find(|pair| matches!(pair.status, 'R' | 'C') && pair.path() == path)
+        .and_then(|pair| pair.old)
+        .map(|old| old.path))
+}
+
+/// Whether two trees, either of which may be missing, agree on every path the pathspec
+/// matches. Identical subtrees aren't read.
+fn same_trees(
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    pathspec: &Pathspec,
+) -> Result<bool> {
+    if old == new {
+        return Ok(true);
+    }
+    let entries = |tree: Option<&str>| -> Result<HashMap<String, (u32, String)>> {
+        Ok(match tree {
+            Some(tree) => Tree::read_from_hash(tree)?
+                .entries
+                .into_iter()
+                .map(|entry| (entry.filename.clone(), (entry.mode, entry.hash)))
+                .collect(),
+            None => HashMap::new(),
+        })
+    };
+    let old = entries(old)?;
+    let new = entries(new)?;
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let (a, b) = (old.get(name), new.get(name));
+        if a == b {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        let is_tree = |entry: &&(u32, String)| entry.0 == 40000;
+        let (a_tree, b_tree) = (a.filter(is_tree), b.filter(is_tree));
+        let (a_file, b_file) = (
+            a.filter(|entry| !is_tree(entry)),
+            b.filter(|entry| !is_tree(entry)),
+        );
+        if a_file != b_file && pathspec.matches(&path) {
+            return Ok(false);
+        }
+        if a_tree != b_tree
+            && pathspec.matches_within(&path)
+            && !same_trees(
+                a_tree.map(|entry| entry.1.as_str()),
+                b_tree.map(|entry| entry.1.as_str()),
+                &format!("{}/", path),
+                pathspec,
+            )?
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
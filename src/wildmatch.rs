@@ -0,0 +1,152 @@
+/// Matches `text` against a git glob `pattern`, the way `.gitignore` and `.gitattributes`
+/// patterns are matched (git's `wildmatch` with `WM_PATHNAME`).
+///
+/// `*` and `?` don't cross a `/`, `**` does when it is a whole path component (`**/x`,
+/// `x/**`, `a/**/b`), and `[...]` classes take ranges and `!`/`^` negation. A backslash
+/// escapes the next character.
+pub fn wildmatch(pattern: &str, text: &str) -> bool {
+    matches(pattern.as_bytes(), text.as_bytes(), true)
+}
+
+/// Like `wildmatch` but without `WM_PATHNAME`, so `*`, `?` and classes match `/` too. This is
+/// how pathspecs are matched.
+pub fn fnmatch(pattern: &str, text: &str) -> bool {
+    matches(pattern.as_bytes(), text.as_bytes(), false)
+}
+
+fn matches(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                // without WM_PATHNAME every star already crosses slashes
+                let double = pathname && pattern.get(p + 1) == Some(&b'*');
+                if double {
+                    let at_start = p == 0 || pattern[p - 1] == b'/';
+                    let mut rest = p + 2;
+                    while pattern.get(rest) == Some(&b'*') {
+                        rest += 1;
+                    }
+                    let at_end = rest == pattern.len();
+                    if at_start && at_end {
+                        // `**` or `dir/**` matches everything left
+                        return true;
+                    }
+                    if at_start && pattern.get(rest) == Some(&b'/') {
+                        // `**/` matches zero or more leading directories
+                        let rest = &pattern[rest + 1..];
+                        let mut start = t;
+                        loop {
+                            if matches(rest, &text[start..], pathname) {
+                                return true;
+                            }
+                            match text[start..].iter().position(|&c| c == b'/') {
+                                Some(slash) => start += slash + 1,
+                                None => return false,
+                            }
+                        }
+                    }
+                    // otherwise two stars act like one
+                }
+                let mut rest = p + 1;
+                while pattern.get(rest) == Some(&b'*') {
+                    rest += 1;
+                }
+                let rest = &pattern[rest..];
+                // try every split point, not swallowing a slash under WM_PATHNAME
+                let mut start = t;
+                loop {
+                    if matches(rest, &text[start..], pathname) {
+                        return true;
+                    }
+                    if start == text.len() || (pathname && text[start] == b'/') {
+                        return false;
+                    }
+                    start += 1;
+                }
+            }
+            b'?' => {
+                if t == text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                if t == text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                match match_class(&pattern[p..], text[t]) {
+                    Some((true, len)) => {
+                        p += len;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    // no closing bracket, so it is a literal [
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t == text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t == text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Matches `c` against the class starting at `pattern[0] == b'['`, returning whether it
+/// matched and how long the class is, or `None` if the class is never closed.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut current = *pattern.get(i)?;
+        if current == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if current == b'\\' {
+            i += 1;
+            current = *pattern.get(i)?;
+        }
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&e| e != b']') {
+            let mut end = pattern[i + 2];
+            let mut skip = 3;
+            if end == b'\\' {
+                end = *pattern.get(i + 3)?;
+                skip = 4;
+            }
+            if current <= c && c <= end {
+                matched = true;
+            }
+            i += skip;
+        } else {
+            if current == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+}
@@ -0,0 +1,622 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::{self, ErrorKind},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    config::Config,
+    convert,
+    ignore::{self, Ignore},
+    objects::{
+        index::{IndexEntry, IndexFile},
+        object::{Kind, Object},
+        untracked_cache::{
+            CachedDir, ExcludeFile, StatData, UntrackedCache, HIDE_EMPTY_DIRECTORIES,
+            SHOW_OTHER_DIRECTORIES,
+        },
+    },
+};
+
+/// A file found in the worktree, with its path relative to the top.
+#[derive(Debug)]
+pub(crate) struct WorktreeFile {
+    pub path: String,
+    /// matched by the ignore rules, itself or through a directory above it
+    pub ignored: bool,
+}
+
+/// Every file in the worktree outside `.git`, sorted by path. Symlinks are listed as files and
+/// not followed. The `.gitignore` of each directory is loaded on the way down, over the rules
+/// in `ignore`.
+pub fn walk(ignore: &Ignore) -> Result<Vec<WorktreeFile>> {
+    let mut files = walk_dir(&Spare::new(), "", false, ignore.clone())?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// The files in the directory `prefix` (empty or ending in a slash) and below, `ignore`
+/// holding the rules of the directories above it. Each subdirectory is a job of its own.
+fn walk_dir(
+    spare: &Spare,
+    prefix: &str,
+    ignored: bool,
+    mut ignore: Ignore,
+) -> Result<Vec<WorktreeFile>> {
+    ignore.load_dir(prefix)?;
+    let dir = if prefix.is_empty() { "." } else { prefix };
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().context("file names must be valid utf-8")?;
+        if prefix.is_empty() && name == ".git" {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        let is_dir = entry.file_type()?.is_dir();
+        // nothing inside an ignored directory can be brought back
+        let ignored = ignored || ignore.is_ignored(&path, is_dir);
+
+        if is_dir {
+            subdirs.push((format!("{}/", path), ignored));
+        } else {
+            files.push(WorktreeFile { path, ignored });
+        }
+    }
+    let listed = spare.map(subdirs, |(prefix, ignored)| {
+        walk_dir(spare, &prefix, ignored, ignore.clone())
+    });
+    for subdir in listed {
+        files.extend(subdir?);
+    }
+    Ok(files)
+}
+
+/// The threads a walk of the worktree may still start, besides the one it runs on, so that
+/// directories are read in parallel without starting a thread for each.
+struct Spare(AtomicUsize);
+
+impl Spare {
+    fn new() -> Spare {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Spare(AtomicUsize::new(threads - 1))
+    }
+
+    /// `job` for each of `items`, on spare threads while there are any and on this one
+    /// otherwise, the results in the order of the items.
+    fn map<T: Send, R: Send>(&self, items: Vec<T>, job: impl Fn(T) -> R + Sync) -> Vec<R> {
+        enum Slot<'scope, R> {
+            Done(R),
+            Running(thread::ScopedJoinHandle<'scope, R>),
+        }
+        let job = &job;
+        let count = items.len();
+        thread::scope(|scope| {
+            let mut slots = Vec::with_capacity(count);
+            for (n, item) in items.into_iter().enumerate() {
+                // the last item is kept for this thread, which would only wait otherwise
+                let take = |n: usize| n.checked_sub(1);
+                if n + 1 < count
+                    && self
+                        .0
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, take)
+                        .is_ok()
+                {
+                    slots.push(Slot::Running(scope.spawn(move || {
+                        let result = job(item);
+                        self.0.fetch_add(1, Ordering::Relaxed);
+                        result
+                    })));
+                } else {
+                    slots.push(Slot::Done(job(item)));
+                }
+            }
+            slots
+                .into_iter()
+                .map(|slot| match slot {
+                    Slot::Done(result) => result,
+                    Slot::Running(handle) => handle.join().expect("worktree walk thread panicked"),
+                })
+                .collect()
+        })
+    }
+}
+
+/// How a tracked file in the worktree compares to its index entry. Each variant but `Deleted`
+/// carries the entry as the file is now, with the hash of the index entry.
+pub(crate) enum EntryState {
+    Unchanged,
+    /// the contents match but the stat data in the index is out of date
+    Stale(IndexEntry),
+    Modified(IndexEntry),
+    TypeChanged(IndexEntry),
+    Deleted,
+}
+
+/// Entries are handed out to the threads this many at a time.
+const BATCH_SIZE: usize = 256;
+
+/// `check_entry` for every entry of the index, spread over the available cores. The states
+/// come back in the order of the entries whichever thread got to them first.
+pub fn check_entries(index: &IndexFile) -> Result<Vec<EntryState>> {
+    let batches = index.entries.len().div_ceil(BATCH_SIZE);
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(batches);
+    if threads <= 1 {
+        return index
+            .entries
+            .iter()
+            .map(|e| check_entry(index, e))
+            .collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut done: Vec<(usize, Result<Vec<EntryState>>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let batch = next.fetch_add(1, Ordering::Relaxed);
+                        if batch >= batches {
+                            break done;
+                        }
+                        let start = batch * BATCH_SIZE;
+                        let end = (start + BATCH_SIZE).min(index.entries.len());
+                        let states = index.entries[start..end]
+                            .iter()
+                            .map(|e| check_entry(index, e))
+                            .collect();
+                        done.push((batch, states));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("worktree check thread panicked"))
+            .collect()
+    });
+    done.sort_by_key(|(batch, _)| *batch);
+
+    let mut states = Vec::with_capacity(index.entries.len());
+    for (_, batch) in done {
+        states.extend(batch?);
+    }
+    Ok(states)
+}
+
+/// Compares `entry` with the file at its path. Stat data is trusted when it matches, unless the
+/// entry is racily clean, otherwise the contents are hashed to tell a touched file from an
+/// edited one.
+pub fn check_entry(index: &IndexFile, entry: &IndexEntry) -> Result<EntryState> {
+    // a submodule is a directory of its own, which we don't look into, and the fsmonitor
+    // hook has vouched for a valid entry
+    if entry.object_type == 0b1110 || entry.fsmonitor_valid {
+        return Ok(EntryState::Unchanged);
+    }
+    if fs::symlink_metadata(&entry.entry_path).is_err() {
+        return Ok(EntryState::Deleted);
+    }
+    let current = IndexEntry::from_path(entry.entry_path.clone(), &entry.hash, entry.flags)
+        .context("constructing index entry from path")?;
+    if current.object_type != entry.object_type {
+        return Ok(EntryState::TypeChanged(current));
+    }
+    if current.unix_permission != entry.unix_permission {
+        return Ok(EntryState::Modified(current));
+    }
+    if current == *entry && !index.is_racy(entry) {
+        return Ok(EntryState::Unchanged);
+    }
+
+    let path = entry
+        .entry_path
+        .to_str()
+        .context("path must be valid utf-8")?;
+    if hash_file(path)? == entry.hash {
+        Ok(EntryState::Stale(current))
+    } else {
+        Ok(EntryState::Modified(current))
+    }
+}
+
+/// What would be stored for the file at `path`: the target of a symlink, or the contents of
+/// a regular file after the clean filters and line ending conversion.
+pub fn contents_for_index(path: &str) -> Result<Vec<u8>> {
+    let file = Path::new(path);
+    let metadata =
+        fs::symlink_metadata(file).with_context(|| format!("reading metadata of {}", path))?;
+    if metadata.is_symlink() {
+        Ok(fs::read_link(file)
+            .with_context(|| format!("reading link {}", path))?
+            .as_os_str()
+            .as_bytes()
+            .to_vec())
+    } else {
+        let contents = fs::read(file).with_context(|| format!("reading {}", path))?;
+        convert::convert_to_git(path, contents)
+    }
+}
+
+/// The blob hash of the file at `path`, without writing the blob.
+pub fn hash_file(path: &str) -> Result<String> {
+    blob_hash(contents_for_index(path)?).with_context(|| format!("hashing {}", path))
+}
+
+/// The hash `contents` would have as a blob.
+pub fn blob_hash(contents: Vec<u8>) -> Result<String> {
+    let hash = Object {
+        kind: Kind::Blob,
+        len: contents.len() as u64,
+        reader: Box::new(io::Cursor::new(contents)),
+    }
+    .hash()?;
+    Ok(hash)
+}
+
+/// Files in the worktree that aren't in the index and aren't ignored, sorted. Unless `all`, a
+/// directory with nothing tracked in it is shown as `dir/`, and only if something in it would
+/// be shown.
+///
+/// With an untracked cache for the same kind of listing in the index, a directory whose stat
+/// data is what the cache recorded isn't read again, and the cache is brought up to date for
+/// the next time. `trust_cache` skips even the stat check, for when the fsmonitor hook has
+/// already invalidated whatever changed. `core.untrackedCache` adds or drops the cache.
+pub fn untracked(index: &mut IndexFile, all: bool, trust_cache: bool) -> Result<Vec<String>> {
+    let flags = if all {
+        0
+    } else {
+        SHOW_OTHER_DIRECTORIES | HIDE_EMPTY_DIRECTORIES
+    };
+    let ident = cache_ident()?;
+    let config = Config::load()?;
+    match config.get("core.untrackedcache") {
+        Some(v) if v.eq_ignore_ascii_case("keep") => {}
+        Some(_) if config.get_bool("core.untrackedcache") == Some(true) => {
+            index
+                .untracked_cache
+                .get_or_insert_with(|| UntrackedCache::new(ident.clone(), flags));
+        }
+        Some(_) => index.untracked_cache = None,
+        None => {}
+    }
+
+    // a cache of the other kind of listing is left for whoever wants that one
+    let mut cache = index
+        .untracked_cache
+        .take_if(|cache| cache.dir_flags == flags);
+    let mut cached_root = None;
+    if let Some(cache) = &mut cache {
+        // mtimes from another worktree or system mean nothing here
+        if cache.ident != ident {
+            *cache = UntrackedCache::new(ident, flags);
+        }
+        let info_exclude = exclude_file(Some(Path::new(".git/info/exclude")))?;
+        let excludes_file = exclude_file(ignore::excludes_file()?.as_deref())?;
+        if info_exclude.hash != cache.info_exclude.hash
+            || excludes_file.hash != cache.excludes_file.hash
+        {
+            cache.root = None;
+        }
+        cache.info_exclude = info_exclude;
+        cache.excludes_file = excludes_file;
+        cached_root = cache.root.take();
+    }
+
+    let mut scan = Scan {
+        tracked: HashSet::new(),
+        tracked_dirs: HashSet::new(),
+        all,
+        timestamp: index.timestamp,
+        trust_cache,
+        spare: Spare::new(),
+    };
+    for entry in &index.entries {
+        let path = entry
+            .entry_path
+            .to_str()
+            .context("path must be valid utf-8")?;
+        scan.tracked.insert(path);
+        for (i, c) in path.char_indices() {
+            if c == '/' {
+                scan.tracked_dirs.insert(&path[..i]);
+            }
+        }
+    }
+    let root = scan.dir(Ignore::standard()?, "", "", cached_root, false, false)?;
+    let mut found = root.found;
+
+    if let Some(mut cache) = cache {
+        cache.root = Some(root.dir);
+        index.untracked_cache = Some(cache);
+    }
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+/// Where git says the untracked cache was made, NUL terminated.
+pub fn cache_ident() -> Result<Vec<u8>> {
+    let top = env::current_dir().context("finding the top of the worktree")?;
+    // the kernel's name for itself, as uname gives it
+    let system = match env::consts::OS {
+        "linux" => "Linux",
+        "macos" => "Darwin",
+        "freebsd" => "FreeBSD",
+        "netbsd" => "NetBSD",
+        "openbsd" => "OpenBSD",
+        other => other,
+    };
+    Ok(format!("Location {}, system {}\0", top.display(), system).into_bytes())
+}
+
+fn exclude_file(path: Option<&Path>) -> Result<ExcludeFile> {
+    let Some(path) = path else {
+        return Ok(ExcludeFile::default());
+    };
+    match fs::metadata(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(ExcludeFile::default()),
+        Err(e) => Err(e).with_context(|| format!("reading metadata of {}", path.display())),
+        Ok(metadata) => {
+            let contents = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+            Ok(ExcludeFile {
+                stat: StatData::from_metadata(&metadata),
+                hash: Some(exclude_hash(contents)?),
+            })
+        }
+    }
+}
+
+/// The hash git records for an ignore file: that of a blob of its contents with a newline
+/// added, which git's parser puts there, unless it is empty.
+fn exclude_hash(mut contents: Vec<u8>) -> Result<String> {
+    if !contents.is_empty() {
+        contents.push(b'\n');
+    }
+    blob_hash(contents)
+}
+
+struct Scan<'a> {
+    tracked: HashSet<&'a str>,
+    /// every directory with something tracked below it
+    tracked_dirs: HashSet<&'a str>,
+    all: bool,
+    /// when the index was written; a directory changed since may change again unnoticed
+    /// within the same tick of its mtime
+    timestamp: Option<(u32, u32)>,
+    trust_cache: bool,
+    spare: Spare,
+}
+
+/// A directory listed by the scan.
+struct Listing {
+    /// the directory as the cache should have it
+    dir: CachedDir,
+    /// whether anything in it was untracked
+    nonempty: bool,
+    /// the untracked paths in it to show
+    found: Vec<String>,
+}
+
+impl Scan<'_> {
+    /// Lists the directory `prefix` (empty or ending in a slash), `ignore` holding the rules
+    /// of the directories above it. A `check_only` directory only has to show whether
+    /// anything in it is untracked, so reading it stops at the first thing found and nothing
+    /// in it is shown.
+    fn dir(
+        &self,
+        mut ignore: Ignore,
+        prefix: &str,
+        name: &str,
+        cached: Option<CachedDir>,
+        rules_changed: bool,
+        check_only: bool,
+    ) -> Result<Listing> {
+        ignore.load_dir(prefix)?;
+        let gitignore = format!("{}.gitignore", prefix);
+        let exclude_hash = match fs::read(&gitignore) {
+            Ok(contents) => Some(exclude_hash(contents)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("reading {}", gitignore)),
+        };
+        // new rules can change what is untracked anywhere below
+        let rules_changed = rules_changed
+            || cached
+                .as_ref()
+                .is_some_and(|c| c.exclude_hash != exclude_hash);
+        let (cached, mut old_dirs) = match cached {
+            Some(c) if rules_changed => (None, c.dirs),
+            // a listing that stopped early is no good for one that can't
+            Some(mut c) if c.valid && (check_only || !c.check_only) => {
+                let dirs = std::mem::take(&mut c.dirs);
+                (Some(c), dirs)
+            }
+            Some(c) => (None, c.dirs),
+            None => (None, Vec::new()),
+        };
+
+        let path = if prefix.is_empty() { "." } else { prefix };
+        let stat = match &cached {
+            Some(c) if self.trust_cache => c.stat.clone(),
+            _ => StatData::from_metadata(
+                &fs::symlink_metadata(path).with_context(|| format!("reading {}", path))?,
+            ),
+        };
+        let racy = self.timestamp.is_some_and(|t| stat.mtime >= t);
+        let mut listing = Listing {
+            dir: CachedDir {
+                name: name.to_string(),
+                valid: true,
+                check_only,
+                stat,
+                exclude_hash,
+                ..CachedDir::default()
+            },
+            nonempty: false,
+            found: Vec::new(),
+        };
+
+        if let Some(cached) =
+            cached.filter(|c| self.trust_cache || (c.stat == listing.dir.stat && !racy))
+        {
+            // the names in the directory are the same, and anything added to or removed from
+            // the index would have invalidated it, so what was untracked still is
+            listing.dir.check_only = cached.check_only;
+            let mut subdirs: Vec<String> = old_dirs.iter().map(|d| d.name.clone()).collect();
+            for entry in &cached.untracked {
+                match entry.strip_suffix('/') {
+                    Some(dir) if !subdirs.iter().any(|d| d == dir) => subdirs.push(dir.to_string()),
+                    Some(_) => {}
+                    None => {
+                        listing.nonempty = true;
+                        listing.dir.untracked.push(entry.clone());
+                        if !check_only {
+                            listing.found.push(format!("{}{}", prefix, entry));
+                        }
+                    }
+                }
+            }
+            let listed = self.subdirs(&ignore, prefix, &subdirs, &mut old_dirs, rules_changed);
+            for (subdir, sub) in subdirs.iter().zip(listed) {
+                self.add_subdir(prefix, subdir, sub?, check_only, &mut listing);
+            }
+            // an early stop may have left out what is untracked now that the thing it stopped
+            // at is gone, so only a full listing can say the directory is empty
+            if listing.nonempty || !cached.check_only {
+                return Ok(listing);
+            }
+            old_dirs = std::mem::take(&mut listing.dir.dirs);
+            listing.dir.untracked.clear();
+            listing.dir.check_only = check_only;
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path).with_context(|| format!("reading {}", path))? {
+            let entry = entry?;
+            let entry_name = entry.file_name();
+            let entry_name = entry_name
+                .to_str()
+                .context("file names must be valid utf-8")?;
+            if prefix.is_empty() && entry_name == ".git" {
+                continue;
+            }
+            let entry_path = format!("{}{}", prefix, entry_name);
+            let is_dir = entry.file_type()?.is_dir();
+            // nothing inside an ignored directory can be brought back
+            if ignore.is_ignored(&entry_path, is_dir) || self.tracked.contains(entry_path.as_str())
+            {
+                continue;
+            }
+            entries.push((entry_name.to_string(), is_dir));
+        }
+        entries.sort();
+
+        // a directory that stops at the first untracked thing has to take them in turn, any
+        // other has its subdirectories listed at once
+        let subdirs: Vec<String> = match check_only {
+            true => Vec::new(),
+            false => entries
+                .iter()
+                .filter(|(_, is_dir)| *is_dir)
+                .map(|(name, _)| name.clone())
+                .collect(),
+        };
+        let mut listed = self
+            .subdirs(&ignore, prefix, &subdirs, &mut old_dirs, rules_changed)
+            .into_iter();
+        for (entry_name, is_dir) in entries {
+            if is_dir {
+                let sub = match check_only {
+                    false => listed.next().expect("every subdirectory is listed"),
+                    true => {
+                        let names = std::slice::from_ref(&entry_name);
+                        let mut one =
+                            self.subdirs(&ignore, prefix, names, &mut old_dirs, rules_changed);
+                        one.remove(0)
+                    }
+                };
+                self.add_subdir(prefix, &entry_name, sub?, check_only, &mut listing);
+            } else {
+                listing.nonempty = true;
+                if !check_only {
+                    listing.found.push(format!("{}{}", prefix, entry_name));
+                }
+                listing.dir.untracked.push(entry_name);
+            }
+            if check_only && listing.nonempty {
+                break;
+            }
+        }
+        Ok(listing)
+    }
+
+    /// Lists the directories `names` inside `prefix`, reusing their entries from `old_dirs`,
+    /// on spare threads while there are any. A directory shown as a whole only has to show
+    /// whether anything in it is untracked.
+    fn subdirs(
+        &self,
+        ignore: &Ignore,
+        prefix: &str,
+        names: &[String],
+        old_dirs: &mut Vec<CachedDir>,
+        rules_changed: bool,
+    ) -> Vec<Result<Listing>> {
+        let mut jobs = Vec::with_capacity(names.len());
+        for name in names {
+            let cached = old_dirs
+                .iter()
+                .position(|d| d.name == *name)
+                .map(|i| old_dirs.swap_remove(i));
+            jobs.push((name, cached));
+        }
+        self.spare.map(jobs, |(name, cached)| {
+            let path = format!("{}{}", prefix, name);
+            let whole = self.whole(&path);
+            self.dir(
+                ignore.clone(),
+                &format!("{}/", path),
+                name,
+                cached,
+                rules_changed,
+                whole,
+            )
+        })
+    }
+
+    /// Whether the directory at `path` is shown as a whole rather than file by file, as one
+    /// with nothing tracked in it is unless showing all files.
+    fn whole(&self, path: &str) -> bool {
+        !self.all && !self.tracked_dirs.contains(path)
+    }
+
+    /// Takes the listing of the directory `name` inside `prefix` into its parent's, where a
+    /// directory shown as a whole is `name/`.
+    fn add_subdir(
+        &self,
+        prefix: &str,
+        name: &str,
+        sub: Listing,
+        check_only: bool,
+        listing: &mut Listing,
+    ) {
+        listing.dir.dirs.push(sub.dir);
+        listing.found.extend(sub.found);
+        if sub.nonempty && self.whole(&format!("{}{}", prefix, name)) {
+            listing.dir.untracked.push(format!("{}/", name));
+            if !check_only {
+                listing.found.push(format!("{}{}/", prefix, name));
+            }
+        }
+        listing.nonempty |= sub.nonempty;
+    }
+}
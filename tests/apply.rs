@@ -0,0 +1,132 @@
+//! `apply` must not write or delete anything outside the worktree, whatever names a patch
+//! carries.
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+/// A fresh repository in its own directory, with room next to it for what mustn't be written.
+struct Repo {
+    outside: PathBuf,
+    root: PathBuf,
+}
+
+impl Repo {
+    fn new(name: &str) -> Repo {
+        let outside =
+            std::env::temp_dir().join(format!("git-clone-apply-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&outside);
+        let root = outside.join("repo");
+        fs::create_dir_all(&root).unwrap();
+        let repo = Repo { outside, root };
+        assert!(repo.run(&["init"]).status.success());
+        repo
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_git-clone"))
+            .args(args)
+            .current_dir(&self.root)
+            .output()
+            .unwrap()
+    }
+
+    /// Applies `patch`, returning the exit code and what was written to stderr.
+    fn apply(&self, patch: &str, args: &[&str]) -> (i32, String) {
+        fs::write(self.root.join("patch"), patch).unwrap();
+        let output = self.run(&[&["apply"], args, &["patch"]].concat());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        (output.status.code().unwrap(), stderr)
+    }
+}
+
+impl Drop for Repo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.outside);
+    }
+}
+
+fn new_file(name: &str) -> String {
+    format!(
+        "diff --git a/{0} b/{0}\nnew file mode 100644\n--- /dev/null\n+++ b/{0}\n\
+         @@ -0,0 +1 @@\n+hi\n",
+        name
+    )
+}
+
+#[test]
+fn refuses_to_climb_out_of_the_worktree() {
+    let repo = Repo::new("dotdot");
+    let (code, stderr) = repo.apply(&new_file("../escaped.txt"), &[]);
+    assert_eq!(code, 128);
+    assert_eq!(stderr, "error: invalid path '../escaped.txt'\n");
+    assert!(!repo.outside.join("escaped.txt").exists());
+}
+
+#[test]
+fn refuses_absolute_paths() {
+    let repo = Repo::new("absolute");
+    let target = repo.outside.join("absolute.txt");
+    let patch = format!(
+        "--- /dev/null\n+++ {}\n@@ -0,0 +1 @@\n+hi\n",
+        target.display()
+    );
+    let (code, stderr) = repo.apply(&patch, &["-p0"]);
+    assert_eq!(code, 128);
+    assert_eq!(
+        stderr,
+        format!("error: invalid path '{}'\n", target.display())
+    );
+    assert!(!target.exists());
+}
+
+#[test]
+fn refuses_to_write_into_the_repository() {
+    let repo = Repo::new("dotgit");
+    for name in [".git/hooks/pre-commit", "sub/.GIT/config", "./a"] {
+        let (code, stderr) = repo.apply(&new_file(name), &[]);
+        assert_eq!(code, 128);
+        assert_eq!(stderr, format!("error: invalid path '{}'\n", name));
+    }
+    assert!(!repo.root.join(".git/hooks/pre-commit").exists());
+    assert!(!repo.root.join("sub").exists());
+}
+
+#[test]
+fn refuses_to_write_beyond_a_symlink() {
+    let repo = Repo::new("symlink");
+    let target = repo.outside.join("target");
+    fs::create_dir(&target).unwrap();
+    symlink(&target, repo.root.join("link")).unwrap();
+
+    let (code, stderr) = repo.apply(&new_file("link/x"), &[]);
+    assert_eq!(code, 1);
+    assert_eq!(
+        stderr,
+        "error: affected file 'link/x' is beyond a symbolic link\n"
+    );
+    assert!(!target.join("x").exists());
+}
+
+#[test]
+fn refuses_to_delete_beyond_a_symlink() {
+    let repo = Repo::new("delete");
+    let target = repo.outside.join("target");
+    fs::create_dir(&target).unwrap();
+    fs::write(target.join("old"), "x\n").unwrap();
+    symlink(&target, repo.root.join("link")).unwrap();
+
+    let patch = "diff --git a/link/old b/link/old\ndeleted file mode 100644\n\
+                 --- a/link/old\n+++ /dev/null\n@@ -1 +0,0 @@\n-x\n";
+    let (code, stderr) = repo.apply(patch, &[]);
+    assert_eq!(code, 1);
+    assert_eq!(
+        stderr,
+        "error: reading from 'link/old' beyond a symbolic link\n\
+         error: link/old: patch does not apply\n"
+    );
+    assert!(target.join("old").exists());
+}